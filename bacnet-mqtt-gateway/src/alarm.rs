@@ -0,0 +1,312 @@
+use crate::config::{AlarmConfig, AlarmSourceRef, EscalationConfig};
+use crate::point::ObjectRef;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Window the transitions of a chattering alarm are counted in
+const CHATTER_WINDOW: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Default)]
+struct AlarmSource {
+    active: bool,
+    /// State changes within the chatter window
+    transitions: VecDeque<Instant>,
+    /// `Some(None)` shelves indefinitely
+    shelved_until: Option<Option<SystemTime>>,
+    /// Last state published to MQTT
+    published: Option<bool>,
+    /// When the active state was published
+    raised_at: Option<Instant>,
+    acknowledged: bool,
+    renotifications: u32,
+    next_renotify: Option<Instant>,
+    escalated: bool,
+}
+
+impl AlarmSource {
+    fn is_shelved(&self, now: SystemTime) -> bool {
+        match self.shelved_until {
+            Some(Some(until)) => now < until,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Starts or ends the acknowledgement, re-notification and escalation cycle
+    fn mark_published(&mut self, active: bool, escalation: &EscalationConfig, now: Instant) {
+        self.published = Some(active);
+        self.acknowledged = false;
+        self.renotifications = 0;
+        self.escalated = false;
+        self.raised_at = active.then_some(now);
+        self.next_renotify = (active && escalation.renotify_after_mins > 0).then(|| now + escalation.renotify_delay(0));
+    }
+}
+
+/// Follow-up of an unacknowledged critical alarm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notification {
+    /// Repeated publication, counting from 1
+    Renotify(u32),
+    /// Hand-over to the escalation topic and webhook
+    Escalate,
+}
+
+#[derive(Debug, Clone)]
+pub struct DueNotification {
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub notification: Notification,
+    /// Time since the alarm was raised
+    pub active_for: Duration,
+}
+
+/// Alarm source as reported by the alarm summary API
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmSummary {
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub active: bool,
+    pub shelved: bool,
+    /// End of the shelving period in seconds since the epoch, absent when
+    /// shelved indefinitely
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shelved_until: Option<u64>,
+    pub chattering: bool,
+    pub transitions_last_hour: usize,
+    pub acknowledged: bool,
+    pub escalated: bool,
+}
+
+/// Tracks the alarm state of polled points and decides which changes reach
+/// MQTT, holding back shelved and chattering alarm sources
+pub struct AlarmManager {
+    max_transitions_per_hour: u32,
+    escalation: EscalationConfig,
+    sources: RwLock<HashMap<(u32, ObjectRef), AlarmSource>>,
+}
+
+impl AlarmManager {
+    pub fn new(config: &AlarmConfig) -> Self {
+        let now = SystemTime::now();
+        let sources = config
+            .shelved
+            .iter()
+            .map(|shelved| {
+                let until = shelved
+                    .hours
+                    .and_then(|hours| Duration::try_from_secs_f64(hours * 3600.0).ok())
+                    .map(|period| now + period);
+                let source = AlarmSource { shelved_until: Some(until), ..Default::default() };
+                ((shelved.device, shelved.object), source)
+            })
+            .collect();
+        Self {
+            max_transitions_per_hour: config.max_transitions_per_hour,
+            escalation: config.escalation.clone(),
+            sources: RwLock::new(sources),
+        }
+    }
+
+    fn is_chattering(&self, source: &AlarmSource) -> bool {
+        self.max_transitions_per_hour > 0 && source.transitions.len() > self.max_transitions_per_hour as usize
+    }
+
+    /// Records the alarm state read from a point and returns the state to
+    /// publish, if it differs from the last published one and the source is
+    /// neither shelved nor chattering
+    pub fn update(&self, device_id: u32, object: ObjectRef, active: bool) -> Option<bool> {
+        let mut sources = self.sources.write().unwrap_or_else(|e| e.into_inner());
+        let source = sources.entry((device_id, object)).or_default();
+        let now = Instant::now();
+        if source.active != active {
+            source.active = active;
+            source.transitions.push_back(now);
+        }
+        while source.transitions.front().is_some_and(|t| now.duration_since(*t) > CHATTER_WINDOW) {
+            source.transitions.pop_front();
+        }
+
+        if source.is_shelved(SystemTime::now()) || self.is_chattering(source) || source.published == Some(active) {
+            return None;
+        }
+        // A source that never alarmed does not need an initial "normal" message
+        let initial_normal = source.published.is_none() && !active;
+        source.mark_published(active, &self.escalation, now);
+        (!initial_normal).then_some(active)
+    }
+
+    fn is_critical(&self, device_id: u32, object: ObjectRef) -> bool {
+        self.escalation.critical.is_empty()
+            || self.escalation.critical.contains(&AlarmSourceRef { device: device_id, object })
+    }
+
+    /// Acknowledges an active alarm, ending its re-notification and escalation;
+    /// returns false if the source has no active alarm to acknowledge
+    pub fn acknowledge(&self, device_id: u32, object: ObjectRef) -> bool {
+        let mut sources = self.sources.write().unwrap_or_else(|e| e.into_inner());
+        match sources.get_mut(&(device_id, object)) {
+            Some(source) if source.published == Some(true) && !source.acknowledged => {
+                source.acknowledged = true;
+                info!("Acknowledged alarm of device {} {}", device_id, object);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Re-notifications and escalations that are due now, recorded as sent
+    pub fn due_notifications(&self) -> Vec<DueNotification> {
+        let mut sources = self.sources.write().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let wall_clock = SystemTime::now();
+        let escalate_after = Duration::from_secs(self.escalation.escalate_after_mins * 60);
+        let mut due = Vec::new();
+        for ((device_id, object), source) in sources.iter_mut() {
+            let Some(raised_at) = source.raised_at else {
+                continue;
+            };
+            if source.acknowledged
+                || source.is_shelved(wall_clock)
+                || self.is_chattering(source)
+                || !self.is_critical(*device_id, *object)
+            {
+                continue;
+            }
+            let mut push = |notification| {
+                due.push(DueNotification {
+                    device_id: *device_id,
+                    object: *object,
+                    notification,
+                    active_for: now.duration_since(raised_at),
+                })
+            };
+            if source.next_renotify.is_some_and(|next| next <= now) {
+                source.renotifications += 1;
+                source.next_renotify = Some(now + self.escalation.renotify_delay(source.renotifications));
+                push(Notification::Renotify(source.renotifications));
+            }
+            if !escalate_after.is_zero() && !source.escalated && now.duration_since(raised_at) >= escalate_after {
+                source.escalated = true;
+                push(Notification::Escalate);
+            }
+        }
+        due
+    }
+
+    /// Shelves an alarm source, for `period` or indefinitely
+    pub fn shelve(&self, device_id: u32, object: ObjectRef, period: Option<Duration>) {
+        let mut sources = self.sources.write().unwrap_or_else(|e| e.into_inner());
+        sources.entry((device_id, object)).or_default().shelved_until = Some(period.map(|p| SystemTime::now() + p));
+        info!("Shelved alarms of device {} {} for {:?}", device_id, object, period);
+    }
+
+    /// Returns false if the source was not shelved
+    pub fn unshelve(&self, device_id: u32, object: ObjectRef) -> bool {
+        let mut sources = self.sources.write().unwrap_or_else(|e| e.into_inner());
+        match sources.get_mut(&(device_id, object)) {
+            Some(source) if source.shelved_until.is_some() => {
+                source.shelved_until = None;
+                info!("Unshelved alarms of device {} {}", device_id, object);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Alarms currently active and not shelved
+    pub fn active_count(&self) -> usize {
+        let sources = self.sources.read().unwrap_or_else(|e| e.into_inner());
+        let now = SystemTime::now();
+        sources.values().filter(|s| s.active && !s.is_shelved(now)).count()
+    }
+
+    pub fn summary(&self) -> Vec<AlarmSummary> {
+        let sources = self.sources.read().unwrap_or_else(|e| e.into_inner());
+        let now = SystemTime::now();
+        let mut summary: Vec<AlarmSummary> = sources
+            .iter()
+            .map(|((device_id, object), source)| AlarmSummary {
+                device_id: *device_id,
+                object: *object,
+                active: source.active,
+                shelved: source.is_shelved(now),
+                shelved_until: match source.shelved_until {
+                    Some(Some(until)) if until > now => until.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()),
+                    _ => None,
+                },
+                chattering: self.is_chattering(source),
+                transitions_last_hour: source.transitions.len(),
+                acknowledged: source.acknowledged,
+                escalated: source.escalated,
+            })
+            .collect();
+        summary.sort_by_key(|s| (s.device_id, s.object));
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ShelvedAlarmConfig;
+
+    const DEVICE: u32 = 1200;
+
+    fn object() -> ObjectRef {
+        ObjectRef::new(0, 1)
+    }
+
+    #[test]
+    fn normal_is_only_published_after_an_alarm() {
+        let alarms = AlarmManager::new(&AlarmConfig::default());
+        assert_eq!(alarms.update(DEVICE, object(), false), None, "no initial normal");
+        assert_eq!(alarms.update(DEVICE, object(), false), None);
+        assert_eq!(alarms.update(DEVICE, object(), true), Some(true));
+        assert_eq!(alarms.update(DEVICE, object(), true), None, "unchanged");
+        assert_eq!(alarms.update(DEVICE, object(), false), Some(false));
+    }
+
+    #[test]
+    fn chattering_source_is_held_back() {
+        let alarms = AlarmManager::new(&AlarmConfig { max_transitions_per_hour: 2, ..Default::default() });
+        assert_eq!(alarms.update(DEVICE, object(), true), Some(true));
+        assert_eq!(alarms.update(DEVICE, object(), false), Some(false));
+        assert_eq!(alarms.update(DEVICE, object(), true), None, "third transition within the hour");
+        assert_eq!(alarms.update(DEVICE, object(), false), None);
+
+        let summary = alarms.summary();
+        assert!(summary[0].chattering);
+        assert_eq!(summary[0].transitions_last_hour, 4);
+    }
+
+    #[test]
+    fn shelved_source_is_published_once_shelving_expires() {
+        let alarms = AlarmManager::new(&AlarmConfig::default());
+        alarms.shelve(DEVICE, object(), Some(Duration::from_secs(3600)));
+        assert_eq!(alarms.update(DEVICE, object(), true), None);
+        assert_eq!(alarms.active_count(), 0);
+
+        // Rewind the end of the shelving period instead of waiting for it
+        let expired = SystemTime::now() - Duration::from_secs(1);
+        alarms.sources.write().unwrap().get_mut(&(DEVICE, object())).unwrap().shelved_until = Some(Some(expired));
+        assert_eq!(alarms.update(DEVICE, object(), true), Some(true), "the state held back while shelved");
+        assert_eq!(alarms.active_count(), 1);
+        assert!(!alarms.summary()[0].shelved);
+    }
+
+    #[test]
+    fn configured_shelving_applies_from_startup() {
+        let shelved = ShelvedAlarmConfig { device: DEVICE, object: object(), hours: Some(2.0) };
+        let alarms = AlarmManager::new(&AlarmConfig { shelved: vec![shelved], ..Default::default() });
+        assert_eq!(alarms.update(DEVICE, object(), true), None);
+        let summary = alarms.summary();
+        assert!(summary[0].shelved);
+        assert!(summary[0].shelved_until.is_some());
+        assert!(alarms.unshelve(DEVICE, object()));
+        assert_eq!(alarms.update(DEVICE, object(), true), Some(true));
+    }
+}
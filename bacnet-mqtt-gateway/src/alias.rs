@@ -0,0 +1,93 @@
+//! Human-readable names configured for devices and their objects, used in
+//! topics, entity names, logs and the web UI instead of instance numbers
+
+use crate::config::DeviceConfig;
+use crate::locale::{Text, Translator};
+use crate::point::ObjectRef;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+#[derive(Debug, Default, PartialEq)]
+struct DeviceAliases {
+    name: Option<String>,
+    objects: BTreeMap<ObjectRef, String>,
+}
+
+fn parse(devices: &[DeviceConfig]) -> HashMap<u32, DeviceAliases> {
+    devices
+        .iter()
+        .filter(|device| device.alias.is_some() || !device.object_aliases.is_empty())
+        .map(|device| {
+            let aliases = DeviceAliases { name: device.alias.clone(), objects: device.object_aliases.clone() };
+            (device.instance, aliases)
+        })
+        .collect()
+}
+
+/// The `alias` and `object_aliases` of the configured devices
+pub struct Aliases {
+    devices: RwLock<HashMap<u32, DeviceAliases>>,
+}
+
+impl Aliases {
+    pub fn new(devices: &[DeviceConfig]) -> Self {
+        Self { devices: RwLock::new(parse(devices)) }
+    }
+
+    /// Replaces the aliases, true if any changed
+    pub fn reload(&self, devices: &[DeviceConfig]) -> bool {
+        let new = parse(devices);
+        let mut aliases = self.devices.write().unwrap_or_else(|e| e.into_inner());
+        let changed = *aliases != new;
+        *aliases = new;
+        changed
+    }
+
+    pub fn device(&self, device_id: u32) -> Option<String> {
+        let aliases = self.devices.read().unwrap_or_else(|e| e.into_inner());
+        aliases.get(&device_id)?.name.clone()
+    }
+
+    pub fn object(&self, device_id: u32, object: ObjectRef) -> Option<String> {
+        let aliases = self.devices.read().unwrap_or_else(|e| e.into_inner());
+        aliases.get(&device_id)?.objects.get(&object).cloned()
+    }
+
+    /// Device whose alias satisfies `matches`
+    pub fn find_device(&self, matches: impl Fn(&str) -> bool) -> Option<u32> {
+        let aliases = self.devices.read().unwrap_or_else(|e| e.into_inner());
+        aliases.iter().find(|(_, device)| device.name.as_deref().is_some_and(&matches)).map(|(id, _)| *id)
+    }
+
+    /// Name of the device in Home Assistant, its alias or the localized default
+    pub fn device_name(&self, device_id: u32, translator: &Translator) -> String {
+        self.device(device_id).unwrap_or_else(|| translator.format(Text::DeviceName, device_id))
+    }
+
+    /// Home Assistant name of a point: the device name followed by the object's
+    /// alias or reference, just the device name for the gateway's poll object
+    pub fn entity_name(&self, device_id: u32, object: ObjectRef, poll_object: ObjectRef, translator: &Translator) -> String {
+        let device = self.device_name(device_id, translator);
+        match self.object(device_id, object) {
+            Some(alias) => format!("{} {}", device, alias),
+            None if object == poll_object => device,
+            None => format!("{} {}", device, object),
+        }
+    }
+
+    /// Device for log messages, e.g. `2001 (AHU-1)`
+    pub fn device_label(&self, device_id: u32) -> String {
+        match self.device(device_id) {
+            Some(alias) => format!("{} ({})", device_id, alias),
+            None => device_id.to_string(),
+        }
+    }
+
+    /// Object for log messages, e.g. `AI:3 (Supply Air Temp)`
+    pub fn object_label(&self, device_id: u32, object: ObjectRef) -> String {
+        match self.object(device_id, object) {
+            Some(alias) => format!("{} ({})", object, alias),
+            None => object.to_string(),
+        }
+    }
+}
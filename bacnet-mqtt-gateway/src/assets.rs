@@ -0,0 +1,30 @@
+//! The web UI's HTML, CSS and JavaScript from `ui/`, compiled into the binary
+//! so no web root has to be deployed next to it
+
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+#[derive(RustEmbed)]
+#[folder = "ui/"]
+struct Assets;
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// An embedded file, 404 if there is none at `path`
+pub fn serve(path: &str) -> Response {
+    match Assets::get(path) {
+        Some(file) => ([(header::CONTENT_TYPE, content_type(path)), (header::CACHE_CONTROL, "no-cache")], file.data).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("no asset '{}'", path)).into_response(),
+    }
+}
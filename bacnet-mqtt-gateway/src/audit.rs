@@ -0,0 +1,97 @@
+use crate::bacnet::BacnetEngine;
+use crate::batch::{BatchWrite, WriteStatus};
+use crate::codec::{self, PropertyReference};
+use crate::config::AuditConfig;
+use crate::mqtt::{self, MqttService};
+use crate::point::ObjectRef;
+use serde::Serialize;
+use std::io::Write;
+use std::net::SocketAddr;
+use tracing::{debug, info, warn};
+
+/// One executed or attempted write
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    pub ts: String,
+    /// Where the write came from, e.g. `api` or the MQTT command topic
+    pub origin: &'a str,
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub property: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// Value read just before the write, when reading it is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<serde_json::Value>,
+    pub after: &'a serde_json::Value,
+    pub status: WriteStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+/// Records writes to devices in the audit file and as change events on MQTT
+pub struct AuditLog {
+    config: AuditConfig,
+    mqtt: MqttService,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig, mqtt: MqttService) -> Self {
+        Self { config, mqtt }
+    }
+
+    /// Reads the value a write is about to replace, if configured to
+    pub async fn before(&self, engine: &BacnetEngine, addr: Option<SocketAddr>, write: &BatchWrite) -> Option<serde_json::Value> {
+        if !self.config.read_before_write {
+            return None;
+        }
+        let reference = PropertyReference { object: write.object, property: write.property, array_index: write.array_index };
+        match engine.read_property_value(addr?, &reference).await {
+            Ok(value) => {
+                let mut values: Vec<serde_json::Value> = codec::decode_property_value(&value).iter().map(|v| v.to_json()).collect();
+                Some(if values.len() == 1 { values.remove(0) } else { values.into() })
+            }
+            Err(e) => {
+                debug!("Could not read prior value of device {} {}: {}", write.device_id, write.object, e);
+                None
+            }
+        }
+    }
+
+    pub async fn record(
+        &self,
+        origin: &str,
+        write: &BatchWrite,
+        before: Option<serde_json::Value>,
+        status: WriteStatus,
+        error: Option<&str>,
+    ) {
+        let entry = AuditEntry {
+            ts: mqtt::utc_timestamp(),
+            origin,
+            device_id: write.device_id,
+            object: write.object,
+            property: write.property,
+            priority: write.priority,
+            before,
+            after: &write.value,
+            status,
+            error,
+        };
+        let Ok(line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        info!("Audit: {}", line);
+        if let Some(path) = &self.config.file {
+            let appended = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+            if let Err(e) = appended {
+                warn!("Failed to append to audit log {}: {}", path.display(), e);
+            }
+        }
+        self.mqtt.publish(&self.mqtt.audit_topic(), &line, false).await;
+    }
+}
@@ -0,0 +1,104 @@
+//! Optional login guarding the web UI and REST API: a static bearer token for
+//! API clients, or a username and password starting a session cookie
+
+use crate::config::WebAuthConfig;
+use crate::secret;
+use axum::http::{header, HeaderMap};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const SESSION_COOKIE: &str = "gateway_session";
+
+/// Compares secrets in time independent of where they differ
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn session_id() -> std::io::Result<String> {
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().fold(String::new(), |mut id, byte| {
+        let _ = write!(id, "{:02x}", byte);
+        id
+    }))
+}
+
+pub struct Auth {
+    token: Option<String>,
+    account: Option<(String, String)>,
+    session_ttl: Duration,
+    /// Session IDs with their start
+    sessions: Mutex<HashMap<String, Instant>>,
+}
+
+impl Auth {
+    pub fn new(config: &WebAuthConfig) -> Result<Self, String> {
+        let token = secret::resolve(config.token.as_deref(), config.token_file.as_deref()).map_err(|e| format!("web.auth.token: {}", e))?;
+        let password =
+            secret::resolve(config.password.as_deref(), config.password_file.as_deref()).map_err(|e| format!("web.auth.password: {}", e))?;
+        Ok(Self {
+            token,
+            account: config.username.clone().zip(password),
+            session_ttl: Duration::from_secs(config.session_secs),
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// True if a login page can start sessions
+    pub fn has_account(&self) -> bool {
+        self.account.is_some()
+    }
+
+    /// Starts a session for valid credentials, returning its ID
+    pub fn login(&self, username: &str, password: &str) -> std::io::Result<Option<String>> {
+        let valid = self.account.as_ref().is_some_and(|(user, pass)| same(user, username) & same(pass, password));
+        if !valid {
+            return Ok(None);
+        }
+        let id = session_id()?;
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone(), Instant::now());
+        Ok(Some(id))
+    }
+
+    pub fn logout(&self, headers: &HeaderMap) {
+        if let Some(id) = session_cookie(headers) {
+            self.sessions.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+        }
+    }
+
+    /// True for the bearer token or a live session cookie
+    pub fn authorized(&self, headers: &HeaderMap) -> bool {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let (Some(token), Some(bearer)) = (&self.token, bearer) {
+            if same(token, bearer.trim()) {
+                return true;
+            }
+        }
+        let Some(id) = session_cookie(headers) else {
+            return false;
+        };
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, started| started.elapsed() < self.session_ttl);
+        sessions.contains_key(id)
+    }
+
+    /// `Set-Cookie` value of a new session
+    pub fn cookie(&self, id: &str) -> String {
+        format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}", SESSION_COOKIE, id, self.session_ttl.as_secs())
+    }
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+}
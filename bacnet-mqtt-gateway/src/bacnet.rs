@@ -1,54 +1,523 @@
-use crate::config::BacnetConfig;
+use crate::capture::{Capture, Capturing};
+use crate::codec::{self, PropertyError, PropertyReference, PropertyResult, WriteSpec};
+use crate::commstats::{CommStats, DeviceCommStats};
+use crate::config::{ApduPolicy, BacnetConfig, NetworkPriority, QuirkProfile};
+use crate::cov::{CovSubscriptions, SubscriptionEntry, SubscriptionParams};
+use crate::datalink::{self, DataLink, MultiDataLink};
+use crate::filter::Subnet;
+use crate::point::{self, ObjectRef, PropertyBundle};
+use crate::quirks::Quirks;
+use crate::server::{LocalDevice, VirtualWrite};
 use bacnet_rs::{
     datalink::bip::BacnetIpDataLink,
-    datalink::{DataLink, DataLinkAddress},
     network::Npdu,
     object::Device,
-    service::{UnconfirmedServiceChoice, WhoIsRequest, IAmRequest, ReadPropertyRequest, ReadPropertyResponse},
-    app::Apdu,
+    service::{ConfirmedServiceChoice, UnconfirmedServiceChoice, WhoIsRequest, IAmRequest, ReadPropertyResponse},
+    app::{Apdu, MaxApduSize},
 };
-use tokio::sync::mpsc;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU8, Ordering};
-use tracing::{info, trace};
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, trace, warn};
+
+const PROP_PROPERTY_LIST: u32 = 371;
+const PROP_OBJECT_LIST: u32 = 76;
+const PROP_MODEL_NAME: u32 = 70;
+
+/// Timed out requests without any frame received in between that make the
+/// receive watchdog suspect the datalink
+const WATCHDOG_MIN_TIMEOUTS: u64 = 3;
 
 #[derive(Debug, Clone)]
 pub enum BacnetEvent {
     WhoIs(WhoIsRequest, SocketAddr),
+    /// I-Am of a device not seen before or whose vendor changed; repeated
+    /// identical I-Ams are not reported again
     IAm(IAmRequest, SocketAddr),
-    ReadProperty(ReadPropertyRequest, u8, SocketAddr),
-    ReadPropertyAck(ReadPropertyResponse, u8, SocketAddr),
+    /// I-Am of a known device from a new address, with the previous address
+    DeviceMoved(IAmRequest, SocketAddr, SocketAddr),
+    /// ReadProperty served from the gateway's own objects
+    ReadProperty(PropertyReference, u8, SocketAddr),
+    /// Acknowledged read with the round trip latency of the matching request, if it was sent by us
+    ReadPropertyAck(ReadPropertyResponse, u8, SocketAddr, Option<Duration>),
+    /// WriteProperty accepted by a virtual object, to be forwarded to its MQTT topic
+    VirtualObjectWritten(VirtualWrite),
+    /// A fire-and-forget request (e.g. a poll) that failed or timed out
+    RequestFailed(u8, SocketAddr, BacnetError),
+}
+
+/// Failure of a confirmed request
+#[derive(Debug, Clone)]
+pub enum BacnetError {
+    Timeout,
+    /// Error PDU, with the first failed write of a WritePropertyMultiple
+    Error { class: u32, code: u32, first_failed: Option<PropertyReference> },
+    Reject(u8),
+    Abort(u8),
+    Send(String),
+    Decode(String),
+    /// Write not sent because the gateway runs in dry-run mode
+    DryRun,
+}
+
+impl fmt::Display for BacnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BacnetError::Timeout => write!(f, "request timed out"),
+            BacnetError::Error { class, code, .. } => {
+                write!(f, "error {} ({})", codec::error_code_name(*code), codec::error_class_name(*class))
+            }
+            BacnetError::Reject(reason) => write!(f, "rejected: {}", codec::reject_reason_name(*reason)),
+            BacnetError::Abort(reason) => write!(f, "aborted: {}", codec::abort_reason_name(*reason)),
+            BacnetError::Send(e) => write!(f, "send failed: {}", e),
+            BacnetError::Decode(e) => write!(f, "invalid reply: {}", e),
+            BacnetError::DryRun => write!(f, "not written in dry-run mode"),
+        }
+    }
+}
+
+impl std::error::Error for BacnetError {}
+
+/// Outcome counters of confirmed requests
+#[derive(Debug, Default)]
+struct ApduCounters {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    rejects: AtomicU64,
+    aborts: AtomicU64,
+    timeouts: AtomicU64,
+    recoveries: AtomicU64,
+    /// The same outcomes by device address
+    devices: CommStats,
+}
+
+impl ApduCounters {
+    fn record(&self, result: &Result<ConfirmedAck, BacnetError>) {
+        let counter = match result {
+            Err(BacnetError::Error { .. }) => &self.errors,
+            Err(BacnetError::Reject(_)) => &self.rejects,
+            Err(BacnetError::Abort(_)) => &self.aborts,
+            Err(BacnetError::Timeout) => &self.timeouts,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of the confirmed request counters since startup
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ApduStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub rejects: u64,
+    pub aborts: u64,
+    pub timeouts: u64,
+    /// Datalink rebuilds by the receive watchdog
+    pub recoveries: u64,
+}
+
+/// What the readiness probe checks of the BACnet side
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BacnetHealth {
+    pub datalink_open: bool,
+    /// The receive loop went around within the last few seconds
+    pub receiving: bool,
+}
+
+/// An I-Am as received, repeats included
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct IAmHeard {
+    pub device_id: u32,
+    pub address: SocketAddr,
+    pub vendor_id: u32,
+}
+
+/// Longest pause of the receive loop before it counts as stalled
+const RECEIVE_LOOP_STALL: Duration = Duration::from_secs(10);
+
+/// Successful answer to a confirmed request
+#[derive(Debug)]
+pub enum ConfirmedAck {
+    Simple,
+    Complex(Vec<u8>),
+}
+
+/// A confirmed request waiting for its answer
+struct Outstanding {
+    target: SocketAddr,
+    /// Encoded request, retransmitted unchanged (same invoke ID) on timeout
+    packet: Vec<u8>,
+    policy: ApduPolicy,
+    /// Retransmissions so far
+    attempt: u32,
+    /// Time of the latest transmission
+    sent_at: Instant,
+    deadline: Instant,
+    /// Callers awaiting the result; fire-and-forget requests are answered through events instead
+    reply: Option<oneshot::Sender<Result<ConfirmedAck, BacnetError>>>,
+}
+
+impl Outstanding {
+    fn new(
+        target: SocketAddr,
+        packet: Vec<u8>,
+        policy: ApduPolicy,
+        reply: Option<oneshot::Sender<Result<ConfirmedAck, BacnetError>>>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            target,
+            packet,
+            policy,
+            attempt: 0,
+            sent_at: now,
+            deadline: now + policy.timeout_for(0),
+            reply,
+        }
+    }
+}
+
+/// Pending confirmed requests by target and invoke ID, the ID alone being reused
+/// across devices
+type OutstandingMap = Arc<std::sync::Mutex<HashMap<(SocketAddr, u8), Outstanding>>>;
+
+/// Opens a fresh datalink to replace a wedged one
+type DatalinkFactory = Arc<dyn Fn() -> Result<Box<dyn DataLink>, Box<dyn std::error::Error>> + Send + Sync>;
+
+/// Notices a receive path that stopped yielding frames while requests keep timing out
+struct ReceiveWatchdog {
+    /// Zero disables the watchdog
+    period: Duration,
+    last_frame: Instant,
+    /// Timeout counter when the last frame was received
+    timeouts_at_frame: u64,
+}
+
+impl ReceiveWatchdog {
+    fn new(period: Duration) -> Self {
+        Self { period, last_frame: Instant::now(), timeouts_at_frame: 0 }
+    }
+
+    fn frame_received(&mut self, timeouts: u64) {
+        self.last_frame = Instant::now();
+        self.timeouts_at_frame = timeouts;
+    }
+
+    /// True when nothing arrived for a whole period although requests went unanswered
+    fn is_wedged(&self, timeouts: u64) -> bool {
+        !self.period.is_zero()
+            && timeouts - self.timeouts_at_frame >= WATCHDOG_MIN_TIMEOUTS
+            && self.last_frame.elapsed() >= self.period
+    }
+}
+
+/// Reports a write the dry-run mode kept from being sent
+fn log_dry_run(target: SocketAddr, write: &WriteSpec) {
+    let value = codec::decode_application_values(&write.value).map_or_else(|_| format!("{:02x?}", write.value), |values| format!("{:?}", values));
+    match write.priority {
+        Some(priority) => info!("Dry run, not writing {} to {} property {} on {} at priority {}", value, write.reference.object, write.reference.property, target, priority),
+        None => info!("Dry run, not writing {} to {} property {} on {}", value, write.reference.object, write.reference.property, target),
+    }
+}
+
+/// Largest encodable reply size not above `octets`
+fn max_apdu_size(octets: u16) -> MaxApduSize {
+    match octets {
+        0..=127 => MaxApduSize::Up50,
+        128..=205 => MaxApduSize::Up128,
+        206..=479 => MaxApduSize::Up206,
+        480..=1023 => MaxApduSize::Up480,
+        1024..=1475 => MaxApduSize::Up1024,
+        _ => MaxApduSize::Up1476,
+    }
+}
+
+/// Replaces the datalink with a freshly opened one; on failure it stays closed
+/// until the watchdog tries again
+fn rebuild_datalink(datalink: &mut Box<dyn DataLink>, factory: Option<&DatalinkFactory>, counters: &ApduCounters) {
+    let Some(factory) = factory else {
+        warn!("Datalink cannot be rebuilt, restart the gateway to recover");
+        return;
+    };
+    // Drop the old socket first so its port can be bound again
+    *datalink = Box::new(datalink::Closed);
+    match factory() {
+        Ok(fresh) => {
+            *datalink = fresh;
+            counters.recoveries.fetch_add(1, Ordering::Relaxed);
+            info!("Datalink rebuilt");
+        }
+        Err(e) => tracing::error!("Failed to rebuild the datalink: {}", e),
+    }
+}
+
+/// Retransmits confirmed requests whose answer is overdue and fails the ones
+/// that ran out of retries, returning the timed out fire-and-forget requests
+fn retransmit_expired(outstanding: &OutstandingMap, dl: &mut dyn DataLink, counters: &ApduCounters) -> Vec<(u8, SocketAddr)> {
+    let now = Instant::now();
+    let mut timed_out = Vec::new();
+    let Ok(mut pending) = outstanding.lock() else {
+        return timed_out;
+    };
+    let expired: Vec<(SocketAddr, u8)> = pending
+        .iter()
+        .filter(|(_, request)| request.deadline <= now)
+        .map(|(key, _)| *key)
+        .collect();
+    for key @ (_, invoke_id) in expired {
+        let Some(request) = pending.get_mut(&key) else {
+            continue;
+        };
+        if request.attempt < request.policy.retries {
+            request.attempt += 1;
+            request.sent_at = now;
+            request.deadline = now + request.policy.timeout_for(request.attempt);
+            debug!("Retrying invoke ID {} to {} (retry {} of {})", invoke_id, request.target, request.attempt, request.policy.retries);
+            if let Err(e) = dl.send_unicast(&request.packet, request.target) {
+                warn!("Failed to retransmit invoke ID {} to {}: {}", invoke_id, request.target, e);
+            }
+        } else if let Some(request) = pending.remove(&key) {
+            debug!("Invoke ID {} to {} timed out after {} retries", invoke_id, request.target, request.attempt);
+            counters.timeouts.fetch_add(1, Ordering::Relaxed);
+            counters.devices.timed_out(request.target);
+            match request.reply {
+                Some(reply) => {
+                    let _ = reply.send(Err(BacnetError::Timeout));
+                }
+                None => timed_out.push((invoke_id, request.target)),
+            }
+        }
+    }
+    timed_out
+}
+
+/// Address and vendor a device last announced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Announcement {
+    addr: SocketAddr,
+    vendor: u32,
+}
+
+/// Turns an I-Am into an event unless it merely repeats the device's last one
+fn classify_i_am(known: &mut HashMap<u32, Announcement>, req: IAmRequest, src: SocketAddr) -> Option<BacnetEvent> {
+    let device_id = req.device_identifier.instance;
+    let announcement = Announcement { addr: src, vendor: req.vendor_identifier };
+    match known.insert(device_id, announcement) {
+        Some(previous) if previous == announcement => {
+            trace!("Duplicate I-Am from device {} at {}", device_id, src);
+            None
+        }
+        Some(previous) if previous.addr != src => {
+            info!("Device {} moved from {} to {}", device_id, previous.addr, src);
+            Some(BacnetEvent::DeviceMoved(req, previous.addr, src))
+        }
+        _ => Some(BacnetEvent::IAm(req, src)),
+    }
+}
+
+/// Decodes SimpleAck, Error, Reject and Abort PDUs, which bacnet-rs does not expose
+fn decode_reply_pdu(apdu: &[u8]) -> Option<(u8, Result<ConfirmedAck, BacnetError>)> {
+    let pdu_type = apdu.first()? >> 4;
+    let invoke_id = *apdu.get(1)?;
+    let result = match pdu_type {
+        codec::PDU_SIMPLE_ACK => Ok(ConfirmedAck::Simple),
+        codec::PDU_ERROR => match codec::decode_error_payload(apdu.get(3..)?) {
+            Ok((PropertyError { class, code }, first_failed)) => Err(BacnetError::Error { class, code, first_failed }),
+            Err(e) => Err(BacnetError::Decode(e.to_string())),
+        },
+        codec::PDU_REJECT => Err(BacnetError::Reject(*apdu.get(2)?)),
+        // Only aborts sent by the server side concern our requests
+        codec::PDU_ABORT if apdu[0] & 0x01 != 0 => Err(BacnetError::Abort(*apdu.get(2)?)),
+        _ => return None,
+    };
+    Some((invoke_id, result))
 }
 
 pub struct BacnetEngine {
     config: BacnetConfig,
-    datalink: Arc<std::sync::Mutex<BacnetIpDataLink>>,
+    datalink: Arc<std::sync::Mutex<Box<dyn DataLink>>>,
     device: Device,
+    local_device: Arc<LocalDevice>,
     invoke_id: AtomicU8,
+    /// Confirmed requests still waiting for an answer, keyed by invoke ID
+    outstanding: OutstandingMap,
+    /// Device instance behind each address that sent an I-Am, for per-device timing
+    device_addresses: Arc<std::sync::Mutex<HashMap<SocketAddr, u32>>>,
+    counters: Arc<ApduCounters>,
+    /// Vendor workarounds of the identified devices
+    quirks: Quirks,
+    /// When the last request to each paced device was sent or is due
+    last_requests: std::sync::Mutex<HashMap<SocketAddr, Instant>>,
+    /// Reopens the datalink when the receive watchdog finds it wedged
+    reconnect: Option<DatalinkFactory>,
+    /// Writes are logged instead of sent
+    dry_run: bool,
+    /// Last time around the receive loop, none before `start`
+    receive_loop: Arc<std::sync::Mutex<Option<Instant>>>,
+    i_ams: broadcast::Sender<IAmHeard>,
+    cov: Arc<CovSubscriptions>,
+    capture: Arc<Capture>,
+    /// Object lists read from devices, by device instance
+    object_lists: std::sync::Mutex<HashMap<u32, Vec<ObjectRef>>>,
 }
 
 impl BacnetEngine {
     pub fn new(config: BacnetConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        if !config.interfaces.is_empty() {
+            return Self::with_interfaces(config);
+        }
         info!("Initializing BACnet IP on {}", config.bind_addr);
         
-        let datalink = BacnetIpDataLink::new(config.bind_addr)?;
-        
-        let mut device = Device::new(config.device_id, "BACnet-MQTT Gateway".to_string());
+        let bind_addr = config.bind_addr;
+        let datalink = BacnetIpDataLink::new(bind_addr)?;
+        Ok(Self::with_datalink(config, Box::new(datalink))
+            .with_reconnect(move || Ok(Box::new(BacnetIpDataLink::new(bind_addr)?))))
+    }
+
+    /// Builds the engine on `bind_addr` and the configured further interfaces,
+    /// the first one named `default`
+    fn with_interfaces(config: BacnetConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut interfaces = vec![("default".to_string(), config.bind_addr, None)];
+        for interface in &config.interfaces {
+            let subnet = interface.subnet.as_deref().map(Subnet::parse).transpose()?;
+            interfaces.push((interface.name.clone(), interface.bind_addr, subnet));
+        }
+        let open = move || -> Result<Box<dyn DataLink>, Box<dyn std::error::Error>> {
+            let mut links: Vec<(String, Option<Subnet>, Box<dyn DataLink>)> = Vec::new();
+            for (name, bind_addr, subnet) in &interfaces {
+                info!("Initializing BACnet IP interface {} on {}", name, bind_addr);
+                links.push((name.clone(), *subnet, Box::new(BacnetIpDataLink::new(*bind_addr)?)));
+            }
+            Ok(Box::new(MultiDataLink::new(links)))
+        };
+        let datalink = open()?;
+        Ok(Self::with_datalink(config, datalink).with_reconnect(open))
+    }
+
+    /// Interface a device address was last heard on, when there are several
+    pub fn interface_of(&self, addr: SocketAddr) -> Option<String> {
+        self.datalink.lock().ok()?.interface_of(addr)
+    }
+
+    /// Sets how a wedged datalink is reopened; without it the watchdog only reports
+    pub fn with_reconnect(
+        mut self,
+        factory: impl Fn() -> Result<Box<dyn DataLink>, Box<dyn std::error::Error>> + Send + Sync + 'static,
+    ) -> Self {
+        let (capture, local) = (self.capture.clone(), self.config.bind_addr);
+        self.reconnect = Some(Arc::new(move || {
+            let datalink: Box<dyn DataLink> = Box::new(Capturing::new(factory()?, capture.clone(), local));
+            Ok(datalink)
+        }));
+        self
+    }
+
+    /// Blocks writes to devices, logging them instead
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Builds the engine on top of any datalink, e.g. a mock in tests
+    pub fn with_datalink(config: BacnetConfig, datalink: Box<dyn DataLink>) -> Self {
+        let object_name = "BACnet-MQTT Gateway";
+        let mut device = Device::new(config.device_id, object_name.to_string());
         device.vendor_name = config.vendor_name.clone();
         device.model_name = config.model_name.clone();
+        let local_device = Arc::new(LocalDevice::new(&config, &device, object_name));
+        let quirks = Quirks::new(&config.quirks);
+        let capture = Arc::new(Capture::default());
+        let datalink: Box<dyn DataLink> = Box::new(Capturing::new(datalink, capture.clone(), config.bind_addr));
 
-        Ok(Self {
+        Self {
             config,
             datalink: Arc::new(std::sync::Mutex::new(datalink)),
             device,
+            local_device,
             invoke_id: AtomicU8::new(1),
-        })
+            outstanding: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            device_addresses: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            counters: Arc::new(ApduCounters::default()),
+            quirks,
+            last_requests: std::sync::Mutex::new(HashMap::new()),
+            reconnect: None,
+            dry_run: false,
+            receive_loop: Arc::new(std::sync::Mutex::new(None)),
+            i_ams: broadcast::channel(256).0,
+            cov: Arc::new(CovSubscriptions::default()),
+            capture,
+            object_lists: std::sync::Mutex::new(HashMap::new()),
+        }
     }
 
-    /// Broadcasts a Who-Is over the network to discover other devices
+    /// The gateway's own objects, including the virtual objects fed from MQTT
+    pub fn local_device(&self) -> Arc<LocalDevice> {
+        self.local_device.clone()
+    }
+
+    /// Broadcasts a Who-Is over the network to discover other devices, using the
+    /// configured instance range and directed broadcast targets
     pub fn discover(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let whois = WhoIsRequest::new();
+        let discovery = &self.config.discovery;
+        self.discover_range(discovery.low_limit, discovery.high_limit)
+    }
+
+    /// Broadcasts a Who-Is for an instance range, to the configured broadcast targets if any
+    pub fn discover_range(&self, low_limit: Option<u32>, high_limit: Option<u32>) -> Result<(), Box<dyn std::error::Error>> {
+        let targets = &self.config.discovery.broadcast_targets;
+        if targets.is_empty() {
+            return self.who_is(low_limit, high_limit, None);
+        }
+        for target in targets {
+            self.who_is(low_limit, high_limit, Some(*target))?;
+        }
+        Ok(())
+    }
+
+    /// Every I-Am received from now on, including the repeats `start` does not report
+    pub fn i_ams(&self) -> broadcast::Receiver<IAmHeard> {
+        self.i_ams.subscribe()
+    }
+
+    /// UDP port of the gateway's BACnet/IP socket, the port devices are usually on too
+    pub fn port(&self) -> u16 {
+        self.config.bind_addr.port()
+    }
+
+    /// Has devices announce themselves again: with the discovery Who-Is, or when
+    /// automatic discovery is disabled with a Who-Is to each of the `known` devices
+    pub fn rediscover(&self, known: &HashMap<u32, SocketAddr>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.discovery.enabled {
+            return self.discover();
+        }
+        for (device_id, addr) in known {
+            self.who_is(Some(*device_id), Some(*device_id), Some(*addr))?;
+        }
+        Ok(())
+    }
+
+    /// Sends a Who-Is, optionally limited to an instance range, either as a global
+    /// broadcast or to a specific (e.g. subnet directed broadcast) address
+    pub fn who_is(
+        &self,
+        low_limit: Option<u32>,
+        high_limit: Option<u32>,
+        target: Option<SocketAddr>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut whois = WhoIsRequest::new();
+        match (low_limit, high_limit) {
+            (Some(low), Some(high)) => {
+                whois.device_instance_range_low_limit = Some(low);
+                whois.device_instance_range_high_limit = Some(high);
+            }
+            (None, None) => {}
+            _ => warn!("Who-Is range needs both low and high limits, sending unbounded Who-Is"),
+        }
         let mut whois_buffer = Vec::new();
         whois.encode(&mut whois_buffer)?;
 
@@ -60,15 +529,58 @@ impl BacnetEngine {
 
         let mut npdu = Npdu::new();
         npdu.control.expecting_reply = false;
-        npdu.control.priority = 0; 
-        
+        npdu.control.priority = self.config.network_priority.discovery.bits();
+
         // Encode NPDU and concatenate
         let mut packet = npdu.encode();
         packet.extend_from_slice(&apdu_bytes);
 
         if let Ok(mut dl) = self.datalink.lock() {
-            dl.send_broadcast_npdu(&packet)?;
-            info!("Broadcasted Who-Is request");
+            match target {
+                Some(addr) => {
+                    dl.send_unicast(&packet, addr)?;
+                    info!("Sent Who-Is request to {}", addr);
+                }
+                None => {
+                    dl.send_broadcast(&packet)?;
+                    info!("Broadcasted Who-Is request");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the I-Am announcing the gateway's own Device object
+    fn encode_i_am(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let iam = IAmRequest {
+            device_identifier: self.device.identifier,
+            max_apdu_length_accepted: self.device.max_apdu_length_accepted as u32,
+            segmentation_supported: self.device.segmentation_supported as u32,
+            vendor_identifier: self.device.vendor_identifier as u32,
+        };
+        let mut iam_buffer = Vec::new();
+        iam.encode(&mut iam_buffer)?;
+
+        let apdu = Apdu::UnconfirmedRequest {
+            service_choice: UnconfirmedServiceChoice::IAm,
+            service_data: iam_buffer,
+        };
+
+        let mut npdu = Npdu::new();
+        npdu.control.expecting_reply = false;
+        npdu.control.priority = self.config.network_priority.discovery.bits();
+
+        let mut packet = npdu.encode();
+        packet.extend_from_slice(&apdu.encode());
+        Ok(packet)
+    }
+
+    /// Broadcasts an I-Am so other BACnet workstations can see the gateway
+    pub fn announce(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let packet = self.encode_i_am()?;
+        if let Ok(mut dl) = self.datalink.lock() {
+            dl.send_broadcast(&packet)?;
+            info!("Broadcasted I-Am for device {}", self.config.device_id);
         }
         Ok(())
     }
@@ -77,88 +589,674 @@ impl BacnetEngine {
     pub fn read_property(
         &self,
         target: SocketAddr,
-        object_identifier: bacnet_rs::object::ObjectIdentifier,
-        property_identifier: u32,
+        reference: &PropertyReference,
     ) -> Result<u8, Box<dyn std::error::Error>> {
-        let req = ReadPropertyRequest::new(object_identifier, property_identifier);
-        let mut service_data = Vec::new();
-        req.encode(&mut service_data)?;
+        let service_data = codec::encode_read_property_request(reference);
+
+        let policy = self.policy_for(target);
+        let (invoke_id, packet) = {
+            let mut outstanding = self.outstanding.lock().unwrap_or_else(|e| e.into_inner());
+            let invoke_id = self.next_invoke_id(&outstanding, target).ok_or_else(|| format!("no free invoke ID for {}", target))?;
+            let packet = self.encode_confirmed_request(target, invoke_id, ConfirmedServiceChoice::ReadProperty, service_data);
+            // Registered before sending so an immediate answer finds it
+            outstanding.insert((target, invoke_id), Outstanding::new(target, packet.clone(), policy, None));
+            (invoke_id, packet)
+        };
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.counters.devices.sent(target);
+
+        let sent = match self.datalink.lock() {
+            Ok(mut dl) => dl.send_unicast(&packet, target),
+            Err(_) => Err("datalink lock poisoned".into()),
+        };
+        if let Err(e) = sent {
+            self.forget(target, invoke_id);
+            return Err(e);
+        }
+        trace!("Sent ReadProperty to {} for {} property {}", target, reference.object, reference.property);
+
+        Ok(invoke_id)
+    }
+
+    /// Outcomes of the confirmed requests sent so far
+    pub fn stats(&self) -> ApduStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ApduStats {
+            requests: load(&self.counters.requests),
+            errors: load(&self.counters.errors),
+            rejects: load(&self.counters.rejects),
+            aborts: load(&self.counters.aborts),
+            timeouts: load(&self.counters.timeouts),
+            recoveries: load(&self.counters.recoveries),
+        }
+    }
+
+    /// Outcomes of the confirmed requests sent to the device at `target`
+    pub fn device_stats(&self, target: SocketAddr) -> DeviceCommStats {
+        self.counters.devices.of(target)
+    }
+
+    pub fn health(&self) -> BacnetHealth {
+        let last = *self.receive_loop.lock().unwrap_or_else(|e| e.into_inner());
+        BacnetHealth {
+            datalink_open: self.datalink.lock().is_ok_and(|dl| dl.is_open()),
+            receiving: last.is_some_and(|last| last.elapsed() < RECEIVE_LOOP_STALL),
+        }
+    }
+
+    /// Retransmission settings for the device at `target`
+    fn policy_for(&self, target: SocketAddr) -> ApduPolicy {
+        let device_id = self.device_addresses.lock().ok().and_then(|a| a.get(&target).copied());
+        self.config.apdu_policy(device_id)
+    }
+
+    /// Quirk profile of the device at `target`
+    fn quirks_for(&self, target: SocketAddr) -> Option<Arc<QuirkProfile>> {
+        let device_id = self.device_addresses.lock().ok().and_then(|a| a.get(&target).copied())?;
+        self.quirks.of(device_id)
+    }
+
+    /// Applies the quirk profile matching the device's vendor and, where
+    /// profiles tell the vendor's models apart, its Model_Name
+    pub async fn apply_quirks(&self, target: SocketAddr, device_id: u32, vendor_id: u32) {
+        let mut model = None;
+        if self.quirks.needs_model(vendor_id) {
+            let reference = PropertyReference { object: ObjectRef::new(8, device_id), property: PROP_MODEL_NAME, array_index: None };
+            match self.read_property_value(target, &reference).await {
+                Ok(raw) => {
+                    if let Some(codec::BacnetValue::CharacterString(name)) =
+                        codec::decode_application_values(&raw).ok().and_then(|values| values.into_iter().next())
+                    {
+                        model = Some(name);
+                    }
+                }
+                Err(e) => debug!("Could not read the model of device {}: {}", device_id, e),
+            }
+        }
+        if let Some(profile) = self.quirks.identify(device_id, vendor_id, model.as_deref()) {
+            info!("Applying quirk profile {} to device {}", profile.name, device_id);
+        }
+    }
+
+    /// Waits until `delay` has passed since the previous request to `target`
+    async fn pace(&self, target: SocketAddr, delay: Duration) {
+        let wait = {
+            let mut last_requests = self.last_requests.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let due = last_requests.get(&target).map_or(now, |last| (*last + delay).max(now));
+            last_requests.insert(target, due);
+            due - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
 
-        // Simple invoke ID generator
-        let invoke_id = self.invoke_id.fetch_add(1, Ordering::Relaxed);
+    /// Next invoke ID not pending for `target`, none when all 256 are
+    fn next_invoke_id(&self, pending: &HashMap<(SocketAddr, u8), Outstanding>, target: SocketAddr) -> Option<u8> {
+        (0..=u8::MAX)
+            .map(|_| self.invoke_id.fetch_add(1, Ordering::Relaxed))
+            .find(|invoke_id| !pending.contains_key(&(target, *invoke_id)))
+    }
 
+    /// Configured network priority of a confirmed service
+    fn network_priority(&self, service_choice: &ConfirmedServiceChoice) -> NetworkPriority {
+        let classes = &self.config.network_priority;
+        match service_choice {
+            ConfirmedServiceChoice::WriteProperty | ConfirmedServiceChoice::WritePropertyMultiple => classes.writes,
+            ConfirmedServiceChoice::AcknowledgeAlarm
+            | ConfirmedServiceChoice::ConfirmedEventNotification
+            | ConfirmedServiceChoice::GetAlarmSummary
+            | ConfirmedServiceChoice::GetEventInformation => classes.alarms,
+            _ => classes.polls,
+        }
+    }
+
+    fn encode_confirmed_request(
+        &self,
+        target: SocketAddr,
+        invoke_id: u8,
+        service_choice: ConfirmedServiceChoice,
+        service_data: Vec<u8>,
+    ) -> Vec<u8> {
+        let priority = self.network_priority(&service_choice);
+        let max_apdu = self.quirks_for(target).and_then(|quirks| quirks.max_apdu).unwrap_or(1476);
         let apdu = Apdu::ConfirmedRequest {
             segmented: false,
             more_follows: false,
             segmented_response_accepted: true,
             max_segments: bacnet_rs::app::MaxSegments::Unspecified,
-            max_response_size: bacnet_rs::app::MaxApduSize::Up1476,
+            max_response_size: max_apdu_size(max_apdu),
             invoke_id,
             sequence_number: None,
             proposed_window_size: None,
-            service_choice: bacnet_rs::service::ConfirmedServiceChoice::ReadProperty,
+            service_choice,
             service_data,
         };
 
         let mut npdu = Npdu::new();
         npdu.control.expecting_reply = true;
-        npdu.control.priority = 0;
-        
+        npdu.control.priority = priority.bits();
+
         let mut packet = npdu.encode();
         packet.extend_from_slice(&apdu.encode());
+        packet
+    }
 
-        if let Ok(mut dl) = self.datalink.lock() {
-            dl.send_unicast_npdu(&packet, target)?;
-            trace!("Sent ReadProperty to {} for {:?}", target, object_identifier);
+    /// Sends a confirmed request and waits for its answer
+    pub async fn confirmed_request(
+        &self,
+        target: SocketAddr,
+        service_choice: ConfirmedServiceChoice,
+        service_data: Vec<u8>,
+    ) -> Result<ConfirmedAck, BacnetError> {
+        if let Some(delay) = self.quirks_for(target).map(|quirks| Duration::from_millis(quirks.request_delay_ms)) {
+            if !delay.is_zero() {
+                self.pace(target, delay).await;
+            }
+        }
+        let policy = self.policy_for(target);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let (invoke_id, packet) = {
+            let mut outstanding = self.outstanding.lock().unwrap_or_else(|e| e.into_inner());
+            let invoke_id = self
+                .next_invoke_id(&outstanding, target)
+                .ok_or_else(|| BacnetError::Send(format!("no free invoke ID for {}", target)))?;
+            let packet = self.encode_confirmed_request(target, invoke_id, service_choice, service_data);
+            outstanding.insert((target, invoke_id), Outstanding::new(target, packet.clone(), policy, Some(reply_tx)));
+            (invoke_id, packet)
+        };
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.counters.devices.sent(target);
+
+        let sent = match self.datalink.lock() {
+            Ok(mut dl) => dl.send_unicast(&packet, target).map_err(|e| BacnetError::Send(e.to_string())),
+            Err(_) => Err(BacnetError::Send("datalink lock poisoned".to_string())),
+        };
+        if let Err(e) = sent {
+            self.forget(target, invoke_id);
+            self.counters.devices.failed(target, &e);
+            return Err(e);
+        }
+
+        // Retries are driven by the receive loop, this only guards against it not running
+        match tokio::time::timeout(policy.total() + Duration::from_secs(1), reply_rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(BacnetError::Send("receive loop stopped".to_string())),
+            Err(_) => {
+                self.forget(target, invoke_id);
+                Err(BacnetError::Timeout)
+            }
+        }
+    }
+
+    fn forget(&self, target: SocketAddr, invoke_id: u8) {
+        if let Ok(mut outstanding) = self.outstanding.lock() {
+            outstanding.remove(&(target, invoke_id));
         }
-        
-        Ok(invoke_id)
+    }
+
+    /// Reads a property and returns its raw application-encoded value
+    pub async fn read_property_value(
+        &self,
+        target: SocketAddr,
+        reference: &PropertyReference,
+    ) -> Result<Vec<u8>, BacnetError> {
+        let service_data = codec::encode_read_property_request(reference);
+        match self.confirmed_request(target, ConfirmedServiceChoice::ReadProperty, service_data).await? {
+            ConfirmedAck::Complex(data) => codec::decode_read_property_ack(&data)
+                .map(|(_, value)| value)
+                .map_err(|e| BacnetError::Decode(e.to_string())),
+            ConfirmedAck::Simple => Err(BacnetError::Decode("unexpected SimpleAck to ReadProperty".to_string())),
+        }
+    }
+
+    /// Reads several properties of one or more objects in a single
+    /// ReadPropertyMultiple request, or one per property on devices whose
+    /// quirk profile asks for single-property reads
+    pub async fn read_property_multiple(
+        &self,
+        target: SocketAddr,
+        specs: &[(ObjectRef, Vec<(u32, Option<u32>)>)],
+    ) -> Result<Vec<(ObjectRef, Vec<PropertyResult>)>, BacnetError> {
+        let quirks = self.quirks_for(target);
+        if quirks.as_ref().is_some_and(|quirks| quirks.disable_rpm) {
+            // Fail as a device without the service would, so callers fall back to ReadProperty
+            return Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE));
+        }
+        if !quirks.is_some_and(|quirks| quirks.single_property_reads) {
+            return self.send_read_property_multiple(target, specs).await;
+        }
+        let mut acked = Vec::new();
+        for (object, properties) in specs {
+            let mut results = Vec::new();
+            for property in properties {
+                let single = self.send_read_property_multiple(target, &[(*object, vec![*property])]).await?;
+                results.extend(single.into_iter().flat_map(|(_, results)| results));
+            }
+            acked.push((*object, results));
+        }
+        Ok(acked)
+    }
+
+    async fn send_read_property_multiple(
+        &self,
+        target: SocketAddr,
+        specs: &[(ObjectRef, Vec<(u32, Option<u32>)>)],
+    ) -> Result<Vec<(ObjectRef, Vec<PropertyResult>)>, BacnetError> {
+        let service_data = codec::encode_read_property_multiple_request(specs);
+        match self.confirmed_request(target, ConfirmedServiceChoice::ReadPropertyMultiple, service_data).await? {
+            ConfirmedAck::Complex(data) => {
+                codec::decode_read_property_multiple_ack(&data).map_err(|e| BacnetError::Decode(e.to_string()))
+            }
+            ConfirmedAck::Simple => Err(BacnetError::Decode("unexpected SimpleAck to ReadPropertyMultiple".to_string())),
+        }
+    }
+
+    /// Reads Present_Value, Status_Flags, Units and Object_Name of an object in
+    /// one round trip, falling back to single reads on devices without
+    /// ReadPropertyMultiple
+    pub async fn read_property_bundle(&self, target: SocketAddr, object: ObjectRef) -> Result<PropertyBundle, BacnetError> {
+        let spec = [(object, point::BUNDLE_PROPERTIES.iter().map(|p| (*p, None)).collect())];
+        let results: Vec<PropertyResult> = match self.read_property_multiple(target, &spec).await {
+            Ok(acked) => acked
+                .into_iter()
+                .filter(|(acked_object, _)| *acked_object == object)
+                .flat_map(|(_, results)| results)
+                .collect(),
+            Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE)) => {
+                debug!("{} does not support ReadPropertyMultiple, reading {} property by property", target, object);
+                let mut results = Vec::new();
+                for property in point::BUNDLE_PROPERTIES {
+                    let reference = PropertyReference { object, property, array_index: None };
+                    let value = match self.read_property_value(target, &reference).await {
+                        Ok(raw) => Ok(codec::decode_application_values(&raw).map_err(|e| BacnetError::Decode(e.to_string()))?),
+                        Err(BacnetError::Error { class, code, .. }) => Err(PropertyError { class, code }),
+                        Err(e) => return Err(e),
+                    };
+                    results.push(PropertyResult { property, array_index: None, value });
+                }
+                results
+            }
+            Err(e) => return Err(e),
+        };
+        let mut bundle = PropertyBundle::from_results(&results);
+        if let (Some(units), Some(quirks)) = (bundle.units, self.quirks_for(target)) {
+            bundle.units = quirks.unit_remap.get(&units).copied().or(Some(units));
+        }
+        Ok(bundle)
+    }
+
+    /// Reads one property or, given ALL, REQUIRED or OPTIONAL, every matching
+    /// property of an object. On devices without ReadPropertyMultiple, ALL is
+    /// answered from the Property_List and REQUIRED/OPTIONAL are unavailable.
+    pub async fn read_object_properties(
+        &self,
+        target: SocketAddr,
+        object: ObjectRef,
+        property: u32,
+    ) -> Result<Vec<PropertyResult>, BacnetError> {
+        let spec = [(object, vec![(property, None)])];
+        match self.read_property_multiple(target, &spec).await {
+            Ok(acked) => Ok(acked.into_iter().flat_map(|(_, results)| results).collect()),
+            Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE)) if property == codec::PROP_ALL => {
+                let list = PropertyReference { object, property: PROP_PROPERTY_LIST, array_index: None };
+                let raw = self.read_property_value(target, &list).await?;
+                let properties = codec::decode_application_values(&raw).map_err(|e| BacnetError::Decode(e.to_string()))?;
+                // Object_Identifier, Object_Name, Object_Type and Property_List itself are not listed
+                let listed: Vec<u32> = [75, 77, 79, PROP_PROPERTY_LIST]
+                    .into_iter()
+                    .chain(properties.iter().filter_map(|p| match p {
+                        codec::BacnetValue::Enumerated(p) => Some(*p),
+                        _ => None,
+                    }))
+                    .collect();
+                self.read_each(target, object, &listed).await
+            }
+            Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE))
+                if !matches!(property, codec::PROP_REQUIRED | codec::PROP_OPTIONAL) =>
+            {
+                self.read_each(target, object, &[property]).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads some properties of an object, in one round trip where the device
+    /// supports ReadPropertyMultiple
+    pub async fn read_properties(&self, target: SocketAddr, object: ObjectRef, properties: &[u32]) -> Result<Vec<PropertyResult>, BacnetError> {
+        let spec = [(object, properties.iter().map(|p| (*p, None)).collect())];
+        match self.read_property_multiple(target, &spec).await {
+            Ok(acked) => Ok(acked.into_iter().flat_map(|(_, results)| results).collect()),
+            Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE)) => self.read_each(target, object, properties).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads properties one ReadProperty at a time, an Error PDU failing only its property
+    async fn read_each(&self, target: SocketAddr, object: ObjectRef, properties: &[u32]) -> Result<Vec<PropertyResult>, BacnetError> {
+        let mut results = Vec::new();
+        for &property in properties {
+            let reference = PropertyReference { object, property, array_index: None };
+            let value = match self.read_property_value(target, &reference).await {
+                Ok(raw) => Ok(codec::decode_property_value(&raw)),
+                Err(BacnetError::Error { class, code, .. }) => Err(PropertyError { class, code }),
+                Err(e) => return Err(e),
+            };
+            results.push(PropertyResult { property, array_index: None, value });
+        }
+        Ok(results)
+    }
+
+    /// Reads the Object_List of a device, element by element when the whole
+    /// list does not fit an unsegmented reply, and remembers it
+    pub async fn read_object_list(&self, target: SocketAddr, device_id: u32) -> Result<Vec<ObjectRef>, BacnetError> {
+        let objects = self.fetch_object_list(target, device_id).await?;
+        self.object_lists.lock().unwrap_or_else(|e| e.into_inner()).insert(device_id, objects.clone());
+        Ok(objects)
+    }
+
+    /// The object list last read from the device, or restored from a saved registry
+    pub fn known_object_list(&self, device_id: u32) -> Option<Vec<ObjectRef>> {
+        self.object_lists.lock().unwrap_or_else(|e| e.into_inner()).get(&device_id).cloned()
+    }
+
+    /// Every object list read or restored so far, by device instance
+    pub fn known_object_lists(&self) -> HashMap<u32, Vec<ObjectRef>> {
+        self.object_lists.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn restore_object_list(&self, device_id: u32, objects: Vec<ObjectRef>) {
+        self.object_lists.lock().unwrap_or_else(|e| e.into_inner()).insert(device_id, objects);
+    }
+
+    async fn fetch_object_list(&self, target: SocketAddr, device_id: u32) -> Result<Vec<ObjectRef>, BacnetError> {
+        let device = ObjectRef::new(8, device_id);
+        let read = |array_index: Option<u32>| async move {
+            let reference = PropertyReference { object: device, property: PROP_OBJECT_LIST, array_index };
+            let raw = self.read_property_value(target, &reference).await?;
+            codec::decode_application_values(&raw).map_err(|e| BacnetError::Decode(e.to_string()))
+        };
+        let object_ids = |values: Vec<codec::BacnetValue>| {
+            values
+                .into_iter()
+                .filter_map(|value| match value {
+                    codec::BacnetValue::ObjectId(object) => Some(object),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        match read(None).await {
+            Ok(values) => return Ok(object_ids(values)),
+            Err(BacnetError::Abort(reason)) => {
+                debug!("Object list of device {} aborted ({}), reading it element by element", device_id, codec::abort_reason_name(reason));
+            }
+            Err(e) => return Err(e),
+        }
+        let length = match read(Some(0)).await?.first() {
+            Some(codec::BacnetValue::Unsigned(length)) => *length,
+            other => return Err(BacnetError::Decode(format!("object list length {:?}", other))),
+        };
+        let mut objects = Vec::new();
+        for index in 1..=length {
+            objects.extend(object_ids(read(Some(index)).await?));
+        }
+        Ok(objects)
+    }
+
+    /// Writes a single property
+    pub async fn write_property(&self, target: SocketAddr, write: &WriteSpec) -> Result<(), BacnetError> {
+        if self.dry_run {
+            log_dry_run(target, write);
+            return Err(BacnetError::DryRun);
+        }
+        let service_data = codec::encode_write_property_request(write);
+        self.confirmed_request(target, ConfirmedServiceChoice::WriteProperty, service_data).await?;
+        info!("Wrote {} property {} on {}", write.reference.object, write.reference.property, target);
+        Ok(())
+    }
+
+    /// Writes several properties of one device in a single WritePropertyMultiple request
+    pub async fn write_property_multiple(&self, target: SocketAddr, writes: &[WriteSpec]) -> Result<(), BacnetError> {
+        if self.dry_run {
+            for write in writes {
+                log_dry_run(target, write);
+            }
+            return Err(BacnetError::DryRun);
+        }
+        let service_data = codec::encode_write_property_multiple_request(writes);
+        self.confirmed_request(target, ConfirmedServiceChoice::WritePropertyMultiple, service_data).await?;
+        info!("Wrote {} properties on {} with WritePropertyMultiple", writes.len(), target);
+        Ok(())
+    }
+
+    /// Subscribes to COV notifications of an object, renewing an existing
+    /// subscription under its process identifier
+    pub async fn subscribe_cov(
+        &self,
+        target: SocketAddr,
+        device_id: u32,
+        object: ObjectRef,
+        confirmed: bool,
+        lifetime_secs: u32,
+    ) -> Result<(), BacnetError> {
+        let process_id = self.cov.process_id(device_id, object);
+        let service_data = codec::encode_subscribe_cov_request(process_id, object, Some((confirmed, lifetime_secs)));
+        self.confirmed_request(target, ConfirmedServiceChoice::SubscribeCOV, service_data).await?;
+        info!("Subscribed to COV of device {} {} for {} s", device_id, object, lifetime_secs);
+        self.cov.subscribed(device_id, object, SubscriptionParams { address: target, process_id, confirmed, lifetime_secs });
+        Ok(())
+    }
+
+    /// Cancels a COV subscription, forgetting it too if the device answers with
+    /// an error such as not knowing it anymore
+    pub async fn cancel_cov(&self, device_id: u32, object: ObjectRef, subscription: SubscriptionParams) -> Result<(), BacnetError> {
+        let service_data = codec::encode_subscribe_cov_request(subscription.process_id, object, None);
+        let result = self.confirmed_request(subscription.address, ConfirmedServiceChoice::SubscribeCOV, service_data).await;
+        if matches!(result, Ok(_) | Err(BacnetError::Error { .. })) {
+            info!("Cancelled the COV subscription to device {} {}", device_id, object);
+            self.cov.cancelled(device_id, object);
+        }
+        result.map(|_| ())
+    }
+
+    /// Packet capture of the frames sent and received
+    pub fn capture(&self) -> &Capture {
+        &self.capture
+    }
+
+    pub fn cov_subscription(&self, device_id: u32, object: ObjectRef) -> Option<SubscriptionParams> {
+        self.cov.params(device_id, object)
+    }
+
+    pub fn cov_subscriptions(&self) -> Vec<SubscriptionEntry> {
+        self.cov.list()
     }
 
     /// Spawns the background Tokio task that constantly receives UDP BACnet datagrams
     pub async fn start(&self) -> mpsc::Receiver<BacnetEvent> {
         let (tx, rx) = mpsc::channel(100);
         let dl = self.datalink.clone();
+        let outstanding = self.outstanding.clone();
+        let device_instance = self.config.device_id;
+        let device_addresses = self.device_addresses.clone();
+        let local_device = self.local_device.clone();
+        let counters = self.counters.clone();
+        let reconnect = self.reconnect.clone();
+        let mut watchdog = ReceiveWatchdog::new(Duration::from_secs(self.config.receive_watchdog_secs));
+        let receive_loop = self.receive_loop.clone();
+        let i_ams = self.i_ams.clone();
+        let cov = self.cov.clone();
+        let iam_packet = match self.encode_i_am() {
+            Ok(packet) => Some(packet),
+            Err(e) => {
+                warn!("Failed to encode I-Am, Who-Is requests will not be answered: {}", e);
+                None
+            }
+        };
         
         tokio::task::spawn_blocking(move || {
+            let mut announcements = HashMap::new();
             loop {
+                if tx.is_closed() {
+                    break; // Receiver disconnected
+                }
+                *receive_loop.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+                if dl.is_poisoned() {
+                    warn!("Datalink lock poisoned by a panicked sender, rebuilding the datalink");
+                    dl.clear_poison();
+                    if let Ok(mut dl_lock) = dl.lock() {
+                        rebuild_datalink(&mut dl_lock, reconnect.as_ref(), &counters);
+                    }
+                }
                 if let Ok(mut dl_lock) = dl.lock() {
-                    if let Ok((buf, src)) = dl_lock.receive_frame() {
+                    for (invoke_id, target) in retransmit_expired(&outstanding, &mut **dl_lock, &counters) {
+                        warn!("Request with invoke ID {} to {} timed out", invoke_id, target);
+                        if tx.blocking_send(BacnetEvent::RequestFailed(invoke_id, target, BacnetError::Timeout)).is_err() {
+                            break;
+                        }
+                    }
+                    let timeouts = counters.timeouts.load(Ordering::Relaxed);
+                    if watchdog.is_wedged(timeouts) {
+                        warn!(
+                            "No BACnet frame received for {:?} while {} requests timed out, rebuilding the datalink",
+                            watchdog.last_frame.elapsed(),
+                            timeouts - watchdog.timeouts_at_frame
+                        );
+                        rebuild_datalink(&mut dl_lock, reconnect.as_ref(), &counters);
+                        watchdog.frame_received(timeouts);
+                    }
+                    if let Ok((buf, source_addr)) = dl_lock.receive() {
+                        watchdog.frame_received(timeouts);
                         if !buf.is_empty() {
-                            trace!("Received {} bytes from {:?}", buf.len(), src);
+                            trace!("Received {} bytes from {}", buf.len(), source_addr);
                             if let Ok((npdu, consumed)) = Npdu::decode(&buf) {
                                 if buf.len() > consumed && !npdu.is_network_message() {
                                     let apdu_bytes = &buf[consumed..];
+
+                                    if let Some((invoke_id, result)) = decode_reply_pdu(apdu_bytes) {
+                                        let pending = outstanding.lock().ok().and_then(|mut p| p.remove(&(source_addr, invoke_id)));
+                                        if let Some(request) = &pending {
+                                            counters.record(&result);
+                                            counters.devices.answered(request.target, request.sent_at.elapsed(), result.as_ref().err());
+                                        }
+                                        if let (Some(_), Err(e)) = (&pending, &result) {
+                                            warn!("{} answered invoke ID {} with {}", source_addr, invoke_id, e);
+                                        }
+                                        match pending {
+                                            Some(Outstanding { reply: Some(reply), .. }) => {
+                                                let _ = reply.send(result);
+                                            }
+                                            Some(Outstanding { reply: None, .. }) => {
+                                                if let Err(e) = result {
+                                                    if tx.blocking_send(BacnetEvent::RequestFailed(invoke_id, source_addr, e)).is_err() {
+                                                        break;
+                                                    }
+                                                }
+                                            }
+                                            None => trace!("Unsolicited reply for invoke ID {} from {}", invoke_id, source_addr),
+                                        }
+                                        continue;
+                                    }
+
                                     if let Ok(apdu) = Apdu::decode(apdu_bytes) {
-                                        let source_addr = match src {
-                                            DataLinkAddress::Ip(addr) => addr,
-                                            _ => continue,
-                                        };
 
                                         let event_opt = match apdu {
                                             Apdu::UnconfirmedRequest { service_choice, service_data } => {
                                                 match service_choice {
                                                     UnconfirmedServiceChoice::WhoIs => {
-                                                        WhoIsRequest::decode(&service_data).ok().map(|req| BacnetEvent::WhoIs(req, source_addr))
+                                                        WhoIsRequest::decode(&service_data).ok().map(|req| {
+                                                            let in_range = match (req.device_instance_range_low_limit, req.device_instance_range_high_limit) {
+                                                                (Some(low), Some(high)) => (low..=high).contains(&device_instance),
+                                                                _ => true,
+                                                            };
+                                                            if let (true, Some(packet)) = (in_range, &iam_packet) {
+                                                                if let Err(e) = dl_lock.send_broadcast(packet) {
+                                                                    warn!("Failed to answer Who-Is from {}: {}", source_addr, e);
+                                                                } else {
+                                                                    trace!("Answered Who-Is from {} with I-Am", source_addr);
+                                                                }
+                                                            }
+                                                            BacnetEvent::WhoIs(req, source_addr)
+                                                        })
                                                     }
                                                     UnconfirmedServiceChoice::IAm => {
-                                                        IAmRequest::decode(&service_data).ok().map(|req| BacnetEvent::IAm(req, source_addr))
+                                                        IAmRequest::decode(&service_data).ok().and_then(|req| {
+                                                            let device_id = req.device_identifier.instance;
+                                                            // Nobody listening is the normal case
+                                                            let _ = i_ams.send(IAmHeard { device_id, address: source_addr, vendor_id: req.vendor_identifier });
+                                                            let event = classify_i_am(&mut announcements, req, source_addr);
+                                                            if let Ok(mut addresses) = device_addresses.lock() {
+                                                                if let Some(BacnetEvent::DeviceMoved(_, previous, _)) = &event {
+                                                                    addresses.remove(previous);
+                                                                }
+                                                                addresses.insert(source_addr, device_id);
+                                                            }
+                                                            event
+                                                        })
+                                                    }
+                                                    UnconfirmedServiceChoice::UnconfirmedCOVNotification => {
+                                                        match codec::decode_cov_notification(&service_data) {
+                                                            Ok(notification) => cov.notified(&notification),
+                                                            Err(e) => debug!("Malformed COV notification from {}: {}", source_addr, e),
+                                                        }
+                                                        None
                                                     }
                                                     _ => None,
                                                 }
                                             }
                                             Apdu::ConfirmedRequest { service_choice, service_data, invoke_id, .. } => {
-                                                match service_choice {
-                                                    bacnet_rs::service::ConfirmedServiceChoice::ReadProperty => {
-                                                        tracing::trace!("ReadPropertyRequest decode not implemented in bacnet-rs yet");
-                                                        None
+                                                // Serve requests against the gateway's own objects
+                                                let mut written = None;
+                                                let reply = match service_choice {
+                                                    ConfirmedServiceChoice::ReadProperty => local_device.handle_read_property(invoke_id, &service_data),
+                                                    ConfirmedServiceChoice::ReadPropertyMultiple => local_device.handle_read_property_multiple(invoke_id, &service_data),
+                                                    ConfirmedServiceChoice::WriteProperty => {
+                                                        let (reply, write) = local_device.handle_write_property(invoke_id, &service_data);
+                                                        written = write;
+                                                        reply
                                                     }
+                                                    ConfirmedServiceChoice::ConfirmedCOVNotification => match codec::decode_cov_notification(&service_data) {
+                                                        Ok(notification) => {
+                                                            cov.notified(&notification);
+                                                            codec::encode_simple_ack_apdu(invoke_id, ConfirmedServiceChoice::ConfirmedCOVNotification as u8)
+                                                        }
+                                                        Err(_) => codec::encode_reject_apdu(invoke_id, codec::REJECT_INVALID_TAG),
+                                                    },
+                                                    _ => codec::encode_reject_apdu(invoke_id, codec::REJECT_UNRECOGNIZED_SERVICE),
+                                                };
+                                                let mut reply_npdu = Npdu::new();
+                                                reply_npdu.control.expecting_reply = false;
+                                                reply_npdu.control.priority = npdu.control.priority;
+                                                let mut packet = reply_npdu.encode();
+                                                packet.extend_from_slice(&reply);
+                                                if let Err(e) = dl_lock.send_unicast(&packet, source_addr) {
+                                                    warn!("Failed to answer confirmed request from {}: {}", source_addr, e);
+                                                }
+
+                                                match service_choice {
+                                                    ConfirmedServiceChoice::ReadProperty => codec::decode_read_property_request(&service_data)
+                                                        .ok()
+                                                        .map(|reference| BacnetEvent::ReadProperty(reference, invoke_id, source_addr)),
+                                                    ConfirmedServiceChoice::WriteProperty => written.map(BacnetEvent::VirtualObjectWritten),
                                                     _ => None,
                                                 }
                                             }
                                             Apdu::ComplexAck { service_choice, service_data, invoke_id, .. } => {
-                                                if service_choice == bacnet_rs::service::ConfirmedServiceChoice::ReadProperty as u8 {
-                                                    ReadPropertyResponse::decode(&service_data).ok().map(|ack| BacnetEvent::ReadPropertyAck(ack, invoke_id, source_addr))
+                                                let pending = outstanding.lock().ok().and_then(|mut p| p.remove(&(source_addr, invoke_id)));
+                                                if let Some(request) = &pending {
+                                                    counters.devices.answered(request.target, request.sent_at.elapsed(), None);
+                                                }
+                                                let latency = match pending {
+                                                    Some(Outstanding { reply: Some(reply), .. }) => {
+                                                        let _ = reply.send(Ok(ConfirmedAck::Complex(service_data)));
+                                                        continue;
+                                                    }
+                                                    Some(Outstanding { sent_at, .. }) => Some(sent_at.elapsed()),
+                                                    None => None,
+                                                };
+                                                if service_choice == ConfirmedServiceChoice::ReadProperty as u8 {
+                                                    ReadPropertyResponse::decode(&service_data).ok().map(|ack| BacnetEvent::ReadPropertyAck(ack, invoke_id, source_addr, latency))
                                                 } else {
                                                     None
                                                 }
@@ -185,3 +1283,556 @@ impl BacnetEngine {
         rx
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::BacnetValue;
+    use crate::config::GatewayConfig;
+    use crate::datalink::mock::{apdu_of, MockDataLink};
+    use crate::point::StatusFlags;
+
+    const PEER: &str = "192.168.1.20:47808";
+
+    fn engine() -> (BacnetEngine, MockDataLink) {
+        engine_with(GatewayConfig::default().bacnet)
+    }
+
+    fn engine_with(config: BacnetConfig) -> (BacnetEngine, MockDataLink) {
+        let mock = MockDataLink::default();
+        let engine = BacnetEngine::with_datalink(config, Box::new(mock.clone()));
+        (engine, mock)
+    }
+
+    /// Short timeouts so retry tests finish quickly
+    fn fast_retry_config(retries: u32) -> BacnetConfig {
+        let mut config = GatewayConfig::default().bacnet;
+        config.apdu_timeout_ms = 50;
+        config.apdu_retries = retries;
+        config
+    }
+
+    fn peer() -> SocketAddr {
+        PEER.parse().unwrap()
+    }
+
+    fn present_value_write() -> WriteSpec {
+        let mut value = Vec::new();
+        codec::encode_application(&mut value, &BacnetValue::Real(21.5));
+        WriteSpec {
+            reference: PropertyReference { object: ObjectRef::new(2, 1), property: 85, array_index: None },
+            value,
+            priority: Some(8),
+        }
+    }
+
+    async fn next_event(events: &mut mpsc::Receiver<BacnetEvent>) -> Option<BacnetEvent> {
+        tokio::time::timeout(Duration::from_secs(2), events.recv()).await.ok().flatten()
+    }
+
+    /// Answers every confirmed request with the APDU built from its invoke ID
+    fn reply_with(mock: &MockDataLink, reply: fn(u8) -> Vec<u8>) {
+        mock.respond_with(move |apdu| (apdu[0] >> 4 == 0).then(|| reply(apdu[2])));
+    }
+
+    #[test]
+    fn who_is_encodes_instance_range() {
+        let (engine, mock) = engine();
+        engine.who_is(Some(10), Some(20), None).unwrap();
+
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, None, "Who-Is without target is broadcast");
+        assert_eq!(apdu_of(&sent[0].1), vec![0x10, 0x08, 0x09, 10, 0x19, 20]);
+    }
+
+    #[test]
+    fn who_is_to_target_is_unicast() {
+        let (engine, mock) = engine();
+        engine.who_is(None, None, Some(peer())).unwrap();
+
+        let sent = mock.sent();
+        assert_eq!(sent[0].0, Some(peer()));
+        assert_eq!(apdu_of(&sent[0].1), vec![0x10, 0x08]);
+    }
+
+    #[test]
+    fn capture_records_frames_only_while_started() {
+        let (engine, _mock) = engine();
+        assert!(engine.capture().pcap().is_none());
+        engine.who_is(None, None, Some(peer())).unwrap();
+        engine.capture().start(crate::capture::DEFAULT_MAX_BYTES);
+        engine.who_is(None, None, Some(peer())).unwrap();
+        engine.capture().stop();
+        engine.who_is(None, None, Some(peer())).unwrap();
+
+        assert_eq!(engine.capture().status().frames, 1);
+        let pcap = engine.capture().pcap().unwrap();
+        // Global header, record header, IPv4, UDP, BVLC and the NPDU
+        assert_eq!(&pcap[..4], &0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(&pcap[24 + 16 + 28..24 + 16 + 30], &[0x81, 0x0A]);
+        assert_eq!(&pcap[24 + 16 + 16..24 + 16 + 20], &[192, 168, 1, 20], "destination address");
+        assert_eq!(&pcap[24 + 16 + 22..24 + 16 + 24], &47808u16.to_be_bytes(), "destination port");
+    }
+
+    #[test]
+    fn read_property_uses_fresh_invoke_ids() {
+        let (engine, mock) = engine();
+        let reference = PropertyReference { object: ObjectRef::new(0, 3), property: 85, array_index: None };
+        let first = engine.read_property(peer(), &reference).unwrap();
+        let second = engine.read_property(peer(), &reference).unwrap();
+        assert_ne!(first, second);
+
+        let sent = mock.sent();
+        for ((_, packet), invoke_id) in sent.iter().zip([first, second]) {
+            let apdu = apdu_of(packet);
+            assert_eq!(apdu[0] >> 4, 0, "confirmed request PDU");
+            assert_eq!(apdu[2], invoke_id);
+            assert_eq!(apdu[3], ConfirmedServiceChoice::ReadProperty as u8);
+            assert_eq!(codec::decode_read_property_request(&apdu[4..]).unwrap(), reference);
+        }
+        assert_eq!(engine.outstanding.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn pending_invoke_id_is_skipped_for_the_same_device_only() {
+        let (engine, _mock) = engine();
+        let reference = PropertyReference { object: ObjectRef::new(0, 3), property: 85, array_index: None };
+        let first = engine.read_property(peer(), &reference).unwrap();
+
+        engine.invoke_id.store(first, Ordering::Relaxed);
+        assert_ne!(engine.read_property(peer(), &reference).unwrap(), first);
+        engine.invoke_id.store(first, Ordering::Relaxed);
+        let other: SocketAddr = "192.168.1.21:47808".parse().unwrap();
+        assert_eq!(engine.read_property(other, &reference).unwrap(), first);
+        assert_eq!(engine.outstanding.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reply_from_another_device_is_not_matched() {
+        let (engine, mock) = engine();
+        let reference = PropertyReference { object: ObjectRef::new(0, 3), property: 85, array_index: None };
+        let invoke_id = engine.read_property(peer(), &reference).unwrap();
+        let reply = codec::encode_error_apdu(invoke_id, ConfirmedServiceChoice::ReadProperty as u8, PropertyError::UNKNOWN_OBJECT);
+        mock.push_inbound(&reply, "192.168.1.21:47808".parse().unwrap());
+        let _events = engine.start().await;
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(engine.outstanding.lock().unwrap().contains_key(&(peer(), invoke_id)));
+        assert_eq!(engine.stats().errors, 0);
+    }
+
+    #[tokio::test]
+    async fn simple_ack_completes_write() {
+        let (engine, mock) = engine();
+        reply_with(&mock, |invoke_id| codec::encode_simple_ack_apdu(invoke_id, ConfirmedServiceChoice::WriteProperty as u8));
+        let _events = engine.start().await;
+
+        engine.write_property(peer(), &present_value_write()).await.unwrap();
+        assert!(engine.outstanding.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn error_pdu_is_surfaced() {
+        let (engine, mock) = engine();
+        reply_with(&mock, |invoke_id| {
+            codec::encode_error_apdu(invoke_id, ConfirmedServiceChoice::WriteProperty as u8, PropertyError::WRITE_ACCESS_DENIED)
+        });
+        let _events = engine.start().await;
+
+        match engine.write_property(peer(), &present_value_write()).await {
+            Err(BacnetError::Error { class: 2, code: 40, first_failed: None }) => {}
+            other => panic!("expected write-access-denied, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_is_surfaced() {
+        let (engine, mock) = engine();
+        reply_with(&mock, |invoke_id| codec::encode_reject_apdu(invoke_id, codec::REJECT_UNRECOGNIZED_SERVICE));
+        let _events = engine.start().await;
+
+        match engine.write_property_multiple(peer(), &[present_value_write()]).await {
+            Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE)) => {}
+            other => panic!("expected reject, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_is_surfaced_and_counted() {
+        let (engine, mock) = engine();
+        // Abort from the server side, reason segmentation-not-supported
+        reply_with(&mock, |invoke_id| vec![0x71, invoke_id, 4]);
+        let _events = engine.start().await;
+
+        match engine.write_property(peer(), &present_value_write()).await {
+            Err(e @ BacnetError::Abort(4)) => assert_eq!(e.to_string(), "aborted: segmentation-not-supported"),
+            other => panic!("expected abort, got {:?}", other),
+        }
+        let stats = engine.stats();
+        assert_eq!((stats.requests, stats.aborts, stats.errors), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn outcomes_are_counted_per_device() {
+        let (engine, mock) = engine();
+        reply_with(&mock, |invoke_id| {
+            codec::encode_error_apdu(invoke_id, ConfirmedServiceChoice::WriteProperty as u8, PropertyError::WRITE_ACCESS_DENIED)
+        });
+        let _events = engine.start().await;
+
+        assert!(engine.write_property(peer(), &present_value_write()).await.is_err());
+        let stats = engine.device_stats(peer());
+        assert_eq!((stats.requests, stats.acks, stats.errors, stats.timeouts), (1, 0, 1, 0));
+        assert!(stats.p50_latency_ms.is_some());
+        assert!(stats.last_error.unwrap().contains("write-access-denied"));
+        assert_eq!(engine.device_stats("192.168.1.21:47808".parse().unwrap()).requests, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_poll_is_reported_as_event() {
+        let (engine, mock) = engine();
+        reply_with(&mock, |invoke_id| {
+            codec::encode_error_apdu(invoke_id, ConfirmedServiceChoice::ReadProperty as u8, PropertyError::UNKNOWN_OBJECT)
+        });
+        let mut events = engine.start().await;
+
+        let reference = PropertyReference { object: ObjectRef::new(0, 99), property: 85, array_index: None };
+        let invoke_id = engine.read_property(peer(), &reference).unwrap();
+        match next_event(&mut events).await {
+            Some(BacnetEvent::RequestFailed(id, src, BacnetError::Error { class: 1, code: 31, .. })) => {
+                assert_eq!((id, src), (invoke_id, peer()));
+            }
+            other => panic!("expected RequestFailed, got {:?}", other),
+        }
+        assert_eq!(engine.stats().errors, 1);
+    }
+
+    #[tokio::test]
+    async fn network_priority_follows_traffic_class() {
+        let mut config = GatewayConfig::default().bacnet;
+        config.network_priority.writes = NetworkPriority::CriticalEquipment;
+        let (engine, mock) = engine_with(config);
+        reply_with(&mock, |invoke_id| codec::encode_simple_ack_apdu(invoke_id, ConfirmedServiceChoice::WriteProperty as u8));
+        let _events = engine.start().await;
+
+        engine.write_property(peer(), &present_value_write()).await.unwrap();
+        let reference = PropertyReference { object: ObjectRef::new(0, 3), property: 85, array_index: None };
+        engine.read_property(peer(), &reference).unwrap();
+
+        // Control octet: expecting-reply bit 0x04, priority in the low two bits
+        let control: Vec<u8> = mock.sent().iter().map(|(_, packet)| packet[1]).collect();
+        assert_eq!(control, vec![0x04 | 2, 0x04]);
+    }
+
+    #[tokio::test]
+    async fn watchdog_rebuilds_silent_datalink() {
+        let mut config = fast_retry_config(0);
+        config.receive_watchdog_secs = 1;
+        let fresh = MockDataLink::default();
+        let reopened = fresh.clone();
+        let (engine, _mock) = engine_with(config);
+        let engine = engine.with_reconnect(move || Ok(Box::new(reopened.clone())));
+        let _events = engine.start().await;
+
+        let reference = PropertyReference { object: ObjectRef::new(0, 3), property: 85, array_index: None };
+        for _ in 0..WATCHDOG_MIN_TIMEOUTS {
+            engine.read_property(peer(), &reference).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while engine.stats().recoveries == 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(engine.stats().recoveries, 1);
+
+        engine.read_property(peer(), &reference).unwrap();
+        assert_eq!(fresh.sent().len(), 1, "requests go out on the rebuilt datalink");
+    }
+
+    #[tokio::test]
+    async fn unanswered_request_is_retransmitted_with_same_invoke_id() {
+        let (engine, mock) = engine_with(fast_retry_config(2));
+        let mut transmissions = 0;
+        mock.respond_with(move |apdu| {
+            transmissions += 1;
+            (transmissions == 2).then(|| codec::encode_simple_ack_apdu(apdu[2], ConfirmedServiceChoice::WriteProperty as u8))
+        });
+        let _events = engine.start().await;
+
+        engine.write_property(peer(), &present_value_write()).await.unwrap();
+
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(apdu_of(&sent[0].1), apdu_of(&sent[1].1), "retry repeats the request unchanged");
+    }
+
+    #[tokio::test]
+    async fn request_times_out_after_retries() {
+        let (engine, mock) = engine_with(fast_retry_config(1));
+        let _events = engine.start().await;
+
+        match engine.write_property(peer(), &present_value_write()).await {
+            Err(BacnetError::Timeout) => {}
+            other => panic!("expected timeout, got {:?}", other),
+        }
+        assert_eq!(mock.sent().len(), 2, "first transmission and one retry");
+        assert!(engine.outstanding.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn property_bundle_is_read_in_one_request() {
+        let (engine, mock) = engine();
+        let object = ObjectRef::new(0, 3);
+        mock.respond_with(move |apdu| {
+            let ack = codec::encode_read_property_multiple_ack(&[(
+                object,
+                vec![
+                    PropertyResult { property: 85, array_index: None, value: Ok(vec![BacnetValue::Real(21.5)]) },
+                    PropertyResult { property: 111, array_index: None, value: Ok(vec![BacnetValue::BitString(vec![false, true, false, false])]) },
+                    PropertyResult { property: 117, array_index: None, value: Ok(vec![BacnetValue::Enumerated(62)]) },
+                    PropertyResult { property: 77, array_index: None, value: Err(PropertyError::UNKNOWN_PROPERTY) },
+                ],
+            )]);
+            let mut reply = vec![0x30, apdu[2], ConfirmedServiceChoice::ReadPropertyMultiple as u8];
+            reply.extend(ack);
+            Some(reply)
+        });
+        let _events = engine.start().await;
+
+        let bundle = engine.read_property_bundle(peer(), object).await.unwrap();
+        assert_eq!(bundle.present_value, Some(BacnetValue::Real(21.5)));
+        assert_eq!(bundle.status_flags, Some(StatusFlags { fault: true, ..Default::default() }));
+        assert_eq!(bundle.units, Some(62));
+        assert_eq!(bundle.object_name, None);
+
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 1);
+        let apdu = apdu_of(&sent[0].1);
+        assert_eq!(apdu[3], ConfirmedServiceChoice::ReadPropertyMultiple as u8);
+        let specs = codec::decode_read_property_multiple_request(&apdu[4..]).unwrap();
+        assert_eq!(specs, vec![(object, vec![(85, None), (111, None), (117, None), (77, None)])]);
+    }
+
+    #[tokio::test]
+    async fn property_bundle_falls_back_to_single_reads() {
+        let (engine, mock) = engine();
+        let object = ObjectRef::new(0, 3);
+        mock.respond_with(move |apdu| {
+            if apdu[3] == ConfirmedServiceChoice::ReadPropertyMultiple as u8 {
+                return Some(codec::encode_reject_apdu(apdu[2], codec::REJECT_UNRECOGNIZED_SERVICE));
+            }
+            let reference = codec::decode_read_property_request(&apdu[4..]).unwrap();
+            let mut reply = vec![0x30, apdu[2], ConfirmedServiceChoice::ReadProperty as u8];
+            match reference.property {
+                85 => reply.extend(codec::encode_read_property_ack(&reference, &[BacnetValue::Real(1.0)])),
+                77 => reply.extend(codec::encode_read_property_ack(&reference, &[BacnetValue::CharacterString("Supply".to_string())])),
+                _ => return Some(codec::encode_error_apdu(apdu[2], ConfirmedServiceChoice::ReadProperty as u8, PropertyError::UNKNOWN_PROPERTY)),
+            }
+            Some(reply)
+        });
+        let _events = engine.start().await;
+
+        let bundle = engine.read_property_bundle(peer(), object).await.unwrap();
+        assert_eq!(bundle.present_value, Some(BacnetValue::Real(1.0)));
+        assert_eq!(bundle.object_name.as_deref(), Some("Supply"));
+        assert_eq!(bundle.units, None);
+        assert_eq!(mock.sent().len(), 5, "ReadPropertyMultiple and four ReadProperty requests");
+    }
+
+    #[tokio::test]
+    async fn health_reports_the_receive_loop() {
+        let (engine, _mock) = engine();
+        assert!(!engine.health().receiving, "not started yet");
+
+        let _events = engine.start().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let health = engine.health();
+        assert!(health.datalink_open);
+        assert!(health.receiving);
+    }
+
+    #[tokio::test]
+    async fn dry_run_sends_no_writes() {
+        let (engine, mock) = engine();
+        let engine = engine.with_dry_run(true);
+
+        assert!(matches!(engine.write_property(peer(), &present_value_write()).await, Err(BacnetError::DryRun)));
+        assert!(matches!(engine.write_property_multiple(peer(), &[present_value_write()]).await, Err(BacnetError::DryRun)));
+        assert!(mock.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn quirk_profile_disables_read_property_multiple_and_remaps_units() {
+        let mut config = GatewayConfig::default().bacnet;
+        config.quirks.push(QuirkProfile {
+            name: "legacy".to_string(),
+            vendor_id: 7,
+            disable_rpm: true,
+            unit_remap: [(95, 62)].into(),
+            ..Default::default()
+        });
+        let (engine, mock) = engine_with(config);
+        let object = ObjectRef::new(0, 3);
+        mock.respond_with(move |apdu| {
+            let reference = codec::decode_read_property_request(&apdu[4..]).unwrap();
+            let mut reply = vec![0x30, apdu[2], ConfirmedServiceChoice::ReadProperty as u8];
+            match reference.property {
+                117 => reply.extend(codec::encode_read_property_ack(&reference, &[BacnetValue::Enumerated(95)])),
+                _ => return Some(codec::encode_error_apdu(apdu[2], ConfirmedServiceChoice::ReadProperty as u8, PropertyError::UNKNOWN_PROPERTY)),
+            }
+            Some(reply)
+        });
+        let _events = engine.start().await;
+        engine.device_addresses.lock().unwrap().insert(peer(), 42);
+        engine.apply_quirks(peer(), 42, 7).await;
+
+        let bundle = engine.read_property_bundle(peer(), object).await.unwrap();
+        assert_eq!(bundle.units, Some(62));
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 4, "four ReadProperty requests and no ReadPropertyMultiple");
+        assert!(sent.iter().all(|(_, packet)| apdu_of(packet)[3] == ConfirmedServiceChoice::ReadProperty as u8));
+    }
+
+    #[tokio::test]
+    async fn object_list_is_read_element_by_element_after_an_abort() {
+        let (engine, mock) = engine();
+        let objects = [ObjectRef::new(8, 42), ObjectRef::new(0, 1), ObjectRef::new(3, 2)];
+        mock.respond_with(move |apdu| {
+            let reference = codec::decode_read_property_request(&apdu[4..]).unwrap();
+            let values = match reference.array_index {
+                // Abort, reason segmentation-not-supported
+                None => return Some(vec![0x71, apdu[2], 4]),
+                Some(0) => vec![BacnetValue::Unsigned(objects.len() as u32)],
+                Some(index) => vec![BacnetValue::ObjectId(objects[index as usize - 1])],
+            };
+            let mut reply = vec![0x30, apdu[2], ConfirmedServiceChoice::ReadProperty as u8];
+            reply.extend(codec::encode_read_property_ack(&reference, &values));
+            Some(reply)
+        });
+        let _events = engine.start().await;
+
+        assert_eq!(engine.read_object_list(peer(), 42).await.unwrap(), objects.to_vec());
+        assert_eq!(mock.sent().len(), 5, "the whole list, its length and three elements");
+    }
+
+    #[test]
+    fn device_timing_overrides_defaults() {
+        let mut config = GatewayConfig::default().bacnet;
+        config.device_timing.insert(
+            7,
+            crate::config::DeviceTiming { apdu_timeout_ms: Some(10_000), apdu_retries: None, apdu_backoff: Some(crate::config::Backoff::Exponential) },
+        );
+        let policy = config.apdu_policy(Some(7));
+        assert_eq!(policy.timeout, Duration::from_secs(10));
+        assert_eq!(policy.retries, 3);
+        assert_eq!(policy.timeout_for(2), Duration::from_secs(40));
+        assert_eq!(config.apdu_policy(Some(8)).timeout, Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn i_am_generates_event() {
+        let (engine, mock) = engine();
+        // I-Am from device 42: max APDU 1476, no segmentation, vendor 15
+        mock.push_inbound(
+            &[0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x2A, 0x22, 0x05, 0xC4, 0x91, 0x03, 0x21, 0x0F],
+            peer(),
+        );
+        let mut events = engine.start().await;
+
+        match tokio::time::timeout(Duration::from_secs(2), events.recv()).await {
+            Ok(Some(BacnetEvent::IAm(iam, src))) => {
+                assert_eq!(iam.device_identifier.instance, 42);
+                assert_eq!(src, peer());
+            }
+            other => panic!("expected I-Am event, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_i_am_is_deduplicated_and_moves_are_reported() {
+        let (engine, mock) = engine();
+        let moved: SocketAddr = "192.168.1.21:47808".parse().unwrap();
+        let iam = [0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x2A, 0x22, 0x05, 0xC4, 0x91, 0x03, 0x21, 0x0F];
+        mock.push_inbound(&iam, peer());
+        mock.push_inbound(&iam, peer());
+        mock.push_inbound(&iam, moved);
+        let mut events = engine.start().await;
+
+        assert!(matches!(next_event(&mut events).await, Some(BacnetEvent::IAm(_, src)) if src == peer()));
+        match next_event(&mut events).await {
+            Some(BacnetEvent::DeviceMoved(iam, from, to)) => {
+                assert_eq!(iam.device_identifier.instance, 42);
+                assert_eq!((from, to), (peer(), moved));
+            }
+            other => panic!("expected DeviceMoved, the duplicate I-Am must be dropped, got {:?}", other),
+        }
+        assert_eq!(engine.device_addresses.lock().unwrap().get(&moved), Some(&42));
+        assert!(!engine.device_addresses.lock().unwrap().contains_key(&peer()));
+    }
+
+    #[tokio::test]
+    async fn serves_read_property_of_gateway_device() {
+        let (engine, mock) = engine();
+        let device = ObjectRef::new(8, GatewayConfig::default().bacnet.device_id);
+        let reference = PropertyReference { object: device, property: 77, array_index: None };
+        let mut request = vec![0x02, 0x05, 7, ConfirmedServiceChoice::ReadProperty as u8];
+        request.extend(codec::encode_read_property_request(&reference));
+        mock.push_inbound(&request, peer());
+        let mut events = engine.start().await;
+
+        match tokio::time::timeout(Duration::from_secs(2), events.recv()).await {
+            Ok(Some(BacnetEvent::ReadProperty(served, 7, src))) => {
+                assert_eq!(served, reference);
+                assert_eq!(src, peer());
+            }
+            other => panic!("expected ReadProperty event, got {:?}", other),
+        }
+
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, Some(peer()), "answer goes back to the requester");
+        let apdu = apdu_of(&sent[0].1);
+        assert_eq!(apdu[0] >> 4, 3, "complex ack PDU");
+        assert_eq!(apdu[1], 7);
+        let (acked, value) = codec::decode_read_property_ack(&apdu[3..]).unwrap();
+        assert_eq!(acked, reference);
+        assert_eq!(
+            codec::decode_application(&value).unwrap().0,
+            BacnetValue::CharacterString("BACnet-MQTT Gateway".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn cov_notifications_are_counted_against_their_subscription() {
+        let (engine, mock) = engine();
+        reply_with(&mock, |invoke_id| codec::encode_simple_ack_apdu(invoke_id, ConfirmedServiceChoice::SubscribeCOV as u8));
+        let _events = engine.start().await;
+        let object = ObjectRef::new(0, 3);
+
+        engine.subscribe_cov(peer(), 42, object, false, 300).await.unwrap();
+        let request = apdu_of(&mock.sent()[0].1);
+        assert_eq!(request[3], ConfirmedServiceChoice::SubscribeCOV as u8);
+        let subscription = engine.cov_subscription(42, object).unwrap();
+        assert_eq!(request[4..], codec::encode_subscribe_cov_request(subscription.process_id, object, Some((false, 300))));
+
+        let mut notification = vec![0x10, UnconfirmedServiceChoice::UnconfirmedCOVNotification as u8];
+        codec::encode_context_unsigned(&mut notification, 0, subscription.process_id);
+        codec::encode_context_object_id(&mut notification, 1, ObjectRef::new(8, 42));
+        codec::encode_context_object_id(&mut notification, 2, object);
+        codec::encode_context_unsigned(&mut notification, 3, 240);
+        codec::encode_opening_tag(&mut notification, 4);
+        codec::encode_context_unsigned(&mut notification, 0, 85);
+        codec::encode_opening_tag(&mut notification, 2);
+        codec::encode_application(&mut notification, &BacnetValue::Real(21.5));
+        codec::encode_closing_tag(&mut notification, 2);
+        codec::encode_closing_tag(&mut notification, 4);
+        mock.push_inbound(&notification, peer());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let entries = engine.cov_subscriptions();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].notifications, 1);
+        assert_eq!(entries[0].present_value, Some(serde_json::json!(21.5)));
+        assert!(entries[0].device_remaining_secs.is_some_and(|secs| secs <= 240));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::bacnet::{BacnetEngine, BacnetError};
+use crate::codec::{self, PropertyReference, WriteSpec};
+use crate::point::ObjectRef;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::{info, warn};
+
+const PROP_PRESENT_VALUE: u32 = 85;
+const PROP_PRIORITY_ARRAY: u32 = 87;
+
+fn default_property() -> u32 {
+    PROP_PRESENT_VALUE
+}
+
+fn default_atomic() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    /// Roll back a device's applied writes when one of its writes fails
+    #[serde(default = "default_atomic")]
+    pub atomic: bool,
+    pub writes: Vec<BatchWrite>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BatchWrite {
+    pub device_id: u32,
+    pub object: ObjectRef,
+    #[serde(default = "default_property")]
+    pub property: u32,
+    #[serde(default)]
+    pub array_index: Option<u32>,
+    pub value: serde_json::Value,
+    /// Explicit BACnet type ("real", "enumerated", ...), inferred from the object type if omitted
+    #[serde(default, rename = "type")]
+    pub value_type: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteStatus {
+    Written,
+    Failed,
+    NotAttempted,
+    RolledBack,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WriteResult {
+    pub index: usize,
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub property: u32,
+    pub status: WriteStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl WriteResult {
+    fn set(&mut self, status: WriteStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+    }
+}
+
+/// Executes a batch of writes, one WritePropertyMultiple per device where the
+/// device supports it, and returns a result per requested write
+pub async fn execute(
+    engine: &BacnetEngine,
+    devices: &HashMap<u32, SocketAddr>,
+    request: BatchRequest,
+) -> Vec<WriteResult> {
+    let mut results: Vec<WriteResult> = request
+        .writes
+        .iter()
+        .enumerate()
+        .map(|(index, write)| WriteResult {
+            index,
+            device_id: write.device_id,
+            object: write.object,
+            property: write.property,
+            status: WriteStatus::NotAttempted,
+            error: None,
+        })
+        .collect();
+
+    // Group writes per device, keeping request order
+    let mut groups: Vec<(u32, Vec<usize>)> = Vec::new();
+    for (index, write) in request.writes.iter().enumerate() {
+        match groups.iter_mut().find(|(device_id, _)| *device_id == write.device_id) {
+            Some((_, indices)) => indices.push(index),
+            None => groups.push((write.device_id, vec![index])),
+        }
+    }
+
+    for (device_id, indices) in groups {
+        let Some(addr) = devices.get(&device_id).copied() else {
+            for index in indices {
+                results[index].set(WriteStatus::Failed, Some(format!("device {} has not been discovered", device_id)));
+            }
+            continue;
+        };
+
+        let mut specs: Vec<(usize, WriteSpec)> = Vec::new();
+        let mut invalid = false;
+        for index in indices {
+            match encode_write(&request.writes[index]) {
+                Ok(spec) => specs.push((index, spec)),
+                Err(e) => {
+                    results[index].set(WriteStatus::Failed, Some(e));
+                    invalid = true;
+                }
+            }
+        }
+        if invalid && request.atomic {
+            continue;
+        }
+
+        execute_device(engine, addr, &specs, request.atomic, &mut results).await;
+    }
+
+    results
+}
+
+pub fn encode_write(write: &BatchWrite) -> Result<WriteSpec, String> {
+    let value = codec::value_from_json(&write.value, write.value_type.as_deref(), write.object)?;
+    let mut encoded = Vec::new();
+    codec::encode_application(&mut encoded, &value);
+    Ok(WriteSpec {
+        reference: PropertyReference {
+            object: write.object,
+            property: write.property,
+            array_index: write.array_index,
+        },
+        value: encoded,
+        priority: write.priority,
+    })
+}
+
+async fn execute_device(
+    engine: &BacnetEngine,
+    addr: SocketAddr,
+    specs: &[(usize, WriteSpec)],
+    atomic: bool,
+    results: &mut [WriteResult],
+) {
+    // Capture what each write overrides so an atomic batch can be undone: the
+    // priority array slot for commanded writes, the property itself otherwise
+    let mut rollback = Vec::new();
+    if atomic {
+        for (index, spec) in specs {
+            let reference = match spec.priority {
+                Some(priority) => PropertyReference {
+                    object: spec.reference.object,
+                    property: PROP_PRIORITY_ARRAY,
+                    array_index: Some(priority as u32),
+                },
+                None => spec.reference,
+            };
+            match engine.read_property_value(addr, &reference).await {
+                Ok(value) => rollback.push(WriteSpec { value, ..spec.clone() }),
+                Err(e) => {
+                    results[*index].set(WriteStatus::Failed, Some(format!("could not read prior value: {}", e)));
+                    return;
+                }
+            }
+        }
+    }
+
+    let writes: Vec<WriteSpec> = specs.iter().map(|(_, spec)| spec.clone()).collect();
+    let (applied, failure) = match engine.write_property_multiple(addr, &writes).await {
+        Ok(()) => (specs.len(), None),
+        Err(BacnetError::Reject(codec::REJECT_UNRECOGNIZED_SERVICE)) => {
+            info!("{} does not support WritePropertyMultiple, writing properties one by one", addr);
+            write_sequential(engine, addr, specs, atomic, results).await
+        }
+        Err(BacnetError::Error { class, code, first_failed }) => {
+            let position = first_failed
+                .and_then(|reference| specs.iter().position(|(_, spec)| spec.reference == reference))
+                .unwrap_or(0);
+            let error = BacnetError::Error { class, code, first_failed };
+            if atomic {
+                (position, Some((position, error)))
+            } else {
+                // Best effort: carry on with the writes after the failed one individually
+                results[specs[position].0].set(WriteStatus::Failed, Some(error.to_string()));
+                for (index, _) in &specs[..position] {
+                    results[*index].set(WriteStatus::Written, None);
+                }
+                write_sequential(engine, addr, &specs[position + 1..], false, results).await;
+                return;
+            }
+        }
+        Err(BacnetError::DryRun) => {
+            for (index, _) in specs {
+                results[*index].set(WriteStatus::NotAttempted, Some(BacnetError::DryRun.to_string()));
+            }
+            return;
+        }
+        Err(e) => {
+            // No reply: whether anything was applied is unknown
+            for (index, _) in specs {
+                results[*index].set(WriteStatus::Failed, Some(format!("{} (outcome unknown)", e)));
+            }
+            return;
+        }
+    };
+
+    for (index, _) in &specs[..applied] {
+        results[*index].set(WriteStatus::Written, None);
+    }
+    let Some((position, error)) = failure else {
+        return;
+    };
+    results[specs[position].0].set(WriteStatus::Failed, Some(error.to_string()));
+
+    if atomic {
+        for (offset, undo) in rollback[..applied].iter().enumerate().rev() {
+            let index = specs[offset].0;
+            match engine.write_property(addr, undo).await {
+                Ok(()) => results[index].set(WriteStatus::RolledBack, None),
+                Err(e) => {
+                    warn!("Rollback of {} property {} on {} failed: {}", undo.reference.object, undo.reference.property, addr, e);
+                    results[index].set(WriteStatus::Written, Some(format!("rollback failed: {}", e)));
+                }
+            }
+        }
+    }
+}
+
+/// Writes properties one WriteProperty at a time, returning how many leading
+/// writes succeeded and the first failure if `stop_on_error` is set
+async fn write_sequential(
+    engine: &BacnetEngine,
+    addr: SocketAddr,
+    specs: &[(usize, WriteSpec)],
+    stop_on_error: bool,
+    results: &mut [WriteResult],
+) -> (usize, Option<(usize, BacnetError)>) {
+    let mut applied = 0;
+    for (position, (index, spec)) in specs.iter().enumerate() {
+        match engine.write_property(addr, spec).await {
+            Ok(()) => {
+                results[*index].set(WriteStatus::Written, None);
+                if applied == position {
+                    applied += 1;
+                }
+            }
+            Err(e) if stop_on_error => return (applied, Some((position, e))),
+            Err(e) => results[*index].set(WriteStatus::Failed, Some(e.to_string())),
+        }
+    }
+    (applied, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{BacnetValue, PropertyError};
+    use crate::config::GatewayConfig;
+    use crate::datalink::mock::{apdu_of, MockDataLink};
+    use bacnet_rs::service::ConfirmedServiceChoice;
+
+    const DEVICE: u32 = 1200;
+    const READ: u8 = ConfirmedServiceChoice::ReadProperty as u8;
+    const WRITE: u8 = ConfirmedServiceChoice::WriteProperty as u8;
+    const WRITE_MULTIPLE: u8 = ConfirmedServiceChoice::WritePropertyMultiple as u8;
+
+    fn peer() -> SocketAddr {
+        "192.168.1.20:47808".parse().unwrap()
+    }
+
+    /// Engine answering confirmed requests with `reply(service, invoke_id, service data)`
+    fn engine(reply: fn(u8, u8, &[u8]) -> Vec<u8>) -> (BacnetEngine, MockDataLink) {
+        let mock = MockDataLink::default();
+        mock.respond_with(move |apdu| (apdu[0] >> 4 == 0).then(|| reply(apdu[3], apdu[2], &apdu[4..])));
+        let engine = BacnetEngine::with_datalink(GatewayConfig::default().bacnet, Box::new(mock.clone()));
+        (engine, mock)
+    }
+
+    /// Commanded writes of 21.5 to AV:1 upwards
+    fn request(atomic: bool, count: u32) -> BatchRequest {
+        let writes = (1..=count)
+            .map(|instance| BatchWrite {
+                device_id: DEVICE,
+                object: ObjectRef::new(2, instance),
+                property: PROP_PRESENT_VALUE,
+                array_index: None,
+                value: serde_json::json!(21.5),
+                value_type: None,
+                priority: Some(8),
+            })
+            .collect();
+        BatchRequest { atomic, writes }
+    }
+
+    async fn run(engine: &BacnetEngine, request: BatchRequest) -> Vec<WriteStatus> {
+        let devices = HashMap::from([(DEVICE, peer())]);
+        execute(engine, &devices, request).await.iter().map(|result| result.status).collect()
+    }
+
+    /// Services of the sent confirmed requests, in order
+    fn services(mock: &MockDataLink) -> Vec<u8> {
+        mock.sent().iter().map(|(_, packet)| apdu_of(packet)).filter(|apdu| apdu[0] >> 4 == 0).map(|apdu| apdu[3]).collect()
+    }
+
+    /// Relinquished priority array slot
+    fn prior_value(invoke_id: u8, data: &[u8]) -> Vec<u8> {
+        let reference = codec::decode_read_property_request(data).unwrap();
+        let mut reply = vec![0x30, invoke_id, READ];
+        reply.extend(codec::encode_read_property_ack(&reference, &[BacnetValue::Null]));
+        reply
+    }
+
+    /// WritePropertyMultiple error naming AV:2 as the first failed write
+    fn second_write_denied(invoke_id: u8) -> Vec<u8> {
+        let error = PropertyError::WRITE_ACCESS_DENIED;
+        let mut reply = vec![0x50, invoke_id, WRITE_MULTIPLE];
+        codec::encode_opening_tag(&mut reply, 0);
+        codec::encode_application(&mut reply, &BacnetValue::Enumerated(error.class));
+        codec::encode_application(&mut reply, &BacnetValue::Enumerated(error.code));
+        codec::encode_closing_tag(&mut reply, 0);
+        codec::encode_opening_tag(&mut reply, 1);
+        codec::encode_context_object_id(&mut reply, 0, ObjectRef::new(2, 2));
+        codec::encode_context_unsigned(&mut reply, 1, PROP_PRESENT_VALUE);
+        codec::encode_closing_tag(&mut reply, 1);
+        reply
+    }
+
+    #[tokio::test]
+    async fn atomic_batch_is_rolled_back_after_a_failed_write() {
+        let (engine, mock) = engine(|service, invoke_id, data| match service {
+            READ => prior_value(invoke_id, data),
+            WRITE_MULTIPLE => second_write_denied(invoke_id),
+            _ => codec::encode_simple_ack_apdu(invoke_id, service),
+        });
+        let _events = engine.start().await;
+
+        let statuses = run(&engine, request(true, 3)).await;
+        assert_eq!(statuses, [WriteStatus::RolledBack, WriteStatus::Failed, WriteStatus::NotAttempted]);
+        assert_eq!(services(&mock), [READ, READ, READ, WRITE_MULTIPLE, WRITE]);
+
+        let sent = mock.sent();
+        let undo = codec::decode_write_property_request(&apdu_of(&sent.last().unwrap().1)[4..]).unwrap();
+        assert_eq!(undo.reference.object, ObjectRef::new(2, 1));
+        assert_eq!(undo.value, [0x00], "the prior null is written back");
+        assert_eq!(undo.priority, Some(8));
+    }
+
+    #[tokio::test]
+    async fn non_atomic_batch_continues_after_a_failed_write() {
+        let (engine, mock) = engine(|service, invoke_id, _| match service {
+            WRITE_MULTIPLE => second_write_denied(invoke_id),
+            _ => codec::encode_simple_ack_apdu(invoke_id, service),
+        });
+        let _events = engine.start().await;
+
+        let statuses = run(&engine, request(false, 3)).await;
+        assert_eq!(statuses, [WriteStatus::Written, WriteStatus::Failed, WriteStatus::Written]);
+        assert_eq!(services(&mock), [WRITE_MULTIPLE, WRITE], "no prior values are read, AV:3 is written alone");
+    }
+
+    #[tokio::test]
+    async fn unrecognized_write_multiple_falls_back_to_single_writes() {
+        let (engine, mock) = engine(|service, invoke_id, data| match service {
+            READ => prior_value(invoke_id, data),
+            WRITE_MULTIPLE => codec::encode_reject_apdu(invoke_id, codec::REJECT_UNRECOGNIZED_SERVICE),
+            _ => codec::encode_simple_ack_apdu(invoke_id, service),
+        });
+        let _events = engine.start().await;
+
+        let statuses = run(&engine, request(true, 2)).await;
+        assert_eq!(statuses, [WriteStatus::Written, WriteStatus::Written]);
+        assert_eq!(services(&mock), [READ, READ, WRITE_MULTIPLE, WRITE, WRITE]);
+    }
+
+    #[tokio::test]
+    async fn failed_read_of_the_prior_value_writes_nothing() {
+        let (engine, mock) = engine(|service, invoke_id, _| match service {
+            READ => codec::encode_error_apdu(invoke_id, READ, PropertyError::UNKNOWN_PROPERTY),
+            _ => codec::encode_simple_ack_apdu(invoke_id, service),
+        });
+        let _events = engine.start().await;
+
+        let devices = HashMap::from([(DEVICE, peer())]);
+        let results = execute(&engine, &devices, request(true, 2)).await;
+        assert_eq!(results[0].status, WriteStatus::Failed);
+        assert!(results[0].error.as_deref().unwrap().starts_with("could not read prior value"));
+        assert_eq!(results[1].status, WriteStatus::NotAttempted);
+        assert_eq!(services(&mock), [READ]);
+    }
+}
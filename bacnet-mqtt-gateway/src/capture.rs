@@ -0,0 +1,200 @@
+//! On-demand capture of the BACnet/IP frames the gateway sends and receives,
+//! downloadable as a pcap file for Wireshark
+
+use crate::datalink::DataLink;
+use crate::mqtt::utc_timestamp;
+use serde::Serialize;
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Raw IPv4 packets, so no link-layer addresses have to be made up
+const LINKTYPE_IPV4: u32 = 228;
+const SNAPLEN: u32 = 65535;
+/// BVLC functions of the reconstructed BACnet/IP headers
+const BVLC_ORIGINAL_UNICAST: u8 = 0x0A;
+const BVLC_ORIGINAL_BROADCAST: u8 = 0x0B;
+/// Capture size a start request without a limit gets
+pub const DEFAULT_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+fn pcap_header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&0i32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_IPV4.to_le_bytes());
+    header
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let sum = header.chunks(2).fold(0u32, |sum, word| sum + u32::from(u16::from_be_bytes([word[0], word[1]])));
+    let folded = (sum & 0xFFFF) + (sum >> 16);
+    !(((folded & 0xFFFF) + (folded >> 16)) as u16)
+}
+
+/// IPv4/UDP packet carrying the NPDU behind a BVLC header, as the frame
+/// appeared on the wire; the UDP checksum is left out, which IPv4 allows
+fn packet(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), bvlc_function: u8, npdu: &[u8]) -> Vec<u8> {
+    let bvlc_len = 4 + npdu.len();
+    let udp_len = 8 + bvlc_len;
+    let total_len = 20 + udp_len;
+    let mut packet = Vec::with_capacity(total_len);
+    packet.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 17, 0, 0]);
+    packet[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    packet.extend_from_slice(&src.0.octets());
+    packet.extend_from_slice(&dst.0.octets());
+    let checksum = ipv4_checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&src.1.to_be_bytes());
+    packet.extend_from_slice(&dst.1.to_be_bytes());
+    packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&[0x81, bvlc_function]);
+    packet.extend_from_slice(&(bvlc_len as u16).to_be_bytes());
+    packet.extend_from_slice(npdu);
+    packet
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureStatus {
+    pub recording: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started: Option<String>,
+    pub frames: u64,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    /// Frames left out because the capture was full or not IPv4
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct Recording {
+    started: Option<String>,
+    pcap: Vec<u8>,
+    frames: u64,
+    max_bytes: usize,
+    dropped: u64,
+}
+
+/// The frames recorded since the last start, kept in memory
+#[derive(Default)]
+pub struct Capture {
+    recording: AtomicBool,
+    state: Mutex<Recording>,
+}
+
+impl Capture {
+    /// Starts a new capture of at most `max_bytes`, discarding the previous one
+    pub fn start(&self, max_bytes: usize) -> CaptureStatus {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = Recording { started: Some(utc_timestamp()), pcap: pcap_header(), frames: 0, max_bytes, dropped: 0 };
+        self.recording.store(true, Ordering::Relaxed);
+        info!("Started a packet capture of up to {} bytes", max_bytes);
+        Self::status_of(&state, true)
+    }
+
+    pub fn stop(&self) -> CaptureStatus {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if self.recording.swap(false, Ordering::Relaxed) {
+            info!("Stopped the packet capture after {} frames", state.frames);
+        }
+        Self::status_of(&state, false)
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        Self::status_of(&state, self.recording.load(Ordering::Relaxed))
+    }
+
+    fn status_of(state: &Recording, recording: bool) -> CaptureStatus {
+        CaptureStatus {
+            recording,
+            started: state.started.clone(),
+            frames: state.frames,
+            bytes: state.pcap.len(),
+            max_bytes: state.max_bytes,
+            dropped: state.dropped,
+        }
+    }
+
+    /// The pcap file of the current or last capture, none before the first start
+    pub fn pcap(&self) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.started.is_some().then(|| state.pcap.clone())
+    }
+
+    fn record(&self, src: SocketAddr, dst: SocketAddr, bvlc_function: u8, npdu: &[u8]) {
+        if !self.recording.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) = (src.ip(), dst.ip()) else {
+            state.dropped += 1;
+            return;
+        };
+        let packet = packet((src_ip, src.port()), (dst_ip, dst.port()), bvlc_function, npdu);
+        if state.pcap.len() + 16 + packet.len() > state.max_bytes {
+            state.dropped += 1;
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let len = packet.len() as u32;
+        for field in [now.as_secs() as u32, now.subsec_micros(), len, len] {
+            state.pcap.extend_from_slice(&field.to_le_bytes());
+        }
+        state.pcap.extend_from_slice(&packet);
+        state.frames += 1;
+    }
+}
+
+/// A datalink whose frames are copied into the capture while it records
+pub struct Capturing {
+    inner: Box<dyn DataLink>,
+    capture: Arc<Capture>,
+    /// Address of the gateway's socket in the capture
+    local: SocketAddr,
+}
+
+impl Capturing {
+    pub fn new(inner: Box<dyn DataLink>, capture: Arc<Capture>, local: SocketAddr) -> Self {
+        Self { inner, capture, local }
+    }
+}
+
+impl DataLink for Capturing {
+    fn send_broadcast(&mut self, npdu: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.inner.send_broadcast(npdu)?;
+        let broadcast = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), self.local.port());
+        self.capture.record(self.local, broadcast, BVLC_ORIGINAL_BROADCAST, npdu);
+        Ok(())
+    }
+
+    fn send_unicast(&mut self, npdu: &[u8], dest: SocketAddr) -> Result<(), Box<dyn Error>> {
+        self.inner.send_unicast(npdu, dest)?;
+        self.capture.record(self.local, dest, BVLC_ORIGINAL_UNICAST, npdu);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<(Vec<u8>, SocketAddr), Box<dyn Error>> {
+        let (npdu, src) = self.inner.receive()?;
+        if !npdu.is_empty() {
+            // Whether it was broadcast is not known past the BVLC layer
+            self.capture.record(src, self.local, BVLC_ORIGINAL_UNICAST, &npdu);
+        }
+        Ok((npdu, src))
+    }
+
+    fn interface_of(&self, addr: SocketAddr) -> Option<String> {
+        self.inner.interface_of(addr)
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+}
@@ -0,0 +1,230 @@
+//! Adjustments of the published messages for brokers run by cloud IoT services
+
+use crate::config::{BrokerConfig, BrokerProfile};
+use crate::secret;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rumqttc::QoS;
+use sha2::Sha256;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// AWS IoT Core rejects topics with more forward slashes
+const AWS_MAX_SLASHES: usize = 7;
+
+/// ALPN protocol AWS IoT Core serves MQTT on port 443 with
+const AWS_ALPN: &[u8] = b"x-amzn-mqtt-ca";
+
+const AZURE_API_VERSION: &str = "2021-04-12";
+
+/// Topic the desired properties of an Azure IoT Hub device twin are
+/// delivered on, in place of the IoT Hub's own response and patch topics
+pub const TWIN_DESIRED_TOPIC: &str = "$iothub/twin/desired";
+
+/// Request ID of the twin GET sent on every connection
+const TWIN_GET_REQUEST: &str = "get";
+
+/// Request IDs of reported property patches
+static NEXT_TWIN_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+/// A message as it goes to the broker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub topic: String,
+    pub qos: QoS,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+/// Rewrites messages to what a broker profile accepts
+#[derive(Debug, Clone)]
+pub enum Adapter {
+    Standard,
+    /// No retained messages or QoS 2; retained messages are mirrored into a
+    /// named device shadow as the last value of their topic
+    AwsIot { shadow_topic: String },
+    /// Everything goes to the device's telemetry topic, the original topic
+    /// as the `topic` message property; SAS token credentials
+    AzureIotHub(AzureDevice),
+}
+
+#[derive(Debug, Clone)]
+pub struct AzureDevice {
+    events_topic: String,
+    username: String,
+    /// `<hub>/devices/<device id>`, the scope of the SAS tokens
+    resource: String,
+    key: Vec<u8>,
+    token_ttl_secs: u64,
+}
+
+impl Adapter {
+    pub fn new(broker: &BrokerConfig, client_id: &str) -> Result<Self, String> {
+        Ok(match broker.profile {
+            BrokerProfile::Standard => Self::Standard,
+            BrokerProfile::AwsIot => {
+                let thing = broker.aws.thing_name.as_deref().unwrap_or(client_id);
+                Self::AwsIot { shadow_topic: format!("$aws/things/{}/shadow/name/{}/update", thing, broker.aws.shadow_name) }
+            }
+            BrokerProfile::AzureIotHub => {
+                let azure = &broker.azure;
+                let key = secret::resolve(Some(azure.shared_access_key.as_str()), azure.shared_access_key_file.as_deref())
+                    .map_err(|e| format!("broker {}: {}", broker.name, e))?
+                    .unwrap_or_default();
+                let key = BASE64
+                    .decode(key.trim())
+                    .map_err(|_| format!("broker {}: shared_access_key is not base64", broker.name))?;
+                Self::AzureIotHub(AzureDevice {
+                    events_topic: format!("devices/{}/messages/events/", client_id),
+                    username: format!("{}/{}/?api-version={}", broker.broker_host, client_id, AZURE_API_VERSION),
+                    resource: format!("{}/devices/{}", broker.broker_host, client_id),
+                    key,
+                    token_ttl_secs: broker.azure.token_ttl_secs.max(60),
+                })
+            }
+        })
+    }
+
+    /// Credentials replacing the configured ones, renewed before every connection attempt
+    pub fn credentials(&self) -> Option<(String, String)> {
+        let Self::AzureIotHub(device) = self else {
+            return None;
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Some((device.username.clone(), sas_token(&device.resource, &device.key, now + device.token_ttl_secs)))
+    }
+
+    /// False for brokers that refuse subscriptions to arbitrary topics
+    pub fn subscribes(&self) -> bool {
+        !matches!(self, Self::AzureIotHub(_))
+    }
+
+    pub fn has_twin(&self) -> bool {
+        matches!(self, Self::AzureIotHub(_))
+    }
+
+    /// Subscriptions and requests of the service itself, sent on every connection
+    pub fn on_connect(&self) -> (Vec<&'static str>, Vec<Outgoing>) {
+        match self {
+            Self::AzureIotHub(_) => {
+                let get = Outgoing {
+                    topic: format!("$iothub/twin/GET/?$rid={}", TWIN_GET_REQUEST),
+                    qos: QoS::AtLeastOnce,
+                    retain: false,
+                    payload: Vec::new(),
+                };
+                (vec!["$iothub/twin/res/#", "$iothub/twin/PATCH/properties/desired/#"], vec![get])
+            }
+            _ => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Desired twin properties carried by an inbound message: the whole twin
+    /// answering the GET, or a patch
+    pub fn twin_desired(&self, topic: &str, payload: &[u8]) -> Option<Vec<u8>> {
+        if !self.has_twin() {
+            return None;
+        }
+        if topic.starts_with("$iothub/twin/PATCH/properties/desired/") {
+            return Some(payload.to_vec());
+        }
+        if topic.starts_with("$iothub/twin/res/200/") && topic.ends_with(&format!("$rid={}", TWIN_GET_REQUEST)) {
+            let twin: serde_json::Value = serde_json::from_slice(payload).ok()?;
+            return Some(twin.get("desired")?.to_string().into_bytes());
+        }
+        None
+    }
+
+    /// TLS ALPN protocols to offer on a port
+    pub fn alpn(&self, port: u16) -> Option<Vec<Vec<u8>>> {
+        match self {
+            Self::AwsIot { .. } if port == 443 => Some(vec![AWS_ALPN.to_vec()]),
+            _ => None,
+        }
+    }
+
+    pub fn adapt(&self, topic: &str, qos: QoS, retain: bool, payload: Vec<u8>) -> Vec<Outgoing> {
+        match self {
+            Self::Standard => vec![Outgoing { topic: topic.to_string(), qos, retain, payload }],
+            Self::AwsIot { shadow_topic } => {
+                let qos = if qos == QoS::ExactlyOnce { QoS::AtLeastOnce } else { qos };
+                let mut messages = Vec::new();
+                if retain {
+                    messages.push(Outgoing { topic: shadow_topic.clone(), qos, retain: false, payload: shadow_update(topic, &payload) });
+                }
+                messages.push(Outgoing { topic: limit_depth(topic, AWS_MAX_SLASHES), qos, retain: false, payload });
+                messages
+            }
+            Self::AzureIotHub(device) => {
+                let qos = if qos == QoS::ExactlyOnce { QoS::AtLeastOnce } else { qos };
+                let topic = if topic.starts_with("$iothub/") {
+                    topic.to_string()
+                } else {
+                    format!("{}topic={}", device.events_topic, url_encode(topic))
+                };
+                vec![Outgoing { topic, qos, retain: false, payload }]
+            }
+        }
+    }
+}
+
+/// Shadow update reporting a topic's last value; an empty payload, which
+/// clears a retained topic, removes it from the shadow
+fn shadow_update(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let value = if payload.is_empty() {
+        serde_json::Value::Null
+    } else {
+        let text = String::from_utf8_lossy(payload);
+        serde_json::from_str(&text).unwrap_or_else(|_| serde_json::Value::String(text.into_owned()))
+    };
+    serde_json::json!({ "state": { "reported": { topic: value } } }).to_string().into_bytes()
+}
+
+/// Folds the levels beyond the limit into the last allowed one, joined by `_`
+fn limit_depth(topic: &str, max_slashes: usize) -> String {
+    let levels: Vec<&str> = topic.split('/').collect();
+    if levels.len() <= max_slashes + 1 {
+        return topic.to_string();
+    }
+    let (kept, folded) = levels.split_at(max_slashes);
+    format!("{}/{}", kept.join("/"), folded.join("_"))
+}
+
+/// Topic of a reported properties patch of the device twin
+pub fn twin_reported_topic() -> String {
+    format!("$iothub/twin/PATCH/properties/reported/?$rid={}", NEXT_TWIN_REQUEST.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Shared access signature of an IoT Hub device, valid until `expiry` (Unix seconds)
+fn sas_token(resource: &str, key: &[u8], expiry: u64) -> String {
+    let resource = url_encode(resource);
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}\n{}", resource, expiry).as_bytes());
+    let signature = BASE64.encode(mac.finalize().into_bytes());
+    format!("SharedAccessSignature sr={}&sig={}&se={}", resource, url_encode(&signature), expiry)
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986
+pub fn url_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sas_token_signs_encoded_resource_and_expiry() {
+        let key = BASE64.decode("c2VjcmV0LWRldmljZS1rZXktZm9yLXRlc3Rz").unwrap();
+        assert_eq!(
+            sas_token("myhub.azure-devices.net/devices/ahu-1", &key, 1_700_000_000),
+            "SharedAccessSignature sr=myhub.azure-devices.net%2Fdevices%2Fahu-1&sig=NnPoXXHdK3LaNg9EshpNr9t1HRKbazKfygtphO5CJLA%3D&se=1700000000"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::ClusterConfig;
+use crate::mqtt::MqttService;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Missed heartbeats after which a member is considered gone
+const MISSED_HEARTBEATS: u32 = 3;
+
+/// FNV-1a, stable across hosts and Rust versions unlike the std hasher
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterStatus {
+    pub node_id: String,
+    pub members: Vec<String>,
+}
+
+/// Splits device ownership between gateway instances sharing one broker and
+/// base topic: devices assigned explicitly go to their node while it is
+/// alive, every other device to the live member winning its rendezvous hash
+pub struct Cluster {
+    config: Option<ClusterConfig>,
+    /// Live members with the time their last heartbeat arrived
+    members: RwLock<BTreeMap<String, Instant>>,
+}
+
+impl Cluster {
+    pub fn new(config: Option<ClusterConfig>) -> Self {
+        let members = config.iter().map(|c| (c.node_id.clone(), Instant::now())).collect();
+        Self { config, members: RwLock::new(members) }
+    }
+
+    /// True if this instance polls and publishes the device; always true without clustering
+    pub fn owns(&self, device_id: u32) -> bool {
+        let Some(config) = &self.config else {
+            return true;
+        };
+        let members = self.members.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(node) = config.assignments.get(&device_id).filter(|node| members.contains_key(*node)) {
+            return *node == config.node_id;
+        }
+        let owner = members
+            .keys()
+            .max_by_key(|member| fnv1a(format!("{}/{}", member, device_id).as_bytes()));
+        owner.is_none_or(|owner| *owner == config.node_id)
+    }
+
+    /// Records a member's heartbeat, true if the member just joined
+    fn heartbeat(&self, node_id: &str) -> bool {
+        let mut members = self.members.write().unwrap_or_else(|e| e.into_inner());
+        members.insert(node_id.to_string(), Instant::now()).is_none()
+    }
+
+    /// Drops members whose heartbeats stopped, returning them
+    fn expire(&self, interval: Duration) -> Vec<String> {
+        let Some(config) = &self.config else {
+            return Vec::new();
+        };
+        let mut members = self.members.write().unwrap_or_else(|e| e.into_inner());
+        let expired: Vec<String> = members
+            .iter()
+            .filter(|(node, seen)| **node != config.node_id && seen.elapsed() > interval * MISSED_HEARTBEATS)
+            .map(|(node, _)| node.clone())
+            .collect();
+        for node in &expired {
+            members.remove(node);
+        }
+        expired
+    }
+
+    pub fn status(&self) -> Option<ClusterStatus> {
+        let config = self.config.as_ref()?;
+        let members = self.members.read().unwrap_or_else(|e| e.into_inner());
+        Some(ClusterStatus { node_id: config.node_id.clone(), members: members.keys().cloned().collect() })
+    }
+}
+
+/// Announces this instance and tracks the other members' announcements,
+/// shifting device ownership whenever a member joins or leaves
+pub async fn run(cluster: Arc<Cluster>, mqtt: MqttService) {
+    let Some(config) = cluster.config.clone() else {
+        return;
+    };
+    let interval = Duration::from_secs(config.heartbeat_secs.max(1));
+    let filter = mqtt.cluster_topic("+");
+    let own_topic = mqtt.cluster_topic(&config.node_id);
+    let mut inbound = mqtt.incoming();
+    mqtt.subscribe(&filter).await;
+    info!("Joining gateway cluster as {}", config.node_id);
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                mqtt.publish(&own_topic, &config.node_id, false).await;
+                for node in cluster.expire(interval) {
+                    warn!("Cluster member {} stopped sending heartbeats, taking over its devices", node);
+                }
+            }
+            msg = inbound.recv() => match msg {
+                Ok(msg) => {
+                    let Some(node_id) = mqtt.parse_cluster_topic(&msg.topic) else {
+                        continue;
+                    };
+                    if cluster.heartbeat(&node_id) {
+                        info!("Cluster member {} joined, rebalancing devices", node_id);
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
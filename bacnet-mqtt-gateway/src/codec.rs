@@ -0,0 +1,1233 @@
+//! Hand-rolled BACnet tag encoding for the services bacnet-rs does not cover
+//! (server-side request decoding, Error/Reject PDUs, ReadPropertyMultiple,
+//! WriteProperty and WritePropertyMultiple).
+
+use crate::point::ObjectRef;
+use std::fmt;
+
+/// Application tag numbers
+pub const TAG_NULL: u8 = 0;
+pub const TAG_BOOLEAN: u8 = 1;
+pub const TAG_UNSIGNED: u8 = 2;
+pub const TAG_SIGNED: u8 = 3;
+pub const TAG_REAL: u8 = 4;
+pub const TAG_DOUBLE: u8 = 5;
+pub const TAG_OCTET_STRING: u8 = 6;
+pub const TAG_CHARACTER_STRING: u8 = 7;
+pub const TAG_BIT_STRING: u8 = 8;
+pub const TAG_ENUMERATED: u8 = 9;
+pub const TAG_DATE: u8 = 10;
+pub const TAG_TIME: u8 = 11;
+pub const TAG_OBJECT_ID: u8 = 12;
+
+/// Octet value marking an unspecified date or time field
+const WILDCARD: u8 = 0xFF;
+
+/// APDU types (high nibble of the first APDU octet)
+pub const PDU_SIMPLE_ACK: u8 = 2;
+pub const PDU_ERROR: u8 = 5;
+pub const PDU_REJECT: u8 = 6;
+pub const PDU_ABORT: u8 = 7;
+
+/// Reject reasons
+pub const REJECT_INVALID_TAG: u8 = 4;
+pub const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
+
+const ERROR_CLASSES: &[&str] = &[
+    "device",
+    "object",
+    "property",
+    "resources",
+    "security",
+    "services",
+    "vt",
+    "communication",
+];
+
+const ERROR_CODES: &[(u32, &str)] = &[
+    (0, "other"),
+    (2, "configuration-in-progress"),
+    (3, "device-busy"),
+    (5, "file-access-denied"),
+    (7, "inconsistent-parameters"),
+    (9, "invalid-data-type"),
+    (13, "invalid-parameter-data-type"),
+    (16, "missing-required-parameter"),
+    (18, "no-space-for-object"),
+    (20, "no-space-to-write-property"),
+    (25, "operational-problem"),
+    (26, "password-failure"),
+    (27, "read-access-denied"),
+    (29, "service-request-denied"),
+    (30, "timeout"),
+    (31, "unknown-object"),
+    (32, "unknown-property"),
+    (36, "unsupported-object-type"),
+    (37, "value-out-of-range"),
+    (40, "write-access-denied"),
+    (41, "character-set-not-supported"),
+    (42, "invalid-array-index"),
+    (45, "optional-functionality-not-supported"),
+    (47, "datatype-not-supported"),
+    (50, "property-is-not-an-array"),
+];
+
+const REJECT_REASONS: &[&str] = &[
+    "other",
+    "buffer-overflow",
+    "inconsistent-parameters",
+    "invalid-parameter-data-type",
+    "invalid-tag",
+    "missing-required-parameter",
+    "parameter-out-of-range",
+    "too-many-arguments",
+    "undefined-enumeration",
+    "unrecognized-service",
+];
+
+const ABORT_REASONS: &[&str] = &[
+    "other",
+    "buffer-overflow",
+    "invalid-apdu-in-this-state",
+    "preempted-by-higher-priority-task",
+    "segmentation-not-supported",
+    "security-error",
+    "insufficient-security",
+    "window-size-out-of-range",
+    "application-exceeded-reply-time",
+    "out-of-resources",
+    "tsm-timeout",
+    "apdu-too-long",
+];
+
+/// Name of an error class, or its number for proprietary classes
+pub fn error_class_name(class: u32) -> String {
+    ERROR_CLASSES.get(class as usize).map_or_else(|| class.to_string(), |name| name.to_string())
+}
+
+/// Name of an error code, or its number for uncommon and proprietary codes
+pub fn error_code_name(code: u32) -> String {
+    ERROR_CODES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map_or_else(|| code.to_string(), |(_, name)| name.to_string())
+}
+
+pub fn reject_reason_name(reason: u8) -> String {
+    REJECT_REASONS.get(reason as usize).map_or_else(|| reason.to_string(), |name| name.to_string())
+}
+
+pub fn abort_reason_name(reason: u8) -> String {
+    ABORT_REASONS.get(reason as usize).map_or_else(|| reason.to_string(), |name| name.to_string())
+}
+
+/// Special property identifiers usable in ReadPropertyMultiple
+pub const PROP_ALL: u32 = 8;
+pub const PROP_OPTIONAL: u32 = 80;
+pub const PROP_REQUIRED: u32 = 105;
+
+/// Parses a property identifier given as a number or as `all`, `required` or `optional`
+pub fn parse_property_identifier(s: &str) -> Result<u32, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "all" => Ok(PROP_ALL),
+        "required" => Ok(PROP_REQUIRED),
+        "optional" => Ok(PROP_OPTIONAL),
+        other => other
+            .parse()
+            .map_err(|_| format!("invalid property '{}', expected a number, all, required or optional", s)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CodecError(pub String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BACnet decode error: {}", self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+fn malformed<T>(msg: impl Into<String>) -> Result<T, CodecError> {
+    Err(CodecError(msg.into()))
+}
+
+/// An application-tagged BACnet value
+#[derive(Debug, Clone, PartialEq)]
+pub enum BacnetValue {
+    Null,
+    Boolean(bool),
+    Unsigned(u32),
+    Signed(i32),
+    Real(f32),
+    Double(f64),
+    OctetString(Vec<u8>),
+    CharacterString(String),
+    BitString(Vec<bool>),
+    Enumerated(u32),
+    /// Year - 1900, month, day, weekday, 0xFF where unspecified
+    Date([u8; 4]),
+    /// Hour, minute, second, hundredths, 0xFF where unspecified
+    Time([u8; 4]),
+    ObjectId(ObjectRef),
+    /// Raw encoding of a constructed value (context tags, nested lists) that
+    /// has no generic application-tagged form
+    Constructed(Vec<u8>),
+}
+
+impl BacnetValue {
+    /// Name of the application data type, as accepted by `value_from_json`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            BacnetValue::Null => "null",
+            BacnetValue::Boolean(_) => "boolean",
+            BacnetValue::Unsigned(_) => "unsigned",
+            BacnetValue::Signed(_) => "signed",
+            BacnetValue::Real(_) => "real",
+            BacnetValue::Double(_) => "double",
+            BacnetValue::OctetString(_) => "octet_string",
+            BacnetValue::CharacterString(_) => "string",
+            BacnetValue::BitString(_) => "bit_string",
+            BacnetValue::Enumerated(_) => "enumerated",
+            BacnetValue::Date(_) => "date",
+            BacnetValue::Time(_) => "time",
+            BacnetValue::ObjectId(_) => "object_id",
+            BacnetValue::Constructed(_) => "constructed",
+        }
+    }
+
+    /// Typed JSON representation, numbers stay numbers and flags stay booleans
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value;
+        match self {
+            BacnetValue::Null => Value::Null,
+            BacnetValue::Boolean(v) => Value::Bool(*v),
+            BacnetValue::Unsigned(v) | BacnetValue::Enumerated(v) => Value::from(*v),
+            BacnetValue::Signed(v) => Value::from(*v),
+            BacnetValue::Real(v) => Value::from(*v as f64),
+            BacnetValue::Double(v) => Value::from(*v),
+            BacnetValue::BitString(bits) => Value::Array(bits.iter().map(|b| Value::Bool(*b)).collect()),
+            other => Value::String(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for BacnetValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Unspecified date/time fields are rendered as '*'
+        let field = |v: u8, width: usize| match v {
+            WILDCARD => "*".repeat(width),
+            v => format!("{:0width$}", v, width = width),
+        };
+        match self {
+            BacnetValue::Null => write!(f, "null"),
+            BacnetValue::Boolean(v) => write!(f, "{}", v),
+            BacnetValue::Unsigned(v) | BacnetValue::Enumerated(v) => write!(f, "{}", v),
+            BacnetValue::Signed(v) => write!(f, "{}", v),
+            BacnetValue::Real(v) => write!(f, "{}", v),
+            BacnetValue::Double(v) => write!(f, "{}", v),
+            BacnetValue::OctetString(bytes) | BacnetValue::Constructed(bytes) => {
+                bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            BacnetValue::CharacterString(s) => write!(f, "{}", s),
+            BacnetValue::BitString(bits) => bits.iter().try_for_each(|b| write!(f, "{}", *b as u8)),
+            BacnetValue::Date([year, month, day, _]) => {
+                let year = match *year {
+                    WILDCARD => "****".to_string(),
+                    y => (1900 + y as u16).to_string(),
+                };
+                write!(f, "{}-{}-{}", year, field(*month, 2), field(*day, 2))
+            }
+            BacnetValue::Time([hour, minute, second, hundredths]) => write!(
+                f,
+                "{}:{}:{}.{}",
+                field(*hour, 2),
+                field(*minute, 2),
+                field(*second, 2),
+                field(*hundredths, 2)
+            ),
+            BacnetValue::ObjectId(object) => write!(f, "{}", object),
+        }
+    }
+}
+
+/// MQTT state payload of a present-value: binary objects report ON/OFF, everything
+/// else its plain text form
+pub fn state_text(object: ObjectRef, value: &BacnetValue) -> String {
+    match value {
+        BacnetValue::Enumerated(v) if object.is_binary() => if *v == 0 { "OFF" } else { "ON" }.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Error class/code pair returned in an Error PDU or RPM access error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropertyError {
+    pub class: u32,
+    pub code: u32,
+}
+
+impl PropertyError {
+    pub const UNKNOWN_OBJECT: Self = Self { class: 1, code: 31 };
+    pub const UNKNOWN_PROPERTY: Self = Self { class: 2, code: 32 };
+    pub const INVALID_ARRAY_INDEX: Self = Self { class: 2, code: 42 };
+    pub const PROPERTY_IS_NOT_AN_ARRAY: Self = Self { class: 2, code: 50 };
+    pub const INVALID_DATA_TYPE: Self = Self { class: 2, code: 9 };
+    pub const WRITE_ACCESS_DENIED: Self = Self { class: 2, code: 40 };
+}
+
+/// Object/property/array index triple addressed by a read or write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PropertyReference {
+    pub object: ObjectRef,
+    pub property: u32,
+    pub array_index: Option<u32>,
+}
+
+// ---- Encoding ----
+
+/// Writes a tag header, `context` selects context-specific instead of application class
+pub fn encode_tag(buf: &mut Vec<u8>, number: u8, context: bool, len: u32) {
+    let class = if context { 0x08 } else { 0x00 };
+    let lvt = if len <= 4 { len as u8 } else { 5 };
+    if number >= 15 {
+        buf.push(0xF0 | class | lvt);
+        buf.push(number);
+    } else {
+        buf.push((number << 4) | class | lvt);
+    }
+    if len > 4 {
+        if len < 254 {
+            buf.push(len as u8);
+        } else if len <= 0xFFFF {
+            buf.push(254);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            buf.push(255);
+            buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+}
+
+fn encode_paired_tag(buf: &mut Vec<u8>, number: u8, lvt: u8) {
+    if number >= 15 {
+        buf.push(0xF8 | lvt);
+        buf.push(number);
+    } else {
+        buf.push((number << 4) | 0x08 | lvt);
+    }
+}
+
+pub fn encode_opening_tag(buf: &mut Vec<u8>, number: u8) {
+    encode_paired_tag(buf, number, 6);
+}
+
+pub fn encode_closing_tag(buf: &mut Vec<u8>, number: u8) {
+    encode_paired_tag(buf, number, 7);
+}
+
+/// Minimal big-endian representation of an unsigned value
+fn unsigned_bytes(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+/// Minimal big-endian two's complement representation of a signed value
+fn signed_bytes(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 3 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn object_id_raw(object: ObjectRef) -> u32 {
+    ((object.object_type as u32) << 22) | (object.instance & 0x3F_FFFF)
+}
+
+pub fn encode_context_unsigned(buf: &mut Vec<u8>, number: u8, value: u32) {
+    let bytes = unsigned_bytes(value);
+    encode_tag(buf, number, true, bytes.len() as u32);
+    buf.extend_from_slice(&bytes);
+}
+
+pub fn encode_context_object_id(buf: &mut Vec<u8>, number: u8, object: ObjectRef) {
+    encode_tag(buf, number, true, 4);
+    buf.extend_from_slice(&object_id_raw(object).to_be_bytes());
+}
+
+/// Encodes a value with its application tag
+pub fn encode_application(buf: &mut Vec<u8>, value: &BacnetValue) {
+    match value {
+        BacnetValue::Null => encode_tag(buf, TAG_NULL, false, 0),
+        BacnetValue::Boolean(v) => encode_tag(buf, TAG_BOOLEAN, false, *v as u32),
+        BacnetValue::Unsigned(v) => {
+            let bytes = unsigned_bytes(*v);
+            encode_tag(buf, TAG_UNSIGNED, false, bytes.len() as u32);
+            buf.extend_from_slice(&bytes);
+        }
+        BacnetValue::Signed(v) => {
+            let bytes = signed_bytes(*v);
+            encode_tag(buf, TAG_SIGNED, false, bytes.len() as u32);
+            buf.extend_from_slice(&bytes);
+        }
+        BacnetValue::Real(v) => {
+            encode_tag(buf, TAG_REAL, false, 4);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        BacnetValue::Double(v) => {
+            encode_tag(buf, TAG_DOUBLE, false, 8);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        BacnetValue::OctetString(bytes) => {
+            encode_tag(buf, TAG_OCTET_STRING, false, bytes.len() as u32);
+            buf.extend_from_slice(bytes);
+        }
+        BacnetValue::CharacterString(s) => {
+            // Character set 0 = ANSI X3.4 / UTF-8
+            encode_tag(buf, TAG_CHARACTER_STRING, false, s.len() as u32 + 1);
+            buf.push(0);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        BacnetValue::BitString(bits) => {
+            let octets = bits.len().div_ceil(8);
+            let unused = (octets * 8 - bits.len()) as u8;
+            encode_tag(buf, TAG_BIT_STRING, false, octets as u32 + 1);
+            buf.push(unused);
+            for chunk in bits.chunks(8) {
+                let mut octet = 0u8;
+                for (i, bit) in chunk.iter().enumerate() {
+                    if *bit {
+                        octet |= 0x80 >> i;
+                    }
+                }
+                buf.push(octet);
+            }
+        }
+        BacnetValue::Enumerated(v) => {
+            let bytes = unsigned_bytes(*v);
+            encode_tag(buf, TAG_ENUMERATED, false, bytes.len() as u32);
+            buf.extend_from_slice(&bytes);
+        }
+        BacnetValue::Date(octets) => {
+            encode_tag(buf, TAG_DATE, false, 4);
+            buf.extend_from_slice(octets);
+        }
+        BacnetValue::Time(octets) => {
+            encode_tag(buf, TAG_TIME, false, 4);
+            buf.extend_from_slice(octets);
+        }
+        BacnetValue::ObjectId(object) => {
+            encode_tag(buf, TAG_OBJECT_ID, false, 4);
+            buf.extend_from_slice(&object_id_raw(*object).to_be_bytes());
+        }
+        BacnetValue::Constructed(bytes) => buf.extend_from_slice(bytes),
+    }
+}
+
+/// Service data of a ReadProperty-ACK
+pub fn encode_read_property_ack(reference: &PropertyReference, values: &[BacnetValue]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_context_object_id(&mut buf, 0, reference.object);
+    encode_context_unsigned(&mut buf, 1, reference.property);
+    if let Some(index) = reference.array_index {
+        encode_context_unsigned(&mut buf, 2, index);
+    }
+    encode_opening_tag(&mut buf, 3);
+    for value in values {
+        encode_application(&mut buf, value);
+    }
+    encode_closing_tag(&mut buf, 3);
+    buf
+}
+
+/// Result of reading one property of a ReadPropertyMultiple request
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyResult {
+    pub property: u32,
+    pub array_index: Option<u32>,
+    pub value: Result<Vec<BacnetValue>, PropertyError>,
+}
+
+/// Service data of a ReadPropertyMultiple-ACK
+pub fn encode_read_property_multiple_ack(results: &[(ObjectRef, Vec<PropertyResult>)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (object, properties) in results {
+        encode_context_object_id(&mut buf, 0, *object);
+        encode_opening_tag(&mut buf, 1);
+        for result in properties {
+            encode_context_unsigned(&mut buf, 2, result.property);
+            if let Some(index) = result.array_index {
+                encode_context_unsigned(&mut buf, 3, index);
+            }
+            match &result.value {
+                Ok(values) => {
+                    encode_opening_tag(&mut buf, 4);
+                    for value in values {
+                        encode_application(&mut buf, value);
+                    }
+                    encode_closing_tag(&mut buf, 4);
+                }
+                Err(error) => {
+                    encode_opening_tag(&mut buf, 5);
+                    encode_application(&mut buf, &BacnetValue::Enumerated(error.class));
+                    encode_application(&mut buf, &BacnetValue::Enumerated(error.code));
+                    encode_closing_tag(&mut buf, 5);
+                }
+            }
+        }
+        encode_closing_tag(&mut buf, 1);
+    }
+    buf
+}
+
+/// Converts a JSON value into a BACnet value, either of the explicitly named type
+/// or the natural present-value type of the object
+pub fn value_from_json(
+    json: &serde_json::Value,
+    value_type: Option<&str>,
+    object: ObjectRef,
+) -> Result<BacnetValue, String> {
+    use serde_json::Value;
+    let value_type = match value_type {
+        Some(t) => t.to_ascii_lowercase(),
+        None if json.is_null() => "null".to_string(),
+        None if object.is_binary() => "enumerated".to_string(),
+        None if object.is_multistate() => "unsigned".to_string(),
+        None => "real".to_string(),
+    };
+    let as_f64 = || match json {
+        Value::Number(n) => n.as_f64(),
+        Value::Bool(b) => Some(*b as u8 as f64),
+        Value::String(s) => match s.to_ascii_lowercase().as_str() {
+            "active" | "on" | "true" => Some(1.0),
+            "inactive" | "off" | "false" => Some(0.0),
+            other => other.parse().ok(),
+        },
+        _ => None,
+    };
+    let invalid = || format!("cannot convert {} to {}", json, value_type);
+    let value = match value_type.as_str() {
+        "null" => BacnetValue::Null,
+        "boolean" => BacnetValue::Boolean(as_f64().ok_or_else(invalid)? != 0.0),
+        "unsigned" => BacnetValue::Unsigned(as_f64().filter(|v| *v >= 0.0).ok_or_else(invalid)? as u32),
+        "signed" => BacnetValue::Signed(as_f64().ok_or_else(invalid)? as i32),
+        "real" => BacnetValue::Real(as_f64().ok_or_else(invalid)? as f32),
+        "double" => BacnetValue::Double(as_f64().ok_or_else(invalid)?),
+        "enumerated" => BacnetValue::Enumerated(as_f64().filter(|v| *v >= 0.0).ok_or_else(invalid)? as u32),
+        "string" => match json {
+            Value::String(s) => BacnetValue::CharacterString(s.clone()),
+            other => BacnetValue::CharacterString(other.to_string()),
+        },
+        other => return Err(format!("unknown value type '{}'", other)),
+    };
+    Ok(value)
+}
+
+/// Service data of a ReadProperty request
+pub fn encode_read_property_request(reference: &PropertyReference) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_context_object_id(&mut buf, 0, reference.object);
+    encode_context_unsigned(&mut buf, 1, reference.property);
+    if let Some(index) = reference.array_index {
+        encode_context_unsigned(&mut buf, 2, index);
+    }
+    buf
+}
+
+/// Service data of a ReadPropertyMultiple request for `(object, [(property, array index)])`
+pub fn encode_read_property_multiple_request(specs: &[(ObjectRef, Vec<(u32, Option<u32>)>)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (object, properties) in specs {
+        encode_context_object_id(&mut buf, 0, *object);
+        encode_opening_tag(&mut buf, 1);
+        for (property, array_index) in properties {
+            encode_context_unsigned(&mut buf, 0, *property);
+            if let Some(index) = array_index {
+                encode_context_unsigned(&mut buf, 1, *index);
+            }
+        }
+        encode_closing_tag(&mut buf, 1);
+    }
+    buf
+}
+
+/// A single property write as carried by WriteProperty or WritePropertyMultiple
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteSpec {
+    pub reference: PropertyReference,
+    /// Application-encoded value octets
+    pub value: Vec<u8>,
+    pub priority: Option<u8>,
+}
+
+/// Service data of a WriteProperty request
+pub fn encode_write_property_request(write: &WriteSpec) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_context_object_id(&mut buf, 0, write.reference.object);
+    encode_context_unsigned(&mut buf, 1, write.reference.property);
+    if let Some(index) = write.reference.array_index {
+        encode_context_unsigned(&mut buf, 2, index);
+    }
+    encode_opening_tag(&mut buf, 3);
+    buf.extend_from_slice(&write.value);
+    encode_closing_tag(&mut buf, 3);
+    if let Some(priority) = write.priority {
+        encode_context_unsigned(&mut buf, 4, priority as u32);
+    }
+    buf
+}
+
+/// Service data of a WritePropertyMultiple request, consecutive writes to the
+/// same object share one write-access specification
+pub fn encode_write_property_multiple_request(writes: &[WriteSpec]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut current: Option<ObjectRef> = None;
+    for write in writes {
+        if current != Some(write.reference.object) {
+            if current.is_some() {
+                encode_closing_tag(&mut buf, 1);
+            }
+            encode_context_object_id(&mut buf, 0, write.reference.object);
+            encode_opening_tag(&mut buf, 1);
+            current = Some(write.reference.object);
+        }
+        encode_context_unsigned(&mut buf, 0, write.reference.property);
+        if let Some(index) = write.reference.array_index {
+            encode_context_unsigned(&mut buf, 1, index);
+        }
+        encode_opening_tag(&mut buf, 2);
+        buf.extend_from_slice(&write.value);
+        encode_closing_tag(&mut buf, 2);
+        if let Some(priority) = write.priority {
+            encode_context_unsigned(&mut buf, 3, priority as u32);
+        }
+    }
+    if current.is_some() {
+        encode_closing_tag(&mut buf, 1);
+    }
+    buf
+}
+
+/// Service data of a SubscribeCOV request, a cancellation without `subscription`,
+/// else `(confirmed notifications, lifetime in seconds)` with 0 meaning indefinite
+pub fn encode_subscribe_cov_request(process_id: u32, object: ObjectRef, subscription: Option<(bool, u32)>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_context_unsigned(&mut buf, 0, process_id);
+    encode_context_object_id(&mut buf, 1, object);
+    if let Some((confirmed, lifetime)) = subscription {
+        encode_tag(&mut buf, 2, true, 1);
+        buf.push(confirmed as u8);
+        encode_context_unsigned(&mut buf, 3, lifetime);
+    }
+    buf
+}
+
+/// Complete Error APDU answering a confirmed request
+pub fn encode_error_apdu(invoke_id: u8, service_choice: u8, error: PropertyError) -> Vec<u8> {
+    let mut buf = vec![PDU_ERROR << 4, invoke_id, service_choice];
+    encode_application(&mut buf, &BacnetValue::Enumerated(error.class));
+    encode_application(&mut buf, &BacnetValue::Enumerated(error.code));
+    buf
+}
+
+/// Complete SimpleAck APDU answering a confirmed request
+pub fn encode_simple_ack_apdu(invoke_id: u8, service_choice: u8) -> Vec<u8> {
+    vec![PDU_SIMPLE_ACK << 4, invoke_id, service_choice]
+}
+
+/// Complete Reject APDU answering a confirmed request
+pub fn encode_reject_apdu(invoke_id: u8, reason: u8) -> Vec<u8> {
+    vec![PDU_REJECT << 4, invoke_id, reason]
+}
+
+// ---- Decoding ----
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagKind {
+    /// Length of the content octets (or the value itself for application booleans)
+    Value(u32),
+    Opening,
+    Closing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tag {
+    pub number: u8,
+    pub context: bool,
+    pub kind: TagKind,
+}
+
+/// Decodes a tag header, returning it with the number of octets consumed
+pub fn decode_tag(data: &[u8]) -> Result<(Tag, usize), CodecError> {
+    let first = *data.first().ok_or_else(|| CodecError("missing tag".into()))?;
+    let context = first & 0x08 != 0;
+    let lvt = first & 0x07;
+    let mut pos = 1;
+
+    let mut number = first >> 4;
+    if number == 15 {
+        number = *data.get(pos).ok_or_else(|| CodecError("truncated extended tag".into()))?;
+        pos += 1;
+    }
+
+    let kind = match lvt {
+        6 if context => TagKind::Opening,
+        7 if context => TagKind::Closing,
+        5 => {
+            let ext = *data.get(pos).ok_or_else(|| CodecError("truncated tag length".into()))?;
+            pos += 1;
+            let len = match ext {
+                254 => {
+                    let bytes = data.get(pos..pos + 2).ok_or_else(|| CodecError("truncated tag length".into()))?;
+                    pos += 2;
+                    u16::from_be_bytes([bytes[0], bytes[1]]) as u32
+                }
+                255 => {
+                    let bytes = data.get(pos..pos + 4).ok_or_else(|| CodecError("truncated tag length".into()))?;
+                    pos += 4;
+                    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                }
+                len => len as u32,
+            };
+            TagKind::Value(len)
+        }
+        6 | 7 => return malformed(format!("invalid length {} for application tag {}", lvt, number)),
+        len => TagKind::Value(len as u32),
+    };
+
+    Ok((Tag { number, context, kind }, pos))
+}
+
+fn decode_unsigned(bytes: &[u8]) -> Result<u32, CodecError> {
+    if bytes.is_empty() || bytes.len() > 4 {
+        return malformed(format!("invalid unsigned length {}", bytes.len()));
+    }
+    Ok(bytes.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32))
+}
+
+fn decode_signed(bytes: &[u8]) -> Result<i32, CodecError> {
+    if bytes.is_empty() || bytes.len() > 4 {
+        return malformed(format!("invalid signed length {}", bytes.len()));
+    }
+    let initial = if bytes[0] & 0x80 != 0 { -1i32 } else { 0 };
+    Ok(bytes.iter().fold(initial, |acc, b| (acc << 8) | *b as i32))
+}
+
+fn decode_object_id(bytes: &[u8]) -> Result<ObjectRef, CodecError> {
+    if bytes.len() != 4 {
+        return malformed(format!("invalid object identifier length {}", bytes.len()));
+    }
+    let raw = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    Ok(ObjectRef::new((raw >> 22) as u16, raw & 0x3F_FFFF))
+}
+
+/// Sequential reader over tagged service data
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn peek_tag(&self) -> Option<Tag> {
+        decode_tag(&self.data[self.pos..]).ok().map(|(tag, _)| tag)
+    }
+
+    pub fn read_tag(&mut self) -> Result<Tag, CodecError> {
+        let (tag, consumed) = decode_tag(&self.data[self.pos..])?;
+        self.pos += consumed;
+        Ok(tag)
+    }
+
+    fn read_bytes(&mut self, len: u32) -> Result<&'a [u8], CodecError> {
+        let end = self.pos + len as usize;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or_else(|| CodecError(format!("truncated value, wanted {} octets", len)))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// True if the next tag is the given context tag holding a value
+    pub fn next_is_context(&self, number: u8) -> bool {
+        matches!(self.peek_tag(), Some(Tag { number: n, context: true, kind: TagKind::Value(_) }) if n == number)
+    }
+
+    /// True if the next tag is the given closing tag
+    pub fn next_is_closing(&self, number: u8) -> bool {
+        self.peek_tag() == Some(Tag { number, context: true, kind: TagKind::Closing })
+    }
+
+    fn read_context_value(&mut self, number: u8) -> Result<&'a [u8], CodecError> {
+        match self.read_tag()? {
+            Tag { number: n, context: true, kind: TagKind::Value(len) } if n == number => self.read_bytes(len),
+            other => malformed(format!("expected context tag {}, found {:?}", number, other)),
+        }
+    }
+
+    pub fn read_context_unsigned(&mut self, number: u8) -> Result<u32, CodecError> {
+        decode_unsigned(self.read_context_value(number)?)
+    }
+
+    pub fn read_optional_context_unsigned(&mut self, number: u8) -> Result<Option<u32>, CodecError> {
+        if self.next_is_context(number) {
+            self.read_context_unsigned(number).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn read_context_object_id(&mut self, number: u8) -> Result<ObjectRef, CodecError> {
+        decode_object_id(self.read_context_value(number)?)
+    }
+
+    /// Consumes everything up to the closing tag matching an already consumed
+    /// opening tag, returning the enclosed octets
+    pub fn read_enclosed(&mut self, number: u8) -> Result<&'a [u8], CodecError> {
+        let start = self.pos;
+        let mut depth = 0usize;
+        loop {
+            let tag_start = self.pos;
+            let tag = self.read_tag()?;
+            match tag.kind {
+                TagKind::Opening => depth += 1,
+                TagKind::Closing if depth == 0 => {
+                    if tag.number != number {
+                        return malformed(format!("expected closing tag {}, found {}", number, tag.number));
+                    }
+                    return Ok(&self.data[start..tag_start]);
+                }
+                TagKind::Closing => depth -= 1,
+                // Application booleans carry their value in the tag itself
+                TagKind::Value(_) if !tag.context && tag.number == TAG_BOOLEAN => {}
+                TagKind::Value(len) => {
+                    self.read_bytes(len)?;
+                }
+            }
+        }
+    }
+
+    pub fn expect_opening(&mut self, number: u8) -> Result<(), CodecError> {
+        match self.read_tag()? {
+            Tag { number: n, context: true, kind: TagKind::Opening } if n == number => Ok(()),
+            other => malformed(format!("expected opening tag {}, found {:?}", number, other)),
+        }
+    }
+
+    pub fn expect_closing(&mut self, number: u8) -> Result<(), CodecError> {
+        match self.read_tag()? {
+            Tag { number: n, context: true, kind: TagKind::Closing } if n == number => Ok(()),
+            other => malformed(format!("expected closing tag {}, found {:?}", number, other)),
+        }
+    }
+}
+
+/// Decodes one application-tagged value, returning it with the octets consumed
+pub fn decode_application(data: &[u8]) -> Result<(BacnetValue, usize), CodecError> {
+    let mut reader = Reader::new(data);
+    let tag = reader.read_tag()?;
+    let len = match tag {
+        Tag { context: false, kind: TagKind::Value(len), .. } => len,
+        other => return malformed(format!("expected application tag, found {:?}", other)),
+    };
+    // Application booleans carry their value in the tag itself
+    if tag.number == TAG_BOOLEAN {
+        return Ok((BacnetValue::Boolean(len != 0), reader.pos));
+    }
+    let bytes = reader.read_bytes(len)?;
+    let value = match tag.number {
+        TAG_NULL => BacnetValue::Null,
+        TAG_UNSIGNED => BacnetValue::Unsigned(decode_unsigned(bytes)?),
+        TAG_SIGNED => BacnetValue::Signed(decode_signed(bytes)?),
+        TAG_REAL => BacnetValue::Real(f32::from_be_bytes(fixed(bytes, "real")?)),
+        TAG_DOUBLE => BacnetValue::Double(f64::from_be_bytes(fixed(bytes, "double")?)),
+        TAG_OCTET_STRING => BacnetValue::OctetString(bytes.to_vec()),
+        TAG_CHARACTER_STRING => BacnetValue::CharacterString(decode_character_string(bytes)?),
+        TAG_BIT_STRING => BacnetValue::BitString(decode_bit_string(bytes)?),
+        TAG_ENUMERATED => BacnetValue::Enumerated(decode_unsigned(bytes)?),
+        TAG_DATE => BacnetValue::Date(fixed(bytes, "date")?),
+        TAG_TIME => BacnetValue::Time(fixed(bytes, "time")?),
+        TAG_OBJECT_ID => BacnetValue::ObjectId(decode_object_id(bytes)?),
+        other => return malformed(format!("unsupported application tag {}", other)),
+    };
+    Ok((value, reader.pos))
+}
+
+/// Decodes every application-tagged value of a property, e.g. the contents of
+/// a ReadProperty-ACK's property-value; constructed values are not supported
+pub fn decode_application_values(data: &[u8]) -> Result<Vec<BacnetValue>, CodecError> {
+    let mut values = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (value, consumed) = decode_application(&data[pos..])?;
+        values.push(value);
+        pos += consumed;
+    }
+    Ok(values)
+}
+
+/// Decodes a property value of any datatype, keeping constructed values that
+/// are not a plain list of application-tagged values as raw octets
+pub fn decode_property_value(data: &[u8]) -> Vec<BacnetValue> {
+    decode_application_values(data).unwrap_or_else(|_| vec![BacnetValue::Constructed(data.to_vec())])
+}
+
+fn fixed<const N: usize>(bytes: &[u8], name: &str) -> Result<[u8; N], CodecError> {
+    bytes
+        .try_into()
+        .map_err(|_| CodecError(format!("invalid {} length {}", name, bytes.len())))
+}
+
+fn decode_character_string(bytes: &[u8]) -> Result<String, CodecError> {
+    let (charset, text) = bytes
+        .split_first()
+        .ok_or_else(|| CodecError("missing character set".into()))?;
+    match charset {
+        // ANSI X3.4 / UTF-8
+        0 => Ok(String::from_utf8_lossy(text).into_owned()),
+        // ISO 8859-1
+        5 => Ok(text.iter().map(|b| *b as char).collect()),
+        // UCS-2
+        4 => Ok(char::decode_utf16(text.chunks(2).map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()),
+        other => malformed(format!("unsupported character set {}", other)),
+    }
+}
+
+fn decode_bit_string(bytes: &[u8]) -> Result<Vec<bool>, CodecError> {
+    let (unused, octets) = bytes
+        .split_first()
+        .ok_or_else(|| CodecError("missing bit string length".into()))?;
+    if *unused > 7 || (octets.is_empty() && *unused != 0) {
+        return malformed(format!("invalid unused bit count {}", unused));
+    }
+    let len = octets.len() * 8 - *unused as usize;
+    Ok((0..len).map(|i| octets[i / 8] & (0x80 >> (i % 8)) != 0).collect())
+}
+
+/// Decodes the service data of a ReadProperty request
+pub fn decode_read_property_request(data: &[u8]) -> Result<PropertyReference, CodecError> {
+    let mut reader = Reader::new(data);
+    let object = reader.read_context_object_id(0)?;
+    let property = reader.read_context_unsigned(1)?;
+    let array_index = reader.read_optional_context_unsigned(2)?;
+    Ok(PropertyReference { object, property, array_index })
+}
+
+/// A confirmed or unconfirmed COV notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct CovNotification {
+    pub process_id: u32,
+    pub device_id: u32,
+    pub object: ObjectRef,
+    /// Seconds left of the subscription, 0 if indefinite
+    pub time_remaining: u32,
+    /// The changed properties with their values
+    pub values: Vec<(u32, Vec<BacnetValue>)>,
+}
+
+/// Decodes the service data of a COV notification
+pub fn decode_cov_notification(data: &[u8]) -> Result<CovNotification, CodecError> {
+    let mut reader = Reader::new(data);
+    let process_id = reader.read_context_unsigned(0)?;
+    let device_id = reader.read_context_object_id(1)?.instance;
+    let object = reader.read_context_object_id(2)?;
+    let time_remaining = reader.read_context_unsigned(3)?;
+    reader.expect_opening(4)?;
+    let mut values = Vec::new();
+    while !reader.next_is_closing(4) {
+        let property = reader.read_context_unsigned(0)?;
+        reader.read_optional_context_unsigned(1)?;
+        reader.expect_opening(2)?;
+        values.push((property, decode_property_value(reader.read_enclosed(2)?)));
+        reader.read_optional_context_unsigned(3)?;
+    }
+    reader.expect_closing(4)?;
+    Ok(CovNotification { process_id, device_id, object, time_remaining, values })
+}
+
+/// Decodes the service data of a WriteProperty request
+pub fn decode_write_property_request(data: &[u8]) -> Result<WriteSpec, CodecError> {
+    let mut reader = Reader::new(data);
+    let object = reader.read_context_object_id(0)?;
+    let property = reader.read_context_unsigned(1)?;
+    let array_index = reader.read_optional_context_unsigned(2)?;
+    reader.expect_opening(3)?;
+    let value = reader.read_enclosed(3)?.to_vec();
+    let priority = reader.read_optional_context_unsigned(4)?.map(|p| p as u8);
+    Ok(WriteSpec {
+        reference: PropertyReference { object, property, array_index },
+        value,
+        priority,
+    })
+}
+
+/// Decodes the service data of a ReadPropertyMultiple request into
+/// `(object, [(property, array index)])` specifications
+pub fn decode_read_property_multiple_request(
+    data: &[u8],
+) -> Result<Vec<(ObjectRef, Vec<(u32, Option<u32>)>)>, CodecError> {
+    let mut reader = Reader::new(data);
+    let mut specs = Vec::new();
+    while !reader.is_empty() {
+        let object = reader.read_context_object_id(0)?;
+        reader.expect_opening(1)?;
+        let mut properties = Vec::new();
+        while !reader.next_is_closing(1) {
+            let property = reader.read_context_unsigned(0)?;
+            let array_index = reader.read_optional_context_unsigned(1)?;
+            properties.push((property, array_index));
+        }
+        reader.expect_closing(1)?;
+        specs.push((object, properties));
+    }
+    if specs.is_empty() {
+        return malformed("empty ReadPropertyMultiple request");
+    }
+    Ok(specs)
+}
+
+/// Decodes a ReadProperty-ACK, returning the reference and the raw
+/// application-encoded value octets
+pub fn decode_read_property_ack(data: &[u8]) -> Result<(PropertyReference, Vec<u8>), CodecError> {
+    let mut reader = Reader::new(data);
+    let object = reader.read_context_object_id(0)?;
+    let property = reader.read_context_unsigned(1)?;
+    let array_index = reader.read_optional_context_unsigned(2)?;
+    reader.expect_opening(3)?;
+    let value = reader.read_enclosed(3)?.to_vec();
+    Ok((PropertyReference { object, property, array_index }, value))
+}
+
+/// Decodes a ReadPropertyMultiple-ACK into the per-object property results,
+/// keeping access errors of individual properties
+pub fn decode_read_property_multiple_ack(data: &[u8]) -> Result<Vec<(ObjectRef, Vec<PropertyResult>)>, CodecError> {
+    let mut reader = Reader::new(data);
+    let mut results = Vec::new();
+    while !reader.is_empty() {
+        let object = reader.read_context_object_id(0)?;
+        reader.expect_opening(1)?;
+        let mut properties = Vec::new();
+        while !reader.next_is_closing(1) {
+            let property = reader.read_context_unsigned(2)?;
+            let array_index = reader.read_optional_context_unsigned(3)?;
+            let value = match reader.read_tag()? {
+                Tag { number: 4, context: true, kind: TagKind::Opening } => Ok(decode_property_value(reader.read_enclosed(4)?)),
+                Tag { number: 5, context: true, kind: TagKind::Opening } => {
+                    let class = read_application_enumerated(&mut reader)?;
+                    let code = read_application_enumerated(&mut reader)?;
+                    reader.expect_closing(5)?;
+                    Err(PropertyError { class, code })
+                }
+                other => return malformed(format!("expected property value or access error, found {:?}", other)),
+            };
+            properties.push(PropertyResult { property, array_index, value });
+        }
+        reader.expect_closing(1)?;
+        results.push((object, properties));
+    }
+    Ok(results)
+}
+
+/// Decodes the payload of an Error PDU: plain `class, code` or, for
+/// services like WritePropertyMultiple, a constructed error with the first
+/// failed property reference
+pub fn decode_error_payload(data: &[u8]) -> Result<(PropertyError, Option<PropertyReference>), CodecError> {
+    let mut reader = Reader::new(data);
+    let constructed = matches!(reader.peek_tag(), Some(Tag { number: 0, context: true, kind: TagKind::Opening }));
+    if constructed {
+        reader.expect_opening(0)?;
+    }
+    let class = read_application_enumerated(&mut reader)?;
+    let code = read_application_enumerated(&mut reader)?;
+    let mut first_failed = None;
+    if constructed {
+        reader.expect_closing(0)?;
+        if !reader.is_empty() {
+            reader.expect_opening(1)?;
+            let object = reader.read_context_object_id(0)?;
+            let property = reader.read_context_unsigned(1)?;
+            let array_index = reader.read_optional_context_unsigned(2)?;
+            reader.expect_closing(1)?;
+            first_failed = Some(PropertyReference { object, property, array_index });
+        }
+    }
+    Ok((PropertyError { class, code }, first_failed))
+}
+
+fn read_application_enumerated(reader: &mut Reader<'_>) -> Result<u32, CodecError> {
+    match reader.read_tag()? {
+        Tag { number: TAG_ENUMERATED, context: false, kind: TagKind::Value(len) } => decode_unsigned(reader.read_bytes(len)?),
+        other => malformed(format!("expected enumerated, found {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(value: &BacnetValue) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_application(&mut buf, value);
+        buf
+    }
+
+    fn round_trip(value: BacnetValue) {
+        let buf = encoded(&value);
+        assert_eq!(decode_application(&buf).unwrap(), (value, buf.len()));
+    }
+
+    #[test]
+    fn application_values_round_trip() {
+        for value in [
+            BacnetValue::Null,
+            BacnetValue::Boolean(true),
+            BacnetValue::Boolean(false),
+            BacnetValue::Unsigned(0),
+            BacnetValue::Unsigned(u32::MAX),
+            BacnetValue::Signed(i32::MIN),
+            BacnetValue::Real(21.5),
+            BacnetValue::Double(-0.125),
+            BacnetValue::OctetString(vec![1, 2, 3]),
+            BacnetValue::CharacterString("Zone 1 Temp".to_string()),
+            BacnetValue::BitString(vec![true, false, false, true, false, false, false, false, true]),
+            BacnetValue::Enumerated(3),
+            BacnetValue::Date([124, 3, 15, 5]),
+            BacnetValue::Time([12, 30, 0, 0]),
+            BacnetValue::ObjectId(ObjectRef::new(8, 1200)),
+        ] {
+            round_trip(value);
+        }
+        assert_eq!(encoded(&BacnetValue::Real(21.5)), vec![0x44, 0x41, 0xAC, 0x00, 0x00]);
+        assert_eq!(encoded(&BacnetValue::ObjectId(ObjectRef::new(2, 1))), vec![0xC4, 0x00, 0x80, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn extended_lengths_and_tag_numbers() {
+        let short = encoded(&BacnetValue::OctetString(vec![0; 253]));
+        assert_eq!(&short[..2], &[0x65, 253]);
+        let medium = encoded(&BacnetValue::OctetString(vec![0; 300]));
+        assert_eq!(&medium[..4], &[0x65, 254, 0x01, 0x2C]);
+        let long = encoded(&BacnetValue::OctetString(vec![0; 70_000]));
+        assert_eq!(&long[..6], &[0x65, 255, 0x00, 0x01, 0x11, 0x70]);
+        for buf in [short, medium, long] {
+            assert_eq!(decode_application(&buf).unwrap().1, buf.len());
+        }
+
+        let mut buf = Vec::new();
+        encode_tag(&mut buf, 20, true, 300);
+        assert_eq!(buf, vec![0xFD, 20, 254, 0x01, 0x2C]);
+        assert_eq!(decode_tag(&buf).unwrap(), (Tag { number: 20, context: true, kind: TagKind::Value(300) }, 5));
+        assert!(decode_tag(&[0x65, 254, 0x01]).is_err());
+        assert!(decode_tag(&[0xF9]).is_err());
+    }
+
+    #[test]
+    fn signed_values_use_the_fewest_octets() {
+        for (value, bytes) in [
+            (0, vec![0x00]),
+            (-1, vec![0xFF]),
+            (127, vec![0x7F]),
+            (128, vec![0x00, 0x80]),
+            (-128, vec![0x80]),
+            (-129, vec![0xFF, 0x7F]),
+            (i32::MIN, vec![0x80, 0x00, 0x00, 0x00]),
+        ] {
+            assert_eq!(signed_bytes(value), bytes, "{}", value);
+            round_trip(BacnetValue::Signed(value));
+        }
+        assert_eq!(encoded(&BacnetValue::Signed(-129)), vec![0x32, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn bit_strings_carry_their_unused_bits() {
+        assert_eq!(encoded(&BacnetValue::BitString(vec![true, false, true])), vec![0x82, 0x05, 0xA0]);
+        assert_eq!(encoded(&BacnetValue::BitString(Vec::new())), vec![0x81, 0x00]);
+        assert_eq!(decode_application(&[0x82, 0x05, 0xA0]).unwrap().0, BacnetValue::BitString(vec![true, false, true]));
+        assert_eq!(decode_application(&[0x81, 0x00]).unwrap().0, BacnetValue::BitString(Vec::new()));
+        assert!(decode_application(&[0x82, 0x08, 0xFF]).is_err(), "more than 7 unused bits");
+        assert!(decode_application(&[0x81, 0x03]).is_err(), "unused bits without octets");
+    }
+
+    #[test]
+    fn character_sets_are_decoded() {
+        let text = |bytes: &[u8]| decode_application(bytes).unwrap().0;
+        assert_eq!(encoded(&BacnetValue::CharacterString("Hé".to_string())), vec![0x74, 0x00, 0x48, 0xC3, 0xA9]);
+        // UCS-2
+        assert_eq!(text(&[0x75, 0x05, 0x04, 0x00, 0x48, 0x00, 0xE9]), BacnetValue::CharacterString("Hé".to_string()));
+        // ISO 8859-1
+        assert_eq!(text(&[0x73, 0x05, 0x48, 0xE9]), BacnetValue::CharacterString("Hé".to_string()));
+        assert!(decode_application(&[0x72, 0x01, 0x48]).is_err(), "IBM/Microsoft DBCS is not supported");
+        assert!(decode_application(&[0x70]).is_err(), "missing character set");
+    }
+
+    #[test]
+    fn date_and_time_wildcards_render_as_stars() {
+        assert_eq!(BacnetValue::Date([124, 3, 15, 5]).to_string(), "2024-03-15");
+        assert_eq!(BacnetValue::Date([WILDCARD, 6, WILDCARD, WILDCARD]).to_string(), "****-06-**");
+        assert_eq!(BacnetValue::Time([12, 30, WILDCARD, WILDCARD]).to_string(), "12:30:**.**");
+        assert_eq!(encoded(&BacnetValue::Date([WILDCARD, 6, WILDCARD, WILDCARD])), vec![0xA4, 0xFF, 0x06, 0xFF, 0xFF]);
+        round_trip(BacnetValue::Time([WILDCARD; 4]));
+    }
+
+    #[test]
+    fn read_enclosed_skips_nested_constructs() {
+        // [3] { [3] { unsigned 7 } boolean true [0] { [1] 5 } }
+        let data = [0x3E, 0x3E, 0x21, 0x07, 0x3F, 0x11, 0x0E, 0x19, 0x05, 0x0F, 0x3F];
+        let mut reader = Reader::new(&data);
+        reader.expect_opening(3).unwrap();
+        assert_eq!(reader.read_enclosed(3).unwrap(), &data[1..10]);
+        assert!(reader.is_empty());
+
+        let mut reader = Reader::new(&[0x3E, 0x0F]);
+        reader.expect_opening(3).unwrap();
+        assert!(reader.read_enclosed(3).is_err(), "mismatched closing tag");
+        let mut reader = Reader::new(&[0x3E, 0x0E, 0x21, 0x07]);
+        reader.expect_opening(3).unwrap();
+        assert!(reader.read_enclosed(3).is_err(), "unterminated");
+    }
+
+    #[test]
+    fn read_property_multiple_ack_keeps_access_errors() {
+        let object = ObjectRef::new(0, 1);
+        let failed = PropertyResult { property: 28, array_index: None, value: Err(PropertyError::UNKNOWN_PROPERTY) };
+        let fixture = [0x0C, 0x00, 0x00, 0x00, 0x01, 0x1E, 0x29, 0x1C, 0x5E, 0x91, 0x02, 0x91, 0x20, 0x5F, 0x1F];
+        assert_eq!(encode_read_property_multiple_ack(&[(object, vec![failed.clone()])]), fixture);
+        assert_eq!(decode_read_property_multiple_ack(&fixture).unwrap(), vec![(object, vec![failed.clone()])]);
+
+        let read = PropertyResult { property: 85, array_index: None, value: Ok(vec![BacnetValue::Real(21.5)]) };
+        let results = vec![(object, vec![read, failed])];
+        assert_eq!(decode_read_property_multiple_ack(&encode_read_property_multiple_ack(&results)).unwrap(), results);
+    }
+
+    #[test]
+    fn error_payloads_plain_and_constructed() {
+        assert_eq!(encode_error_apdu(3, 15, PropertyError::WRITE_ACCESS_DENIED), vec![0x50, 3, 15, 0x91, 0x02, 0x91, 0x28]);
+        assert_eq!(decode_error_payload(&[0x91, 0x01, 0x91, 0x1F]).unwrap(), (PropertyError::UNKNOWN_OBJECT, None));
+
+        // WritePropertyMultiple: [0] { class, code } [1] { AV:1 present-value }
+        let constructed = [0x0E, 0x91, 0x02, 0x91, 0x28, 0x0F, 0x1E, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x19, 0x55, 0x1F];
+        let reference = PropertyReference { object: ObjectRef::new(2, 1), property: 85, array_index: None };
+        assert_eq!(decode_error_payload(&constructed).unwrap(), (PropertyError::WRITE_ACCESS_DENIED, Some(reference)));
+        assert_eq!(decode_error_payload(&constructed[..6]).unwrap(), (PropertyError::WRITE_ACCESS_DENIED, None));
+        assert!(decode_error_payload(&constructed[..4]).is_err());
+    }
+}
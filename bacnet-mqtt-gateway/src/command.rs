@@ -0,0 +1,168 @@
+use crate::audit::AuditLog;
+use crate::bacnet::{BacnetEngine, BacnetError};
+use crate::batch::{self, BatchWrite, WriteStatus};
+use crate::codec;
+use crate::point::{ObjectRef, PointMetadata};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+fn default_property() -> u32 {
+    85
+}
+
+/// Command given as a JSON object instead of a bare value
+#[derive(Debug, Deserialize)]
+struct CommandObject {
+    value: serde_json::Value,
+    #[serde(default = "default_property")]
+    property: u32,
+    #[serde(default, rename = "type")]
+    value_type: Option<String>,
+    #[serde(default)]
+    priority: Option<u8>,
+}
+
+/// Parses a command payload: a bare JSON value (`21.5`, `true`, `null` to
+/// relinquish), plain text (`ON`, or a state text of the point such as
+/// `Occupied`) or `{"value": .., "type": .., "priority": ..}`, optionally
+/// with a `correlation_id` echoed on the result topic
+pub fn parse_command(
+    device_id: u32,
+    object: ObjectRef,
+    payload: &[u8],
+    metadata: Option<&PointMetadata>,
+) -> Result<BatchWrite, String> {
+    let text = std::str::from_utf8(payload).map_err(|_| "command payload is not UTF-8".to_string())?.trim();
+    let json = serde_json::from_str(text).unwrap_or_else(|_| serde_json::Value::String(text.to_string()));
+    let command = match json {
+        serde_json::Value::Object(_) => {
+            serde_json::from_value(json).map_err(|e| format!("invalid command object: {}", e))?
+        }
+        value => CommandObject { value, property: default_property(), value_type: None, priority: None },
+    };
+    if command.priority.is_some_and(|p| !(1..=16).contains(&p)) {
+        return Err(format!("priority {} is outside 1-16", command.priority.unwrap_or_default()));
+    }
+    // Select and switch entities send the state names they were discovered with
+    let state = match (&command.value, &command.value_type) {
+        (serde_json::Value::String(text), None) => metadata.and_then(|m| m.state_value(text)),
+        _ => None,
+    };
+    Ok(BatchWrite {
+        device_id,
+        object,
+        property: command.property,
+        array_index: None,
+        value: state.map_or(command.value, serde_json::Value::from),
+        value_type: command.value_type,
+        priority: command.priority,
+    })
+}
+
+/// Correlation ID of a command given as a JSON object, as text
+pub fn correlation_id(payload: &[u8]) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_slice(payload).ok()?;
+    match json.get("correlation_id")? {
+        serde_json::Value::String(id) => Some(id.clone()),
+        serde_json::Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+/// Why a command did not reach its point
+#[derive(Debug)]
+pub enum CommandError {
+    /// The payload or target was refused before anything was sent
+    Invalid(String),
+    Bacnet(BacnetError),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Invalid(e) => write!(f, "{}", e),
+            CommandError::Bacnet(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    Success,
+    Error,
+    Timeout,
+}
+
+/// Outcome of a command as published on its `.../result` topic
+#[derive(Debug, Serialize)]
+pub struct CommandResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object: Option<ObjectRef>,
+    pub status: ResultStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub timestamp: String,
+}
+
+impl CommandResult {
+    pub fn new(correlation_id: Option<String>, target: Option<(u32, ObjectRef)>, result: &Result<(), CommandError>) -> Self {
+        let status = match result {
+            Ok(()) => ResultStatus::Success,
+            Err(CommandError::Bacnet(BacnetError::Timeout)) => ResultStatus::Timeout,
+            Err(_) => ResultStatus::Error,
+        };
+        let (error_class, error_code) = match result {
+            Err(CommandError::Bacnet(BacnetError::Error { class, code, .. })) => (
+                Some(codec::error_class_name(*class)),
+                Some(codec::error_code_name(*code)),
+            ),
+            _ => (None, None),
+        };
+        Self {
+            correlation_id,
+            device_id: target.map(|(device_id, _)| device_id),
+            object: target.map(|(_, object)| object),
+            status,
+            error_class,
+            error_code,
+            message: result.as_ref().err().map(ToString::to_string),
+            timestamp: crate::mqtt::utc_timestamp(),
+        }
+    }
+}
+
+/// Writes a command received on an MQTT command topic to its device and
+/// records it in the audit log
+pub async fn execute(
+    engine: &BacnetEngine,
+    devices: &HashMap<u32, SocketAddr>,
+    audit: &AuditLog,
+    topic: &str,
+    write: BatchWrite,
+) -> Result<(), CommandError> {
+    let device_id = write.device_id;
+    let addr = devices.get(&device_id).copied();
+    let before = audit.before(engine, addr, &write).await;
+    let result = match addr {
+        Some(addr) => match batch::encode_write(&write) {
+            Ok(spec) => engine.write_property(addr, &spec).await.map_err(CommandError::Bacnet),
+            Err(e) => Err(CommandError::Invalid(e)),
+        },
+        None => Err(CommandError::Invalid(format!("device {} has not been discovered", device_id))),
+    };
+    let status = if result.is_ok() { WriteStatus::Written } else { WriteStatus::Failed };
+    let error = result.as_ref().err().map(ToString::to_string);
+    audit.record(topic, &write, before, status, error.as_deref()).await;
+    result
+}
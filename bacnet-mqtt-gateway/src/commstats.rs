@@ -0,0 +1,126 @@
+//! Per-device counters of the confirmed requests sent to each address, for
+//! spotting flaky controllers on large networks
+
+use crate::bacnet::BacnetError;
+use crate::mqtt::utc_timestamp;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Answers the latency figures are computed over
+const LATENCY_WINDOW: usize = 256;
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: u64,
+    acks: u64,
+    errors: u64,
+    timeouts: u64,
+    latencies: VecDeque<Duration>,
+    last_error: Option<(String, String)>,
+}
+
+/// Communication with one device since startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeviceCommStats {
+    pub requests: u64,
+    /// Requests answered with an acknowledgement
+    pub acks: u64,
+    /// Requests answered with an Error, Reject or Abort PDU, or not sent at all
+    pub errors: u64,
+    /// Requests left unanswered after every retry
+    pub timeouts: u64,
+    /// Round trip of the last answers, from their latest transmission
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99_latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<String>,
+}
+
+/// Nearest-rank percentile of sorted latencies
+fn percentile(sorted: &[Duration], percent: usize) -> Option<u64> {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted.get(rank - 1).map(|latency| latency.as_millis() as u64)
+}
+
+#[derive(Debug, Default)]
+pub struct CommStats {
+    devices: Mutex<HashMap<SocketAddr, Counters>>,
+}
+
+impl CommStats {
+    fn update(&self, target: SocketAddr, update: impl FnOnce(&mut Counters)) {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        update(devices.entry(target).or_default());
+    }
+
+    pub fn sent(&self, target: SocketAddr) {
+        self.update(target, |counters| counters.requests += 1);
+    }
+
+    /// Records the answer to a request, `error` set for an Error, Reject or Abort
+    pub fn answered(&self, target: SocketAddr, latency: Duration, error: Option<&BacnetError>) {
+        self.update(target, |counters| {
+            if counters.latencies.len() == LATENCY_WINDOW {
+                counters.latencies.pop_front();
+            }
+            counters.latencies.push_back(latency);
+            match error {
+                Some(error) => {
+                    counters.errors += 1;
+                    counters.last_error = Some((error.to_string(), utc_timestamp()));
+                }
+                None => counters.acks += 1,
+            }
+        });
+    }
+
+    /// Records a request that could not be sent
+    pub fn failed(&self, target: SocketAddr, error: &BacnetError) {
+        self.update(target, |counters| {
+            counters.errors += 1;
+            counters.last_error = Some((error.to_string(), utc_timestamp()));
+        });
+    }
+
+    pub fn timed_out(&self, target: SocketAddr) {
+        self.update(target, |counters| {
+            counters.timeouts += 1;
+            counters.last_error = Some((BacnetError::Timeout.to_string(), utc_timestamp()));
+        });
+    }
+
+    /// Counters of the device at `target`, all zero if nothing was sent to it
+    pub fn of(&self, target: SocketAddr) -> DeviceCommStats {
+        let devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(counters) = devices.get(&target) else {
+            return DeviceCommStats::default();
+        };
+        let mut sorted: Vec<Duration> = counters.latencies.iter().copied().collect();
+        sorted.sort();
+        let (last_error, last_error_at) = counters.last_error.clone().unzip();
+        DeviceCommStats {
+            requests: counters.requests,
+            acks: counters.acks,
+            errors: counters.errors,
+            timeouts: counters.timeouts,
+            avg_latency_ms: (!sorted.is_empty())
+                .then(|| sorted.iter().map(|latency| latency.as_secs_f64() * 1000.0).sum::<f64>() / sorted.len() as f64),
+            p50_latency_ms: percentile(&sorted, 50),
+            p95_latency_ms: percentile(&sorted, 95),
+            p99_latency_ms: percentile(&sorted, 99),
+            last_error,
+            last_error_at,
+        }
+    }
+}
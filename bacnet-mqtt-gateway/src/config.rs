@@ -1,30 +1,1442 @@
+use crate::locale::LocaleConfig;
+use crate::point::ObjectRef;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Maps keyed by device instances or unit numbers, written with string keys
+/// since TOML has no others and read from strings or integers
+mod numeric_keys {
+    use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+    use serde::{Serialize, Serializer};
+    use std::fmt::{self, Display};
+    use std::marker::PhantomData;
+    use std::str::FromStr;
+
+    pub fn serialize<'a, K, V, M, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Display + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_map(map.into_iter().map(|(key, value)| (key.to_string(), value)))
+    }
+
+    pub fn deserialize<'de, K, V, M, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        K: FromStr + TryFrom<u64>,
+        V: Deserialize<'de>,
+        M: Default + Extend<(K, V)>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(Entries::<K, V, M>(PhantomData))
+    }
+
+    struct Entries<K, V, M>(PhantomData<(K, V, M)>);
+
+    impl<'de, K, V, M> Visitor<'de> for Entries<K, V, M>
+    where
+        K: FromStr + TryFrom<u64>,
+        V: Deserialize<'de>,
+        M: Default + Extend<(K, V)>,
+    {
+        type Value = M;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map with numeric keys")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<M, A::Error> {
+            let mut map = M::default();
+            while let Some((Key(key), value)) = access.next_entry::<Key<K>, V>()? {
+                map.extend([(key, value)]);
+            }
+            Ok(map)
+        }
+    }
+
+    struct Key<K>(K);
+
+    impl<'de, K: FromStr + TryFrom<u64>> Deserialize<'de> for Key<K> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(KeyVisitor(PhantomData)).map(Key)
+        }
+    }
+
+    struct KeyVisitor<K>(PhantomData<K>);
+
+    impl<K: FromStr + TryFrom<u64>> Visitor<'_> for KeyVisitor<K> {
+        type Value = K;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a non-negative number")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<K, E> {
+            K::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<K, E> {
+            value.parse().map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct GatewayConfig {
     pub bacnet: BacnetConfig,
     pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub web: WebConfig,
+    /// Language of generated entity names and labels
+    #[serde(default)]
+    pub locale: LocaleConfig,
+    #[serde(default)]
+    pub polling: PollingConfig,
+    /// Devices with their own points and poll intervals; unlisted devices
+    /// have the `poll_object` polled at the default interval
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    /// Discovered devices and objects to poll and publish
+    #[serde(default)]
+    pub device_filters: DeviceFilterConfig,
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceWindowConfig>,
+    #[serde(default)]
+    pub alarms: AlarmConfig,
+    /// Supervisory heartbeat writes to controllers that fall back to standalone
+    /// mode without them
+    #[serde(default)]
+    pub heartbeats: Vec<HeartbeatConfig>,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Sharding of device ownership between gateway instances
+    #[serde(default)]
+    pub cluster: Option<ClusterConfig>,
+    /// Points of a controller combined into a Home Assistant thermostat
+    #[serde(default)]
+    pub climates: Vec<ClimateConfig>,
+    /// Blind and damper actuators positioned through an AV
+    #[serde(default)]
+    pub covers: Vec<CoverConfig>,
+    /// Fans switched through a BO, optionally with an MSV speed
+    #[serde(default)]
+    pub fans: Vec<FanConfig>,
+    /// Validating a configuration against a live building without touching it
+    #[serde(default)]
+    pub dry_run: DryRunConfig,
+    /// The device registry saved across restarts
+    #[serde(default)]
+    pub persistence: PersistenceConfig,
+    /// Short-term history of published values, kept in SQLite
+    #[serde(default)]
+    pub historian: HistorianConfig,
+    /// Point values written to InfluxDB alongside MQTT
+    #[serde(default)]
+    pub influxdb: Option<InfluxConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// JSON file the discovered devices, their object lists and point metadata
+    /// are saved to and restored from at startup; nothing is saved if unset
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Seconds between saves
+    #[serde(default = "default_persistence_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self { file: None, interval_secs: default_persistence_interval_secs() }
+    }
+}
+
+fn default_persistence_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HistorianConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// SQLite database the values are stored in
+    #[serde(default = "default_historian_file")]
+    pub file: PathBuf,
+    /// Hours values are kept before they are pruned
+    #[serde(default = "default_historian_retention_hours")]
+    pub retention_hours: u64,
+}
+
+impl Default for HistorianConfig {
+    fn default() -> Self {
+        Self { enabled: false, file: default_historian_file(), retention_hours: default_historian_retention_hours() }
+    }
+}
+
+fn default_historian_file() -> PathBuf {
+    PathBuf::from("history.sqlite")
+}
+
+fn default_historian_retention_hours() -> u64 {
+    7 * 24
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InfluxApi {
+    /// `/write` of InfluxDB 1.x, with `username` and `password`
+    V1,
+    /// `/api/v2/write` of InfluxDB 2.x and 3.x, with `org` and `token`
+    #[default]
+    V2,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxConfig {
+    /// Server URL, e.g. `http://influxdb:8086`
+    pub url: String,
+    #[serde(default)]
+    pub api: InfluxApi,
+    /// Bucket of the v2 API, database of the v1 API
+    pub bucket: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// Sent as `Authorization: Token <token>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    /// Sent with `password` as `Authorization: Basic ...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    #[serde(default = "default_influx_measurement")]
+    pub measurement: String,
+    /// `site` tag of every point, the topics' `site` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    /// Points written per request; a full batch is written right away
+    #[serde(default = "default_influx_batch_size")]
+    pub batch_size: usize,
+    /// Seconds between writes of a partial batch
+    #[serde(default = "default_influx_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+}
+
+fn default_influx_measurement() -> String {
+    "bacnet".to_string()
+}
+
+fn default_influx_batch_size() -> usize {
+    1000
+}
+
+fn default_influx_flush_interval_secs() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DryRunConfig {
+    /// Block every write to BACnet devices, logging what would have been
+    /// written; also set by the `--dry-run` flag
+    #[serde(default)]
+    pub enabled: bool,
+    /// In dry-run mode, log MQTT messages instead of publishing them
+    #[serde(default)]
+    pub suppress_publishes: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub instance: u32,
+    /// Registered at startup instead of waiting for the device's I-Am
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<SocketAddr>,
+    /// Points polled on the device, the `poll_object` when empty
+    #[serde(default)]
+    pub points: Vec<PointConfig>,
+    /// Poll interval of the device's points without one of their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    /// Name shown for the device instead of its instance, e.g. `AHU-1`;
+    /// also resolves `{device_name}` in topics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Names shown for the device's objects, e.g. `AI:3: Supply Air Temp`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub object_aliases: BTreeMap<ObjectRef, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PointConfig {
+    pub object: ObjectRef,
+    /// Properties read on every poll, present-value (85) by default
+    #[serde(default = "default_point_properties")]
+    pub properties: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+}
+
+fn default_point_properties() -> Vec<u32> {
+    vec![85]
+}
+
+/// Anything allowed (everything when `allow` is empty) and not denied is polled
+/// and published. Devices denied by instance range or subnet alone, e.g. lab
+/// devices `99000-99999`, are not even registered
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceFilterConfig {
+    #[serde(default)]
+    pub allow: Vec<FilterRule>,
+    #[serde(default)]
+    pub deny: Vec<FilterRule>,
+}
+
+/// Matches when every criterion given matches
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRule {
+    /// Device instance or inclusive range, e.g. `1000-1999`
+    #[serde(default)]
+    pub instances: Option<String>,
+    /// Network of the device's address, e.g. `10.20.0.0/16`
+    #[serde(default)]
+    pub subnet: Option<String>,
+    /// Object types such as `AI` or `8`; a rule listing types only matches objects
+    #[serde(default)]
+    pub object_types: Vec<String>,
+    /// Glob with `*` and `?` matched against the device's object name
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CoverConfig {
+    pub device: u32,
+    pub name: String,
+    /// Position in percent, 0 closed and 100 open
+    pub position: ObjectRef,
+    /// Home Assistant device class, e.g. `blind`, `shade` or `damper`
+    #[serde(default)]
+    pub device_class: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FanConfig {
+    pub device: u32,
+    pub name: String,
+    /// Start/stop command, typically a BO
+    pub command: ObjectRef,
+    /// Speed selection, typically an MSV
+    #[serde(default)]
+    pub speed: Option<ObjectRef>,
+    /// Preset mode name (`low`, `high`, ...) to the state of the speed object
+    #[serde(default)]
+    pub speeds: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClimateConfig {
+    pub device: u32,
+    pub name: String,
+    /// Zone temperature, typically an AI
+    pub temperature: ObjectRef,
+    /// Writable setpoint, typically an AV
+    pub setpoint: ObjectRef,
+    /// Operating mode, typically an MSV
+    #[serde(default)]
+    pub mode: Option<ObjectRef>,
+    /// Home Assistant HVAC mode (`off`, `heat`, `cool`, `auto`, ...) to the
+    /// state of the mode object
+    #[serde(default)]
+    pub modes: BTreeMap<String, u32>,
+    /// Priority setpoint and mode writes are made at
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// `C` or `F`, Home Assistant assumes its own unit system otherwise
+    #[serde(default)]
+    pub temperature_unit: Option<String>,
+    #[serde(default)]
+    pub min_temp: Option<f64>,
+    #[serde(default)]
+    pub max_temp: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// Unique name of this instance within the cluster
+    pub node_id: String,
+    /// Interval of the membership heartbeats over MQTT
+    #[serde(default = "default_cluster_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    /// Devices pinned to a node, hashed across the live members while it is down
+    #[serde(default, with = "numeric_keys")]
+    pub assignments: HashMap<u32, String>,
+}
+
+fn default_cluster_heartbeat_secs() -> u64 {
+    10
+}
+
+/// Record of the writes made to devices through MQTT commands and the REST API
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Read each written property first to record the value it replaced
+    #[serde(default)]
+    pub read_before_write: bool,
+    /// JSON lines file entries are appended to, besides the MQTT audit topic
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct BacnetConfig {
     pub device_id: u32,
     pub bind_addr: SocketAddr,
+    /// Further BACnet/IP interfaces, e.g. a second segment on another NIC,
+    /// bridged by the same gateway device
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceConfig>,
     pub vendor_name: String,
     pub model_name: String,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    /// Object whose present-value is polled on devices without configured points
+    #[serde(default = "default_poll_object")]
+    pub poll_object: ObjectRef,
+    /// Time to wait for the answer to a confirmed request before retrying
+    #[serde(default = "default_apdu_timeout_ms")]
+    pub apdu_timeout_ms: u64,
+    /// Retransmissions of an unanswered confirmed request
+    #[serde(default = "default_apdu_retries")]
+    pub apdu_retries: u32,
+    #[serde(default)]
+    pub apdu_backoff: Backoff,
+    /// Rebuild the datalink when no frame arrived for this long while requests
+    /// kept timing out, 0 disables the watchdog
+    #[serde(default = "default_receive_watchdog_secs")]
+    pub receive_watchdog_secs: u64,
+    /// NPDU network priority of each kind of traffic the gateway originates
+    #[serde(default)]
+    pub network_priority: NetworkPriorityConfig,
+    /// Timing overrides keyed by device instance, e.g. for slow MS/TP devices behind routers
+    #[serde(default, with = "numeric_keys")]
+    pub device_timing: HashMap<u32, DeviceTiming>,
+    /// Workarounds applied to the devices of a vendor, optionally of one model
+    #[serde(default)]
+    pub quirks: Vec<QuirkProfile>,
+    /// MQTT topics exposed as objects of the gateway's own BACnet device
+    #[serde(default)]
+    pub virtual_objects: Vec<VirtualObjectConfig>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InterfaceConfig {
+    /// Name events and devices are tagged with
+    pub name: String,
+    pub bind_addr: SocketAddr,
+    /// Network of the devices behind the interface, used to reach a device
+    /// before it was heard on any interface
+    #[serde(default)]
+    pub subnet: Option<String>,
+}
+
+fn default_poll_object() -> ObjectRef {
+    ObjectRef::new(0, 0)
+}
+
+fn default_apdu_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_apdu_retries() -> u32 {
+    3
+}
+
+fn default_receive_watchdog_secs() -> u64 {
+    120
+}
+
+/// How the timeout grows between retries of the same request
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backoff {
+    /// Every attempt waits `apdu_timeout_ms`, as the BACnet standard prescribes
+    #[default]
+    Fixed,
+    /// Attempt n waits n times the timeout
+    Linear,
+    /// Each attempt doubles the previous wait
+    Exponential,
+}
+
+/// NPDU network priority, from lowest to highest
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkPriority {
+    #[default]
+    Normal,
+    Urgent,
+    CriticalEquipment,
+    LifeSafety,
+}
+
+impl NetworkPriority {
+    /// Value of the two priority bits of the NPDU control octet
+    pub fn bits(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Network priority per traffic class; replies to requests served by the
+/// gateway always carry the priority of the request
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkPriorityConfig {
+    /// Who-Is and I-Am
+    #[serde(default)]
+    pub discovery: NetworkPriority,
+    /// Polls and other reads
+    #[serde(default)]
+    pub polls: NetworkPriority,
+    /// WriteProperty and WritePropertyMultiple, including heartbeats and commands
+    #[serde(default)]
+    pub writes: NetworkPriority,
+    /// Alarm and event services such as AcknowledgeAlarm
+    #[serde(default)]
+    pub alarms: NetworkPriority,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceTiming {
+    pub apdu_timeout_ms: Option<u64>,
+    pub apdu_retries: Option<u32>,
+    pub apdu_backoff: Option<Backoff>,
+}
+
+/// Workarounds for the devices of one vendor whose BACnet stack misbehaves
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuirkProfile {
+    pub name: String,
+    /// Vendor identifier announced in the I-Am
+    pub vendor_id: u32,
+    /// Glob on the device's Model_Name, any model of the vendor if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Never send ReadPropertyMultiple, reading property by property instead
+    #[serde(default)]
+    pub disable_rpm: bool,
+    /// Ask for one property per ReadPropertyMultiple request
+    #[serde(default)]
+    pub single_property_reads: bool,
+    /// Largest reply the device is asked for, one of 50, 128, 206, 480, 1024 or 1476 octets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_apdu: Option<u16>,
+    /// Minimum pause between two requests to the device
+    #[serde(default)]
+    pub request_delay_ms: u64,
+    /// Engineering units reported by the device to the units they stand for
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", with = "numeric_keys")]
+    pub unit_remap: BTreeMap<u32, u32>,
+}
+
+/// Reply sizes a confirmed request can announce it accepts
+pub const MAX_APDU_SIZES: [u16; 6] = [50, 128, 206, 480, 1024, 1476];
+
+/// Effective retransmission settings of confirmed requests to one device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApduPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Backoff,
+}
+
+impl ApduPolicy {
+    /// Wait before giving up on attempt `attempt` (0 is the first transmission)
+    pub fn timeout_for(&self, attempt: u32) -> Duration {
+        match self.backoff {
+            Backoff::Fixed => self.timeout,
+            Backoff::Linear => self.timeout * (attempt + 1),
+            Backoff::Exponential => self.timeout * 2u32.pow(attempt.min(16)),
+        }
+    }
+
+    /// Longest time a request can stay unanswered including every retry
+    pub fn total(&self) -> Duration {
+        (0..=self.retries).map(|attempt| self.timeout_for(attempt)).sum()
+    }
+}
+
+impl BacnetConfig {
+    /// Retransmission settings for a device, applying its overrides if any
+    pub fn apdu_policy(&self, device_id: Option<u32>) -> ApduPolicy {
+        let timing = device_id.and_then(|id| self.device_timing.get(&id));
+        ApduPolicy {
+            timeout: Duration::from_millis(timing.and_then(|t| t.apdu_timeout_ms).unwrap_or(self.apdu_timeout_ms)),
+            retries: timing.and_then(|t| t.apdu_retries).unwrap_or(self.apdu_retries),
+            backoff: timing.and_then(|t| t.apdu_backoff).unwrap_or(self.apdu_backoff),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Broadcast Who-Is at startup and periodically. When disabled only the
+    /// configured devices are registered and polled, and Who-Is goes to
+    /// their addresses alone, for sites that forbid broadcasts
+    #[serde(default = "default_auto_discovery")]
+    pub enabled: bool,
+    /// Seconds between periodic rediscovery, 0 disables it
+    #[serde(default = "default_discovery_interval_secs")]
+    pub interval_secs: u64,
+    /// Optional device instance range limits, both or neither
+    #[serde(default)]
+    pub low_limit: Option<u32>,
+    #[serde(default)]
+    pub high_limit: Option<u32>,
+    /// Directed broadcast addresses (e.g. 192.168.1.255:47808), empty means global broadcast
+    #[serde(default)]
+    pub broadcast_targets: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualObjectConfig {
+    /// Hosted object, an Analog Value (AV:n) or Binary Value (BV:n)
+    pub object: ObjectRef,
+    pub name: String,
+    /// Topic whose payloads update the object's present-value
+    pub topic: String,
+    /// Allow BACnet clients to write the present-value
+    #[serde(default)]
+    pub writable: bool,
+    /// Topic BACnet writes are published to, defaults to `topic`
+    #[serde(default)]
+    pub command_topic: Option<String>,
+    /// BACnet engineering units enumeration for analog values
+    #[serde(default)]
+    pub units: Option<u32>,
+}
+
+fn default_auto_discovery() -> bool {
+    true
+}
+
+fn default_discovery_interval_secs() -> u64 {
+    300
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: default_discovery_interval_secs(),
+            low_limit: None,
+            high_limit: None,
+            broadcast_targets: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PollingConfig {
+    /// Poll interval of points without one configured
+    #[serde(default = "default_poll_interval_secs")]
+    pub interval_secs: u64,
+    /// Named sets of device instances that can be suspended together
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<u32>>,
+    /// File the suspended devices/groups are persisted to
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
+    /// Interval of the status refresh re-reading value, status flags, units and
+    /// name of each polled point, 0 disables it
+    #[serde(default = "default_status_refresh_secs")]
+    pub status_refresh_secs: u64,
+    /// Unanswered polls in a row after which a device is reported offline
+    #[serde(default = "default_offline_after_failures")]
+    pub offline_after_failures: u32,
+    /// Time a device may stay offline before its Home Assistant entities are
+    /// removed, 0 keeps them; they return once the device answers again
+    #[serde(default)]
+    pub retract_discovery_after_secs: u64,
+    /// Age after which a point's last value is reported stale, 0 for three
+    /// of its poll intervals
+    #[serde(default)]
+    pub stale_after_secs: u64,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    10
+}
+
+fn default_state_file() -> PathBuf {
+    PathBuf::from("gateway-state.json")
+}
+
+fn default_status_refresh_secs() -> u64 {
+    300
+}
+
+fn default_offline_after_failures() -> u32 {
+    3
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_poll_interval_secs(),
+            groups: HashMap::new(),
+            state_file: default_state_file(),
+            status_refresh_secs: default_status_refresh_secs(),
+            offline_after_failures: default_offline_after_failures(),
+            retract_discovery_after_secs: 0,
+            stale_after_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceMode {
+    /// Skip polling while the window is open, so rebooting controllers raise no offline transitions
+    #[default]
+    Suppress,
+    /// Keep polling but tag published values with the window name
+    Tag,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindowConfig {
+    pub name: String,
+    /// Start times as a five field cron expression in UTC, e.g. "0 3 * * 0" for Sundays 03:00
+    pub schedule: String,
+    pub duration_mins: u64,
+    /// Devices and polling groups covered, both empty means every device
+    #[serde(default)]
+    pub devices: Vec<u32>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub mode: MaintenanceMode,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmConfig {
+    /// Alarm sources shelved from startup, e.g. a sensor known to be broken
+    #[serde(default)]
+    pub shelved: Vec<ShelvedAlarmConfig>,
+    /// Alarms changing state more often than this per hour are held back as
+    /// chattering until they settle, 0 disables the check
+    #[serde(default)]
+    pub max_transitions_per_hour: u32,
+    #[serde(default)]
+    pub escalation: EscalationConfig,
+}
+
+/// One object of one device
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmSourceRef {
+    pub device: u32,
+    pub object: ObjectRef,
+}
+
+/// Re-notification and escalation of active, unacknowledged critical alarms
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EscalationConfig {
+    /// Critical alarm sources, empty treats every alarm as critical
+    #[serde(default)]
+    pub critical: Vec<AlarmSourceRef>,
+    /// Delay before the first re-notification, 0 disables re-notifying
+    #[serde(default)]
+    pub renotify_after_mins: u64,
+    /// Factor each further re-notification delay grows by
+    #[serde(default = "default_renotify_factor")]
+    pub renotify_factor: f64,
+    /// Longest delay between re-notifications
+    #[serde(default = "default_max_renotify_mins")]
+    pub max_renotify_mins: u64,
+    /// Time an alarm may stay unacknowledged before it is escalated, 0 disables escalation
+    #[serde(default)]
+    pub escalate_after_mins: u64,
+    /// MQTT topic escalations are published to
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// `http://` URL escalations are POSTed to as JSON
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+}
+
+fn default_renotify_factor() -> f64 {
+    2.0
+}
+
+fn default_max_renotify_mins() -> u64 {
+    240
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        Self {
+            critical: Vec::new(),
+            renotify_after_mins: 0,
+            renotify_factor: default_renotify_factor(),
+            max_renotify_mins: default_max_renotify_mins(),
+            escalate_after_mins: 0,
+            topic: None,
+            webhook_url: None,
+        }
+    }
+}
+
+impl EscalationConfig {
+    /// Delay before re-notification number `count` (0 = first)
+    pub fn renotify_delay(&self, count: u32) -> Duration {
+        let factor = self.renotify_factor.max(1.0).powi(count.min(32) as i32);
+        let mins = (self.renotify_after_mins as f64 * factor).min(self.max_renotify_mins.max(self.renotify_after_mins) as f64);
+        Duration::from_secs_f64(mins * 60.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ShelvedAlarmConfig {
+    pub device: u32,
+    pub object: ObjectRef,
+    /// Shelving period, indefinitely if not set
+    #[serde(default)]
+    pub hours: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub device: u32,
+    /// Written object, typically an AV or BV the controller supervises
+    pub object: ObjectRef,
+    #[serde(default = "default_heartbeat_interval_secs")]
+    pub interval_secs: u64,
+    /// Values written in turn
+    #[serde(default = "default_heartbeat_values")]
+    pub values: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// Consecutive failed writes before the heartbeat is reported as failed
+    #[serde(default = "default_heartbeat_max_failures")]
+    pub max_failures: u32,
+}
+
+fn default_heartbeat_interval_secs() -> u64 {
+    60
+}
+
+fn default_heartbeat_values() -> Vec<serde_json::Value> {
+    vec![0.into(), 1.into()]
+}
+
+fn default_heartbeat_max_failures() -> u32 {
+    3
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct MqttConfig {
     pub broker_host: String,
     pub broker_port: u16,
+    /// Credentials may reference environment variables as `${NAME}`
     pub username: Option<String>,
     pub password: Option<String>,
+    /// File holding the password, e.g. `/run/secrets/mqtt_password`
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    /// Stable client ID, needed for persistent sessions; `bacnet-gateway-<pid>` otherwise
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// False asks brokers to keep the session, subscriptions and queued
+    /// messages across reconnects and restarts
+    #[serde(default = "default_clean_session")]
+    pub clean_session: bool,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub profile: BrokerProfile,
+    #[serde(default)]
+    pub aws: AwsIotConfig,
+    #[serde(default)]
+    pub azure: AzureIotHubConfig,
+    /// Further brokers receiving the same stream, each with its own
+    /// connection, availability and offline buffer
+    #[serde(default)]
+    pub brokers: Vec<BrokerConfig>,
     pub discovery_prefix: String,
     pub base_topic: String,
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+    #[serde(default)]
+    pub mode: MqttMode,
+    #[serde(default)]
+    pub sparkplug: SparkplugConfig,
+    #[serde(default)]
+    pub homie: HomieConfig,
+    #[serde(default)]
+    pub publish: PublishConfig,
+    #[serde(default)]
+    pub topics: TopicConfig,
+    #[serde(default)]
+    pub naming: NamingConfig,
+    /// Per-point overrides of the derived Home Assistant entity fields
+    #[serde(default)]
+    pub entities: Vec<EntityOverride>,
+    /// Priority switch commands on binary outputs and values are written at,
+    /// unless the command names one; the device's default (16) otherwise
+    #[serde(default)]
+    pub switch_priority: Option<u8>,
+    #[serde(default)]
+    pub offline_buffer: OfflineBufferConfig,
+    /// Deadband and rate limits of polled point states
+    #[serde(default)]
+    pub filters: Vec<PublishFilterConfig>,
+    #[serde(default)]
+    pub snapshot: SnapshotMode,
+    /// Rhai scripts applied to point values or state payloads before publishing
+    #[serde(default)]
+    pub transforms: Vec<TransformConfig>,
+}
+
+/// A transform script and what it applies to: the points matching `device`
+/// and `object`, or, with `topic`, state payloads on matching topics
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<ObjectRef>,
+    /// MQTT topic filter, `+` and `#` allowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_file: Option<PathBuf>,
+}
+
+/// Per-device documents with every value of a poll cycle on `<base>/<device>/snapshot`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMode {
+    #[default]
+    Off,
+    /// Snapshots and the per-point state topics
+    Alongside,
+    /// Snapshots only; Home Assistant entities read the per-point topics and
+    /// stay unknown
+    Instead,
+}
+
+/// Publish filter of the points it matches; without `device` or `object` it
+/// applies to every device or object, the most specific match wins
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PublishFilterConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<ObjectRef>,
+    /// Change of a numeric value needed before it is published again; other
+    /// values are published on any change
+    #[serde(default)]
+    pub deadband: f64,
+    /// Seconds a change waits after the last publish at least
+    #[serde(default)]
+    pub min_interval_secs: u64,
+    /// Seconds after which the value is republished even if unchanged, 0 never
+    #[serde(default)]
+    pub max_interval_secs: u64,
+}
+
+fn default_clean_session() -> bool {
+    true
+}
+
+/// An additional broker the gateway publishes to
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BrokerConfig {
+    /// Name used in logs
+    pub name: String,
+    pub broker_host: String,
+    pub broker_port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    /// Client ID on this broker, the primary broker's otherwise
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub profile: BrokerProfile,
+    #[serde(default)]
+    pub aws: AwsIotConfig,
+    #[serde(default)]
+    pub azure: AzureIotHubConfig,
+    /// Subscribe to commands and requests on this broker too, not only publish to it
+    #[serde(default)]
+    pub accept_commands: bool,
+}
+
+/// Restrictions of the service running a broker
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrokerProfile {
+    #[default]
+    Standard,
+    /// AWS IoT Core: mutual TLS, ALPN on port 443, no retained messages and
+    /// at most 7 slashes per topic
+    AwsIot,
+    /// Azure IoT Hub: the client ID is the device ID, SAS token credentials,
+    /// no retained messages, subscriptions only to the device twin
+    AzureIotHub,
+}
+
+/// Device shadow retained messages are mirrored into on AWS IoT Core
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AwsIotConfig {
+    /// Thing the shadow belongs to, the client ID by default
+    #[serde(default)]
+    pub thing_name: Option<String>,
+    #[serde(default = "default_aws_shadow_name")]
+    pub shadow_name: String,
+}
+
+fn default_aws_shadow_name() -> String {
+    "bacnet-gateway".to_string()
+}
+
+impl Default for AwsIotConfig {
+    fn default() -> Self {
+        Self { thing_name: None, shadow_name: default_aws_shadow_name() }
+    }
+}
+
+/// Device credentials on Azure IoT Hub
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AzureIotHubConfig {
+    /// Base64 symmetric key of the device
+    #[serde(default)]
+    pub shared_access_key: String,
+    /// File holding the key instead
+    #[serde(default)]
+    pub shared_access_key_file: Option<PathBuf>,
+    /// Lifetime of the SAS tokens; the hub disconnects when one expires and
+    /// the gateway reconnects with a new one
+    #[serde(default = "default_azure_token_ttl_secs")]
+    pub token_ttl_secs: u64,
+}
+
+fn default_azure_token_ttl_secs() -> u64 {
+    3600
+}
+
+impl Default for AzureIotHubConfig {
+    fn default() -> Self {
+        Self {
+            shared_access_key: String::new(),
+            shared_access_key_file: None,
+            token_ttl_secs: default_azure_token_ttl_secs(),
+        }
+    }
+}
+
+/// TLS of a broker connection, PEM files
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub ca_file: PathBuf,
+    /// Client certificate and key for brokers authenticating clients by certificate
+    #[serde(default)]
+    pub client_cert_file: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key_file: Option<PathBuf>,
+}
+
+/// Queue of state messages published while the broker is unreachable
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct OfflineBufferConfig {
+    /// Messages kept at most, 0 drops everything published while disconnected
+    #[serde(default = "default_offline_buffer_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+fn default_offline_buffer_capacity() -> usize {
+    10_000
+}
+
+impl Default for OfflineBufferConfig {
+    fn default() -> Self {
+        Self { capacity: default_offline_buffer_capacity(), overflow: OverflowPolicy::default() }
+    }
+}
+
+/// What a full offline buffer gives up
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// The oldest queued message, keeping the most recent readings
+    #[default]
+    DropOldest,
+    /// The message being published, keeping the start of the outage
+    DropNewest,
+}
+
+/// Home Assistant discovery fields of one point; unset fields keep the
+/// values derived from the object type and units
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EntityOverride {
+    pub device: u32,
+    pub object: ObjectRef,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    /// `measurement`, `total` or `total_increasing`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<String>,
+    /// e.g. `mdi:thermometer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// `config` or `diagnostic`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_category: Option<String>,
+    /// Seconds without a state after which Home Assistant shows the entity unavailable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_template: Option<String>,
+}
+
+/// State and command topic templates. Besides `{base}` (the base topic),
+/// `{prefix}` (the discovery prefix) and `{site}` they can use `{device_id}`,
+/// `{device_name}` (the device's alias, else its object name), `{object}`
+/// (e.g. `AI:3`), `{object_name}` (the object's alias, else `{object}`),
+/// `{object_type}` (e.g. `AI`) and `{instance}`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TopicConfig {
+    #[serde(default)]
+    pub site: String,
+    #[serde(default = "default_state_topic")]
+    pub state: String,
+    /// Must identify the device and the object
+    #[serde(default = "default_command_topic")]
+    pub command: String,
+    /// How device names are made safe for `{device_name}`
+    #[serde(default)]
+    pub name_policy: NamePolicy,
+    /// Character replacing what the policy removes
+    #[serde(default = "default_name_replacement")]
+    pub name_replacement: char,
+}
+
+/// Templates of the Home Assistant entity names and unique IDs of points, e.g.
+/// `{device_alias} {object_name}`. They can use `{site}`, `{device}` (the
+/// instance), `{device_alias}` (the alias, else the device object's name),
+/// `{object}` (e.g. `AI:3`), `{object_name}` (the object's alias, else its
+/// name), `{type}` (e.g. `AI`) and `{instance}`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NamingConfig {
+    /// Entity name, the device name and object by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_name: Option<String>,
+    /// Unique ID, `bacnet_<device>_<type>_<instance>` by default; changing it
+    /// makes Home Assistant create new entities
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique_id: Option<String>,
+}
+
+/// Characters of device names kept in topics; whatever else a policy removes
+/// becomes `name_replacement`. Names two devices share get `_<device id>` appended
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NamePolicy {
+    /// Everything but `/`, `+` and `#`
+    Minimal,
+    /// Printable ASCII except `/`, `+`, `#` and spaces
+    #[default]
+    Ascii,
+    /// Lowercase ASCII letters, digits, `-` and `_`, e.g. `ahu-1_sat`
+    Slug,
+}
+
+fn default_name_replacement() -> char {
+    '_'
+}
+
+fn default_state_topic() -> String {
+    "{prefix}/sensor/bacnet_{device_id}/state".to_string()
+}
+
+fn default_command_topic() -> String {
+    "{base}/{device_id}/{object}/set".to_string()
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            site: String::new(),
+            state: default_state_topic(),
+            command: default_command_topic(),
+            name_policy: NamePolicy::default(),
+            name_replacement: default_name_replacement(),
+        }
+    }
+}
+
+/// QoS and retain flag of one class of topics
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct PublishOptions {
+    /// 0, 1 or 2
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    #[serde(default = "default_retain")]
+    pub retain: bool,
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+fn default_retain() -> bool {
+    true
+}
+
+impl Default for PublishOptions {
+    fn default() -> Self {
+        Self { qos: default_qos(), retain: default_retain() }
+    }
+}
+
+/// Publish options per topic class, QoS 1 and retained unless configured
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PublishConfig {
+    /// Point states, attributes, status refreshes, roll-ups and heartbeats
+    #[serde(default)]
+    pub state: PublishOptions,
+    /// Home Assistant discovery configs
+    #[serde(default)]
+    pub discovery: PublishOptions,
+    /// Gateway and device availability, including the gateway's will
+    #[serde(default)]
+    pub availability: PublishOptions,
+    #[serde(default)]
+    pub alarms: PublishOptions,
+}
+
+/// Namespace the gateway publishes in
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MqttMode {
+    /// `<base_topic>` state topics with Home Assistant discovery
+    #[default]
+    HomeAssistant,
+    /// Sparkplug B edge node next to the plain state topics; NDEATH replaces
+    /// the gateway status will and no discovery configs are published
+    SparkplugB,
+    /// Homie 4.0 topology under `homie.root` next to the plain state topics;
+    /// its `$state` replaces the gateway status will and no discovery configs
+    /// are published
+    Homie,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HomieConfig {
+    #[serde(default = "default_homie_root")]
+    pub root: String,
+    /// Homie device id of the gateway: lowercase letters, digits and hyphens
+    #[serde(default = "default_homie_device_id")]
+    pub device_id: String,
+    #[serde(default = "default_homie_name")]
+    pub name: String,
+}
+
+fn default_homie_root() -> String {
+    "homie".to_string()
+}
+
+fn default_homie_device_id() -> String {
+    "bacnet-gateway".to_string()
+}
+
+fn default_homie_name() -> String {
+    "BACnet gateway".to_string()
+}
+
+impl Default for HomieConfig {
+    fn default() -> Self {
+        Self { root: default_homie_root(), device_id: default_homie_device_id(), name: default_homie_name() }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SparkplugConfig {
+    pub group_id: String,
+    pub edge_node_id: String,
+}
+
+impl Default for SparkplugConfig {
+    fn default() -> Self {
+        Self {
+            group_id: "BACnet".to_string(),
+            edge_node_id: "bacnet-mqtt-gateway".to_string(),
+        }
+    }
+}
+
+/// Encoding of point state payloads
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// The bare value, e.g. `22.4`, as Home Assistant templates expect
+    #[default]
+    Plain,
+    /// `{"value": 22.4, "ts": "...", "quality": "good", "units": "°C"}`
+    Json,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebConfig {
+    pub bind_addr: SocketAddr,
+    /// URL the web UI is reachable at from other machines (e.g. http://gateway.local:8123),
+    /// used for Home Assistant configuration links
+    pub public_url: Option<String>,
+    /// Login guarding the web UI and REST API, open to anyone reaching the port if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<WebAuthConfig>,
+    #[serde(default)]
+    pub mdns: MdnsConfig,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "0.0.0.0:8123".parse().unwrap(),
+            public_url: None,
+            auth: None,
+            mdns: MdnsConfig::default(),
+        }
+    }
+}
+
+/// mDNS advertisement of the web UI and REST API on the local network
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MdnsConfig {
+    #[serde(default = "default_mdns_enabled")]
+    pub enabled: bool,
+    /// Service instance name, `BACnet-MQTT Gateway <device_id>` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_name: Option<String>,
+    /// Host name announced, e.g. `gateway-3` for `gateway-3.local`;
+    /// `bacnet-gateway-<device_id>` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        Self { enabled: default_mdns_enabled(), instance_name: None, hostname: None }
+    }
+}
+
+fn default_mdns_enabled() -> bool {
+    true
+}
+
+/// A bearer token for API clients and/or an account for the login page;
+/// the health probes stay open
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebAuthConfig {
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    /// Account whose login starts a session cookie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// Lifetime of a login session
+    #[serde(default = "default_session_secs")]
+    pub session_secs: u64,
+}
+
+fn default_session_secs() -> u64 {
+    8 * 3600
+}
+
+impl WebConfig {
+    /// Base URL of the web UI, derived from the bind address when no public URL is set
+    pub fn base_url(&self) -> Option<String> {
+        match &self.public_url {
+            Some(url) => Some(url.trim_end_matches('/').to_string()),
+            None if self.bind_addr.ip().is_unspecified() => None,
+            None => Some(format!("http://{}", self.bind_addr)),
+        }
+    }
 }
 
 impl Default for GatewayConfig {
@@ -33,31 +1445,444 @@ impl Default for GatewayConfig {
             bacnet: BacnetConfig {
                 device_id: 12345,
                 bind_addr: "0.0.0.0:47808".parse().unwrap(),
+                interfaces: Vec::new(),
                 vendor_name: "Rust BACnet Gateway".to_string(),
                 model_name: "MQTT Bridge V1".to_string(),
+                discovery: DiscoveryConfig::default(),
+                poll_object: default_poll_object(),
+                apdu_timeout_ms: default_apdu_timeout_ms(),
+                apdu_retries: default_apdu_retries(),
+                apdu_backoff: Backoff::default(),
+                receive_watchdog_secs: default_receive_watchdog_secs(),
+                network_priority: NetworkPriorityConfig::default(),
+                device_timing: HashMap::new(),
+                quirks: Vec::new(),
+                virtual_objects: Vec::new(),
             },
             mqtt: MqttConfig {
                 broker_host: "127.0.0.1".to_string(),
                 broker_port: 1883,
                 username: None,
                 password: None,
+                password_file: None,
+                client_id: None,
+                clean_session: default_clean_session(),
+                tls: None,
+                profile: BrokerProfile::default(),
+                aws: AwsIotConfig::default(),
+                azure: AzureIotHubConfig::default(),
+                brokers: Vec::new(),
                 discovery_prefix: "homeassistant".to_string(),
                 base_topic: "bacnet".to_string(),
+                payload_format: PayloadFormat::default(),
+                mode: MqttMode::default(),
+                sparkplug: SparkplugConfig::default(),
+                homie: HomieConfig::default(),
+                publish: PublishConfig::default(),
+                topics: TopicConfig::default(),
+                naming: NamingConfig::default(),
+                entities: Vec::new(),
+                switch_priority: None,
+                offline_buffer: OfflineBufferConfig::default(),
+                filters: Vec::new(),
+                snapshot: SnapshotMode::default(),
+                transforms: Vec::new(),
             },
+            web: WebConfig::default(),
+            locale: LocaleConfig::default(),
+            polling: PollingConfig::default(),
+            devices: Vec::new(),
+            device_filters: DeviceFilterConfig::default(),
+            maintenance: Vec::new(),
+            alarms: AlarmConfig::default(),
+            heartbeats: Vec::new(),
+            audit: AuditConfig::default(),
+            cluster: None,
+            climates: Vec::new(),
+            covers: Vec::new(),
+            fans: Vec::new(),
+            dry_run: DryRunConfig::default(),
+            persistence: PersistenceConfig::default(),
+            historian: HistorianConfig::default(),
+            influxdb: None,
+        }
+    }
+}
+
+/// Highest BACnet object instance
+pub const MAX_INSTANCE: u32 = 4194303;
+
+/// Configuration file used when neither `--config` nor `GATEWAY_CONFIG` is given
+pub const DEFAULT_CONFIG_PATH: &str = "gateway.yaml";
+
+/// Configuration file path from `--config <path>` or `--config=<path>`,
+/// falling back to the `GATEWAY_CONFIG` environment variable
+pub fn config_path(mut args: impl Iterator<Item = String>) -> Result<PathBuf, String> {
+    while let Some(arg) = args.next() {
+        if arg == "--config" || arg == "-c" {
+            return args.next().map(PathBuf::from).ok_or_else(|| format!("{} needs a file path", arg));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Ok(PathBuf::from(path));
+        }
+    }
+    Ok(std::env::var_os("GATEWAY_CONFIG").map_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH), PathBuf::from))
+}
+
+/// Stands in for passwords and keys in configurations shown by the web UI
+pub const REDACTED: &str = "********";
+
+/// Syntax of a configuration file, told by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// `.json` and `.toml` files, YAML for `.yaml`, `.yml` and anything else
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("json") => Self::Json,
+            Some("toml") => Self::Toml,
+            _ => Self::Yaml,
         }
     }
 }
 
 impl GatewayConfig {
+    /// Loads the configuration file, writing the defaults to it on first run
+    pub fn load_or_create(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            let config = Self::default();
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            config
+                .save_to_file(path)
+                .map_err(|e| format!("failed to write default configuration to {}: {}", path.display(), e))?;
+            tracing::info!("Wrote default configuration to {}", path.display());
+            return Ok(config);
+        }
+        Self::load_from_file(path).map_err(|e| format!("invalid configuration file {}: {}", path.display(), e).into())
+    }
+
+    /// Reads and validates a configuration file in the format its extension
+    /// names; unknown keys and type errors are reported with their line and column
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let contents = fs::read_to_string(path)?;
-        let config = serde_yaml::from_str(&contents)?;
+        let contents = fs::read_to_string(&path)?;
+        let config: Self = match ConfigFormat::of(path.as_ref()) {
+            ConfigFormat::Yaml => serde_yaml::from_str(&contents)?,
+            ConfigFormat::Json => serde_json::from_str(&contents)?,
+            ConfigFormat::Toml => toml::from_str(&contents)?,
+        };
+        config.validate()?;
         Ok(config)
     }
 
+    /// The configuration with its passwords and keys replaced by `REDACTED`
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        let redact = |password: &mut Option<String>, azure: &mut AzureIotHubConfig| {
+            if password.is_some() {
+                *password = Some(REDACTED.to_string());
+            }
+            if !azure.shared_access_key.is_empty() {
+                azure.shared_access_key = REDACTED.to_string();
+            }
+        };
+        redact(&mut config.mqtt.password, &mut config.mqtt.azure);
+        for broker in &mut config.mqtt.brokers {
+            redact(&mut broker.password, &mut broker.azure);
+        }
+        if let Some(auth) = &mut config.web.auth {
+            for secret in [&mut auth.token, &mut auth.password].into_iter().filter(|secret| secret.is_some()) {
+                *secret = Some(REDACTED.to_string());
+            }
+        }
+        if let Some(influx) = &mut config.influxdb {
+            for secret in [&mut influx.token, &mut influx.password].into_iter().filter(|secret| secret.is_some()) {
+                *secret = Some(REDACTED.to_string());
+            }
+        }
+        config
+    }
+
+    /// Puts back the secrets of `current` that an edited configuration still
+    /// has as `REDACTED`, matching additional brokers by name
+    pub fn restore_secrets(&mut self, current: &GatewayConfig) {
+        let restore = |password: &mut Option<String>, azure: &mut AzureIotHubConfig, current: Option<(&Option<String>, &AzureIotHubConfig)>| {
+            if password.as_deref() == Some(REDACTED) {
+                *password = current.and_then(|(password, _)| password.clone());
+            }
+            if azure.shared_access_key == REDACTED {
+                azure.shared_access_key = current.map(|(_, azure)| azure.shared_access_key.clone()).unwrap_or_default();
+            }
+        };
+        restore(&mut self.mqtt.password, &mut self.mqtt.azure, Some((&current.mqtt.password, &current.mqtt.azure)));
+        for broker in &mut self.mqtt.brokers {
+            let known = current.mqtt.brokers.iter().find(|known| known.name == broker.name);
+            restore(&mut broker.password, &mut broker.azure, known.map(|known| (&known.password, &known.azure)));
+        }
+        if let Some(auth) = &mut self.web.auth {
+            let known = current.web.auth.as_ref();
+            if auth.token.as_deref() == Some(REDACTED) {
+                auth.token = known.and_then(|known| known.token.clone());
+            }
+            if auth.password.as_deref() == Some(REDACTED) {
+                auth.password = known.and_then(|known| known.password.clone());
+            }
+        }
+        if let Some(influx) = &mut self.influxdb {
+            let known = current.influxdb.as_ref();
+            if influx.token.as_deref() == Some(REDACTED) {
+                influx.token = known.and_then(|known| known.token.clone());
+            }
+            if influx.password.as_deref() == Some(REDACTED) {
+                influx.password = known.and_then(|known| known.password.clone());
+            }
+        }
+    }
+
+    /// Checks what deserialization cannot, reporting every problem found
+    /// with the path of the offending setting
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        let mut instance = |path: String, instance: u32| {
+            if instance > MAX_INSTANCE {
+                errors.push(format!("{}: device instance {} exceeds {}", path, instance, MAX_INSTANCE));
+            }
+        };
+        instance("bacnet.device_id".to_string(), self.bacnet.device_id);
+        for (i, device) in self.devices.iter().enumerate() {
+            instance(format!("devices[{}].instance", i), device.instance);
+        }
+        for (i, cover) in self.covers.iter().enumerate() {
+            instance(format!("covers[{}].device", i), cover.device);
+        }
+        for (i, fan) in self.fans.iter().enumerate() {
+            instance(format!("fans[{}].device", i), fan.device);
+        }
+        for (i, climate) in self.climates.iter().enumerate() {
+            instance(format!("climates[{}].device", i), climate.device);
+        }
+        for (i, heartbeat) in self.heartbeats.iter().enumerate() {
+            instance(format!("heartbeats[{}].device", i), heartbeat.device);
+        }
+        for (name, members) in &self.polling.groups {
+            for device in members {
+                instance(format!("polling.groups.{}", name), *device);
+            }
+        }
+
+        for (i, device) in self.devices.iter().enumerate() {
+            let path = format!("devices[{}]", i);
+            if let Some(j) = self.devices[..i].iter().position(|other| other.instance == device.instance) {
+                errors.push(format!("{}: device {} is already listed as devices[{}]", path, device.instance, j));
+            }
+            if let Some(j) = device
+                .address
+                .and_then(|addr| self.devices[..i].iter().position(|other| other.address == Some(addr)))
+            {
+                errors.push(format!("{}: address is already used by devices[{}]", path, j));
+            }
+            if device.interval_secs == Some(0) {
+                errors.push(format!("{}.interval_secs: must be at least 1", path));
+            }
+            if let Some(alias) = &device.alias {
+                if alias.trim().is_empty() {
+                    errors.push(format!("{}.alias: must not be empty", path));
+                } else if let Some(j) = self.devices[..i].iter().position(|other| other.alias == device.alias) {
+                    errors.push(format!("{}.alias: '{}' is already the alias of devices[{}]", path, alias, j));
+                }
+            }
+            for (object, alias) in &device.object_aliases {
+                if alias.trim().is_empty() {
+                    errors.push(format!("{}.object_aliases.{}: must not be empty", path, object));
+                }
+            }
+            for (k, point) in device.points.iter().enumerate() {
+                let point_path = format!("{}.points[{}]", path, k);
+                if device.points[..k].iter().any(|other| other.object == point.object) {
+                    errors.push(format!("{}: {} is listed twice", point_path, point.object));
+                }
+                if point.properties.is_empty() {
+                    errors.push(format!("{}.properties: no property to poll", point_path));
+                }
+                if point.interval_secs == Some(0) {
+                    errors.push(format!("{}.interval_secs: must be at least 1", point_path));
+                }
+            }
+        }
+
+        let discovery = &self.bacnet.discovery;
+        if discovery.low_limit.is_some() != discovery.high_limit.is_some() {
+            errors.push("bacnet.discovery: low_limit and high_limit go together".to_string());
+        }
+        for (i, shelved) in self.alarms.shelved.iter().enumerate() {
+            if let Some(hours) = shelved.hours.filter(|hours| !hours.is_finite() || *hours <= 0.0) {
+                errors.push(format!("alarms.shelved[{}].hours: {} is not a positive number of hours", i, hours));
+            }
+        }
+
+        if self.polling.interval_secs == 0 {
+            errors.push("polling.interval_secs: must be at least 1".to_string());
+        }
+        if self.persistence.file.is_some() && self.persistence.interval_secs == 0 {
+            errors.push("persistence.interval_secs: must be at least 1".to_string());
+        }
+        if self.historian.enabled && self.historian.retention_hours == 0 {
+            errors.push("historian.retention_hours: must be at least 1".to_string());
+        }
+        if let Some(influx) = &self.influxdb {
+            if !influx.url.starts_with("http://") {
+                errors.push(format!("influxdb.url: '{}' is not an http:// URL", influx.url));
+            }
+            if influx.api == InfluxApi::V2 && influx.org.is_none() {
+                errors.push("influxdb.org: required by the v2 API".to_string());
+            }
+            if influx.batch_size == 0 {
+                errors.push("influxdb.batch_size: must be at least 1".to_string());
+            }
+            if influx.flush_interval_secs == 0 {
+                errors.push("influxdb.flush_interval_secs: must be at least 1".to_string());
+            }
+        }
+        for (i, heartbeat) in self.heartbeats.iter().enumerate() {
+            if heartbeat.interval_secs == 0 {
+                errors.push(format!("heartbeats[{}].interval_secs: must be at least 1", i));
+            }
+        }
+        for (i, quirk) in self.bacnet.quirks.iter().enumerate() {
+            let path = format!("bacnet.quirks[{}]", i);
+            if quirk.name.trim().is_empty() {
+                errors.push(format!("{}.name: must not be empty", path));
+            } else if let Some(j) = self.bacnet.quirks[..i].iter().position(|other| other.name == quirk.name) {
+                errors.push(format!("{}.name: '{}' is already the name of bacnet.quirks[{}]", path, quirk.name, j));
+            }
+            if let Some(size) = quirk.max_apdu.filter(|size| !MAX_APDU_SIZES.contains(size)) {
+                errors.push(format!("{}.max_apdu: {} is not one of {:?}", path, size, MAX_APDU_SIZES));
+            }
+        }
+        let naming = &self.mqtt.naming;
+        for (key, template) in [("entity_name", &naming.entity_name), ("unique_id", &naming.unique_id)] {
+            if let Some(Err(e)) = template.as_deref().map(crate::naming::check) {
+                errors.push(format!("mqtt.naming.{}: {}", key, e));
+            }
+        }
+        for (i, filter) in self.mqtt.filters.iter().enumerate() {
+            if filter.max_interval_secs > 0 && filter.max_interval_secs < filter.min_interval_secs {
+                errors.push(format!("mqtt.filters[{}]: max_interval_secs is below min_interval_secs", i));
+            }
+        }
+
+        if let Some(auth) = &self.web.auth {
+            let token = auth.token.is_some() || auth.token_file.is_some();
+            let password = auth.password.is_some() || auth.password_file.is_some();
+            if !token && !(auth.username.is_some() && password) {
+                errors.push("web.auth: needs a token or a username with a password".to_string());
+            } else if auth.username.is_some() != password {
+                errors.push("web.auth: username and password go together".to_string());
+            }
+        }
+        if self.mqtt.broker_host.trim().is_empty() {
+            errors.push("mqtt.broker_host: missing broker host".to_string());
+        }
+        for (i, broker) in self.mqtt.brokers.iter().enumerate() {
+            if broker.broker_host.trim().is_empty() {
+                errors.push(format!("mqtt.brokers[{}].broker_host: missing broker host", i));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors.join("; ")) }
+    }
+
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
-        let yaml = serde_yaml::to_string(self)?;
-        fs::write(path, yaml)?;
+        let contents = match ConfigFormat::of(path.as_ref()) {
+            ConfigFormat::Yaml => serde_yaml::to_string(self)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+            ConfigFormat::Toml => toml::to_string_pretty(self)?,
+        };
+        fs::write(path, contents)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_keys_default_individually() {
+        let discovery: DiscoveryConfig = serde_yaml::from_str("enabled: false").unwrap();
+        assert!(!discovery.enabled);
+        assert_eq!(discovery.interval_secs, DiscoveryConfig::default().interval_secs);
+        assert!(discovery.broadcast_targets.is_empty());
+        assert_eq!((discovery.low_limit, discovery.high_limit), (None, None));
+    }
+
+    #[test]
+    fn numeric_keys_round_trip_through_every_format() {
+        let mut config = GatewayConfig::default();
+        config.cluster = Some(ClusterConfig {
+            node_id: "gw-a".to_string(),
+            heartbeat_secs: 10,
+            assignments: [(1200, "gw-b".to_string())].into(),
+        });
+        config.bacnet.device_timing.insert(1200, DeviceTiming { apdu_timeout_ms: Some(10_000), ..Default::default() });
+        config.bacnet.quirks.push(QuirkProfile { name: "legacy".to_string(), vendor_id: 7, unit_remap: [(95, 62)].into(), ..Default::default() });
+
+        let parsed: Vec<GatewayConfig> = vec![
+            serde_yaml::from_str(&serde_yaml::to_string(&config).unwrap()).unwrap(),
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap(),
+            toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap(),
+        ];
+        for parsed in parsed {
+            assert_eq!(parsed.cluster.unwrap().assignments.get(&1200).map(String::as_str), Some("gw-b"));
+            assert_eq!(parsed.bacnet.device_timing.get(&1200).and_then(|timing| timing.apdu_timeout_ms), Some(10_000));
+            assert_eq!(parsed.bacnet.quirks[0].unit_remap.get(&95), Some(&62));
+        }
+    }
+
+    #[test]
+    fn numeric_keys_are_read_from_yaml_integers() {
+        let timing: BacnetTimingMap = serde_yaml::from_str("device_timing: { 1200: { apdu_retries: 5 }, '1300': {} }").unwrap();
+        assert_eq!(timing.device_timing.get(&1200).and_then(|timing| timing.apdu_retries), Some(5));
+        assert!(timing.device_timing.contains_key(&1300));
+        assert!(serde_yaml::from_str::<BacnetTimingMap>("device_timing: { -1: {} }").is_err());
+    }
+
+    #[test]
+    fn discovery_limits_are_rejected_alone() {
+        let mut config = GatewayConfig::default();
+        config.bacnet.discovery.low_limit = Some(100);
+        assert!(config.validate().unwrap_err().contains("bacnet.discovery: low_limit and high_limit go together"));
+
+        config.bacnet.discovery.low_limit = None;
+        config.bacnet.discovery.high_limit = Some(200);
+        assert!(config.validate().unwrap_err().contains("bacnet.discovery: low_limit and high_limit go together"));
+
+        config.bacnet.discovery.low_limit = Some(100);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn shelving_periods_must_be_positive_and_finite() {
+        let mut config = GatewayConfig::default();
+        for hours in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            config.alarms.shelved = vec![ShelvedAlarmConfig { device: 1200, object: ObjectRef::new(0, 1), hours: Some(hours) }];
+            assert!(config.validate().unwrap_err().contains("alarms.shelved[0].hours"), "{} hours", hours);
+        }
+        for hours in [Some(0.5), None] {
+            config.alarms.shelved = vec![ShelvedAlarmConfig { device: 1200, object: ObjectRef::new(0, 1), hours }];
+            assert!(config.validate().is_ok());
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct BacnetTimingMap {
+        #[serde(with = "numeric_keys")]
+        device_timing: HashMap<u32, DeviceTiming>,
+    }
+}
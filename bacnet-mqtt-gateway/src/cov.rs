@@ -0,0 +1,158 @@
+//! COV subscriptions the gateway made on devices, with the notifications
+//! received for each, for listing, renewing and cancelling them by hand
+
+use crate::codec::{BacnetValue, CovNotification};
+use crate::mqtt::utc_timestamp;
+use crate::point::ObjectRef;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::RwLock;
+use std::time::Instant;
+use tracing::trace;
+
+const PROP_PRESENT_VALUE: u32 = 85;
+
+#[derive(Debug, Clone)]
+struct Subscription {
+    params: SubscriptionParams,
+    renewed: Instant,
+    /// Lifetime left as last reported by a notification
+    reported_remaining: Option<(u32, Instant)>,
+    notifications: u64,
+    last_notification: Option<String>,
+    present_value: Option<serde_json::Value>,
+}
+
+/// A subscription as listed by the REST API
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionEntry {
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub address: SocketAddr,
+    pub process_id: u32,
+    pub confirmed: bool,
+    /// 0 if indefinite
+    pub lifetime_secs: u32,
+    /// Seconds until the subscription lapses, none if indefinite
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_secs: Option<u64>,
+    /// Remaining lifetime the device announced in its last notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_remaining_secs: Option<u64>,
+    pub notifications: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_notification: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub present_value: Option<serde_json::Value>,
+}
+
+/// What a subscription was made with
+#[derive(Debug, Clone, Copy)]
+pub struct SubscriptionParams {
+    pub address: SocketAddr,
+    pub process_id: u32,
+    pub confirmed: bool,
+    pub lifetime_secs: u32,
+}
+
+pub struct CovSubscriptions {
+    next_process_id: AtomicU32,
+    entries: RwLock<BTreeMap<(u32, ObjectRef), Subscription>>,
+}
+
+impl Default for CovSubscriptions {
+    fn default() -> Self {
+        Self { next_process_id: AtomicU32::new(1), entries: RwLock::new(BTreeMap::new()) }
+    }
+}
+
+fn seconds_left(secs: u32, since: Instant) -> u64 {
+    u64::from(secs).saturating_sub(since.elapsed().as_secs())
+}
+
+impl CovSubscriptions {
+    /// Process identifier of the subscription to an object, a new one if there is none
+    pub fn process_id(&self, device_id: u32, object: ObjectRef) -> u32 {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        match entries.get(&(device_id, object)) {
+            Some(subscription) => subscription.params.process_id,
+            None => self.next_process_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// What an existing subscription was made with
+    pub fn params(&self, device_id: u32, object: ObjectRef) -> Option<SubscriptionParams> {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        entries.get(&(device_id, object)).map(|subscription| subscription.params)
+    }
+
+    /// Records a subscription the device accepted, keeping the notifications of a renewed one
+    pub fn subscribed(&self, device_id: u32, object: ObjectRef, params: SubscriptionParams) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let subscription = entries.entry((device_id, object)).or_insert_with(|| Subscription {
+            params,
+            renewed: Instant::now(),
+            reported_remaining: None,
+            notifications: 0,
+            last_notification: None,
+            present_value: None,
+        });
+        subscription.params = params;
+        subscription.renewed = Instant::now();
+    }
+
+    pub fn cancelled(&self, device_id: u32, object: ObjectRef) {
+        self.entries.write().unwrap_or_else(|e| e.into_inner()).remove(&(device_id, object));
+    }
+
+    /// Counts a notification against its subscription
+    pub fn notified(&self, notification: &CovNotification) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let Some(subscription) = entries
+            .get_mut(&(notification.device_id, notification.object))
+            .filter(|subscription| subscription.params.process_id == notification.process_id)
+        else {
+            trace!(
+                "COV notification of device {} {} for unknown process {}",
+                notification.device_id, notification.object, notification.process_id
+            );
+            return;
+        };
+        subscription.notifications += 1;
+        subscription.last_notification = Some(utc_timestamp());
+        subscription.reported_remaining = Some((notification.time_remaining, Instant::now()));
+        if let Some((_, values)) = notification.values.iter().find(|(property, _)| *property == PROP_PRESENT_VALUE) {
+            subscription.present_value = Some(match values.as_slice() {
+                [value] => value.to_json(),
+                values => serde_json::Value::Array(values.iter().map(BacnetValue::to_json).collect()),
+            });
+        }
+    }
+
+    /// Every subscription, by device and object
+    pub fn list(&self) -> Vec<SubscriptionEntry> {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .map(|((device_id, object), subscription)| SubscriptionEntry {
+                device_id: *device_id,
+                object: *object,
+                address: subscription.params.address,
+                process_id: subscription.params.process_id,
+                confirmed: subscription.params.confirmed,
+                lifetime_secs: subscription.params.lifetime_secs,
+                remaining_secs: (subscription.params.lifetime_secs > 0)
+                    .then(|| seconds_left(subscription.params.lifetime_secs, subscription.renewed)),
+                device_remaining_secs: subscription
+                    .reported_remaining
+                    .filter(|(secs, _)| *secs > 0)
+                    .map(|(secs, at)| seconds_left(secs, at)),
+                notifications: subscription.notifications,
+                last_notification: subscription.last_notification.clone(),
+                present_value: subscription.present_value.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,308 @@
+use crate::filter::Subnet;
+use bacnet_rs::datalink::bip::BacnetIpDataLink;
+use bacnet_rs::datalink::{DataLink as _, DataLinkAddress};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// Time `MultiDataLink::receive` waits for a frame from any interface
+const MULTI_RECEIVE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// NPDU transport used by the BACnet engine, implemented by BACnet/IP and by
+/// the scripted mock the unit tests run against
+pub trait DataLink: Send {
+    fn send_broadcast(&mut self, npdu: &[u8]) -> Result<(), Box<dyn Error>>;
+    fn send_unicast(&mut self, npdu: &[u8], dest: SocketAddr) -> Result<(), Box<dyn Error>>;
+    /// Returns the next received frame, or an error if none is available
+    fn receive(&mut self) -> Result<(Vec<u8>, SocketAddr), Box<dyn Error>>;
+    /// Name of the interface an address was heard on, with several interfaces
+    fn interface_of(&self, _addr: SocketAddr) -> Option<String> {
+        None
+    }
+    /// False once the socket is closed and could not be reopened
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+impl DataLink for BacnetIpDataLink {
+    fn send_broadcast(&mut self, npdu: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.send_broadcast_npdu(npdu)?;
+        Ok(())
+    }
+
+    fn send_unicast(&mut self, npdu: &[u8], dest: SocketAddr) -> Result<(), Box<dyn Error>> {
+        self.send_unicast_npdu(npdu, dest)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<(Vec<u8>, SocketAddr), Box<dyn Error>> {
+        match self.receive_frame()? {
+            (frame, DataLinkAddress::Ip(addr)) => Ok((frame, addr)),
+            (_, other) => Err(format!("unsupported source address {:?}", other).into()),
+        }
+    }
+}
+
+/// Stand-in while the real datalink is being rebuilt, failing every operation
+pub struct Closed;
+
+impl DataLink for Closed {
+    fn send_broadcast(&mut self, _npdu: &[u8]) -> Result<(), Box<dyn Error>> {
+        Err("datalink closed".into())
+    }
+
+    fn send_unicast(&mut self, _npdu: &[u8], _dest: SocketAddr) -> Result<(), Box<dyn Error>> {
+        Err("datalink closed".into())
+    }
+
+    fn receive(&mut self) -> Result<(Vec<u8>, SocketAddr), Box<dyn Error>> {
+        Err("datalink closed".into())
+    }
+
+    fn is_open(&self) -> bool {
+        false
+    }
+}
+
+struct Interface {
+    name: String,
+    subnet: Option<Subnet>,
+    link: Arc<Mutex<Box<dyn DataLink>>>,
+}
+
+/// Several datalinks behind one, e.g. BACnet/IP on two network segments:
+/// each interface is read by its own thread, broadcasts go out on every
+/// interface and unicasts on the one the destination was last heard on
+pub struct MultiDataLink {
+    interfaces: Vec<Interface>,
+    inbound: mpsc::Receiver<(Vec<u8>, SocketAddr, usize)>,
+    /// Interface each address was last heard on
+    routes: HashMap<SocketAddr, usize>,
+    /// Stops the receive threads once the datalink is dropped
+    closed: Arc<AtomicBool>,
+}
+
+impl MultiDataLink {
+    /// Starts a receive thread per interface, given as name, the subnet of the
+    /// devices reached through it and its datalink
+    pub fn new(interfaces: Vec<(String, Option<Subnet>, Box<dyn DataLink>)>) -> Self {
+        let (tx, inbound) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let interfaces = interfaces
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, subnet, link))| {
+                let link = Arc::new(Mutex::new(link));
+                let (thread_link, tx, closed) = (link.clone(), tx.clone(), closed.clone());
+                std::thread::spawn(move || {
+                    while !closed.load(Ordering::Relaxed) {
+                        let received = thread_link.lock().unwrap_or_else(|e| e.into_inner()).receive();
+                        match received {
+                            Ok((frame, src)) => {
+                                if tx.send((frame, src, index)).is_err() {
+                                    break;
+                                }
+                            }
+                            // Datalinks without a receive timeout return at once when idle
+                            Err(_) => std::thread::sleep(Duration::from_millis(1)),
+                        }
+                    }
+                });
+                Interface { name, subnet, link }
+            })
+            .collect();
+        Self { interfaces, inbound, routes: HashMap::new(), closed }
+    }
+
+    /// Interface a unicast goes out on: where the address was heard, else the
+    /// interface whose subnet contains it, else the first one
+    fn route(&self, dest: SocketAddr) -> usize {
+        self.routes.get(&dest).copied().unwrap_or_else(|| {
+            self.interfaces
+                .iter()
+                .position(|interface| interface.subnet.is_some_and(|subnet| subnet.contains(dest.ip())))
+                .unwrap_or(0)
+        })
+    }
+}
+
+impl Drop for MultiDataLink {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        // Close the sockets now, not when the threads notice, so a rebuild can bind them again
+        for interface in &self.interfaces {
+            *interface.link.lock().unwrap_or_else(|e| e.into_inner()) = Box::new(Closed);
+        }
+    }
+}
+
+impl DataLink for MultiDataLink {
+    fn send_broadcast(&mut self, npdu: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut errors = Vec::new();
+        for interface in &self.interfaces {
+            let sent = interface.link.lock().unwrap_or_else(|e| e.into_inner()).send_broadcast(npdu);
+            if let Err(e) = sent {
+                errors.push(format!("{}: {}", interface.name, e));
+            }
+        }
+        if errors.len() == self.interfaces.len() && !errors.is_empty() {
+            return Err(errors.join("; ").into());
+        }
+        Ok(())
+    }
+
+    fn send_unicast(&mut self, npdu: &[u8], dest: SocketAddr) -> Result<(), Box<dyn Error>> {
+        let interface = self.interfaces.get(self.route(dest)).ok_or("no datalink interface")?;
+        interface.link.lock().unwrap_or_else(|e| e.into_inner()).send_unicast(npdu, dest)
+    }
+
+    fn receive(&mut self) -> Result<(Vec<u8>, SocketAddr), Box<dyn Error>> {
+        let (frame, src, index) = self.inbound.recv_timeout(MULTI_RECEIVE_TIMEOUT).map_err(|_| "no frame")?;
+        self.routes.insert(src, index);
+        Ok((frame, src))
+    }
+
+    fn interface_of(&self, addr: SocketAddr) -> Option<String> {
+        self.routes.get(&addr).map(|index| self.interfaces[*index].name.clone())
+    }
+
+    fn is_open(&self) -> bool {
+        self.interfaces.iter().any(|interface| interface.link.lock().unwrap_or_else(|e| e.into_inner()).is_open())
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::DataLink;
+    use bacnet_rs::network::Npdu;
+    use std::collections::VecDeque;
+    use std::error::Error;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    /// Produces the APDU answering a sent APDU, if any
+    pub type Responder = Box<dyn FnMut(&[u8]) -> Option<Vec<u8>> + Send>;
+
+    #[derive(Default)]
+    pub struct MockState {
+        /// Sent NPDUs with their destination, `None` for broadcasts
+        pub sent: Vec<(Option<SocketAddr>, Vec<u8>)>,
+        pub inbound: VecDeque<(Vec<u8>, SocketAddr)>,
+        pub responder: Option<Responder>,
+    }
+
+    /// In-memory datalink whose handle stays with the test to script and inspect traffic
+    #[derive(Clone, Default)]
+    pub struct MockDataLink {
+        pub state: Arc<Mutex<MockState>>,
+    }
+
+    impl MockDataLink {
+        pub fn push_inbound(&self, apdu: &[u8], src: SocketAddr) {
+            self.state.lock().unwrap().inbound.push_back((frame(apdu), src));
+        }
+
+        pub fn respond_with(&self, responder: impl FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static) {
+            self.state.lock().unwrap().responder = Some(Box::new(responder));
+        }
+
+        pub fn sent(&self) -> Vec<(Option<SocketAddr>, Vec<u8>)> {
+            self.state.lock().unwrap().sent.clone()
+        }
+    }
+
+    /// Wraps an APDU in a minimal NPDU
+    pub fn frame(apdu: &[u8]) -> Vec<u8> {
+        let mut packet = Npdu::new().encode();
+        packet.extend_from_slice(apdu);
+        packet
+    }
+
+    /// Strips the NPDU from a sent packet
+    pub fn apdu_of(packet: &[u8]) -> Vec<u8> {
+        let (_, consumed) = Npdu::decode(packet).expect("valid NPDU");
+        packet[consumed..].to_vec()
+    }
+
+    impl DataLink for MockDataLink {
+        fn send_broadcast(&mut self, npdu: &[u8]) -> Result<(), Box<dyn Error>> {
+            self.state.lock().unwrap().sent.push((None, npdu.to_vec()));
+            Ok(())
+        }
+
+        fn send_unicast(&mut self, npdu: &[u8], dest: SocketAddr) -> Result<(), Box<dyn Error>> {
+            let mut state = self.state.lock().unwrap();
+            state.sent.push((Some(dest), npdu.to_vec()));
+            let reply = state.responder.as_mut().and_then(|respond| respond(&apdu_of(npdu)));
+            if let Some(reply) = reply {
+                state.inbound.push_back((frame(&reply), dest));
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<(Vec<u8>, SocketAddr), Box<dyn Error>> {
+            self.state
+                .lock()
+                .unwrap()
+                .inbound
+                .pop_front()
+                .ok_or_else(|| "no frame".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{frame, MockDataLink};
+    use super::{DataLink, MultiDataLink};
+    use crate::filter::Subnet;
+    use std::net::SocketAddr;
+
+    fn interface(name: &str, subnet: Option<Subnet>, link: &MockDataLink) -> (String, Option<Subnet>, Box<dyn DataLink>) {
+        (name.to_string(), subnet, Box::new(link.clone()))
+    }
+
+    fn receive_frame(link: &mut MultiDataLink) -> (Vec<u8>, SocketAddr) {
+        for _ in 0..100 {
+            if let Ok(received) = link.receive() {
+                return received;
+            }
+        }
+        panic!("no frame received");
+    }
+
+    #[test]
+    fn unicasts_go_out_on_the_interface_a_device_was_heard_on() {
+        let (a, b) = (MockDataLink::default(), MockDataLink::default());
+        let device: SocketAddr = "10.1.0.5:47808".parse().unwrap();
+        let mut link = MultiDataLink::new(vec![interface("a", None, &a), interface("b", None, &b)]);
+
+        b.push_inbound(&[0x10, 0x08], device);
+        assert_eq!(receive_frame(&mut link), (frame(&[0x10, 0x08]), device));
+        assert_eq!(link.interface_of(device).as_deref(), Some("b"));
+
+        link.send_unicast(&[0x01, 0x02], device).unwrap();
+        assert!(a.sent().is_empty());
+        assert_eq!(b.sent(), vec![(Some(device), vec![0x01, 0x02])]);
+    }
+
+    #[test]
+    fn unheard_devices_are_reached_through_their_subnet() {
+        let (a, b) = (MockDataLink::default(), MockDataLink::default());
+        let subnet = Subnet::parse("192.168.2.0/24").unwrap();
+        let mut link = MultiDataLink::new(vec![interface("a", None, &a), interface("b", Some(subnet), &b)]);
+        let inside: SocketAddr = "192.168.2.7:47808".parse().unwrap();
+        let outside: SocketAddr = "10.0.0.9:47808".parse().unwrap();
+
+        link.send_unicast(&[0x01], inside).unwrap();
+        link.send_unicast(&[0x02], outside).unwrap();
+        link.send_broadcast(&[0x03]).unwrap();
+        assert_eq!(a.sent(), vec![(Some(outside), vec![0x02]), (None, vec![0x03])]);
+        assert_eq!(b.sent(), vec![(Some(inside), vec![0x01]), (None, vec![0x03])]);
+        assert_eq!(link.interface_of(inside), None);
+    }
+}
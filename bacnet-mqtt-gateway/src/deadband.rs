@@ -0,0 +1,67 @@
+use crate::codec::BacnetValue;
+use crate::config::PublishFilterConfig;
+use crate::point::ObjectRef;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+fn numeric(value: &BacnetValue) -> Option<f64> {
+    match value {
+        BacnetValue::Unsigned(v) => Some(f64::from(*v)),
+        BacnetValue::Signed(v) => Some(f64::from(*v)),
+        BacnetValue::Real(v) => Some(f64::from(*v)),
+        BacnetValue::Double(v) => Some(*v),
+        _ => None,
+    }
+}
+
+/// Drops polled values that moved less than the point's deadband or arrive
+/// within its minimum interval of the last publish, unless the maximum
+/// interval has passed since then
+pub struct PublishFilters {
+    rules: Vec<PublishFilterConfig>,
+    /// Last published value of each point and when it went out
+    published: Mutex<HashMap<(u32, ObjectRef), (BacnetValue, Instant)>>,
+}
+
+impl PublishFilters {
+    pub fn new(rules: Vec<PublishFilterConfig>) -> Self {
+        Self { rules, published: Mutex::new(HashMap::new()) }
+    }
+
+    /// Most specific rule for a point: device and object, then object, then device
+    fn rule(&self, device_id: u32, object: ObjectRef) -> Option<&PublishFilterConfig> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.device.is_none_or(|d| d == device_id) && rule.object.is_none_or(|o| o == object))
+            .max_by_key(|rule| (rule.device.is_some() && rule.object.is_some(), rule.object.is_some(), rule.device.is_some()))
+    }
+
+    /// True if the value should be published, recording it as published if so
+    pub fn admit(&self, device_id: u32, object: ObjectRef, value: &BacnetValue) -> bool {
+        let Some(rule) = self.rule(device_id, object) else {
+            return true;
+        };
+        let mut published = self.published.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((last, at)) = published.get(&(device_id, object)) {
+            let elapsed = at.elapsed();
+            let heartbeat_due = rule.max_interval_secs > 0 && elapsed >= Duration::from_secs(rule.max_interval_secs);
+            let changed = match (numeric(last), numeric(value)) {
+                (Some(last), Some(value)) => (value - last).abs() > rule.deadband,
+                _ => last != value,
+            };
+            let too_soon = elapsed < Duration::from_secs(rule.min_interval_secs);
+            if !heartbeat_due && (!changed || too_soon) {
+                return false;
+            }
+        }
+        published.insert((device_id, object), (value.clone(), Instant::now()));
+        true
+    }
+
+    /// Forgets what was last published for a point, so its next value goes out
+    /// whatever it is, e.g. the real value after a simulated one
+    pub fn forget(&self, device_id: u32, object: ObjectRef) {
+        self.published.lock().unwrap_or_else(|e| e.into_inner()).remove(&(device_id, object));
+    }
+}
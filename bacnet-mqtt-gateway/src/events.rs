@@ -0,0 +1,155 @@
+//! Live feed of gateway activity: BACnet traffic, poll results, MQTT
+//! publishes and logged warnings, streamed to WebSocket clients
+
+use crate::bacnet::BacnetEvent;
+use crate::codec;
+use crate::mqtt::utc_timestamp;
+use crate::point::ObjectRef;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    WhoIs {
+        source: SocketAddr,
+        low_limit: Option<u32>,
+        high_limit: Option<u32>,
+    },
+    /// I-Am of a new device or one whose vendor changed
+    IAm { device_id: u32, address: SocketAddr, vendor_id: u32 },
+    DeviceMoved { device_id: u32, from: SocketAddr, to: SocketAddr },
+    /// ReadProperty served from the gateway's own objects
+    ReadProperty { source: SocketAddr, object: ObjectRef, property: u32 },
+    /// Property read from a device, by a poll or on request
+    PropertyRead {
+        address: SocketAddr,
+        object: ObjectRef,
+        property: u32,
+        value: serde_json::Value,
+        latency_ms: Option<u64>,
+    },
+    /// Poll left unanswered or answered with an error
+    RequestFailed { address: SocketAddr, invoke_id: u8, error: String },
+    /// WriteProperty to a virtual object, forwarded to MQTT
+    VirtualWrite { object: ObjectRef, topic: String, payload: String },
+    /// Message published to the MQTT brokers
+    Published { topic: String, payload: String, retain: bool },
+    /// Warning or error logged by the gateway
+    Log { level: String, target: String, message: String },
+}
+
+impl Event {
+    pub fn from_bacnet(event: &BacnetEvent) -> Self {
+        match event {
+            BacnetEvent::WhoIs(req, source) => Event::WhoIs {
+                source: *source,
+                low_limit: req.device_instance_range_low_limit,
+                high_limit: req.device_instance_range_high_limit,
+            },
+            BacnetEvent::IAm(iam, address) => Event::IAm {
+                device_id: iam.device_identifier.instance,
+                address: *address,
+                vendor_id: iam.vendor_identifier,
+            },
+            BacnetEvent::DeviceMoved(iam, from, to) => Event::DeviceMoved { device_id: iam.device_identifier.instance, from: *from, to: *to },
+            BacnetEvent::ReadProperty(reference, _, source) => {
+                Event::ReadProperty { source: *source, object: reference.object, property: reference.property }
+            }
+            BacnetEvent::ReadPropertyAck(ack, _, address, latency) => {
+                let values = codec::decode_property_value(&ack.property_value);
+                Event::PropertyRead {
+                    address: *address,
+                    object: ObjectRef::new(ack.object_identifier.object_type as u16, ack.object_identifier.instance),
+                    property: ack.property_identifier,
+                    value: match values.as_slice() {
+                        [value] => value.to_json(),
+                        values => serde_json::Value::Array(values.iter().map(codec::BacnetValue::to_json).collect()),
+                    },
+                    latency_ms: latency.map(|l| l.as_millis() as u64),
+                }
+            }
+            BacnetEvent::VirtualObjectWritten(write) => {
+                Event::VirtualWrite { object: write.object, topic: write.topic.clone(), payload: write.payload.clone() }
+            }
+            BacnetEvent::RequestFailed(invoke_id, address, error) => {
+                Event::RequestFailed { address: *address, invoke_id: *invoke_id, error: error.to_string() }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub ts: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Sender side of the feed, cheap to clone into every task that reports activity
+#[derive(Clone)]
+pub struct Events {
+    tx: broadcast::Sender<Record>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self { tx: broadcast::channel(1024).0 }
+    }
+}
+
+impl Events {
+    pub fn emit(&self, event: Event) {
+        // Nobody watching is the normal case
+        if self.tx.receiver_count() > 0 {
+            let _ = self.tx.send(Record { ts: utc_timestamp(), event });
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Record> {
+        self.tx.subscribe()
+    }
+
+    /// Tracing layer feeding logged warnings and errors into the feed
+    pub fn log_layer(&self) -> LogLayer {
+        LogLayer { events: self.clone() }
+    }
+}
+
+pub struct LogLayer {
+    events: Events,
+}
+
+/// Collects the message and fields of a log event into one line
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        if *metadata.level() > Level::WARN {
+            return;
+        }
+        let mut message = Message::default();
+        event.record(&mut message);
+        self.events.emit(Event::Log { level: metadata.level().to_string(), target: metadata.target().to_string(), message: message.0 });
+    }
+}
@@ -0,0 +1,218 @@
+use crate::bacnet::BacnetEngine;
+use crate::codec::BacnetValue;
+use crate::config::{DeviceConfig, PointConfig};
+use crate::locale::Translator;
+use crate::mqtt::{self, MqttService};
+use crate::naming;
+use crate::point::{self, ObjectRef, PointMetadata};
+use crate::server::LocalDevice;
+use crate::units;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use tracing::warn;
+
+/// One row of the data dictionary: where a point lives on BACnet and where it
+/// shows up in MQTT and Home Assistant
+#[derive(Debug, Serialize)]
+pub struct RegistryEntry {
+    pub device_id: u32,
+    pub device_alias: Option<String>,
+    /// BACnet/IP address, `local` for the gateway's own virtual objects
+    pub address: String,
+    pub object: ObjectRef,
+    pub object_alias: Option<String>,
+    pub state_topic: String,
+    pub command_topic: Option<String>,
+    pub ha_unique_id: Option<String>,
+    pub ha_entity_id: Option<String>,
+    pub units: Option<u32>,
+    pub unit_of_measurement: Option<String>,
+    /// How values are converted between BACnet and MQTT
+    pub transform: String,
+}
+
+const CSV_HEADER: &str =
+    "device_id,device_alias,address,object,object_alias,state_topic,command_topic,ha_unique_id,ha_entity_id,units,unit_of_measurement,transform";
+
+/// Entity id Home Assistant derives from an entity name
+fn entity_id(component: &str, name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+    format!("{}.{}", component, slug.trim_matches('_'))
+}
+
+fn transform(object: ObjectRef, metadata: Option<&PointMetadata>, simulated: bool) -> String {
+    if simulated {
+        return "simulated".to_string();
+    }
+    let states = |metadata: &PointMetadata| {
+        metadata
+            .options()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                // Binary states count from 0, multi-state values from 1
+                let value = if object.is_binary() { i } else { i + 1 };
+                format!("{}={}", value, name)
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    };
+    match metadata {
+        Some(metadata) if !metadata.options().is_empty() => states(metadata),
+        _ if object.is_binary() => format!(
+            "0={}; 1={}",
+            crate::codec::state_text(object, &BacnetValue::Enumerated(0)),
+            crate::codec::state_text(object, &BacnetValue::Enumerated(1))
+        ),
+        _ => "none".to_string(),
+    }
+}
+
+/// Builds the data dictionary from the live registry
+pub fn build(
+    devices: &HashMap<u32, SocketAddr>,
+    metadata: &HashMap<(u32, ObjectRef), PointMetadata>,
+    simulations: &HashMap<(u32, ObjectRef), String>,
+    poll_object: ObjectRef,
+    mqtt: &MqttService,
+    translator: &Translator,
+    local_device: &LocalDevice,
+) -> Vec<RegistryEntry> {
+    let site = mqtt.site();
+    let mut ids: Vec<u32> = devices.keys().copied().collect();
+    ids.sort_unstable();
+    let mut entries: Vec<RegistryEntry> = ids
+        .into_iter()
+        .map(|device_id| {
+            let point = metadata.get(&(device_id, poll_object));
+            let units = point.and_then(|m| m.units);
+            let aliases = mqtt.aliases();
+            let device_alias = aliases.device_name(device_id, translator);
+            let object_name = aliases
+                .object(device_id, poll_object)
+                .or_else(|| point.and_then(|m| m.object_name.clone()))
+                .unwrap_or_else(|| poll_object.to_string());
+            let names = naming::PointNames { site: &site, device_id, device_alias: &device_alias, object: poll_object, object_name: &object_name };
+            let entity_name = match &mqtt.naming().entity_name {
+                Some(template) => names.render(template),
+                None => aliases.entity_name(device_id, poll_object, poll_object, translator),
+            };
+            RegistryEntry {
+                device_id,
+                device_alias: mqtt.aliases().device(device_id),
+                address: devices[&device_id].to_string(),
+                object: poll_object,
+                object_alias: mqtt.aliases().object(device_id, poll_object),
+                state_topic: mqtt.device_state_topic(device_id, poll_object),
+                command_topic: Some(mqtt.command_topic(device_id, poll_object)),
+                ha_unique_id: Some(match &mqtt.naming().unique_id {
+                    Some(template) => names.render_unique_id(template),
+                    None => format!("bacnet_{}", device_id),
+                }),
+                ha_entity_id: Some(entity_id(
+                    mqtt::ha_component(poll_object, point.is_some_and(|m| !m.options().is_empty())),
+                    &entity_name,
+                )),
+                units,
+                unit_of_measurement: units.and_then(units::ha_unit).and_then(|u| u.unit_of_measurement).map(str::to_string),
+                transform: transform(poll_object, point, simulations.contains_key(&(device_id, poll_object))),
+            }
+        })
+        .collect();
+
+    entries.extend(local_device.virtual_object_infos().into_iter().map(|vo| RegistryEntry {
+        device_id: local_device.instance(),
+        device_alias: None,
+        address: "local".to_string(),
+        object: vo.object,
+        object_alias: None,
+        state_topic: vo.topic,
+        command_topic: vo.command_topic,
+        ha_unique_id: None,
+        ha_entity_id: None,
+        units: vo.units,
+        unit_of_measurement: vo.units.and_then(units::ha_unit).and_then(|u| u.unit_of_measurement).map(str::to_string),
+        transform: format!("mirrored into virtual object '{}'", vo.name),
+    }));
+    entries
+}
+
+/// A `devices:` section for the configuration file, e.g. to commission a site
+#[derive(Debug, Serialize)]
+pub struct ConfigSkeleton {
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// Lists every discovered device at its address with the point objects of its
+/// object list, the object names as aliases; devices whose object list cannot
+/// be read get the one read last, or no points if it never was
+pub async fn skeleton(bacnet: &BacnetEngine, devices: &HashMap<u32, SocketAddr>) -> ConfigSkeleton {
+    let mut ids: Vec<u32> = devices.keys().copied().collect();
+    ids.sort_unstable();
+    let mut skeleton = ConfigSkeleton { devices: Vec::new() };
+    for device_id in ids {
+        let addr = devices[&device_id];
+        let objects = bacnet.read_object_list(addr, device_id).await.unwrap_or_else(|e| {
+            warn!("Cannot read the object list of device {}: {}", device_id, e);
+            bacnet.known_object_list(device_id).unwrap_or_default()
+        });
+        let mut points = Vec::new();
+        let mut object_aliases = BTreeMap::new();
+        for object in objects.into_iter().filter(ObjectRef::is_point) {
+            if let Some(name) = point::read_object_name(bacnet, addr, object).await {
+                object_aliases.insert(object, name);
+            }
+            points.push(PointConfig { object, properties: vec![85], interval_secs: None });
+        }
+        skeleton.devices.push(DeviceConfig {
+            instance: device_id,
+            address: Some(addr),
+            points,
+            interval_secs: None,
+            alias: point::read_object_name(bacnet, addr, ObjectRef::new(8, device_id)).await,
+            object_aliases,
+        });
+    }
+    skeleton
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(entries: &[RegistryEntry]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push_str("\r\n");
+    let optional = |value: &Option<String>| value.as_deref().map(csv_field).unwrap_or_default();
+    for entry in entries {
+        let row = [
+            entry.device_id.to_string(),
+            optional(&entry.device_alias),
+            csv_field(&entry.address),
+            csv_field(&entry.object.to_string()),
+            optional(&entry.object_alias),
+            csv_field(&entry.state_topic),
+            optional(&entry.command_topic),
+            optional(&entry.ha_unique_id),
+            optional(&entry.ha_entity_id),
+            entry.units.map(|u| u.to_string()).unwrap_or_default(),
+            optional(&entry.unit_of_measurement),
+            csv_field(&entry.transform),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
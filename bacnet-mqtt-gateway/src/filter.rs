@@ -0,0 +1,304 @@
+//! Allow and deny rules selecting the discovered devices and objects the
+//! gateway polls and publishes
+
+use crate::config::{DeviceFilterConfig, FilterRule};
+use crate::point::ObjectRef;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::RwLock;
+
+/// True if `text` matches a glob with `*` (any run) and `?` (one character), ignoring case
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text it has consumed up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    t = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// An IPv4 or IPv6 network such as `10.20.0.0/16`
+#[derive(Debug, Clone, Copy)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Subnet {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid subnet '{}', expected e.g. 10.20.0.0/16", text);
+        let (network, prefix) = text.split_once('/').unwrap_or((text, ""));
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() { bits } else { prefix.parse().map_err(|_| invalid())? };
+        if prefix > bits {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+
+    /// Directed broadcast address of an IPv4 network
+    pub fn broadcast(&self) -> Option<IpAddr> {
+        let IpAddr::V4(network) = self.network else {
+            return None;
+        };
+        let hosts = u32::MAX.checked_shr(self.prefix).unwrap_or(0);
+        Some(IpAddr::V4((u32::from(network) | hosts).into()))
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let (network, addr, bits) = match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => (u128::from(u32::from(network)), u128::from(u32::from(addr)), 32),
+            (IpAddr::V6(network), IpAddr::V6(addr)) => (u128::from(network), u128::from(addr), 128),
+            _ => return false,
+        };
+        let host_bits = bits - self.prefix;
+        host_bits == bits || network >> host_bits == addr >> host_bits
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    instances: Option<(u32, u32)>,
+    subnet: Option<Subnet>,
+    object_types: Vec<u16>,
+    name: Option<String>,
+}
+
+impl Rule {
+    fn parse(rule: &FilterRule) -> Result<Self, String> {
+        let instances = match &rule.instances {
+            Some(range) => {
+                let invalid = || format!("invalid instance range '{}', expected e.g. 1000-1999", range);
+                let (low, high) = range.split_once('-').unwrap_or((range, range));
+                let low: u32 = low.trim().parse().map_err(|_| invalid())?;
+                let high: u32 = high.trim().parse().map_err(|_| invalid())?;
+                Some((low.min(high), low.max(high)))
+            }
+            None => None,
+        };
+        let subnet = rule.subnet.as_deref().map(Subnet::parse).transpose()?;
+        let object_types = rule
+            .object_types
+            .iter()
+            .map(|kind| format!("{}:0", kind).parse::<ObjectRef>().map(|object| object.object_type))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { instances, subnet, object_types, name: rule.name.clone() })
+    }
+
+    /// Matches the device criteria; a name pattern never matches a device whose name is unknown
+    fn matches_device(&self, device_id: u32, addr: SocketAddr, name: Option<&str>) -> bool {
+        self.instances.is_none_or(|(low, high)| (low..=high).contains(&device_id))
+            && self.subnet.is_none_or(|subnet| subnet.contains(addr.ip()))
+            && self.name.as_deref().is_none_or(|pattern| name.is_some_and(|name| glob_matches(pattern, name)))
+    }
+
+    fn matches_object(&self, object: ObjectRef) -> bool {
+        self.object_types.is_empty() || self.object_types.contains(&object.object_type)
+    }
+}
+
+#[derive(Debug)]
+struct Rules {
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+}
+
+impl Rules {
+    fn parse(config: &DeviceFilterConfig) -> Result<Self, String> {
+        let parse = |rules: &[FilterRule]| rules.iter().map(Rule::parse).collect::<Result<Vec<_>, _>>();
+        Ok(Self { allow: parse(&config.allow)?, deny: parse(&config.deny)? })
+    }
+}
+
+/// The configured allow and deny rules with the device names read so far
+pub struct DeviceFilter {
+    rules: RwLock<Rules>,
+    names: RwLock<HashMap<u32, String>>,
+}
+
+impl DeviceFilter {
+    pub fn new(config: &DeviceFilterConfig) -> Result<Self, String> {
+        Ok(Self { rules: RwLock::new(Rules::parse(config)?), names: RwLock::new(HashMap::new()) })
+    }
+
+    /// Replaces the rules, keeping the current ones if the new ones are invalid
+    pub fn reload(&self, config: &DeviceFilterConfig) -> Result<(), String> {
+        let rules = Rules::parse(config)?;
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = rules;
+        Ok(())
+    }
+
+    /// True if a rule matches device names, which then have to be read on discovery
+    pub fn uses_names(&self) -> bool {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        rules.allow.iter().chain(&rules.deny).any(|rule| rule.name.is_some())
+    }
+
+    pub fn set_name(&self, device_id: u32, name: &str) {
+        let mut names = self.names.write().unwrap_or_else(|e| e.into_inner());
+        names.insert(device_id, name.to_string());
+    }
+
+    /// True if a deny rule matching on instances and subnet alone excludes the
+    /// device, which then is kept out of the registry as soon as it answers
+    pub fn excludes(&self, device_id: u32, addr: SocketAddr) -> bool {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        rules
+            .deny
+            .iter()
+            .filter(|rule| rule.object_types.is_empty() && rule.name.is_none())
+            .any(|rule| rule.matches_device(device_id, addr, None))
+    }
+
+    /// True if any of the device's objects may be polled and published
+    pub fn admits_device(&self, device_id: u32, addr: SocketAddr) -> bool {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        let names = self.names.read().unwrap_or_else(|e| e.into_inner());
+        let name = names.get(&device_id).map(String::as_str);
+        let allowed = rules.allow.is_empty() || rules.allow.iter().any(|rule| rule.matches_device(device_id, addr, name));
+        // Rules listing object types exclude those objects, not the device
+        allowed
+            && !rules
+                .deny
+                .iter()
+                .any(|rule| rule.object_types.is_empty() && rule.matches_device(device_id, addr, name))
+    }
+
+    /// True if the object of the device may be polled and published
+    pub fn admits_object(&self, device_id: u32, addr: SocketAddr, object: ObjectRef) -> bool {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        let names = self.names.read().unwrap_or_else(|e| e.into_inner());
+        let name = names.get(&device_id).map(String::as_str);
+        let matches = |rule: &Rule| rule.matches_device(device_id, addr, name) && rule.matches_object(object);
+        (rules.allow.is_empty() || rules.allow.iter().any(matches)) && !rules.deny.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(text: &str) -> SocketAddr {
+        SocketAddr::new(text.parse().unwrap(), 47808)
+    }
+
+    fn rule(instances: Option<&str>, object_types: &[&str], name: Option<&str>) -> FilterRule {
+        FilterRule {
+            instances: instances.map(str::to_string),
+            object_types: object_types.iter().map(|kind| kind.to_string()).collect(),
+            name: name.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn globs_match_ignoring_case() {
+        assert!(glob_matches("AHU-?", "ahu-1"));
+        assert!(!glob_matches("AHU-?", "ahu-12"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("ahu*", "AHU"));
+        assert!(!glob_matches("", "ahu"));
+        assert!(!glob_matches("ahu-?*", "ahu-"));
+    }
+
+    #[test]
+    fn star_backtracks_over_partial_matches() {
+        assert!(glob_matches("*ab", "aab"));
+        assert!(glob_matches("*North*Boiler", "Boiler North Boiler North Boiler"));
+        assert!(glob_matches("a*b*c", "abbbxbc"));
+        assert!(!glob_matches("a*b*c", "abbbxbcx"));
+        assert!(!glob_matches("*ab", "abba"));
+        assert!(glob_matches("**?", "x"));
+    }
+
+    #[test]
+    fn subnets_contain_their_addresses() {
+        let subnet = Subnet::parse("10.20.0.0/16").unwrap();
+        assert!(subnet.contains("10.20.255.1".parse().unwrap()));
+        assert!(!subnet.contains("10.21.0.1".parse().unwrap()));
+        assert!(!subnet.contains("::ffff:10.20.0.1".parse().unwrap()), "IPv6 addresses are not in IPv4 networks");
+        assert_eq!(subnet.broadcast(), Some("10.20.255.255".parse().unwrap()));
+
+        let v6 = Subnet::parse("fd00::/8").unwrap();
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains("fe80::1".parse().unwrap()));
+        assert_eq!(v6.broadcast(), None);
+
+        assert!(Subnet::parse("10.20.0.0/33").is_err());
+        assert!(Subnet::parse("10.20.0.0/x").is_err());
+    }
+
+    #[test]
+    fn zero_and_full_prefixes_do_not_overflow() {
+        let all = Subnet::parse("0.0.0.0/0").unwrap();
+        assert!(all.contains("192.168.1.20".parse().unwrap()));
+        assert_eq!(all.broadcast(), Some("255.255.255.255".parse().unwrap()));
+        assert!(Subnet::parse("::/0").unwrap().contains("fd12::1".parse().unwrap()));
+
+        for text in ["192.168.1.20/32", "192.168.1.20"] {
+            let host = Subnet::parse(text).unwrap();
+            assert!(host.contains("192.168.1.20".parse().unwrap()));
+            assert!(!host.contains("192.168.1.21".parse().unwrap()));
+            assert_eq!(host.broadcast(), Some("192.168.1.20".parse().unwrap()));
+        }
+        let host = Subnet::parse("fd00::1/128").unwrap();
+        assert!(host.contains("fd00::1".parse().unwrap()));
+        assert!(!host.contains("fd00::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_rules_take_precedence_over_allow_rules() {
+        let filter = DeviceFilter::new(&DeviceFilterConfig {
+            allow: vec![rule(Some("1000-1999"), &[], None)],
+            deny: vec![rule(Some("1500"), &["BV"], None), rule(None, &[], Some("test*"))],
+        })
+        .unwrap();
+        let (ai, bv) = (ObjectRef::new(0, 1), ObjectRef::new(5, 1));
+        let site = addr("192.168.1.20");
+
+        assert!(filter.admits_object(1200, site, ai));
+        assert!(filter.admits_object(1200, site, bv));
+        assert!(!filter.admits_object(2000, site, ai), "not allowed");
+        assert!(filter.admits_object(1500, site, ai));
+        assert!(!filter.admits_object(1500, site, bv), "denied object type");
+        assert!(filter.admits_device(1500, site), "object types deny objects, not the device");
+
+        filter.set_name(1200, "Test Bench");
+        assert!(!filter.admits_object(1200, site, ai), "denied name");
+        assert!(!filter.admits_device(1200, site));
+        assert!(!filter.excludes(1200, site), "name rules wait until the name is read");
+    }
+
+    #[test]
+    fn no_allow_rules_admit_everything_not_denied() {
+        let filter = DeviceFilter::new(&DeviceFilterConfig {
+            allow: Vec::new(),
+            deny: vec![FilterRule { subnet: Some("10.0.0.0/8".to_string()), ..Default::default() }],
+        })
+        .unwrap();
+        assert!(filter.admits_object(1200, addr("192.168.1.20"), ObjectRef::new(0, 1)));
+        assert!(!filter.admits_object(1200, addr("10.1.2.3"), ObjectRef::new(0, 1)));
+        assert!(filter.excludes(1200, addr("10.1.2.3")));
+    }
+}
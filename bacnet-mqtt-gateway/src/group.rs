@@ -0,0 +1,256 @@
+//! Home Assistant entities combining several points of one controller —
+//! thermostats, blind covers and fans — published on
+//! `<base>/<component>/<device>/<key object>/<value>`
+
+use crate::batch::BatchWrite;
+use crate::codec::BacnetValue;
+use crate::command;
+use crate::config::{ClimateConfig, CoverConfig, FanConfig, GatewayConfig};
+use crate::mqtt::{HaDevice, MqttService};
+use crate::point::ObjectRef;
+use std::collections::BTreeMap;
+use tracing::debug;
+
+fn present_value_write(device_id: u32, object: ObjectRef, value: serde_json::Value) -> BatchWrite {
+    BatchWrite { device_id, object, property: 85, array_index: None, value, value_type: None, priority: None }
+}
+
+/// Name of a multi-state value in a name to state table
+fn state_name<'a>(names: &'a BTreeMap<String, u32>, value: &BacnetValue) -> Option<&'a str> {
+    let BacnetValue::Unsigned(state) = value else {
+        return None;
+    };
+    names.iter().find(|(_, s)| *s == state).map(|(name, _)| name.as_str())
+}
+
+/// Write of the state a command names, e.g. HVAC mode `heat` or fan speed `high`
+fn named_state_write(device_id: u32, object: Option<ObjectRef>, names: &BTreeMap<String, u32>, payload: &[u8]) -> Option<Result<BatchWrite, String>> {
+    let object = object?;
+    let name = String::from_utf8_lossy(payload);
+    Some(match names.get(name.trim()) {
+        Some(state) => Ok(present_value_write(device_id, object, (*state).into())),
+        None => Err(format!("'{}' is not a configured state of {} {}", name.trim(), device_id, object)),
+    })
+}
+
+enum EntityGroup {
+    Climate(ClimateConfig),
+    Cover(CoverConfig),
+    Fan(FanConfig),
+}
+
+impl EntityGroup {
+    fn device(&self) -> u32 {
+        match self {
+            Self::Climate(c) => c.device,
+            Self::Cover(c) => c.device,
+            Self::Fan(f) => f.device,
+        }
+    }
+
+    fn component(&self) -> &'static str {
+        match self {
+            Self::Climate(_) => "climate",
+            Self::Cover(_) => "cover",
+            Self::Fan(_) => "fan",
+        }
+    }
+
+    /// Object identifying the group in its topics
+    fn key(&self) -> ObjectRef {
+        match self {
+            Self::Climate(c) => c.setpoint,
+            Self::Cover(c) => c.position,
+            Self::Fan(f) => f.command,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Climate(c) => &c.name,
+            Self::Cover(c) => &c.name,
+            Self::Fan(f) => &f.name,
+        }
+    }
+
+    fn priority(&self) -> Option<u8> {
+        match self {
+            Self::Climate(c) => c.priority,
+            Self::Cover(c) => c.priority,
+            Self::Fan(f) => f.priority,
+        }
+    }
+
+    /// Polled objects with the topic their values are published on
+    fn members(&self) -> Vec<(&'static str, ObjectRef)> {
+        let mut members = match self {
+            Self::Climate(c) => vec![("current_temperature", c.temperature), ("temperature", c.setpoint)],
+            Self::Cover(c) => vec![("position", c.position)],
+            Self::Fan(f) => vec![("state", f.command)],
+        };
+        match self {
+            Self::Climate(ClimateConfig { mode: Some(mode), .. }) => members.push(("mode", *mode)),
+            Self::Fan(FanConfig { speed: Some(speed), .. }) => members.push(("preset_mode", *speed)),
+            _ => {}
+        }
+        members
+    }
+
+    /// State payload of a member's present-value
+    fn state(&self, leaf: &str, value: &BacnetValue) -> Option<String> {
+        match (self, leaf) {
+            (Self::Climate(c), "mode") => state_name(&c.modes, value).map(str::to_string),
+            (Self::Fan(f), "preset_mode") => state_name(&f.speeds, value).map(str::to_string),
+            (Self::Fan(_), "state") => Some(if matches!(value, BacnetValue::Enumerated(0)) { "OFF" } else { "ON" }.to_string()),
+            _ => Some(value.to_string()),
+        }
+    }
+
+    /// Write requested on one of the group's command topics
+    fn command(&self, leaf: &str, payload: &[u8]) -> Option<Result<BatchWrite, String>> {
+        let device = self.device();
+        match (self, leaf) {
+            (Self::Climate(c), "temperature") => Some(command::parse_command(device, c.setpoint, payload, None)),
+            (Self::Climate(c), "mode") => named_state_write(device, c.mode, &c.modes, payload),
+            (Self::Cover(c), "position") => Some(command::parse_command(device, c.position, payload, None)),
+            (Self::Cover(c), "command") => Some(match String::from_utf8_lossy(payload).trim() {
+                "OPEN" => Ok(present_value_write(device, c.position, 100.into())),
+                "CLOSE" => Ok(present_value_write(device, c.position, 0.into())),
+                other => Err(format!("cover {} {} cannot {}", device, c.position, other)),
+            }),
+            (Self::Fan(f), "state") => Some(command::parse_command(device, f.command, payload, None)),
+            (Self::Fan(f), "preset_mode") => named_state_write(device, f.speed, &f.speeds, payload),
+            _ => None,
+        }
+    }
+
+    /// Component specific discovery fields
+    fn discovery(&self, topic: &dyn Fn(&str) -> String, command_topic: &dyn Fn(&str) -> String) -> serde_json::Value {
+        let mut payload = serde_json::json!({});
+        match self {
+            Self::Climate(c) => {
+                payload["current_temperature_topic"] = topic("current_temperature").into();
+                payload["temperature_state_topic"] = topic("temperature").into();
+                payload["temperature_command_topic"] = command_topic("temperature").into();
+                if c.mode.is_some() && !c.modes.is_empty() {
+                    payload["mode_state_topic"] = topic("mode").into();
+                    payload["mode_command_topic"] = command_topic("mode").into();
+                    payload["modes"] = c.modes.keys().cloned().collect::<Vec<_>>().into();
+                } else {
+                    payload["modes"] = serde_json::json!(["auto"]);
+                }
+                for (key, value) in [("min_temp", c.min_temp), ("max_temp", c.max_temp)] {
+                    if let Some(value) = value {
+                        payload[key] = value.into();
+                    }
+                }
+                if let Some(unit) = &c.temperature_unit {
+                    payload["temperature_unit"] = unit.clone().into();
+                }
+            }
+            Self::Cover(c) => {
+                payload["command_topic"] = command_topic("command").into();
+                payload["position_topic"] = topic("position").into();
+                payload["set_position_topic"] = command_topic("position").into();
+                // Positioning actuators have no stop command
+                payload["payload_stop"] = serde_json::Value::Null;
+                if let Some(device_class) = &c.device_class {
+                    payload["device_class"] = device_class.clone().into();
+                }
+            }
+            Self::Fan(f) => {
+                payload["state_topic"] = topic("state").into();
+                payload["command_topic"] = command_topic("state").into();
+                if f.speed.is_some() && !f.speeds.is_empty() {
+                    payload["preset_mode_state_topic"] = topic("preset_mode").into();
+                    payload["preset_mode_command_topic"] = command_topic("preset_mode").into();
+                    payload["preset_modes"] = f.speeds.keys().cloned().collect::<Vec<_>>().into();
+                }
+            }
+        }
+        payload
+    }
+}
+
+/// Thermostats, covers and fans configured for the gateway's devices
+pub struct EntityGroups {
+    groups: Vec<EntityGroup>,
+    mqtt: MqttService,
+}
+
+impl EntityGroups {
+    pub fn new(config: &GatewayConfig, mqtt: MqttService) -> Self {
+        let groups = config
+            .climates
+            .iter()
+            .cloned()
+            .map(EntityGroup::Climate)
+            .chain(config.covers.iter().cloned().map(EntityGroup::Cover))
+            .chain(config.fans.iter().cloned().map(EntityGroup::Fan))
+            .collect();
+        Self { groups, mqtt }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    fn of_device(&self, device_id: u32) -> impl Iterator<Item = &EntityGroup> {
+        self.groups.iter().filter(move |g| g.device() == device_id)
+    }
+
+    /// Objects the device's grouped entities need polled
+    pub fn points(&self, device_id: u32) -> Vec<ObjectRef> {
+        let mut points: Vec<ObjectRef> =
+            self.of_device(device_id).flat_map(|g| g.members()).map(|(_, object)| object).collect();
+        points.sort_unstable();
+        points.dedup();
+        points
+    }
+
+    /// Publishes the discovery configs of the device's grouped entities
+    pub async fn publish_discovery(&self, device_id: u32, device: &HaDevice) {
+        for group in self.of_device(device_id) {
+            let (component, key) = (group.component(), group.key());
+            let topic = |leaf: &str| self.mqtt.group_topic(component, device_id, key, leaf);
+            let command_topic = |leaf: &str| self.mqtt.group_command_topic(component, device_id, key, leaf);
+            let unique_id = format!("bacnet_{}_{}_{}_{}", device_id, component, key.object_type, key.instance);
+            let (availability, availability_mode) = self.mqtt.availability(Some(device_id));
+            let mut payload = group.discovery(&topic, &command_topic);
+            payload["name"] = group.name().into();
+            payload["unique_id"] = unique_id.clone().into();
+            payload["availability"] = serde_json::json!(availability);
+            payload["availability_mode"] = availability_mode.into();
+            payload["device"] = serde_json::json!(device);
+            self.mqtt.publish_discovery(component, &unique_id, Some(device_id), &payload).await;
+        }
+    }
+
+    /// Publishes a polled present-value to the grouped entities it belongs
+    /// to, returning false if it belongs to none
+    pub async fn update(&self, device_id: u32, object: ObjectRef, value: &BacnetValue) -> bool {
+        let mut matched = false;
+        for group in self.of_device(device_id) {
+            for (leaf, member) in group.members() {
+                if member != object {
+                    continue;
+                }
+                matched = true;
+                let topic = self.mqtt.group_topic(group.component(), device_id, group.key(), leaf);
+                match group.state(leaf, value) {
+                    Some(state) => self.mqtt.publish_state(&topic, &state).await,
+                    None => debug!("{} of {} {} has no configured name", value, device_id, object),
+                }
+            }
+        }
+        matched
+    }
+
+    /// Write requested on a grouped entity's command topic
+    pub fn parse_command(&self, topic: &str, payload: &[u8]) -> Option<Result<BatchWrite, String>> {
+        let (component, device_id, key, leaf) = self.mqtt.parse_group_command_topic(topic)?;
+        let group = self.of_device(device_id).find(|g| g.component() == component && g.key() == key)?;
+        let write = group.command(&leaf, payload)?;
+        Some(write.map(|write| BatchWrite { priority: write.priority.or(group.priority()), ..write }))
+    }
+}
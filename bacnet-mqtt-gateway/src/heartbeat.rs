@@ -0,0 +1,101 @@
+use crate::bacnet::BacnetEngine;
+use crate::batch::{self, BatchWrite};
+use crate::cluster::Cluster;
+use crate::config::HeartbeatConfig;
+use crate::mqtt::MqttService;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{debug, error, info, warn};
+
+/// Consecutive failures of one heartbeat and whether they were reported
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    reported: bool,
+}
+
+impl Health {
+    /// Records a write outcome; true when the heartbeat just crossed into or
+    /// recovered from the failed state
+    fn record(&mut self, ok: bool, max_failures: u32) -> bool {
+        if ok {
+            self.failures = 0;
+            return std::mem::take(&mut self.reported);
+        }
+        self.failures += 1;
+        if !self.reported && self.failures >= max_failures.max(1) {
+            self.reported = true;
+            return true;
+        }
+        false
+    }
+}
+
+async fn write(
+    engine: &BacnetEngine,
+    devices: &RwLock<HashMap<u32, SocketAddr>>,
+    config: &HeartbeatConfig,
+    value: serde_json::Value,
+) -> Result<(), String> {
+    let addr = devices
+        .read()
+        .await
+        .get(&config.device)
+        .copied()
+        .ok_or_else(|| format!("device {} has not been discovered", config.device))?;
+    let spec = batch::encode_write(&BatchWrite {
+        device_id: config.device,
+        object: config.object,
+        property: 85,
+        array_index: None,
+        value,
+        value_type: None,
+        priority: config.priority,
+    })?;
+    engine.write_property(addr, &spec).await.map_err(|e| e.to_string())
+}
+
+/// Writes the configured values in turn forever, publishing the heartbeat's
+/// health whenever writes start failing or succeed again; in a cluster only
+/// the device's owner writes
+pub async fn run(
+    engine: Arc<BacnetEngine>,
+    mqtt: MqttService,
+    devices: Arc<RwLock<HashMap<u32, SocketAddr>>>,
+    cluster: Arc<Cluster>,
+    config: HeartbeatConfig,
+) {
+    if config.values.is_empty() {
+        warn!("Heartbeat of device {} {} has no values to write", config.device, config.object);
+        return;
+    }
+    info!("Heartbeat to device {} {} every {}s", config.device, config.object, config.interval_secs);
+    mqtt.publish_heartbeat(config.device, config.object, 0, None).await;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    let mut health = Health::default();
+    for value in config.values.iter().cycle() {
+        interval.tick().await;
+        if !cluster.owns(config.device) {
+            continue;
+        }
+        let result = write(&engine, &devices, &config, value.clone()).await;
+        match &result {
+            Ok(()) => debug!("Heartbeat {} written to device {} {}", value, config.device, config.object),
+            Err(e) => warn!("Heartbeat write to device {} {} failed: {}", config.device, config.object, e),
+        }
+        if health.record(result.is_ok(), config.max_failures) {
+            let error = result.err();
+            match &error {
+                Some(e) => error!(
+                    "Heartbeat to device {} {} failed {} times in a row, the controller may revert to standalone mode: {}",
+                    config.device, config.object, health.failures, e
+                ),
+                None => info!("Heartbeat to device {} {} recovered", config.device, config.object),
+            }
+            mqtt.publish_heartbeat(config.device, config.object, health.failures, error.as_deref()).await;
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Short-term history of every published point value in an embedded SQLite
+//! database, for sites without an external time-series database, exported
+//! as CSV or Parquet
+
+use crate::config::HistorianConfig;
+use crate::export::csv_field;
+use crate::maintenance;
+use crate::mqtt::{state_json, utc_timestamp, utc_timestamp_at, Quality};
+use crate::point::ObjectRef;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Interval expired values are pruned at
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Interval queued values are written at
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Values queued while the database is busy, the oldest dropped beyond
+const MAX_PENDING: usize = 100_000;
+/// Samples a query returns without a limit
+pub const DEFAULT_LIMIT: u32 = 10_000;
+/// Columns of an export, numbers in `value` and text states in `text`
+const PARQUET_SCHEMA: &str = "message history {
+    required int64 device_id;
+    required binary object (UTF8);
+    required int64 ts (TIMESTAMP(MILLIS,true));
+    optional double value;
+    optional binary text (UTF8);
+    required binary quality (UTF8);
+}";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub ts: String,
+    pub value: serde_json::Value,
+    pub quality: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PointHistory {
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub samples: Vec<Sample>,
+}
+
+/// A point with stored history
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredPoint {
+    pub device_id: u32,
+    pub object: ObjectRef,
+    pub samples: u64,
+    pub first: String,
+    pub last: String,
+}
+
+/// A bound in the stored format, milliseconds added so `...:00Z` does not
+/// sort after `...:00.000Z`
+fn bound(timestamp: Option<&str>) -> Option<String> {
+    timestamp.map(|ts| match ts.strip_suffix('Z') {
+        Some(seconds) if seconds.len() == 19 => format!("{}.000Z", seconds),
+        _ => ts.to_string(),
+    })
+}
+
+/// Milliseconds since the epoch of a timestamp in the stored format
+fn epoch_millis(ts: &str) -> Option<i64> {
+    let field = |range: std::ops::Range<usize>| ts.get(range)?.parse::<i64>().ok();
+    let days = maintenance::days_from_civil(field(0..4)?, field(5..7)? as u32, field(8..10)? as u32);
+    let secs = days * 86_400 + field(11..13)? * 3600 + field(14..16)? * 60 + field(17..19)?;
+    Some(secs * 1000 + field(20..23).unwrap_or(0))
+}
+
+pub fn to_csv(history: &[PointHistory]) -> String {
+    let mut csv = String::from("device_id,object,ts,value,quality\r\n");
+    for point in history {
+        for sample in &point.samples {
+            let value = match &sample.value {
+                serde_json::Value::String(text) => csv_field(text),
+                value => value.to_string(),
+            };
+            csv.push_str(&format!("{},{},{},{},{}\r\n", point.device_id, csv_field(&point.object.to_string()), sample.ts, value, sample.quality));
+        }
+    }
+    csv
+}
+
+/// The samples as a Parquet file with a single row group
+pub fn to_parquet(history: &[PointHistory]) -> Result<Vec<u8>, parquet::errors::ParquetError> {
+    let rows: Vec<(&PointHistory, &Sample)> = history.iter().flat_map(|point| point.samples.iter().map(move |sample| (point, sample))).collect();
+    let text = |value: &str| ByteArray::from(value.as_bytes().to_vec());
+    let device_ids: Vec<i64> = rows.iter().map(|(point, _)| i64::from(point.device_id)).collect();
+    let objects: Vec<ByteArray> = rows.iter().map(|(point, _)| text(&point.object.to_string())).collect();
+    let timestamps: Vec<i64> = rows.iter().map(|(_, sample)| epoch_millis(&sample.ts).unwrap_or_default()).collect();
+    let numbers: Vec<Option<f64>> = rows.iter().map(|(_, sample)| sample.value.as_f64()).collect();
+    let texts: Vec<Option<ByteArray>> = rows.iter().map(|(_, sample)| sample.value.as_str().map(text)).collect();
+    let qualities: Vec<ByteArray> = rows.iter().map(|(_, sample)| text(&sample.quality)).collect();
+
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let mut writer = SerializedFileWriter::new(Vec::new(), schema, Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => column.typed::<Int64Type>().write_batch(&device_ids, None, None)?,
+            1 => column.typed::<ByteArrayType>().write_batch(&objects, None, None)?,
+            2 => column.typed::<Int64Type>().write_batch(&timestamps, None, None)?,
+            3 => {
+                let levels: Vec<i16> = numbers.iter().map(|number| i16::from(number.is_some())).collect();
+                let values: Vec<f64> = numbers.iter().flatten().copied().collect();
+                column.typed::<DoubleType>().write_batch(&values, Some(&levels), None)?
+            }
+            4 => {
+                let levels: Vec<i16> = texts.iter().map(|text| i16::from(text.is_some())).collect();
+                let values: Vec<ByteArray> = texts.iter().flatten().cloned().collect();
+                column.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?
+            }
+            _ => column.typed::<ByteArrayType>().write_batch(&qualities, None, None)?,
+        };
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.into_inner()
+}
+
+/// A published value waiting to be written
+struct Pending {
+    device_id: u32,
+    object: ObjectRef,
+    ts: String,
+    value: String,
+    quality: Quality,
+}
+
+pub struct Historian {
+    connection: Mutex<Connection>,
+    retention: Duration,
+    pending: Mutex<Vec<Pending>>,
+}
+
+impl Historian {
+    pub fn open(config: &HistorianConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let connection = Connection::open(&config.file)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS samples (
+                 device_id INTEGER NOT NULL,
+                 object TEXT NOT NULL,
+                 ts TEXT NOT NULL,
+                 value TEXT NOT NULL,
+                 quality TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS samples_point_ts ON samples (device_id, object, ts);
+             CREATE INDEX IF NOT EXISTS samples_ts ON samples (ts);",
+        )?;
+        info!("Recording history to {}", config.file.display());
+        Ok(Self {
+            connection: Mutex::new(connection),
+            retention: Duration::from_secs(config.retention_hours * 3600),
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Queues a value as it was published, written by `run` off the async workers
+    pub fn record(&self, device_id: u32, object: ObjectRef, value: &str, quality: Quality) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.push(Pending { device_id, object, ts: utc_timestamp(), value: value.to_string(), quality });
+        if pending.len() > MAX_PENDING {
+            let excess = pending.len() - MAX_PENDING;
+            pending.drain(..excess);
+        }
+    }
+
+    /// Writes the queued values in one transaction; blocks on SQLite
+    fn flush(&self) -> Result<usize, rusqlite::Error> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let transaction = connection.transaction()?;
+        {
+            let mut statement =
+                transaction.prepare_cached("INSERT INTO samples (device_id, object, ts, value, quality) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            for sample in &pending {
+                statement.execute(params![sample.device_id, sample.object.to_string(), sample.ts, sample.value, sample.quality.as_str()])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(pending.len())
+    }
+
+    /// Samples of a point, oldest first; `from` and `to` are inclusive RFC 3339
+    /// UTC timestamps, which compare as text. Blocks on SQLite
+    pub fn query(
+        &self,
+        device_id: u32,
+        object: ObjectRef,
+        from: Option<&str>,
+        to: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<Sample>, rusqlite::Error> {
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = connection.prepare(
+            "SELECT ts, value, quality FROM samples
+             WHERE device_id = ?1 AND object = ?2 AND (?3 IS NULL OR ts >= ?3) AND (?4 IS NULL OR ts <= ?4)
+             ORDER BY ts LIMIT ?5",
+        )?;
+        let rows = statement.query_map(params![device_id, object.to_string(), bound(from), bound(to), limit], |row| {
+            let value: String = row.get(1)?;
+            Ok(Sample { ts: row.get(0)?, value: state_json(&value), quality: row.get(2)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Every point with stored samples. Blocks on SQLite
+    pub fn points(&self) -> Result<Vec<StoredPoint>, rusqlite::Error> {
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = connection.prepare(
+            "SELECT device_id, object, COUNT(*), MIN(ts), MAX(ts) FROM samples GROUP BY device_id, object ORDER BY device_id, object",
+        )?;
+        let rows = statement.query_map([], |row| {
+            let object: String = row.get(1)?;
+            Ok((row.get(0)?, object, row.get(2)?, row.get(3)?, row.get(4)?))
+        })?;
+        let mut points = Vec::new();
+        for row in rows {
+            let (device_id, object, samples, first, last) = row?;
+            // Skips rows the gateway did not write
+            let Ok(object) = object.parse() else {
+                continue;
+            };
+            points.push(StoredPoint { device_id, object, samples, first, last });
+        }
+        Ok(points)
+    }
+
+    fn prune(&self) -> Result<usize, rusqlite::Error> {
+        let cutoff = utc_timestamp_at(SystemTime::now() - self.retention);
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        connection.execute("DELETE FROM samples WHERE ts < ?1", params![cutoff])
+    }
+}
+
+/// Writes the queued values every second and prunes values older than the
+/// retention period hourly, both on the blocking thread pool
+pub async fn run(historian: Arc<Historian>) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = flush.tick() => {
+                let writer = historian.clone();
+                match tokio::task::spawn_blocking(move || writer.flush()).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("Failed to record values in the history: {}", e),
+                    Err(e) => warn!("Failed to record values in the history: {}", e),
+                }
+            }
+            _ = prune.tick() => {
+                let pruner = historian.clone();
+                match tokio::task::spawn_blocking(move || pruner.prune()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(pruned)) => info!("Pruned {} expired values from the history", pruned),
+                    Ok(Err(e)) => warn!("Failed to prune the history: {}", e),
+                    Err(e) => warn!("Failed to prune the history: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Homie 4.0 convention output: the gateway is one Homie device, every
+//! discovered BACnet device one of its nodes and every polled point a
+//! property of that node, settable where the object is commandable
+
+use crate::codec::{self, BacnetValue};
+use crate::config::HomieConfig;
+use crate::mqtt::{self, MqttService};
+use crate::point::{ObjectRef, PointMetadata};
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+use tracing::info;
+
+const HOMIE_VERSION: &str = "4.0";
+
+/// `$state` the broker publishes as the gateway's will
+pub fn will(config: &HomieConfig) -> (String, &'static str) {
+    (format!("{}/{}/$state", config.root, config.device_id), "lost")
+}
+
+fn node_id(device_id: u32) -> String {
+    format!("device-{}", device_id)
+}
+
+/// Homie ids allow lowercase letters, digits and hyphens only
+fn property_id(object: ObjectRef) -> String {
+    match object.type_abbreviation() {
+        Some(abbreviation) => format!("{}-{}", abbreviation.to_ascii_lowercase(), object.instance),
+        None => format!("type{}-{}", object.object_type, object.instance),
+    }
+}
+
+fn parse_property_id(id: &str) -> Option<ObjectRef> {
+    let (object_type, instance) = id.rsplit_once('-')?;
+    let object = match object_type.strip_prefix("type") {
+        Some(number) => ObjectRef::new(number.parse().ok()?, instance.parse().ok()?),
+        None => format!("{}:{}", object_type.to_ascii_uppercase(), instance).parse().ok()?,
+    };
+    (property_id(object) == id).then_some(object)
+}
+
+/// Attributes of a property as announced in `$properties`
+#[derive(Debug, Clone, PartialEq)]
+struct Property {
+    datatype: &'static str,
+    /// Comma-separated states of enum properties
+    format: Option<String>,
+    unit: Option<String>,
+    settable: bool,
+}
+
+impl Property {
+    fn new(object: ObjectRef, value: &BacnetValue, metadata: Option<&PointMetadata>) -> Self {
+        let options = metadata.map(PointMetadata::options).unwrap_or_default();
+        let (datatype, format) = match value {
+            _ if object.is_binary() => ("boolean", None),
+            _ if !options.is_empty() => ("enum", Some(options.join(","))),
+            BacnetValue::Boolean(_) => ("boolean", None),
+            BacnetValue::Unsigned(_) | BacnetValue::Signed(_) | BacnetValue::Enumerated(_) => ("integer", None),
+            BacnetValue::Real(_) | BacnetValue::Double(_) => ("float", None),
+            _ => ("string", None),
+        };
+        let unit = metadata
+            .and_then(|metadata| metadata.units)
+            .and_then(crate::units::ha_unit)
+            .and_then(|unit| unit.unit_of_measurement)
+            .map(str::to_string);
+        let settable = mqtt::is_commandable(mqtt::ha_component(object, !options.is_empty()));
+        Self { datatype, format, unit, settable }
+    }
+}
+
+/// Homie payload of a value: binary objects as `true`/`false`, named states by name
+fn payload(object: ObjectRef, value: &BacnetValue, metadata: Option<&PointMetadata>) -> String {
+    if let (true, BacnetValue::Enumerated(state)) = (object.is_binary(), value) {
+        return (*state != 0).to_string();
+    }
+    if let Some(text) = metadata.filter(|m| !m.state_texts.is_empty()).and_then(|m| m.state_text(value)) {
+        return text.to_string();
+    }
+    codec::state_text(object, value)
+}
+
+/// The gateway as a Homie device
+pub struct HomieDevice {
+    config: HomieConfig,
+    mqtt: MqttService,
+    /// Properties of each node, held while publishing so the topology stays consistent
+    nodes: Mutex<BTreeMap<u32, BTreeMap<ObjectRef, Property>>>,
+}
+
+impl HomieDevice {
+    pub fn new(config: HomieConfig, mqtt: MqttService) -> Self {
+        Self { config, mqtt, nodes: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn topic(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.config.root, self.config.device_id, path)
+    }
+
+    /// Subscription filter of the `set` topics of every property
+    pub fn set_filter(&self) -> String {
+        self.topic("+/+/set")
+    }
+
+    /// Device and object a `set` topic addresses
+    pub fn parse_set_topic(&self, topic: &str) -> Option<(u32, ObjectRef)> {
+        let rest = topic.strip_prefix(&self.topic(""))?;
+        let (node, rest) = rest.split_once('/')?;
+        let (property, leaf) = rest.split_once('/')?;
+        if leaf != "set" {
+            return None;
+        }
+        let device_id = node.strip_prefix("device-")?.parse().ok()?;
+        Some((device_id, parse_property_id(property)?))
+    }
+
+    async fn publish(&self, path: &str, payload: &str) {
+        self.mqtt.publish(&self.topic(path), payload, true).await;
+    }
+
+    async fn publish_nodes(&self, nodes: &BTreeMap<u32, BTreeMap<ObjectRef, Property>>) {
+        let node_ids: Vec<String> = nodes.keys().map(|id| node_id(*id)).collect();
+        self.publish("$nodes", &node_ids.join(",")).await;
+    }
+
+    async fn publish_node(&self, device_id: u32, properties: &BTreeMap<ObjectRef, Property>) {
+        let node = node_id(device_id);
+        self.publish(&format!("{}/$name", node), &format!("BACnet device {}", device_id)).await;
+        self.publish(&format!("{}/$type", node), "BACnet device").await;
+        let property_ids: Vec<String> = properties.keys().map(|object| property_id(*object)).collect();
+        self.publish(&format!("{}/$properties", node), &property_ids.join(",")).await;
+        for (object, property) in properties {
+            let path = format!("{}/{}", node, property_id(*object));
+            self.publish(&format!("{}/$name", path), &object.to_string()).await;
+            self.publish(&format!("{}/$datatype", path), property.datatype).await;
+            self.publish(&format!("{}/$settable", path), &property.settable.to_string()).await;
+            if let Some(format) = &property.format {
+                self.publish(&format!("{}/$format", path), format).await;
+            }
+            if let Some(unit) = &property.unit {
+                self.publish(&format!("{}/$unit", path), unit).await;
+            }
+        }
+    }
+
+    /// Announces the whole topology, on every broker connection
+    pub async fn announce(&self) {
+        let nodes = self.nodes.lock().await;
+        self.publish("$state", "init").await;
+        self.publish("$homie", HOMIE_VERSION).await;
+        self.publish("$name", &self.config.name).await;
+        self.publish("$extensions", "").await;
+        self.publish_nodes(&nodes).await;
+        for (device_id, properties) in nodes.iter() {
+            self.publish_node(*device_id, properties).await;
+        }
+        self.publish("$state", "ready").await;
+        info!("Published Homie topology with {} nodes", nodes.len());
+    }
+
+    /// Publishes a point's value, first re-announcing the topology if the
+    /// point is new or its attributes changed
+    pub async fn update(&self, device_id: u32, object: ObjectRef, value: &BacnetValue, metadata: Option<&PointMetadata>) {
+        let property = Property::new(object, value, metadata);
+        let mut nodes = self.nodes.lock().await;
+        let new_node = !nodes.contains_key(&device_id);
+        let properties = nodes.entry(device_id).or_default();
+        if properties.get(&object) != Some(&property) {
+            // Controllers are told the topology changes while it is updated
+            properties.insert(object, property);
+            self.publish("$state", "init").await;
+            if new_node {
+                self.publish_nodes(&nodes).await;
+            }
+            if let Some(properties) = nodes.get(&device_id) {
+                self.publish_node(device_id, properties).await;
+            }
+            self.publish("$state", "ready").await;
+        }
+        let path = format!("{}/{}", node_id(device_id), property_id(object));
+        self.publish(&path, &payload(object, value, metadata)).await;
+    }
+}
@@ -0,0 +1,165 @@
+//! Point values written to InfluxDB as line protocol alongside MQTT, for
+//! feeding Grafana without a bridge from the broker
+
+use crate::cloud::url_encode;
+use crate::config::{InfluxApi, InfluxConfig};
+use crate::mqtt::{state_json, Quality};
+use crate::point::ObjectRef;
+use crate::secret;
+use crate::webhook;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+/// Batches kept while the server cannot be reached, the oldest points dropped beyond
+const MAX_PENDING_BATCHES: usize = 10;
+
+/// Escapes commas, equals signs and spaces in tag keys and values
+fn escape_tag(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn escape_measurement(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(',', "\\,").replace(' ', "\\ ")
+}
+
+fn string_field(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub struct InfluxWriter {
+    /// Write URL with the target and millisecond precision in its query
+    url: String,
+    /// `Authorization` header, kept out of the URL so failures do not log credentials
+    authorization: Option<String>,
+    measurement: String,
+    site: String,
+    batch_size: usize,
+    flush_interval: Duration,
+    lines: Mutex<Vec<String>>,
+    full: Notify,
+}
+
+impl InfluxWriter {
+    pub fn new(config: &InfluxConfig, topic_site: &str) -> Result<Self, String> {
+        let base = config.url.trim_end_matches('/');
+        let (url, authorization) = match config.api {
+            InfluxApi::V2 => {
+                let token = secret::resolve(config.token.as_deref(), config.token_file.as_deref())
+                    .map_err(|e| format!("influxdb.token: {}", e))?;
+                let url = format!(
+                    "{}/api/v2/write?org={}&bucket={}&precision=ms",
+                    base,
+                    url_encode(config.org.as_deref().unwrap_or_default()),
+                    url_encode(&config.bucket)
+                );
+                (url, token.map(|token| format!("Token {}", token)))
+            }
+            InfluxApi::V1 => {
+                let password = secret::resolve(config.password.as_deref(), config.password_file.as_deref())
+                    .map_err(|e| format!("influxdb.password: {}", e))?;
+                let url = format!("{}/write?db={}&precision=ms", base, url_encode(&config.bucket));
+                let authorization = config.username.as_ref().map(|username| {
+                    format!("Basic {}", BASE64.encode(format!("{}:{}", username, password.unwrap_or_default())))
+                });
+                (url, authorization)
+            }
+        };
+        Ok(Self {
+            url,
+            authorization,
+            measurement: escape_measurement(&config.measurement),
+            site: config.site.clone().unwrap_or_else(|| topic_site.to_string()),
+            batch_size: config.batch_size,
+            flush_interval: Duration::from_secs(config.flush_interval_secs),
+            lines: Mutex::new(Vec::new()),
+            full: Notify::new(),
+        })
+    }
+
+    /// Queues a published value: numbers as the float field `value`, state
+    /// names and other text as the string field `text`
+    pub fn record(&self, device_id: u32, object: ObjectRef, value: &str, quality: Quality) {
+        let mut tags = format!("{},device={}", self.measurement, device_id);
+        if !self.site.is_empty() {
+            tags.push_str(&format!(",site={}", escape_tag(&self.site)));
+        }
+        let object_type = object.type_abbreviation().map_or_else(|| object.object_type.to_string(), str::to_string);
+        tags.push_str(&format!(",object_type={},object={}", escape_tag(&object_type), escape_tag(&object.to_string())));
+        let field = match state_json(value) {
+            serde_json::Value::Number(number) => format!("value={}", number),
+            _ => format!("text={}", string_field(value)),
+        };
+        let ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let line = format!("{} {},quality={} {}", tags, field, string_field(quality.as_str()), ms);
+
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.push(line);
+        let pending = lines.len();
+        if pending > self.batch_size * MAX_PENDING_BATCHES {
+            lines.drain(..pending - self.batch_size * MAX_PENDING_BATCHES);
+        }
+        if pending >= self.batch_size {
+            self.full.notify_one();
+        }
+    }
+
+    /// Writes what is queued batch by batch, stopping at the first failure
+    async fn flush(&self) -> Result<(), ()> {
+        loop {
+            let batch: Vec<String> = {
+                let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+                let take = lines.len().min(self.batch_size);
+                lines.drain(..take).collect()
+            };
+            if batch.is_empty() {
+                return Ok(());
+            }
+            let body = batch.join("\n");
+            let headers: Vec<(&str, &str)> = self.authorization.iter().map(|value| ("Authorization", value.as_str())).collect();
+            match webhook::post(&self.url, "text/plain; charset=utf-8", &headers, &body).await {
+                Ok(()) => debug!("Wrote {} points to InfluxDB", batch.len()),
+                Err(e) => {
+                    warn!("Failed to write {} points to InfluxDB: {}", batch.len(), e);
+                    // Written again at the next flush
+                    let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+                    let newer = std::mem::replace(&mut *lines, batch);
+                    lines.extend(newer);
+                    return Err(());
+                }
+            }
+        }
+    }
+}
+
+/// Writes a batch whenever one fills up, and what is queued at every flush
+/// interval; after a failure the next flush interval is awaited
+pub async fn run(writer: Arc<InfluxWriter>) {
+    let mut interval = tokio::time::interval(writer.flush_interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = writer.full.notified() => {}
+        }
+        if writer.flush().await.is_err() {
+            interval.tick().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_credentials_are_sent_as_basic_auth_header() {
+        let config: InfluxConfig =
+            serde_yaml::from_str("{ url: 'http://influxdb:8086/', api: v1, bucket: site, username: grafana, password: s3cret }").unwrap();
+        let writer = InfluxWriter::new(&config, "plant").unwrap();
+        assert_eq!(writer.url, "http://influxdb:8086/write?db=site&precision=ms");
+        assert_eq!(writer.authorization.as_deref(), Some("Basic Z3JhZmFuYTpzM2NyZXQ="));
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Languages with a built-in translation table
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LocaleConfig {
+    #[serde(default)]
+    pub language: Locale,
+    /// Site specific wording, keyed by text name (e.g. `device_name: "Regler {}"`),
+    /// taking precedence over the built-in table
+    #[serde(default)]
+    pub translations: HashMap<String, String>,
+}
+
+/// Generated texts, `{}` marks where an argument is inserted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+    DeviceName,
+    GenericDeviceModel,
+    VendorId,
+    GatewayName,
+    GatewayStatus,
+    ActiveAlarms,
+    DevicesOffline,
+    PointFault,
+    Active,
+    Inactive,
+    EventNormal,
+    EventOffnormal,
+}
+
+impl Text {
+    /// Name used to override the text in the configuration
+    pub fn key(self) -> &'static str {
+        match self {
+            Text::DeviceName => "device_name",
+            Text::GenericDeviceModel => "generic_device_model",
+            Text::VendorId => "vendor_id",
+            Text::GatewayName => "gateway_name",
+            Text::GatewayStatus => "gateway_status",
+            Text::ActiveAlarms => "active_alarms",
+            Text::DevicesOffline => "devices_offline",
+            Text::PointFault => "point_fault",
+            Text::Active => "active",
+            Text::Inactive => "inactive",
+            Text::EventNormal => "event_normal",
+            Text::EventOffnormal => "event_offnormal",
+        }
+    }
+
+    fn builtin(self, locale: Locale) -> &'static str {
+        use Locale::*;
+        match (self, locale) {
+            (Text::DeviceName, En) => "BACnet Device {}",
+            (Text::DeviceName, De) => "BACnet-Gerät {}",
+            (Text::DeviceName, Fr) => "Appareil BACnet {}",
+            (Text::DeviceName, Es) => "Dispositivo BACnet {}",
+            (Text::GenericDeviceModel, En) => "Generic BACnet Device",
+            (Text::GenericDeviceModel, De) => "Generisches BACnet-Gerät",
+            (Text::GenericDeviceModel, Fr) => "Appareil BACnet générique",
+            (Text::GenericDeviceModel, Es) => "Dispositivo BACnet genérico",
+            (Text::VendorId, En) => "Vendor ID {}",
+            (Text::VendorId, De) => "Hersteller-ID {}",
+            (Text::VendorId, Fr) => "ID fabricant {}",
+            (Text::VendorId, Es) => "ID de fabricante {}",
+            (Text::GatewayName, En | De) => "BACnet-MQTT Gateway {}",
+            (Text::GatewayName, Fr) => "Passerelle BACnet-MQTT {}",
+            (Text::GatewayName, Es) => "Pasarela BACnet-MQTT {}",
+            (Text::GatewayStatus, En) => "BACnet Gateway Status",
+            (Text::GatewayStatus, De) => "BACnet-Gateway-Status",
+            (Text::GatewayStatus, Fr) => "État de la passerelle BACnet",
+            (Text::GatewayStatus, Es) => "Estado de la pasarela BACnet",
+            (Text::ActiveAlarms, En) => "Active alarms",
+            (Text::ActiveAlarms, De) => "Aktive Alarme",
+            (Text::ActiveAlarms, Fr) => "Alarmes actives",
+            (Text::ActiveAlarms, Es) => "Alarmas activas",
+            (Text::DevicesOffline, En) => "Devices offline",
+            (Text::DevicesOffline, De) => "Geräte offline",
+            (Text::DevicesOffline, Fr) => "Appareils hors ligne",
+            (Text::DevicesOffline, Es) => "Dispositivos sin conexión",
+            (Text::PointFault, En) => "Point fault",
+            (Text::PointFault, De) => "Datenpunktstörung",
+            (Text::PointFault, Fr) => "Défaut de point",
+            (Text::PointFault, Es) => "Fallo de punto",
+            (Text::Active, En) => "Active",
+            (Text::Active, De) => "Aktiv",
+            (Text::Active, Fr) => "Actif",
+            (Text::Active, Es) => "Activo",
+            (Text::Inactive, En) => "Inactive",
+            (Text::Inactive, De) => "Inaktiv",
+            (Text::Inactive, Fr) => "Inactif",
+            (Text::Inactive, Es) => "Inactivo",
+            (Text::EventNormal, _) => "Normal",
+            (Text::EventOffnormal, En) => "Off-normal",
+            (Text::EventOffnormal, De) => "Abweichung",
+            (Text::EventOffnormal, Fr | Es) => "Anormal",
+        }
+    }
+}
+
+/// Looks up generated texts in the configured language
+#[derive(Debug, Clone)]
+pub struct Translator {
+    locale: Locale,
+    overrides: Arc<HashMap<String, String>>,
+}
+
+impl Translator {
+    pub fn new(config: &LocaleConfig) -> Self {
+        Self {
+            locale: config.language,
+            overrides: Arc::new(config.translations.clone()),
+        }
+    }
+
+    pub fn text(&self, text: Text) -> String {
+        match self.overrides.get(text.key()) {
+            Some(custom) => custom.clone(),
+            None => text.builtin(self.locale).to_string(),
+        }
+    }
+
+    /// Text with its `{}` placeholder replaced by `arg`
+    pub fn format(&self, text: Text, arg: impl std::fmt::Display) -> String {
+        self.text(text).replacen("{}", &arg.to_string(), 1)
+    }
+
+    /// Label of a binary present-value (0 = inactive, 1 = active)
+    pub fn binary_label(&self, value: u32) -> String {
+        self.text(if value == 0 { Text::Inactive } else { Text::Active })
+    }
+
+    /// Label of an alarm's event state, offnormal while active and normal otherwise
+    pub fn alarm_state_label(&self, active: bool) -> String {
+        self.text(if active { Text::EventOffnormal } else { Text::EventNormal })
+    }
+}
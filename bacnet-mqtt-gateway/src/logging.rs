@@ -0,0 +1,99 @@
+//! Tracing filter directives that can be changed while the gateway runs, so
+//! verbose logging can be turned on for a while on a production gateway
+
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// The filter layer installed at startup
+pub type FilterLayer = reload::Layer<EnvFilter, Registry>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLevel {
+    /// Directives in effect, e.g. `info,bacnet_mqtt_gateway::bacnet=trace`
+    pub directives: String,
+    /// Directives from `RUST_LOG` at startup, restored by a reset
+    pub initial: String,
+    /// Seconds until the initial directives come back, if the change is temporary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_in_secs: Option<u64>,
+}
+
+struct Current {
+    directives: String,
+    revert_at: Option<Instant>,
+    /// Counts changes, so a timed revert leaves a later change alone
+    generation: u64,
+}
+
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    initial: String,
+    current: Mutex<Current>,
+}
+
+impl LogFilter {
+    /// Filter layer seeded from `RUST_LOG`, with the filter controlling it
+    pub fn from_default_env() -> (FilterLayer, Arc<Self>) {
+        let filter = EnvFilter::from_default_env();
+        let initial = filter.to_string();
+        let (layer, handle) = reload::Layer::new(filter);
+        let current = Current { directives: initial.clone(), revert_at: None, generation: 0 };
+        (layer, Arc::new(Self { handle, initial, current: Mutex::new(current) }))
+    }
+
+    pub fn level(&self) -> LogLevel {
+        let current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        LogLevel {
+            directives: current.directives.clone(),
+            initial: self.initial.clone(),
+            revert_in_secs: current
+                .revert_at
+                .map(|at| at.saturating_duration_since(Instant::now()).as_secs()),
+        }
+    }
+
+    /// Replaces the directives, going back to the initial ones after `revert_after` if set
+    pub fn set(self: &Arc<Self>, directives: &str, revert_after: Option<Duration>) -> Result<LogLevel, String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| format!("invalid filter directives: {}", e))?;
+        let generation = self.apply(filter, revert_after.map(|after| Instant::now() + after))?;
+        info!("Log filter set to {}", directives);
+        if let Some(after) = revert_after {
+            let filter = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(after).await;
+                filter.revert(generation);
+            });
+        }
+        Ok(self.level())
+    }
+
+    /// Goes back to the initial directives
+    pub fn reset(&self) -> Result<LogLevel, String> {
+        let filter = EnvFilter::try_new(&self.initial).map_err(|e| e.to_string())?;
+        self.apply(filter, None)?;
+        info!("Log filter reset to {}", self.initial);
+        Ok(self.level())
+    }
+
+    fn revert(&self, generation: u64) {
+        let unchanged = self.current.lock().unwrap_or_else(|e| e.into_inner()).generation == generation;
+        if unchanged && self.reset().is_err() {
+            warn!("Could not restore the log filter {}", self.initial);
+        }
+    }
+
+    fn apply(&self, filter: EnvFilter, revert_at: Option<Instant>) -> Result<u64, String> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        let directives = filter.to_string();
+        self.handle
+            .reload(filter)
+            .map_err(|e| format!("could not change the log filter: {}", e))?;
+        current.directives = directives;
+        current.revert_at = revert_at;
+        current.generation += 1;
+        Ok(current.generation)
+    }
+}
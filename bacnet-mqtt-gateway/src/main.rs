@@ -1,140 +1,1143 @@
+mod alarm;
+mod alias;
+mod assets;
+mod audit;
+mod auth;
 mod bacnet;
+mod batch;
+mod capture;
+mod cloud;
+mod cluster;
+mod codec;
+mod command;
+mod commstats;
 mod config;
+mod cov;
+mod datalink;
+mod deadband;
+mod events;
+mod export;
+mod filter;
+mod group;
+mod heartbeat;
+mod historian;
+mod homie;
+mod influx;
+mod locale;
+mod logging;
+mod maintenance;
+mod mdns;
 mod mqtt;
+mod naming;
+mod openapi;
+mod persist;
+mod point;
+mod poll;
+mod progress;
+mod quirks;
+mod registry;
+mod reload;
+mod rollup;
+mod rpc;
+mod scan;
+mod secret;
+mod server;
+mod setup;
+mod snapshot;
+mod sparkplug;
+mod suspend;
+mod topic;
+mod transform;
+mod units;
+mod web;
+mod webhook;
 
-use axum::{routing::get, Router, response::Html};
 use config::GatewayConfig;
+use point::ObjectRef;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::collections::HashMap;
 use tracing::info;
-use tracing_subscriber;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
+    // Initialize logging, warnings and errors also go to the live event feed
+    let events = events::Events::default();
+    let (filter_layer, log_filter) = logging::LogFilter::from_default_env();
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(tracing_subscriber::fmt::layer())
+        .with(events.log_layer())
+        .init();
     info!("Starting BACnet-MQTT Gateway...");
 
-    // Try to load configuration, or spawn default
-    let cfg = GatewayConfig::default();
+    // Load the configuration file, creating it with the defaults on first run
+    let config_path = config::config_path(std::env::args().skip(1))?;
+    let first_run = !config_path.exists();
+    let cfg = GatewayConfig::load_or_create(&config_path)?;
+    info!("Loaded configuration from {}", config_path.display());
+    let dry_run = cfg.dry_run.enabled || std::env::args().skip(1).any(|arg| arg == "--dry-run");
+    if dry_run {
+        tracing::warn!("Dry-run mode: writes to BACnet devices are logged, not sent");
+    }
 
     // Start BACnet engine
-    let bacnet = Arc::new(bacnet::BacnetEngine::new(cfg.bacnet.clone())?);
+    let bacnet = Arc::new(bacnet::BacnetEngine::new(cfg.bacnet.clone())?.with_dry_run(dry_run));
     
     // Broadcast discover on startup
-    if let Err(e) = bacnet.discover() {
+    let auto_discovery = cfg.bacnet.discovery.enabled;
+    if !auto_discovery {
+        info!("Automatic discovery is disabled, contacting the configured devices only");
+    } else if let Err(e) = bacnet.discover() {
         tracing::error!("Failed to send initial Who-Is: {}", e);
     }
 
     // Start background receive loop
     let mut bacnet_rx = bacnet.start().await;
 
+    // Announce the gateway itself on startup
+    if let Err(e) = bacnet.announce() {
+        tracing::error!("Failed to send initial I-Am: {}", e);
+    }
+
     // Start MQTT background publisher
-    let mqtt = mqtt::MqttService::new(cfg.mqtt.clone()).await?;
+    let aliases = Arc::new(alias::Aliases::new(&cfg.devices));
+    let mqtt = mqtt::MqttService::new(cfg.mqtt.clone(), aliases, dry_run && cfg.dry_run.suppress_publishes, events.clone()).await?;
+    let ui_base_url = cfg.web.base_url();
+    let translator = locale::Translator::new(&cfg.locale);
+    mqtt.publish_gateway(&cfg.bacnet, &translator, ui_base_url.clone()).await;
+    let rollups = Arc::new(rollup::Rollups::new(cfg.polling.offline_after_failures));
+    let device_registry = Arc::new(registry::Registry::default());
+    mqtt.publish_rollup("active_alarms", 0).await;
+    mqtt.publish_rollup("devices_offline", 0).await;
+
+    // Devices found and metadata read so far, reported while a site is discovered;
+    // the startup Who-Is went out before MQTT connected
+    let progress = Arc::new(progress::DiscoveryProgress::new(mqtt.clone()));
+    if auto_discovery {
+        progress.discovery_started().await;
+    }
+
+    // Mirror MQTT topics into the gateway's virtual BACnet objects
+    let local_device = bacnet.local_device();
+    let mut inbound = mqtt.incoming();
+    for topic in local_device.virtual_topics() {
+        mqtt.subscribe(&topic).await;
+    }
+    tokio::spawn(async move {
+        loop {
+            match inbound.recv().await {
+                Ok(msg) => {
+                    let payload = String::from_utf8_lossy(&msg.payload);
+                    local_device.update_from_mqtt(&msg.topic, &payload);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Virtual object updater lagged, skipped {} MQTT messages", skipped);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    // Sparkplug B edge node, born on every broker connection and on request of the host application
+    let sparkplug = (cfg.mqtt.mode == config::MqttMode::SparkplugB)
+        .then(|| Arc::new(sparkplug::SparkplugNode::new(cfg.mqtt.sparkplug.clone(), mqtt.clone())));
+    if let Some(node) = sparkplug.clone() {
+        let ncmd_topic = node.command_topic();
+        let mut ncmd_inbound = mqtt.incoming();
+        mqtt.subscribe(&ncmd_topic).await;
+        let mut connections = mqtt.connections();
+        tokio::spawn(async move {
+            if *connections.borrow_and_update() > 0 {
+                node.rebirth().await;
+            }
+            loop {
+                tokio::select! {
+                    changed = connections.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        node.rebirth().await;
+                    }
+                    msg = ncmd_inbound.recv() => match msg {
+                        Ok(msg) if msg.topic == ncmd_topic && sparkplug::is_rebirth_request(&msg.payload) => {
+                            info!("Sparkplug rebirth requested");
+                            node.rebirth().await;
+                        }
+                        Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        });
+    }
+
+    // Homie device announced on every broker connection
+    let homie = (cfg.mqtt.mode == config::MqttMode::Homie)
+        .then(|| Arc::new(homie::HomieDevice::new(cfg.mqtt.homie.clone(), mqtt.clone())));
+    if let Some(device) = homie.clone() {
+        let mut connections = mqtt.connections();
+        tokio::spawn(async move {
+            if *connections.borrow_and_update() > 0 {
+                device.announce().await;
+            }
+            while connections.changed().await.is_ok() {
+                device.announce().await;
+            }
+        });
+    }
 
-    // Device registry
+    // Device registry, seeded with the devices configured at fixed addresses
     let discovered_devices = Arc::new(RwLock::new(HashMap::<u32, SocketAddr>::new()));
+    for device in &cfg.devices {
+        let Some(addr) = device.address else {
+            continue;
+        };
+        discovered_devices.write().await.insert(device.instance, addr);
+        // Its I-Am carries the vendor for the device's discovery
+        if let Err(e) = bacnet.who_is(Some(device.instance), Some(device.instance), Some(addr)) {
+            tracing::warn!("Failed to ask device {} at {} for its I-Am: {}", device.instance, addr, e);
+        }
+    }
+    let poll_plan = Arc::new(poll::PollPlan::new(&cfg.devices, cfg.bacnet.poll_object, cfg.polling.interval_secs));
+    let device_filter = Arc::new(filter::DeviceFilter::new(&cfg.device_filters)?);
+
+    // Devices this instance polls when several gateways share the internetwork
+    let cluster = Arc::new(cluster::Cluster::new(cfg.cluster.clone()));
+    tokio::spawn(cluster::run(cluster.clone(), mqtt.clone()));
+
+    // Devices, groups or the whole gateway excluded from polling, controlled
+    // through `<base>/control/suspend|resume` and the REST API
+    let suspensions = Arc::new(suspend::SuspensionManager::load(&cfg.polling));
+
+    // Planned maintenance windows suppressing or tagging polls
+    let maintenance = Arc::new(maintenance::MaintenanceSchedule::new(&cfg.maintenance, &cfg.polling.groups)?);
+    mqtt.publish_suspensions(&suspensions.snapshot()).await;
+    let suspend_topic = mqtt.control_topic("suspend");
+    let resume_topic = mqtt.control_topic("resume");
+    let mut control_inbound = mqtt.incoming();
+    mqtt.subscribe(&suspend_topic).await;
+    mqtt.subscribe(&resume_topic).await;
+    let control_mqtt = mqtt.clone();
+    let control_devices = discovered_devices.clone();
+    let control_suspensions = suspensions.clone();
+    tokio::spawn(async move {
+        loop {
+            let msg = match control_inbound.recv().await {
+                Ok(msg) => msg,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            let suspended = if msg.topic == suspend_topic {
+                true
+            } else if msg.topic == resume_topic {
+                false
+            } else {
+                continue;
+            };
+            let scope = match String::from_utf8_lossy(&msg.payload).parse::<suspend::Scope>() {
+                Ok(scope) => scope,
+                Err(e) => {
+                    tracing::warn!("Ignoring control message on {}: {}", msg.topic, e);
+                    continue;
+                }
+            };
+            let devices = control_devices.read().await.clone();
+            if let Err(e) = control_suspensions.set(&scope, suspended, &control_mqtt, &devices).await {
+                tracing::warn!("Ignoring control message on {}: {}", msg.topic, e);
+            }
+        }
+    });
+
+    // Suspensions kept in the Azure IoT Hub device twin: desired properties
+    // are applied and the resulting state reported back
+    if mqtt.has_twin() {
+        let mut twin_inbound = mqtt.incoming();
+        let twin_mqtt = mqtt.clone();
+        let twin_devices = discovered_devices.clone();
+        let twin_suspensions = suspensions.clone();
+        tokio::spawn(async move {
+            loop {
+                let msg = match twin_inbound.recv().await {
+                    Ok(msg) if msg.topic == cloud::TWIN_DESIRED_TOPIC => msg,
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let desired: serde_json::Value = match serde_json::from_slice(&msg.payload) {
+                    Ok(desired) => desired,
+                    Err(e) => {
+                        tracing::warn!("Ignoring unreadable desired twin properties: {}", e);
+                        continue;
+                    }
+                };
+                let Some(wanted) = desired.get("suspensions") else {
+                    continue;
+                };
+                let result = match serde_json::from_value::<suspend::Suspensions>(wanted.clone()) {
+                    Ok(wanted) => {
+                        let devices = twin_devices.read().await.clone();
+                        twin_suspensions.apply(&wanted, &twin_mqtt, &devices).await
+                    }
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Err(e) = result {
+                    tracing::warn!("Ignoring desired twin suspensions: {}", e);
+                }
+                let reported = serde_json::json!({ "suspensions": twin_suspensions.snapshot() });
+                twin_mqtt.report_twin(&reported).await;
+            }
+        });
+    }
+
+    // Alarm states of polled points, shelved or chattering ones are held back
+    let alarms = Arc::new(alarm::AlarmManager::new(&cfg.alarms));
+
+    // Units and state texts of polled points, keyed by device and object
+    let point_metadata = Arc::new(RwLock::new(HashMap::<(u32, ObjectRef), point::PointMetadata>::new()));
+
+    // Devices, object lists and metadata known before the restart, saved periodically
+    if let Some(path) = cfg.persistence.file.clone() {
+        let persisted = persist::Persisted {
+            bacnet: bacnet.clone(),
+            devices: discovered_devices.clone(),
+            registry: device_registry.clone(),
+            metadata: point_metadata.clone(),
+        };
+        persisted.restore(&path).await;
+        let interval = std::time::Duration::from_secs(cfg.persistence.interval_secs);
+        tokio::spawn(persist::run(path, interval, persisted));
+    }
+
+    // Published values kept for short-term history queries
+    let historian = cfg.historian.enabled.then(|| historian::Historian::open(&cfg.historian)).transpose()?.map(Arc::new);
+    if let Some(historian) = &historian {
+        tokio::spawn(historian::run(historian.clone()));
+    }
+    // Published values also written to InfluxDB, if configured
+    let influx = cfg
+        .influxdb
+        .as_ref()
+        .map(|config| influx::InfluxWriter::new(config, &cfg.mqtt.topics.site))
+        .transpose()?
+        .map(Arc::new);
+    if let Some(writer) = &influx {
+        tokio::spawn(influx::run(writer.clone()));
+    }
+
+    // Thermostats, covers and fans combining several points of a controller
+    let groups = Arc::new(group::EntityGroups::new(&cfg, mqtt.clone()));
+
+    // Writes requested on the command topics, `<base>/<device>/<object>/set` by default
+    let command_filter = mqtt.command_filter();
+    let mut command_inbound = mqtt.incoming();
+    mqtt.subscribe(&command_filter).await;
+    if !groups.is_empty() {
+        mqtt.subscribe(&mqtt.group_command_filter()).await;
+    }
+    if let Some(device) = &homie {
+        mqtt.subscribe(&device.set_filter()).await;
+    }
+    let command_mqtt = mqtt.clone();
+    let command_bacnet = bacnet.clone();
+    let command_devices = discovered_devices.clone();
+    let command_cluster = cluster.clone();
+    let command_metadata = point_metadata.clone();
+    let command_groups = groups.clone();
+    let command_homie = homie.clone();
+    let switch_priority = cfg.mqtt.switch_priority;
+    let audit = Arc::new(audit::AuditLog::new(cfg.audit.clone(), mqtt.clone()));
+    let command_audit = audit.clone();
+    tokio::spawn(async move {
+        loop {
+            let msg = match command_inbound.recv().await {
+                Ok(msg) => msg,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Command handler lagged, skipped {} MQTT messages", skipped);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            let point = command_mqtt
+                .parse_command_topic(&msg.topic)
+                .or_else(|| command_homie.as_ref().and_then(|device| device.parse_set_topic(&msg.topic)));
+            let write = if let Some((device_id, object)) = point {
+                let metadata = command_metadata.read().await;
+                command::parse_command(device_id, object, &msg.payload, metadata.get(&(device_id, object)))
+            } else if let Some(write) = command_groups.parse_command(&msg.topic, &msg.payload) {
+                write
+            } else {
+                continue;
+            };
+            let correlation_id = command::correlation_id(&msg.payload);
+            let mut write = match write {
+                Ok(write) if !command_cluster.owns(write.device_id) => continue,
+                Ok(write) => write,
+                Err(e) => {
+                    tracing::warn!("Ignoring command on {}: {}", msg.topic, e);
+                    let result = Err(command::CommandError::Invalid(e));
+                    let result = command::CommandResult::new(correlation_id, point, &result);
+                    command_mqtt.publish_command_result(&msg.topic, &result).await;
+                    continue;
+                }
+            };
+            if matches!(write.object.object_type, 4 | 5) {
+                write.priority = write.priority.or(switch_priority);
+            }
+            let devices = command_devices.read().await.clone();
+            let bacnet = command_bacnet.clone();
+            let audit = command_audit.clone();
+            let mqtt = command_mqtt.clone();
+            // Writes wait for the device's answer, keep handling further commands meanwhile
+            tokio::spawn(async move {
+                let (device_id, object) = (write.device_id, write.object);
+                let result = command::execute(&bacnet, &devices, &audit, &msg.topic, write).await;
+                let outcome = command::CommandResult::new(correlation_id, Some((device_id, object)), &result);
+                mqtt.publish_command_result(&msg.topic, &outcome).await;
+                match result {
+                    Ok(()) => {
+                        let aliases = mqtt.aliases();
+                        tracing::info!("Wrote command from {} to device {} {}", msg.topic, aliases.device_label(device_id), aliases.object_label(device_id, object));
+                        // Read the value back right away so the state shows what the device accepted
+                        let present_value = codec::PropertyReference { object, property: 85, array_index: None };
+                        if let Some(addr) = devices.get(&device_id) {
+                            if let Err(e) = bacnet.read_property(*addr, &present_value) {
+                                tracing::warn!("Failed to read back {} {}: {}", device_id, object, e);
+                            }
+                        }
+                    }
+                    Err(e) => tracing::warn!("Command on {} failed: {}", msg.topic, e),
+                }
+            });
+        }
+    });
+
+    // Acknowledgements through `<base>/control/ack` with payload `<device>/<object>`
+    let ack_topic = mqtt.control_topic("ack");
+    let mut ack_inbound = mqtt.incoming();
+    mqtt.subscribe(&ack_topic).await;
+    let ack_alarms = alarms.clone();
+    tokio::spawn(async move {
+        loop {
+            let msg = match ack_inbound.recv().await {
+                Ok(msg) if msg.topic == ack_topic => msg,
+                Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            let payload = String::from_utf8_lossy(&msg.payload);
+            let source = payload
+                .trim()
+                .split_once('/')
+                .and_then(|(device, object)| Some((device.parse::<u32>().ok()?, object.parse::<ObjectRef>().ok()?)));
+            match source {
+                Some((device_id, object)) => {
+                    if !ack_alarms.acknowledge(device_id, object) {
+                        tracing::debug!("No active alarm of device {} {} to acknowledge", device_id, object);
+                    }
+                }
+                None => tracing::warn!("Ignoring acknowledgement '{}', expected <device>/<object>", payload),
+            }
+        }
+    });
+
+    // Re-notification and escalation of unacknowledged critical alarms
+    let escalation = cfg.alarms.escalation.clone();
+    if escalation.renotify_after_mins > 0 || escalation.escalate_after_mins > 0 {
+        let escalation_alarms = alarms.clone();
+        let escalation_mqtt = mqtt.clone();
+        let escalation_translator = translator.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+            loop {
+                interval.tick().await;
+                for due in escalation_alarms.due_notifications() {
+                    let label = escalation_translator.alarm_state_label(true);
+                    match due.notification {
+                        alarm::Notification::Renotify(count) => {
+                            let aliases = escalation_mqtt.aliases();
+                            tracing::info!("Re-notifying unacknowledged alarm of device {} {} ({})", aliases.device_label(due.device_id), aliases.object_label(due.device_id, due.object), count);
+                            escalation_mqtt.publish_alarm(due.device_id, due.object, true, &label, count).await;
+                        }
+                        alarm::Notification::Escalate => {
+                            let aliases = escalation_mqtt.aliases();
+                            tracing::warn!("Escalating alarm of device {} {}, unacknowledged for {:?}", aliases.device_label(due.device_id), aliases.object_label(due.device_id, due.object), due.active_for);
+                            let payload = serde_json::json!({
+                                "device_id": due.device_id,
+                                "object": due.object.to_string(),
+                                "label": label,
+                                "active_for_secs": due.active_for.as_secs(),
+                            });
+                            if let Some(topic) = &escalation.topic {
+                                escalation_mqtt.publish(topic, &payload.to_string(), false).await;
+                            }
+                            if let Some(url) = &escalation.webhook_url {
+                                if let Err(e) = webhook::post_json(url, &payload).await {
+                                    tracing::error!("Alarm escalation webhook failed: {}", e);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    // Poll cycle that issued each outstanding read, keyed by device address and
+    // invoke ID as the engine matches replies
+    let poll_cycles = Arc::new(RwLock::new(HashMap::<(SocketAddr, u8), u64>::new()));
+    let snapshot_mode = cfg.mqtt.snapshot;
+    let snapshots = (snapshot_mode != config::SnapshotMode::Off).then(|| Arc::new(snapshot::SnapshotBatcher::new(mqtt.clone())));
+
+    // Simulated values overriding what devices report, set through the web API
+    let simulations = Arc::new(RwLock::new(HashMap::<(u32, ObjectRef), String>::new()));
+    let publish_filters = Arc::new(deadband::PublishFilters::new(cfg.mqtt.filters.clone()));
 
     // Spawn a task to bridge BACnet events to MQTT
     let bridge_mqtt = mqtt.clone();
     let bridge_devices = discovered_devices.clone();
-    let mqtt_prefix = cfg.mqtt.discovery_prefix.clone();
+    let bridge_poll_cycles = poll_cycles.clone();
+    let bridge_simulations = simulations.clone();
+    let bridge_ui_base_url = ui_base_url.clone();
+    let bridge_translator = translator.clone();
+    let bridge_suspensions = suspensions.clone();
+    let bridge_bacnet = bacnet.clone();
+    let bridge_maintenance = maintenance.clone();
+    let bridge_poll_object = cfg.bacnet.poll_object;
+    let bridge_plan = poll_plan.clone();
+    let bridge_filter = device_filter.clone();
+    let bridge_metadata = point_metadata.clone();
+    let bridge_rollups = rollups.clone();
+    let bridge_registry = device_registry.clone();
+    let bridge_events = events.clone();
+    let bridge_sparkplug = sparkplug.clone();
+    let bridge_homie = homie.clone();
+    let bridge_cluster = cluster.clone();
+    let bridge_progress = progress.clone();
+    let bridge_groups = groups.clone();
+    let bridge_filters = publish_filters.clone();
+    let bridge_snapshots = snapshots.clone();
+    let bridge_historian = historian.clone();
+    let bridge_influx = influx.clone();
+    let bridge_gateway = mqtt::gateway_identifier(cfg.bacnet.device_id);
     tokio::spawn(async move {
         while let Some(event) = bacnet_rx.recv().await {
+            bridge_events.emit(events::Event::from_bacnet(&event));
             match event {
-                bacnet::BacnetEvent::IAm(iam, src) => {
-                    tracing::info!("Discovered BACnet device {} at {}", iam.device_identifier.instance, src);
-                    bridge_devices.write().await.insert(iam.device_identifier.instance, src);
-                    
-                    let unique_id = format!("bacnet_{}", iam.device_identifier.instance);
-                    let payload = mqtt::HaDiscoveryPayload {
-                        name: format!("BACnet Device {}", iam.device_identifier.instance),
-                        state_topic: format!("{}/sensor/{}/state", mqtt_prefix, unique_id),
-                        command_topic: None,
-                        unique_id: unique_id.clone(),
-                        device: mqtt::HaDevice {
-                            identifiers: vec![unique_id.clone()],
-                            name: format!("BACnet Device {}", iam.device_identifier.instance),
-                            manufacturer: format!("Vendor ID {}", iam.vendor_identifier),
-                            model: "Generic BACnet Device".to_string(),
-                        },
-                    };
-                    
-                    bridge_mqtt.publish_discovery("sensor", &unique_id, &payload).await;
-                    bridge_mqtt.publish_state(&payload.state_topic, "online").await;
+                bacnet::BacnetEvent::IAm(iam, src) | bacnet::BacnetEvent::DeviceMoved(iam, _, src) => {
+                    let device_id = iam.device_identifier.instance;
+                    if !auto_discovery && !bridge_plan.is_configured(device_id) {
+                        tracing::debug!("Ignoring I-Am of unconfigured device {} at {}, automatic discovery is disabled", device_id, src);
+                        continue;
+                    }
+                    if bridge_filter.excludes(device_id, src) {
+                        tracing::debug!("Ignoring I-Am of device {} at {}, excluded by the device filters", device_id, src);
+                        continue;
+                    }
+                    let label = bridge_mqtt.aliases().device_label(device_id);
+                    match bridge_bacnet.interface_of(src) {
+                        Some(interface) => tracing::info!("Registering BACnet device {} at {} on {}", label, src, interface),
+                        None => tracing::info!("Registering BACnet device {} at {}", label, src),
+                    }
+                    bridge_devices.write().await.insert(device_id, src);
+                    bridge_registry.announced(device_id, iam.vendor_identifier);
+                    if !bridge_cluster.owns(device_id) {
+                        tracing::debug!("Device {} is owned by another cluster member", device_id);
+                        continue;
+                    }
+                    bridge_progress.device_found(device_id).await;
+                    // Discovery is published again below
+                    bridge_rollups.restore(device_id);
+                    if let Some((online, offline)) = bridge_rollups.record_poll(device_id, true) {
+                        bridge_mqtt.publish_reachability(device_id, online).await;
+                        bridge_mqtt.publish_rollup("devices_offline", offline).await;
+                    }
+
+                    // Reading the point metadata takes round trips, publish discovery off the event loop
+                    let discovery_bacnet = bridge_bacnet.clone();
+                    let discovery_metadata = bridge_metadata.clone();
+                    let default_object = bridge_poll_object;
+                    let poll_objects = bridge_plan.objects(device_id);
+                    let discovery_mqtt = bridge_mqtt.clone();
+                    let translator = bridge_translator.clone();
+                    let suspensions = bridge_suspensions.clone();
+                    let progress = bridge_progress.clone();
+                    let groups = bridge_groups.clone();
+                    let via_device = bridge_gateway.clone();
+                    let filter = bridge_filter.clone();
+                    for _ in &poll_objects {
+                        progress.object_queued();
+                    }
+                    let configuration_url = bridge_ui_base_url
+                        .as_ref()
+                        .map(|base| format!("{}/devices/{}", base, device_id));
+                    tokio::spawn(async move {
+                        discovery_bacnet.apply_quirks(src, device_id, iam.vendor_identifier).await;
+                        let alias = discovery_mqtt.aliases().device(device_id);
+                        let naming = discovery_mqtt.naming().clone();
+                        let templates = [&naming.entity_name, &naming.unique_id];
+                        let named = |variable: &str| templates.iter().any(|t| t.as_deref().is_some_and(|t| naming::uses(t, variable)));
+                        let mut device_object_name = None;
+                        if (alias.is_none() && (discovery_mqtt.uses_device_name() || named("device_alias"))) || filter.uses_names() {
+                            match discovery_bacnet.read_property_bundle(src, ObjectRef::new(8, device_id)).await {
+                                Ok(point::PropertyBundle { object_name: Some(name), .. }) => {
+                                    discovery_mqtt.set_device_name(device_id, &name);
+                                    filter.set_name(device_id, &name);
+                                    device_object_name = Some(name);
+                                }
+                                Ok(_) => tracing::debug!("Device {} has no object name for its topics", device_id),
+                                Err(e) => tracing::debug!("Could not read the name of device {}: {}", device_id, e),
+                            }
+                        }
+                        if !filter.admits_device(device_id, src) {
+                            tracing::info!("Device {} is excluded by the device filters, not publishing it", discovery_mqtt.aliases().device_label(device_id));
+                            for _ in &poll_objects {
+                                progress.object_skipped().await;
+                            }
+                            return;
+                        }
+                        let device_unique_id = format!("bacnet_{}", device_id);
+                        let device = mqtt::HaDevice {
+                            identifiers: vec![device_unique_id.clone()],
+                            name: discovery_mqtt.aliases().device_name(device_id, &translator),
+                            manufacturer: translator.format(locale::Text::VendorId, iam.vendor_identifier),
+                            model: translator.text(locale::Text::GenericDeviceModel),
+                            sw_version: None,
+                            configuration_url,
+                            via_device: Some(via_device),
+                        };
+                        let device_alias = alias.or(device_object_name).unwrap_or_else(|| device.name.clone());
+                        let site = discovery_mqtt.site();
+                        for poll_object in poll_objects {
+                            if !filter.admits_object(device_id, src, poll_object) {
+                                progress.object_skipped().await;
+                                continue;
+                            }
+                            let metadata = point::read_metadata(&discovery_bacnet, src, poll_object).await;
+                            progress.object_read(device_id, poll_object).await;
+                            let ha_unit = metadata.units.and_then(units::ha_unit);
+                            let options = Some(metadata.options()).filter(|options| !options.is_empty());
+                            let component = mqtt::ha_component(poll_object, options.is_some());
+                            // Read-only multi-state points with state texts become enum sensors listing their states
+                            let (options, device_class) = match component {
+                                "sensor" if options.is_some() => (options, Some("enum".to_string())),
+                                "select" => (options, None),
+                                "binary_sensor" | "switch" => (None, None),
+                                _ => (None, ha_unit.and_then(|u| u.device_class).map(str::to_string)),
+                            };
+                            // Binary states are published as their texts when the device has them
+                            let (active, inactive) = (metadata.active_text.clone(), metadata.inactive_text.clone());
+                            // Setpoints are bounded by the object's own limits
+                            let (min, max, step) = match component {
+                                "number" => (metadata.min_value, metadata.max_value, metadata.resolution.filter(|r| *r > 0.0)),
+                                _ => (None, None, None),
+                            };
+                            let mut object_name = discovery_mqtt.aliases().object(device_id, poll_object).or(metadata.object_name.clone());
+                            if object_name.is_none() && named("object_name") {
+                                object_name = point::read_object_name(&discovery_bacnet, src, poll_object).await;
+                            }
+                            discovery_metadata.write().await.insert((device_id, poll_object), metadata);
+
+                            let object_name = object_name.unwrap_or_else(|| poll_object.to_string());
+                            let names = naming::PointNames { site: &site, device_id, device_alias: &device_alias, object: poll_object, object_name: &object_name };
+                            let unique_id = match &naming.unique_id {
+                                Some(template) => names.render_unique_id(template),
+                                None if poll_object == default_object => device_unique_id.clone(),
+                                None => format!("{}_{}_{}", device_unique_id, poll_object.object_type, poll_object.instance),
+                            };
+                            let state_topic = discovery_mqtt.device_state_topic(device_id, poll_object);
+                            let (availability, availability_mode) = discovery_mqtt.availability(Some(device_id));
+                            let mut payload = mqtt::HaDiscoveryPayload {
+                                name: match &naming.entity_name {
+                                    Some(template) => names.render(template),
+                                    None => discovery_mqtt.aliases().entity_name(device_id, poll_object, default_object, &translator),
+                                },
+                                json_attributes_topic: Some(mqtt::attributes_topic(&state_topic)),
+                                availability,
+                                availability_mode,
+                                state_topic,
+                                command_topic: mqtt::is_commandable(component)
+                                    .then(|| discovery_mqtt.command_topic(device_id, poll_object)),
+                                unique_id: unique_id.clone(),
+                                unit_of_measurement: match component {
+                                    "sensor" | "number" => ha_unit.and_then(|u| u.unit_of_measurement).map(str::to_string),
+                                    _ => None,
+                                },
+                                state_class: (component == "sensor" && matches!(poll_object.object_type, 0..=2))
+                                    .then(|| "measurement".to_string()),
+                                device_class,
+                                icon: None,
+                                entity_category: None,
+                                expire_after: None,
+                                min,
+                                max,
+                                step,
+                                options,
+                                value_template: discovery_mqtt.value_template(),
+                                device: device.clone(),
+                                payload_on: if component == "binary_sensor" { active.clone() } else { None },
+                                payload_off: if component == "binary_sensor" { inactive.clone() } else { None },
+                                state_on: if component == "switch" { active } else { None },
+                                state_off: if component == "switch" { inactive } else { None },
+                            };
+                            if let Some(entity) = discovery_mqtt.entity_override(device_id, poll_object) {
+                                payload.apply(entity);
+                            }
+
+                            discovery_mqtt.publish_discovery(component, &unique_id, Some(device_id), &payload).await;
+                        }
+                        discovery_mqtt
+                            .publish_fault_discovery(device_id, translator.text(locale::Text::PointFault), device.clone())
+                            .await;
+                        groups.publish_discovery(device_id, &device).await;
+                        discovery_mqtt.publish_availability(device_id, !suspensions.is_suspended(device_id)).await;
+                    });
                 }
                 bacnet::BacnetEvent::WhoIs(req, src) => {
                     tracing::debug!("Received Who-Is from {} for range {:?}", src, (req.device_instance_range_low_limit, req.device_instance_range_high_limit));
                 }
                 bacnet::BacnetEvent::ReadProperty(req, _, src) => {
-                    tracing::debug!("Received ReadProperty from {} for {:?}", src, req.object_identifier);
+                    tracing::debug!("Served ReadProperty from {} for {} property {}", src, req.object, req.property);
+                }
+                bacnet::BacnetEvent::RequestFailed(invoke_id, src, e) => {
+                    let Some(cycle) = bridge_poll_cycles.write().await.remove(&(src, invoke_id)) else {
+                        continue;
+                    };
+                    tracing::warn!("Poll {} of {} failed: {}", cycle, src, e);
+                    // An error reply still proves the device is reachable
+                    let answered = !matches!(e, bacnet::BacnetError::Timeout);
+                    let device_id = bridge_devices.read().await.iter().find(|(_, addr)| **addr == src).map(|(id, _)| *id);
+                    if let Some(device_id) = device_id {
+                        if answered {
+                            bridge_registry.heard(device_id);
+                        }
+                        if let Some(snapshots) = &bridge_snapshots {
+                            snapshots.record(device_id, cycle, None).await;
+                        }
+                        if let Some((online, offline)) = bridge_rollups.record_poll(device_id, answered) {
+                            tracing::info!("Device {} is {}", bridge_mqtt.aliases().device_label(device_id), if online { "online" } else { "offline" });
+                            bridge_mqtt.publish_reachability(device_id, online).await;
+                            bridge_mqtt.publish_rollup("devices_offline", offline).await;
+                            if let (false, Some(node)) = (online, &bridge_sparkplug) {
+                                node.device_offline(device_id).await;
+                            }
+                        }
+                    }
                 }
-                bacnet::BacnetEvent::ReadPropertyAck(ack, _, src) => {
+                bacnet::BacnetEvent::VirtualObjectWritten(write) => {
+                    tracing::info!("Forwarding BACnet write of {} to {} = {}", write.object, write.topic, write.payload);
+                    bridge_mqtt.publish(&write.topic, &write.payload, false).await;
+                }
+                bacnet::BacnetEvent::ReadPropertyAck(ack, invoke_id, src, latency) => {
                     tracing::debug!("Received ReadPropertyAck from {} for {:?}", src, ack.object_identifier);
-                    // Decode property value if it is PresentValue (85)
-                    if ack.property_identifier == 85 {
-                        if let Ok((val, _)) = bacnet_rs::encoding::decode_real(&ack.property_value) {
-                            tracing::info!("Device {} AI {} Value: {}", ack.object_identifier.instance, ack.object_identifier.instance, val);
-                            
-                            let unique_id = format!("bacnet_{}", src.ip()); // Or use device instance if we mapped it, but src is easiest here
-                            let state_topic = format!("{}/sensor/{}/state", mqtt_prefix, unique_id);
-                            
-                            // To actually map the IP to device instance, we should use bridge_devices
-                            let mut device_id_opt = None;
-                            for (id, addr) in bridge_devices.read().await.iter() {
-                                if *addr == src {
-                                    device_id_opt = Some(*id);
-                                    break;
-                                }
+                    let values = match codec::decode_application_values(&ack.property_value) {
+                        Ok(values) => values,
+                        Err(e) => {
+                            tracing::warn!("Undecodable value of {:?} property {} from {}: {} (raw {:?})", ack.object_identifier, ack.property_identifier, src, e, ack.property_value);
+                            continue;
+                        }
+                    };
+                    let object = ObjectRef::new(ack.object_identifier.object_type as u16, ack.object_identifier.instance);
+
+                    // Only the present-value (85) feeds the state topic, other polled
+                    // properties go to topics of their own below it
+                    if ack.property_identifier != 85 {
+                        tracing::debug!("{} property {} = {:?}", object, ack.property_identifier, values);
+                        let device_id = bridge_devices.read().await.iter().find(|(_, addr)| **addr == src).map(|(id, _)| *id);
+                        if let (Some(dev_id), Some(value)) = (device_id, values.first()) {
+                            if bridge_plan.polls(dev_id, object, ack.property_identifier) {
+                                bridge_mqtt.publish_property(dev_id, object, ack.property_identifier, value).await;
                             }
+                        }
+                        continue;
+                    }
+                    let Some(value) = values.first() else {
+                        continue;
+                    };
+                    tracing::info!("Device at {} {} Value: {}", src, object, value);
+
+                    // To actually map the IP to device instance, we should use bridge_devices
+                    let mut device_id_opt = None;
+                    for (id, addr) in bridge_devices.read().await.iter() {
+                        if *addr == src {
+                            device_id_opt = Some(*id);
+                            break;
+                        }
+                    }
 
-                            if let Some(dev_id) = device_id_opt {
-                                let unique_id = format!("bacnet_{}", dev_id);
-                                let state_topic = format!("{}/sensor/{}/state", mqtt_prefix, unique_id);
-                                bridge_mqtt.publish_state(&state_topic, &val.to_string()).await;
+                    if let Some(dev_id) = device_id_opt {
+                        bridge_registry.record_value(dev_id, object, value);
+                        if let Some((online, offline)) = bridge_rollups.record_poll(dev_id, true) {
+                            tracing::info!("Device {} is online", bridge_mqtt.aliases().device_label(dev_id));
+                            bridge_mqtt.publish_reachability(dev_id, online).await;
+                            bridge_mqtt.publish_rollup("devices_offline", offline).await;
+                            // Its I-Am brings back the entities retracted while it was away
+                            if bridge_rollups.restore(dev_id) {
+                                if let Err(e) = bridge_bacnet.who_is(Some(dev_id), Some(dev_id), Some(src)) {
+                                    tracing::warn!("Failed to ask device {} for its I-Am: {}", dev_id, e);
+                                }
                             }
+                        }
+                        let poll_cycle = bridge_poll_cycles.write().await.remove(&(src, invoke_id));
+                        let state_name = bridge_metadata
+                            .read()
+                            .await
+                            .get(&(dev_id, object))
+                            .and_then(|metadata| metadata.state_text(value).map(str::to_string));
+                        let val = state_name.clone().unwrap_or_else(|| codec::state_text(object, value));
+                        let transformed = bridge_mqtt.transform_point(dev_id, object, &val);
+                        if let (Some(snapshots), Some(cycle)) = (&bridge_snapshots, poll_cycle) {
+                            let answer = transformed.as_ref().map(|val| (object, mqtt::state_json(val)));
+                            snapshots.record(dev_id, cycle, answer).await;
+                        }
+                        // Points polled only for a grouped entity stay off the device's state topic
+                        if bridge_groups.update(dev_id, object, value).await && !bridge_plan.objects(dev_id).contains(&object) {
+                            continue;
+                        }
+                        let Some(val) = transformed else {
+                            tracing::debug!("Device {} {} = {} dropped by its transform", dev_id, object, val);
+                            continue;
+                        };
+                        if bridge_simulations.read().await.contains_key(&(dev_id, object)) {
+                            tracing::debug!("Device {} {} is simulated, not publishing {}", dev_id, object, val);
+                            continue;
+                        }
+                        if !bridge_filters.admit(dev_id, object, value) {
+                            tracing::debug!("Device {} {} = {} is within its publish filter", dev_id, object, val);
+                            continue;
+                        }
+
+                        let maintenance = bridge_maintenance.active(dev_id);
+                        let quality = if bridge_rollups.has_fault(dev_id) {
+                            mqtt::Quality::Bad
+                        } else if maintenance.is_some() {
+                            mqtt::Quality::Uncertain
                         } else {
-                            tracing::debug!("Property 85 Value (raw): {:?}", ack.property_value);
+                            mqtt::Quality::Good
+                        };
+                        let units = bridge_metadata
+                            .read()
+                            .await
+                            .get(&(dev_id, object))
+                            .and_then(|metadata| metadata.units)
+                            .and_then(units::ha_unit)
+                            .and_then(|unit| unit.unit_of_measurement);
+                        let per_point = snapshot_mode != config::SnapshotMode::Instead;
+                        if per_point {
+                            bridge_mqtt.publish_point_state(dev_id, object, &val, quality, units).await;
+                            bridge_registry.record_published(dev_id, object, registry::PublishedState { state: val.clone(), quality, units });
+                        }
+                        if let Some(historian) = &bridge_historian {
+                            historian.record(dev_id, object, &val, quality);
+                        }
+                        if let Some(writer) = &bridge_influx {
+                            writer.record(dev_id, object, &val, quality);
+                        }
+                        if let Some(node) = &bridge_sparkplug {
+                            node.update(dev_id, object.to_string(), value).await;
                         }
+                        if let Some(device) = &bridge_homie {
+                            let metadata = bridge_metadata.read().await;
+                            device.update(dev_id, object, value, metadata.get(&(dev_id, object))).await;
+                        }
+                        let state_topic = bridge_mqtt.device_state_topic(dev_id, object);
+
+                        let provenance = mqtt::ValueProvenance {
+                            source: mqtt::ValueSource::Poll,
+                            latency_ms: latency.map(|l| l.as_millis() as u64),
+                            invoke_id: Some(invoke_id),
+                            poll_cycle,
+                            simulated: false,
+                            value_type: Some(value.type_name()),
+                            value: Some(value.to_json()),
+                            label: match (value, state_name) {
+                                (_, Some(name)) => Some(name),
+                                (codec::BacnetValue::Enumerated(v), None) if object.is_binary() => Some(bridge_translator.binary_label(*v)),
+                                _ => None,
+                            },
+                            maintenance: maintenance.map(|(name, _)| name.to_string()),
+                        };
+                        if per_point {
+                            bridge_mqtt.publish_attributes(&state_topic, &provenance).await;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    // Periodic rediscovery so devices that power up later are still found; with
+    // automatic discovery disabled the configured devices are asked directly
+    let rediscovery_secs = cfg.bacnet.discovery.interval_secs;
+    if rediscovery_secs > 0 {
+        let discovery_bacnet = bacnet.clone();
+        let discovery_devices = discovered_devices.clone();
+        let discovery_progress = progress.clone();
+        tokio::spawn(async move {
+            let period = std::time::Duration::from_secs(rediscovery_secs);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let devices = discovery_devices.read().await.clone();
+                match discovery_bacnet.rediscover(&devices) {
+                    Ok(()) if auto_discovery => discovery_progress.discovery_started().await,
+                    Ok(()) => {}
+                    Err(e) => tracing::error!("Failed to send periodic Who-Is: {}", e),
+                }
+            }
+        });
+    }
+
+    // Entities of devices offline for too long are removed from Home Assistant
+    let retract_after_secs = cfg.polling.retract_discovery_after_secs;
+    if retract_after_secs > 0 {
+        let retract_mqtt = mqtt.clone();
+        let retract_rollups = rollups.clone();
+        tokio::spawn(async move {
+            let ttl = std::time::Duration::from_secs(retract_after_secs);
+            let mut interval = tokio::time::interval(ttl.min(std::time::Duration::from_secs(60)));
+            loop {
+                interval.tick().await;
+                for device_id in retract_rollups.offline_beyond(ttl) {
+                    tracing::info!("Device {} offline for over {:?}, retracting its discovery", retract_mqtt.aliases().device_label(device_id), ttl);
+                    retract_mqtt.retract_discovery(device_id).await;
+                }
+            }
+        });
+    }
+
+    // Values not refreshed in time are flagged stale, on their state topics too
+    let stale_after_secs = cfg.polling.stale_after_secs;
+    let stale_mqtt = mqtt.clone();
+    let stale_registry = device_registry.clone();
+    let stale_plan = poll_plan.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(stale_plan.tick());
+        loop {
+            interval.tick().await;
+            let max_age = |device_id: u32, object: ObjectRef| match stale_after_secs {
+                0 => stale_plan.interval(device_id, object) * 3,
+                secs => std::time::Duration::from_secs(secs),
+            };
+            for (device_id, object, latest) in stale_registry.mark_stale(max_age) {
+                tracing::debug!("Device {} {} is stale, last updated {}", device_id, object, latest.updated);
+                if let Some(published) = &latest.published {
+                    stale_mqtt.publish_cached_point(device_id, object, &latest.updated, published, true).await;
+                }
+            }
+        }
+    });
+
+    // Entities get their configs and last states back when the broker or Home Assistant restarts
+    let recovery_mqtt = mqtt.clone();
+    let recovery_registry = device_registry.clone();
+    let birth_topic = mqtt.birth_topic();
+    let mut birth_inbound = mqtt.incoming();
+    if let Some(topic) = &birth_topic {
+        mqtt.subscribe(topic).await;
+    }
+    let mut recovery_connections = mqtt.connections();
+    tokio::spawn(async move {
+        recovery_connections.borrow_and_update();
+        loop {
+            let reason = tokio::select! {
+                changed = recovery_connections.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    "the broker connection was re-established"
+                }
+                msg = birth_inbound.recv() => match msg {
+                    Ok(msg) if birth_topic.as_ref() == Some(&msg.topic) && msg.payload == b"online" => "Home Assistant came online",
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            let configs = recovery_mqtt.republish_discovery().await;
+            let mut states = 0;
+            for (device_id, points) in recovery_registry.all_values() {
+                for (object, latest) in points {
+                    if let Some(published) = &latest.published {
+                        recovery_mqtt.publish_cached_point(device_id, object, &latest.updated, published, latest.stale).await;
+                        states += 1;
                     }
                 }
             }
+            info!("Republished {} discovery configs and {} states, {}", configs, states, reason);
         }
     });
 
+    // Devices, poll intervals, filters and topics follow changes to the configuration file
+    let reloadable = reload::Reloadable {
+        bacnet: bacnet.clone(),
+        mqtt: mqtt.clone(),
+        devices: discovered_devices.clone(),
+        poll_plan: poll_plan.clone(),
+        filter: device_filter.clone(),
+    };
+    let reload_requests = Arc::new(tokio::sync::Notify::new());
+    tokio::spawn(reload::run(config_path.clone(), cfg.clone(), reloadable, reload_requests.clone()));
+
+    // Ad-hoc reads requested on `<base>/rpc/read`
+    tokio::spawn(rpc::run(bacnet.clone(), mqtt.clone(), discovered_devices.clone(), cluster.clone()));
+
+    // Supervisory heartbeats keep controllers out of standalone mode
+    for heartbeat in cfg.heartbeats.clone() {
+        tokio::spawn(heartbeat::run(bacnet.clone(), mqtt.clone(), discovered_devices.clone(), cluster.clone(), heartbeat));
+    }
+
     // Start Polling task
     let poll_bacnet = bacnet.clone();
     let poll_devices = discovered_devices.clone();
+    let poll_suspensions = suspensions.clone();
+    let poll_maintenance = maintenance.clone();
+    let poll_cluster = cluster.clone();
+    let poll_groups = groups.clone();
+    let poll_snapshots = snapshots.clone();
+    let poll_schedule = poll_plan.clone();
+    let poll_filter = device_filter.clone();
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
+        let mut interval = tokio::time::interval(poll_schedule.tick());
+        let mut poll_cycle: u64 = 0;
         loop {
-            interval.tick().await;
+            let now = interval.tick().await.into_std();
+            // Reloaded intervals may need a finer tick
+            if interval.period() != poll_schedule.tick() {
+                interval = tokio::time::interval(poll_schedule.tick());
+            }
+            poll_cycle += 1;
             let devices = poll_devices.read().await.clone();
             for (device_id, addr) in devices {
-                tracing::debug!("Polling device {} at {}", device_id, addr);
-                // Analog Input 0 (0 << 22 | 0) => instance 0
-                let ai_0 = bacnet_rs::object::ObjectIdentifier::new(bacnet_rs::object::ObjectType::AnalogInput, 0);
-                if let Err(e) = poll_bacnet.read_property(addr, ai_0, 85) {
-                    tracing::error!("Failed to poll {} AI 0: {}", device_id, e);
+                if !poll_cluster.owns(device_id) {
+                    continue;
+                }
+                if poll_suspensions.is_suspended(device_id) {
+                    tracing::trace!("Polling of device {} is suspended", device_id);
+                    continue;
+                }
+                if poll_maintenance.is_suppressed(device_id) {
+                    tracing::trace!("Device {} is in a maintenance window, not polling", device_id);
+                    continue;
+                }
+                if !poll_filter.admits_device(device_id, addr) {
+                    continue;
+                }
+                let mut reads = poll_schedule.due(device_id, &poll_groups.points(device_id), now);
+                reads.retain(|read| poll_filter.admits_object(device_id, addr, read.object));
+                if reads.is_empty() {
+                    continue;
+                }
+                tracing::debug!("Polling {} properties of device {} at {}", reads.len(), device_id, addr);
+                let mut issued = 0;
+                for read in reads {
+                    match poll_bacnet.read_property(addr, &read) {
+                        // Present-values are tracked for reachability and snapshots
+                        Ok(invoke_id) if read.property == 85 => {
+                            poll_cycles.write().await.insert((addr, invoke_id), poll_cycle);
+                            issued += 1;
+                        }
+                        Ok(_) => {}
+                        Err(e) => tracing::error!("Failed to poll {} {} property {}: {}", device_id, read.object, read.property, e),
+                    }
+                }
+                if let Some(snapshots) = poll_snapshots.as_ref().filter(|_| issued > 0) {
+                    snapshots.expect(device_id, poll_cycle, issued).await;
                 }
             }
         }
     });
 
+    // Periodic status refresh, one ReadPropertyMultiple per device instead of four reads
+    let status_refresh_secs = cfg.polling.status_refresh_secs;
+    if status_refresh_secs > 0 {
+        let status_bacnet = bacnet.clone();
+        let status_mqtt = mqtt.clone();
+        let status_devices = discovered_devices.clone();
+        let status_suspensions = suspensions.clone();
+        let status_maintenance = maintenance.clone();
+        let status_cluster = cluster.clone();
+        let status_object = cfg.bacnet.poll_object;
+        let status_alarms = alarms.clone();
+        let status_translator = translator.clone();
+        let status_rollups = rollups.clone();
+        let status_filter = device_filter.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(status_refresh_secs));
+            loop {
+                interval.tick().await;
+                let devices = status_devices.read().await.clone();
+                for (device_id, addr) in devices {
+                    if !status_cluster.owns(device_id)
+                        || status_suspensions.is_suspended(device_id)
+                        || status_maintenance.is_suppressed(device_id)
+                        || !status_filter.admits_object(device_id, addr, status_object)
+                    {
+                        continue;
+                    }
+                    match status_bacnet.read_property_bundle(addr, status_object).await {
+                        Ok(bundle) => {
+                            status_mqtt.publish_point_status(device_id, status_object, &bundle).await;
+                            let in_alarm = bundle.status_flags.is_some_and(|flags| flags.in_alarm);
+                            if let Some(active) = status_alarms.update(device_id, status_object, in_alarm) {
+                                let label = status_translator.alarm_state_label(active);
+                                status_mqtt.publish_alarm(device_id, status_object, active, &label, 0).await;
+                                status_mqtt.publish_rollup("active_alarms", status_alarms.active_count()).await;
+                            }
+                            let fault = bundle.status_flags.is_some_and(|flags| flags.fault);
+                            if status_rollups.set_fault(device_id, fault) {
+                                status_mqtt.publish_device_fault(device_id, fault).await;
+                            }
+                        }
+                        Err(e) => tracing::warn!("Status refresh of {} {} failed: {}", device_id, status_object, e),
+                    }
+                }
+            }
+        });
+    }
+
     // Build the configuration Web UI
-    let app = Router::new().route("/", get(serve_ui));
+    let app = web::router(web::AppState {
+        bacnet: bacnet.clone(),
+        mqtt: mqtt.clone(),
+        devices: discovered_devices.clone(),
+        simulations: simulations.clone(),
+        publish_filters: publish_filters.clone(),
+        suspensions: suspensions.clone(),
+        alarms: alarms.clone(),
+        audit: audit.clone(),
+        cluster: cluster.clone(),
+        progress: progress.clone(),
+        metadata: point_metadata.clone(),
+        registry: device_registry.clone(),
+        events,
+        log_filter,
+        rollups: rollups.clone(),
+        poll_object: cfg.bacnet.poll_object,
+        translator: translator.clone(),
+        config_path,
+        reload: reload_requests,
+        setup: Arc::new(setup::Setup::new(first_run)),
+        historian,
+        auth: cfg.web.auth.as_ref().map(auth::Auth::new).transpose()?.map(Arc::new),
+    });
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8123));
+    let addr = cfg.web.bind_addr;
     info!("Web UI listening on {}", addr);
-    
+    if first_run {
+        // A wildcard bind address is no place to browse to, an interface's address is
+        let base_url = ui_base_url
+            .or_else(|| setup::interfaces().ok()?.first().map(|interface| format!("http://{}:{}", interface.address, addr.port())))
+            .unwrap_or_else(|| format!("http://localhost:{}", addr.port()));
+        info!("No configuration file was found, finish the setup at {}/setup", base_url);
+    }
+
     let listener = tokio::net::TcpListener::bind(addr).await?;
+    // Announced for as long as the web server runs
+    let _mdns = cfg.web.mdns.enabled.then(|| mdns::advertise(&cfg)).and_then(|advertised| {
+        advertised.map_err(|e| tracing::warn!("Failed to advertise the gateway via mDNS: {}", e)).ok()
+    });
     axum::serve(listener, app).await?;
 
     Ok(())
 }
-
-async fn serve_ui() -> Html<&'static str> {
-    Html("<html><body><h1>BACnet-MQTT Gateway</h1><p>Gateway configuration will be generated here.</p></body></html>")
-}
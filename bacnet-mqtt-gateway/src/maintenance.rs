@@ -0,0 +1,276 @@
+use crate::config::{MaintenanceMode, MaintenanceWindowConfig};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Longest window honoured, bounds the backwards search for a matching start
+const MAX_WINDOW_MINUTES: u64 = 7 * 24 * 60;
+
+/// Calendar fields of a UTC minute
+#[derive(Debug, Clone, Copy)]
+struct Minute {
+    minute: u32,
+    hour: u32,
+    day: u32,
+    month: u32,
+    /// 0 = Sunday
+    weekday: u32,
+}
+
+/// Year, month and day of a count of days since 1970-01-01 (Howard Hinnant's algorithm)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 of a date, the inverse of [`civil_from_days`]
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+impl Minute {
+    fn from_epoch_minutes(minutes: u64) -> Self {
+        let days = (minutes / 1440) as i64;
+        let of_day = (minutes % 1440) as u32;
+        let (_, month, day) = civil_from_days(days);
+        Self {
+            minute: of_day % 60,
+            hour: of_day / 60,
+            day,
+            month,
+            weekday: (days + 4).rem_euclid(7) as u32, // 1970-01-01 was a Thursday
+        }
+    }
+}
+
+/// A five field cron expression (minute hour day-of-month month day-of-week),
+/// supporting `*`, lists, ranges and steps
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("invalid step '{}'", step))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let a = a.parse::<u32>().map_err(|_| format!("invalid value '{}'", a))?;
+            let b = b.parse::<u32>().map_err(|_| format!("invalid value '{}'", b))?;
+            (a, b)
+        } else {
+            let v = range.parse::<u32>().map_err(|_| format!("invalid value '{}'", range))?;
+            // A bare value with a step means "from value to max"
+            (v, if part.contains('/') { max } else { v })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("cron expression '{}' must have 5 fields", expr));
+        };
+        let context = |e: String| format!("cron expression '{}': {}", expr, e);
+        let mut weekdays = parse_field(weekday, 0, 7).map_err(context)?;
+        // Both 0 and 7 mean Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59).map_err(context)?,
+            hours: parse_field(hour, 0, 23).map_err(context)?,
+            days: parse_field(day, 1, 31).map_err(context)?,
+            months: parse_field(month, 1, 12).map_err(context)?,
+            weekdays,
+            // Like cron, a field starting with `*` (also `*/2`) leaves it unrestricted
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    fn matches(&self, t: Minute) -> bool {
+        let bit = |mask: u64, v: u32| mask & (1 << v) != 0;
+        // Like cron, a restricted day-of-month and day-of-week match either
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => bit(self.days, t.day) || bit(self.weekdays, t.weekday),
+            _ => bit(self.days, t.day) && bit(self.weekdays, t.weekday),
+        };
+        bit(self.minutes, t.minute) && bit(self.hours, t.hour) && bit(self.months, t.month) && day_matches
+    }
+}
+
+struct Window {
+    config: MaintenanceWindowConfig,
+    schedule: CronSchedule,
+}
+
+impl Window {
+    /// True if the window started within its duration before `now` (epoch minutes)
+    fn active_at(&self, now: u64) -> bool {
+        let duration = self.config.duration_mins.min(MAX_WINDOW_MINUTES);
+        (0..duration)
+            .filter_map(|offset| now.checked_sub(offset))
+            .any(|start| self.schedule.matches(Minute::from_epoch_minutes(start)))
+    }
+
+    fn covers(&self, device_id: u32, groups: &HashMap<String, Vec<u32>>) -> bool {
+        (self.config.devices.is_empty() && self.config.groups.is_empty())
+            || self.config.devices.contains(&device_id)
+            || self
+                .config
+                .groups
+                .iter()
+                .any(|group| groups.get(group).is_some_and(|members| members.contains(&device_id)))
+    }
+}
+
+/// Planned maintenance windows (e.g. weekly controller reboots), evaluated in UTC
+pub struct MaintenanceSchedule {
+    windows: Vec<Window>,
+    groups: HashMap<String, Vec<u32>>,
+}
+
+impl MaintenanceSchedule {
+    pub fn new(
+        windows: &[MaintenanceWindowConfig],
+        groups: &HashMap<String, Vec<u32>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let windows = windows
+            .iter()
+            .map(|config| {
+                for group in &config.groups {
+                    if !groups.contains_key(group) {
+                        return Err(format!("maintenance window '{}' references unknown group '{}'", config.name, group));
+                    }
+                }
+                Ok(Window {
+                    schedule: CronSchedule::parse(&config.schedule)?,
+                    config: config.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { windows, groups: groups.clone() })
+    }
+
+    /// The window a device is currently in, if any; suppressing windows take
+    /// precedence over tagging ones
+    pub fn active(&self, device_id: u32) -> Option<(&str, MaintenanceMode)> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 60).unwrap_or(0);
+        let mut active = self
+            .windows
+            .iter()
+            .filter(|w| w.covers(device_id, &self.groups) && w.active_at(now))
+            .map(|w| (w.config.name.as_str(), w.config.mode));
+        let first = active.next()?;
+        if first.1 == MaintenanceMode::Suppress {
+            return Some(first);
+        }
+        Some(active.find(|(_, mode)| *mode == MaintenanceMode::Suppress).unwrap_or(first))
+    }
+
+    /// True while polling and availability changes of the device are suppressed
+    pub fn is_suppressed(&self, device_id: u32) -> bool {
+        matches!(self.active(device_id), Some((_, MaintenanceMode::Suppress)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Epoch minutes of a UTC time
+    fn at(year: i64, month: u32, day: u32, hour: u64, minute: u64) -> u64 {
+        days_from_civil(year, month, day) as u64 * 1440 + hour * 60 + minute
+    }
+
+    fn matches(expr: &str, t: u64) -> bool {
+        CronSchedule::parse(expr).unwrap().matches(Minute::from_epoch_minutes(t))
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expr in ["0 3 * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "0 3 0 * *", "0 3 * 13 *", "x * * * *"] {
+            assert!(CronSchedule::parse(expr).is_err(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn fields_match_lists_ranges_and_steps() {
+        assert!(matches("*/15 1-3 * * *", at(2024, 1, 3, 2, 45)));
+        assert!(!matches("*/15 1-3 * * *", at(2024, 1, 3, 2, 46)));
+        assert!(matches("0 3,15 * * *", at(2024, 1, 3, 15, 0)));
+        assert!(!matches("0 3,15 * * *", at(2024, 1, 3, 4, 0)));
+        // Both 0 and 7 are Sunday
+        assert!(matches("0 3 * * 7", at(2024, 1, 7, 3, 0)));
+        assert!(matches("0 3 * * 0", at(2024, 1, 7, 3, 0)));
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 1st of the month or any Monday
+        assert!(matches("0 3 1 * 1", at(2024, 1, 15, 3, 0)));
+        assert!(matches("0 3 1 * 1", at(2024, 2, 1, 3, 0)));
+        assert!(!matches("0 3 1 * 1", at(2024, 1, 3, 3, 0)));
+        // A stepped `*` is unrestricted, so odd days that are Mondays
+        assert!(matches("0 3 */2 * 1", at(2024, 1, 1, 3, 0)));
+        assert!(!matches("0 3 */2 * 1", at(2024, 1, 3, 3, 0)));
+        assert!(!matches("0 3 */2 * 1", at(2024, 1, 8, 3, 0)));
+    }
+
+    #[test]
+    fn window_is_active_for_its_duration() {
+        let config = MaintenanceWindowConfig {
+            name: "reboot".to_string(),
+            schedule: "0 3 * * 0".to_string(),
+            duration_mins: 60,
+            devices: Vec::new(),
+            groups: Vec::new(),
+            mode: MaintenanceMode::Suppress,
+        };
+        let window = Window { schedule: CronSchedule::parse(&config.schedule).unwrap(), config };
+        assert!(!window.active_at(at(2024, 1, 7, 2, 59)));
+        assert!(window.active_at(at(2024, 1, 7, 3, 0)));
+        assert!(window.active_at(at(2024, 1, 7, 3, 59)));
+        assert!(!window.active_at(at(2024, 1, 7, 4, 0)));
+        assert!(!window.active_at(at(2024, 1, 8, 3, 30)));
+    }
+}
@@ -0,0 +1,53 @@
+//! mDNS advertisement of the web UI and REST API as `_http._tcp` and
+//! `_bacnet-mqtt._tcp`, so gateways can be found without knowing their IPs
+
+use crate::config::{GatewayConfig, MdnsConfig};
+use crate::openapi;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::collections::HashMap;
+use tracing::info;
+
+/// The generic web service browsers and Home Assistant look for, and the gateway's own
+const SERVICE_TYPES: [&str; 2] = ["_http._tcp.local.", "_bacnet-mqtt._tcp.local."];
+
+fn instance_name(config: &MdnsConfig, device_id: u32) -> String {
+    config.instance_name.clone().unwrap_or_else(|| format!("BACnet-MQTT Gateway {}", device_id))
+}
+
+fn hostname(config: &MdnsConfig, device_id: u32) -> String {
+    let name = config.hostname.clone().unwrap_or_else(|| format!("bacnet-gateway-{}", device_id));
+    format!("{}.local.", name.trim_end_matches('.').trim_end_matches(".local"))
+}
+
+/// TXT record telling clients where the UI and API are and which gateway answers
+fn properties(config: &GatewayConfig) -> HashMap<String, String> {
+    HashMap::from([
+        ("path".to_string(), "/".to_string()),
+        ("api".to_string(), "/api".to_string()),
+        ("openapi".to_string(), openapi::DOCUMENT_PATH.to_string()),
+        ("device_id".to_string(), config.bacnet.device_id.to_string()),
+        ("base_topic".to_string(), config.mqtt.base_topic.clone()),
+        ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+    ])
+}
+
+/// Registers the services; they are announced for as long as the returned daemon runs
+pub fn advertise(config: &GatewayConfig) -> Result<ServiceDaemon, Box<dyn std::error::Error>> {
+    let mdns = &config.web.mdns;
+    let device_id = config.bacnet.device_id;
+    let name = instance_name(mdns, device_id);
+    let host = hostname(mdns, device_id);
+    let bind = config.web.bind_addr;
+    // On every interface the addresses are found as they come and go
+    let addresses = if bind.ip().is_unspecified() { String::new() } else { bind.ip().to_string() };
+    let daemon = ServiceDaemon::new()?;
+    for service_type in SERVICE_TYPES {
+        let mut service = ServiceInfo::new(service_type, &name, &host, addresses.as_str(), bind.port(), properties(config))?;
+        if addresses.is_empty() {
+            service = service.enable_addr_auto();
+        }
+        daemon.register(service)?;
+    }
+    info!("Advertising '{}' as {} on port {} via mDNS", name, host, bind.port());
+    Ok(daemon)
+}
@@ -1,72 +1,1144 @@
-use crate::config::MqttConfig;
-use rumqttc::{AsyncClient, MqttOptions, QoS};
+use crate::alias::Aliases;
+use crate::cloud::{self, Adapter, Outgoing, TWIN_DESIRED_TOPIC};
+use crate::codec::BacnetValue;
+use crate::config::{
+    BacnetConfig, BrokerConfig, BrokerProfile, EntityOverride, MqttConfig, MqttMode, NamingConfig, OverflowPolicy, PayloadFormat,
+    PublishOptions, TopicConfig,
+};
+use crate::events::{Event as FeedEvent, Events};
+use crate::homie;
+use crate::locale::{Text, Translator};
+use crate::maintenance;
+use crate::point::{ObjectRef, PropertyBundle};
+use crate::registry::PublishedState;
+use crate::secret;
+use crate::sparkplug;
+use crate::suspend::Suspensions;
+use crate::topic::{self, TopicTemplate};
+use crate::transform::Transforms;
+use rumqttc::{AsyncClient, ClientError, Event, LastWill, MqttOptions, Packet, QoS, Transport};
 use serde::Serialize;
-use std::time::Duration;
-use tracing::{error, info};
+use std::collections::{BTreeMap, HashMap};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
+use tracing::{error, info, warn};
 
 #[derive(Clone)]
 pub struct MqttService {
-    client: AsyncClient,
+    /// The primary broker first, then the configured additional ones
+    brokers: Arc<Vec<Broker>>,
     config: MqttConfig,
+    /// Topic filters to restore whenever the broker connection is re-established
+    subscriptions: Arc<Mutex<Vec<String>>>,
+    incoming: broadcast::Sender<InboundMessage>,
+    /// Number of broker connections established so far
+    connections: Arc<watch::Sender<u64>>,
+    /// Sparkplug B birth/death sequence number announced in the will
+    bd_seq: u64,
+    /// Topic settings with the parsed templates, replaced when the configuration is reloaded
+    topics: Arc<RwLock<Topics>>,
+    /// Device object names resolving `{device_name}` for devices without an alias
+    device_names: Arc<RwLock<HashMap<u32, String>>>,
+    aliases: Arc<Aliases>,
+    /// Discovery configs published per device, by topic; gateway entities
+    /// are kept under no device
+    discovered: Arc<Mutex<HashMap<Option<u32>, BTreeMap<String, String>>>>,
+    transforms: Arc<Transforms>,
+    /// Live feed every published message is reported to
+    events: Events,
 }
 
-#[derive(Serialize)]
-pub struct HaDiscoveryPayload {
-    pub name: String,
-    pub state_topic: String,
-    pub command_topic: Option<String>,
-    pub unique_id: String,
-    pub device: HaDevice,
+/// `topics` settings with the `topics.state` and `topics.command` templates parsed
+struct Topics {
+    config: TopicConfig,
+    state: TopicTemplate,
+    command: TopicTemplate,
 }
 
-#[derive(Serialize)]
-pub struct HaDevice {
-    pub identifiers: Vec<String>,
-    pub name: String,
-    pub manufacturer: String,
-    pub model: String,
+impl Topics {
+    fn parse(config: &MqttConfig, topics: &TopicConfig) -> Result<Self, String> {
+        let parse = |template: &str| TopicTemplate::parse(template, &config.base_topic, &config.discovery_prefix, &topics.site);
+        let (state, command) = (parse(&topics.state)?, parse(&topics.command)?);
+        command.check_reversible()?;
+        Ok(Self { config: topics.clone(), state, command })
+    }
 }
 
-impl MqttService {
-    pub async fn new(config: MqttConfig) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut mqttoptions = MqttOptions::new(
-            format!("bacnet-gateway-{}", std::process::id()),
-            &config.broker_host,
-            config.broker_port,
-        );
+/// State shared by the connections to all brokers
+struct Shared {
+    /// Topic filters to restore whenever a broker connection is re-established
+    subscriptions: Arc<Mutex<Vec<String>>>,
+    incoming: broadcast::Sender<InboundMessage>,
+    /// Number of broker connections established so far, to any broker
+    connections: Arc<watch::Sender<u64>>,
+    /// Log messages instead of publishing them
+    dry_run: bool,
+}
+
+/// Connection to one broker, with its own will and offline buffer
+struct Broker {
+    name: String,
+    client: AsyncClient,
+    /// Publishes wait for room in the primary broker's request queue; the
+    /// others never block the gateway while they are unreachable
+    primary: bool,
+    accept_commands: bool,
+    adapter: Adapter,
+    /// Whether the broker connection is up
+    connected: Arc<AtomicBool>,
+    /// State messages published while disconnected, replayed in order on reconnect
+    buffer: Arc<Mutex<VecDeque<(String, String)>>>,
+    dry_run: bool,
+}
+
+/// Sends messages in order, waiting for room in the request queue only if `wait`
+async fn send(client: &AsyncClient, messages: Vec<Outgoing>, wait: bool) -> Result<(), ClientError> {
+    for message in messages {
+        if wait {
+            client.publish(message.topic, message.qos, message.retain, message.payload).await?;
+        } else {
+            client.try_publish(message.topic, message.qos, message.retain, message.payload)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the broker's credentials and TLS transport
+fn secure(mqttoptions: &mut MqttOptions, broker: &BrokerConfig, adapter: &Adapter) -> Result<(), Box<dyn std::error::Error>> {
+    let username = secret::resolve(broker.username.as_deref(), None).map_err(|e| format!("broker {}: {}", broker.name, e))?;
+    let password = secret::resolve(broker.password.as_deref(), broker.password_file.as_deref())
+        .map_err(|e| format!("broker {}: {}", broker.name, e))?;
+    if let Some((username, password)) = adapter.credentials() {
+        mqttoptions.set_credentials(username, password);
+    } else if let (Some(u), Some(p)) = (username, password) {
+        mqttoptions.set_credentials(u, p);
+    }
+    if let Some(tls) = &broker.tls {
+        let ca = std::fs::read(&tls.ca_file)?;
+        let client_auth = match (&tls.client_cert_file, &tls.client_key_file) {
+            (Some(cert), Some(key)) => Some((std::fs::read(cert)?, std::fs::read(key)?)),
+            (None, None) => None,
+            _ => return Err(format!("broker {}: client_cert_file and client_key_file go together", broker.name).into()),
+        };
+        mqttoptions.set_transport(Transport::tls(ca, client_auth, adapter.alpn(broker.broker_port)));
+    } else if broker.profile == BrokerProfile::AwsIot {
+        return Err(format!("broker {}: AWS IoT Core needs tls with a client certificate", broker.name).into());
+    }
+    Ok(())
+}
+
+/// The broker configured at the top of the `mqtt` section
+fn primary_broker(config: &MqttConfig) -> BrokerConfig {
+    BrokerConfig {
+        name: "primary".to_string(),
+        broker_host: config.broker_host.clone(),
+        broker_port: config.broker_port,
+        username: config.username.clone(),
+        password: config.password.clone(),
+        password_file: config.password_file.clone(),
+        client_id: None,
+        tls: config.tls.clone(),
+        profile: config.profile,
+        aws: config.aws.clone(),
+        azure: config.azure.clone(),
+        accept_commands: true,
+    }
+}
+
+/// Connects to the primary broker of `config` and disconnects again, for
+/// checking the settings before they are saved
+pub async fn test_connection(config: &MqttConfig, timeout: Duration) -> Result<(), String> {
+    let broker = primary_broker(config);
+    let client_id = config.client_id.clone().unwrap_or_else(|| format!("bacnet-gateway-test-{}", std::process::id()));
+    let adapter = Adapter::new(&broker, &client_id).map_err(|e| e.to_string())?;
+    let mut mqttoptions = MqttOptions::new(client_id, &broker.broker_host, broker.broker_port);
+    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    secure(&mut mqttoptions, &broker, &adapter).map_err(|e| e.to_string())?;
+    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+    let connect = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    };
+    let result = tokio::time::timeout(timeout, connect)
+        .await
+        .unwrap_or_else(|_| Err(format!("no answer from {}:{} within {:?}", broker.broker_host, broker.broker_port, timeout)));
+    let _ = client.try_disconnect();
+    result
+}
+
+impl Broker {
+    fn connect(broker: &BrokerConfig, config: &MqttConfig, bd_seq: u64, primary: bool, shared: &Shared) -> Result<Self, Box<dyn std::error::Error>> {
+        let client_id = broker
+            .client_id
+            .clone()
+            .or_else(|| config.client_id.clone())
+            .unwrap_or_else(|| format!("bacnet-gateway-{}", std::process::id()));
+        if broker.profile == BrokerProfile::AzureIotHub && broker.client_id.is_none() && config.client_id.is_none() {
+            return Err(format!("broker {}: Azure IoT Hub needs the device ID as client_id", broker.name).into());
+        }
+        let adapter = Adapter::new(broker, &client_id)?;
+        let mut mqttoptions = MqttOptions::new(client_id, &broker.broker_host, broker.broker_port);
         mqttoptions.set_keep_alive(Duration::from_secs(5));
-        
-        if let (Some(u), Some(p)) = (&config.username, &config.password) {
-            mqttoptions.set_credentials(u, p);
+        mqttoptions.set_clean_session(config.clean_session);
+        let status_topic = gateway_status_topic(config);
+        let availability = config.publish.availability;
+        // The broker flags the gateway offline if the connection drops without a goodbye
+        let (topic, payload, will_qos, retain) = match config.mode {
+            MqttMode::HomeAssistant => (status_topic.clone(), b"offline".to_vec(), qos(&availability), availability.retain),
+            MqttMode::SparkplugB => {
+                let (topic, payload) = sparkplug::node_death(&config.sparkplug, bd_seq);
+                (topic, payload, QoS::AtLeastOnce, false)
+            }
+            MqttMode::Homie => {
+                let (topic, payload) = homie::will(&config.homie);
+                (topic, payload.as_bytes().to_vec(), QoS::AtLeastOnce, true)
+            }
+        };
+        // The will is the message itself, not the shadow update AWS retained messages come with
+        if let Some(will) = adapter.adapt(&topic, will_qos, retain, payload).pop().filter(|_| !shared.dry_run) {
+            mqttoptions.set_last_will(LastWill::new(will.topic, will.payload, will.qos, will.retain));
         }
+        let announce_status = config.mode == MqttMode::HomeAssistant && !shared.dry_run;
+        secure(&mut mqttoptions, broker, &adapter)?;
 
         let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+        let connected = Arc::new(AtomicBool::new(false));
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+        let reconnected = Arc::new(watch::Sender::new(()));
 
         // Spawn background task to keep the MQTT connection and receive events
+        let name = broker.name.clone();
+        let accept_commands = broker.accept_commands && adapter.subscribes();
+        let loop_client = client.clone();
+        let loop_subscriptions = shared.subscriptions.clone();
+        let loop_incoming = shared.incoming.clone();
+        let loop_connections = shared.connections.clone();
+        let loop_connected = connected.clone();
+        let loop_reconnected = reconnected.clone();
+        let loop_adapter = adapter.clone();
         tokio::spawn(async move {
             loop {
                 match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let message = match loop_adapter.twin_desired(&publish.topic, &publish.payload) {
+                            Some(desired) => InboundMessage { topic: TWIN_DESIRED_TOPIC.to_string(), payload: desired },
+                            None => InboundMessage { topic: publish.topic.clone(), payload: publish.payload.to_vec() },
+                        };
+                        // No receivers just means nothing is interested in inbound messages yet
+                        let _ = loop_incoming.send(message);
+                    }
+                    Ok(Event::Incoming(Packet::ConnAck(connack))) => {
+                        info!("Connected to MQTT broker {} (session present: {})", name, connack.session_present);
+                        if announce_status {
+                            for message in loop_adapter.adapt(&status_topic, qos(&availability), availability.retain, b"online".to_vec()) {
+                                if let Err(e) = loop_client.try_publish(message.topic, message.qos, message.retain, message.payload) {
+                                    error!("Failed to publish gateway status {}: {}", status_topic, e);
+                                }
+                            }
+                        }
+                        // The request queue is drained by this very loop, so never block on it here.
+                        // A resumed session still holds the subscriptions; subscribing again
+                        // would only have the broker resend its retained messages
+                        let topics = if accept_commands && !connack.session_present {
+                            loop_subscriptions.lock().map(|t| t.clone()).unwrap_or_default()
+                        } else {
+                            Vec::new()
+                        };
+                        let (service_topics, requests) = loop_adapter.on_connect();
+                        for topic in topics.iter().map(String::as_str).chain(service_topics) {
+                            if let Err(e) = loop_client.try_subscribe(topic, QoS::AtLeastOnce) {
+                                error!("Failed to subscribe to {}: {}", topic, e);
+                            }
+                        }
+                        for request in requests {
+                            if let Err(e) = loop_client.try_publish(request.topic, request.qos, request.retain, request.payload) {
+                                error!("Failed to send request to broker {}: {}", name, e);
+                            }
+                        }
+                        loop_connected.store(true, Ordering::Relaxed);
+                        loop_reconnected.send_replace(());
+                        loop_connections.send_modify(|count| *count += 1);
+                    }
                     Ok(event) => {
-                        // TODO: Handle incoming command messages here and route them back to BACnet
                         tracing::trace!("MQTT Event: {:?}", event);
                     }
                     Err(e) => {
-                        tracing::error!("MQTT Connection Error: {:?}", e);
+                        loop_connected.store(false, Ordering::Relaxed);
+                        tracing::error!("MQTT Connection Error on broker {}: {:?}", name, e);
                         tokio::time::sleep(Duration::from_secs(3)).await;
+                        if let Some((username, password)) = loop_adapter.credentials() {
+                            eventloop.mqtt_options.set_credentials(username, password);
+                        }
                     }
                 }
             }
         });
 
-        Ok(Self { client, config })
+        // Replay what was buffered during an outage once the broker accepts us again
+        let flush_client = client.clone();
+        let flush_buffer = buffer.clone();
+        let mut flush_connections = reconnected.subscribe();
+        let state = config.publish.state;
+        let flush_name = broker.name.clone();
+        let flush_adapter = adapter.clone();
+        tokio::spawn(async move {
+            while flush_connections.changed().await.is_ok() {
+                let mut replayed = 0;
+                loop {
+                    let next = flush_buffer.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+                    let Some((topic, payload)) = next else {
+                        break;
+                    };
+                    let messages = flush_adapter.adapt(&topic, qos(&state), state.retain, payload.into_bytes());
+                    if let Err(e) = send(&flush_client, messages, true).await {
+                        error!("Failed to replay {}: {}", topic, e);
+                        break;
+                    }
+                    replayed += 1;
+                }
+                if replayed > 0 {
+                    info!("Replayed {} messages buffered while broker {} was unreachable", replayed, flush_name);
+                }
+            }
+        });
+
+        Ok(Self { name: broker.name.clone(), client, primary, accept_commands, adapter, connected, buffer, dry_run: shared.dry_run })
+    }
+
+    async fn publish(&self, topic: &str, qos: QoS, retain: bool, payload: Vec<u8>) -> Result<(), ClientError> {
+        if self.dry_run {
+            info!("Dry run, not publishing to broker {}: {} {}", self.name, topic, String::from_utf8_lossy(&payload));
+            return Ok(());
+        }
+        send(&self.client, self.adapter.adapt(topic, qos, retain, payload), self.primary).await
+    }
+
+    /// Publishes a state-class message, or queues it while the broker is
+    /// unreachable and earlier messages are still waiting
+    async fn publish_buffered(&self, topic: &str, payload: &str, config: &MqttConfig) -> Result<(), ClientError> {
+        if !self.dry_run {
+            let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+            if !self.connected.load(Ordering::Relaxed) || !buffer.is_empty() {
+                if config.offline_buffer.capacity == 0 {
+                    return Ok(());
+                }
+                if buffer.len() >= config.offline_buffer.capacity {
+                    match config.offline_buffer.overflow {
+                        OverflowPolicy::DropOldest => {
+                            buffer.pop_front();
+                        }
+                        OverflowPolicy::DropNewest => {
+                            warn!("Offline buffer of broker {} full, dropping {}", self.name, topic);
+                            return Ok(());
+                        }
+                    }
+                }
+                buffer.push_back((topic.to_string(), payload.to_string()));
+                return Ok(());
+            }
+        }
+        self.publish(topic, qos(&config.publish.state), config.publish.state.retain, payload.into()).await
     }
+}
+
+/// A message received on one of the subscribed topics
+#[derive(Clone, Debug)]
+pub struct InboundMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Serialize, Default)]
+pub struct HaDiscoveryPayload {
+    pub name: String,
+    pub state_topic: String,
+    pub command_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_attributes_topic: Option<String>,
+    /// Topics carrying `online`/`offline`: the gateway's LWT topic and, for
+    /// device entities, the device topic that is offline while polling is suspended
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub availability: Vec<HaAvailability>,
+    /// `all` when several availability topics must be online at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability_mode: Option<&'static str>,
+    pub unique_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    /// `measurement` for analog sensors so Home Assistant keeps statistics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// `diagnostic` for fault and gateway health entities
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u64>,
+    /// Range and step of a `number`, Home Assistant allows 1-100 otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+    /// Possible states of an `enum` sensor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<String>>,
+    /// Extracts the value from JSON state payloads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_template: Option<String>,
+    pub device: HaDevice,
+    /// States of a `binary_sensor` when not the default `ON`/`OFF`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_on: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_off: Option<String>,
+    /// States of a `switch` when not its `ON`/`OFF` commands
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_on: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_off: Option<String>,
+}
+
+impl HaDiscoveryPayload {
+    /// Replaces the derived fields with those a per-point override sets
+    pub fn apply(&mut self, entity: &EntityOverride) {
+        let fields = [
+            (&mut self.device_class, &entity.device_class),
+            (&mut self.state_class, &entity.state_class),
+            (&mut self.unit_of_measurement, &entity.unit_of_measurement),
+            (&mut self.icon, &entity.icon),
+            (&mut self.entity_category, &entity.entity_category),
+            (&mut self.value_template, &entity.value_template),
+        ];
+        for (field, value) in fields {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+        self.expire_after = entity.expire_after.or(self.expire_after);
+    }
+}
+
+/// Home Assistant component of a point: inputs are read-only sensors, outputs
+/// and values controllable, multi-state values selects when their states are named
+pub fn ha_component(object: ObjectRef, has_options: bool) -> &'static str {
+    match object.object_type {
+        3 => "binary_sensor",
+        1 | 2 => "number",
+        4 | 5 => "switch",
+        19 if has_options => "select",
+        _ => "sensor",
+    }
+}
+
+/// True for components Home Assistant sends commands from
+pub fn is_commandable(component: &str) -> bool {
+    matches!(component, "number" | "switch" | "select")
+}
+
+#[derive(Serialize, Clone)]
+pub struct HaAvailability {
+    pub topic: String,
+}
 
-    /// Publishes a Home Assistant Auto-Discovery payload for a sensor/binary_sensor
-    pub async fn publish_discovery(&self, component: &str, unique_id: &str, payload: &HaDiscoveryPayload) {
+#[derive(Serialize, Clone, Default)]
+pub struct HaDevice {
+    pub identifiers: Vec<String>,
+    pub name: String,
+    pub manufacturer: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sw_version: Option<String>,
+    /// Link to the device's page in the gateway web UI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub configuration_url: Option<String>,
+    /// Identifier of the gateway device a BACnet device is reached through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub via_device: Option<String>,
+}
+
+/// Home Assistant identifier of the gateway device
+pub fn gateway_identifier(gateway_device_id: u32) -> String {
+    format!("bacnet_gateway_{}", gateway_device_id)
+}
+
+/// Where a published value came from
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueSource {
+    Poll,
+    Simulation,
+}
+
+/// Trust in a published point value
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    Good,
+    /// Polled during a tagging maintenance window
+    Uncertain,
+    /// The device reports a fault on the point
+    Bad,
+    Simulated,
+}
+
+impl Quality {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quality::Good => "good",
+            Quality::Uncertain => "uncertain",
+            Quality::Bad => "bad",
+            Quality::Simulated => "simulated",
+        }
+    }
+}
+
+fn point_payload(value: &str, ts: &str, quality: Quality, units: Option<&str>, stale: bool) -> serde_json::Value {
+    serde_json::json!({
+        "value": state_json(value),
+        "ts": ts,
+        "quality": quality,
+        "units": units,
+        "stale": stale,
+    })
+}
+
+/// A state as JSON: numbers as numbers, state names and the like as strings
+pub fn state_json(value: &str) -> serde_json::Value {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map_or_else(|| serde_json::Value::String(value.to_string()), serde_json::Value::Number)
+}
+
+/// Current UTC time as RFC 3339, e.g. `2024-05-01T12:30:00.125Z`
+pub fn utc_timestamp() -> String {
+    utc_timestamp_at(SystemTime::now())
+}
+
+/// `time` as RFC 3339 in UTC, in the format of [`utc_timestamp`]
+pub fn utc_timestamp_at(time: SystemTime) -> String {
+    let now = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = maintenance::civil_from_days((secs / 86_400) as i64);
+    let of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Provenance published alongside a state, to explain where a value came from
+#[derive(Serialize, Clone, Debug)]
+pub struct ValueProvenance {
+    pub source: ValueSource,
+    pub latency_ms: Option<u64>,
+    pub invoke_id: Option<u8>,
+    pub poll_cycle: Option<u64>,
+    /// Set while a simulated value overrides the device's real one
+    pub simulated: bool,
+    /// BACnet application data type of the value ("real", "enumerated", ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_type: Option<&'static str>,
+    /// Typed JSON form of the value, the state topic only carries text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    /// Human readable label of an enumerated value, in the configured language
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Maintenance window open while the value was read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<String>,
+}
+
+fn qos(options: &PublishOptions) -> QoS {
+    match options.qos {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
+
+/// Retained `online`/`offline` status of the gateway, backed by the LWT
+fn gateway_status_topic(config: &MqttConfig) -> String {
+    format!("{}/gateway/status", config.base_topic)
+}
+
+/// Attributes topic paired with a state topic, referenced as `json_attributes_topic`
+pub fn attributes_topic(state_topic: &str) -> String {
+    format!("{}/attributes", state_topic)
+}
+
+impl MqttService {
+    /// Connects to the brokers; in `dry_run` messages are logged instead of published
+    pub async fn new(config: MqttConfig, aliases: Arc<Aliases>, dry_run: bool, events: Events) -> Result<Self, Box<dyn std::error::Error>> {
+        let topics = Arc::new(RwLock::new(Topics::parse(&config, &config.topics)?));
+        let transforms = Transforms::new(&config.transforms)?;
+        if !config.clean_session && config.client_id.is_none() {
+            return Err("mqtt.clean_session: false needs a fixed mqtt.client_id to resume the session after a restart".into());
+        }
+
+        let bd_seq = sparkplug::birth_death_sequence();
+        let subscriptions = Arc::new(Mutex::new(Vec::<String>::new()));
+        let (incoming, _) = broadcast::channel(256);
+        let connections = Arc::new(watch::Sender::new(0));
+        let primary = primary_broker(&config);
+        let shared = Shared { subscriptions: subscriptions.clone(), incoming: incoming.clone(), connections: connections.clone(), dry_run };
+        let mut brokers = vec![Broker::connect(&primary, &config, bd_seq, true, &shared)?];
+        for broker in &config.brokers {
+            brokers.push(Broker::connect(broker, &config, bd_seq, false, &shared)?);
+        }
+
+        Ok(Self {
+            brokers: Arc::new(brokers),
+            config,
+            subscriptions,
+            incoming,
+            connections,
+            bd_seq,
+            topics,
+            device_names: Arc::new(RwLock::new(HashMap::new())),
+            aliases,
+            discovered: Arc::new(Mutex::new(HashMap::new())),
+            transforms: Arc::new(transforms),
+            events,
+        })
+    }
+
+    /// Applies the point transform scripts to a state text, `None` if a script dropped it
+    pub fn transform_point(&self, device_id: u32, object: ObjectRef, value: &str) -> Option<String> {
+        self.transforms.point(device_id, object, value)
+    }
+
+    /// True if a broker keeps a device twin, see `report_twin`
+    pub fn has_twin(&self) -> bool {
+        self.brokers.iter().any(|broker| broker.adapter.has_twin())
+    }
+
+    /// Reports properties to the device twin of every broker keeping one
+    pub async fn report_twin(&self, reported: &serde_json::Value) {
+        for broker in self.brokers.iter().filter(|broker| broker.adapter.has_twin()) {
+            let topic = cloud::twin_reported_topic();
+            if let Err(e) = broker.publish(&topic, QoS::AtLeastOnce, false, reported.to_string().into_bytes()).await {
+                error!("Failed to report twin properties to broker {}: {}", broker.name, e);
+            }
+        }
+    }
+
+    /// Publishes to every broker, returning the primary broker's result
+    async fn send(&self, topic: &str, qos: QoS, retain: bool, payload: impl Into<Vec<u8>>) -> Result<(), ClientError> {
+        let payload = payload.into();
+        self.events.emit(FeedEvent::Published { topic: topic.to_string(), payload: String::from_utf8_lossy(&payload).into_owned(), retain });
+        let mut result = Ok(());
+        for broker in self.brokers.iter() {
+            match broker.publish(topic, qos, retain, payload.clone()).await {
+                Err(e) if broker.primary => result = Err(e),
+                Err(e) => warn!("Failed to publish {} to broker {}: {}", topic, broker.name, e),
+                Ok(()) => {}
+            }
+        }
+        result
+    }
+
+    /// Whether the primary broker connection is up
+    pub fn is_connected(&self) -> bool {
+        self.brokers.iter().any(|broker| broker.primary && broker.connected.load(Ordering::Relaxed))
+    }
+
+    /// Changes whenever the broker connection is (re-)established
+    pub fn connections(&self) -> watch::Receiver<u64> {
+        self.connections.subscribe()
+    }
+
+    /// Sparkplug B birth/death sequence number of this process
+    pub fn bd_seq(&self) -> u64 {
+        self.bd_seq
+    }
+
+    /// Subscribes to a topic filter, kept across reconnects
+    pub async fn subscribe(&self, topic: &str) {
+        if let Ok(mut subscriptions) = self.subscriptions.lock() {
+            if !subscriptions.iter().any(|t| t == topic) {
+                subscriptions.push(topic.to_string());
+            }
+        }
+        for broker in self.brokers.iter().filter(|broker| broker.accept_commands) {
+            let result = if broker.primary {
+                broker.client.subscribe(topic, QoS::AtLeastOnce).await
+            } else {
+                broker.client.try_subscribe(topic, QoS::AtLeastOnce)
+            };
+            if let Err(e) = result {
+                error!("Failed to subscribe to {} on broker {}: {}", topic, broker.name, e);
+            }
+        }
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) {
+        if let Ok(mut subscriptions) = self.subscriptions.lock() {
+            subscriptions.retain(|t| t != topic);
+        }
+        for broker in self.brokers.iter().filter(|broker| broker.accept_commands) {
+            let result = if broker.primary {
+                broker.client.unsubscribe(topic).await
+            } else {
+                broker.client.try_unsubscribe(topic)
+            };
+            if let Err(e) = result {
+                error!("Failed to unsubscribe from {} on broker {}: {}", topic, broker.name, e);
+            }
+        }
+    }
+
+    fn topics(&self) -> std::sync::RwLockReadGuard<'_, Topics> {
+        self.topics.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replaces the topic settings, moving the command subscription if its
+    /// filter changed; true if any topic a point is published on changed
+    pub async fn reload_topics(&self, config: &TopicConfig) -> Result<bool, String> {
+        let topics = Topics::parse(&self.config, config)?;
+        let new_filter = topics.command.filter();
+        let (old_filter, changed) = {
+            let mut current = self.topics.write().unwrap_or_else(|e| e.into_inner());
+            let changed = current.config.state != config.state
+                || current.config.command != config.command
+                || current.config.site != config.site;
+            let old_filter = current.command.filter();
+            *current = topics;
+            (old_filter, changed)
+        };
+        if old_filter != new_filter {
+            info!("Command topics moved from {} to {}", old_filter, new_filter);
+            self.unsubscribe(&old_filter).await;
+            self.subscribe(&new_filter).await;
+        }
+        Ok(changed)
+    }
+
+    /// Stream of messages received on subscribed topics
+    pub fn incoming(&self) -> broadcast::Receiver<InboundMessage> {
+        self.incoming.subscribe()
+    }
+
+    /// Publishes a binary payload, e.g. a Sparkplug B protobuf
+    pub async fn publish_bytes(&self, topic: &str, payload: Vec<u8>) {
+        if let Err(e) = self.send(topic, QoS::AtLeastOnce, false, payload).await {
+            error!("Failed to publish {}: {}", topic, e);
+        }
+    }
+
+    /// Publishes a raw payload, e.g. a BACnet write forwarded to an MQTT topic
+    pub async fn publish(&self, topic: &str, payload: &str, retain: bool) {
+        if let Err(e) = self.send(topic, QoS::AtLeastOnce, retain, payload).await {
+            error!("Failed to publish {}: {}", topic, e);
+        }
+    }
+
+    /// True if a topic template refers to `{device_name}`, which takes a read
+    /// of the device object's name
+    pub fn uses_device_name(&self) -> bool {
+        let topics = self.topics();
+        topics.state.uses_device_name() || topics.command.uses_device_name()
+    }
+
+    /// Records a device's object name for `{device_name}`, sanitized under
+    /// `topics.name_policy` and made unique among the known devices
+    pub fn set_device_name(&self, device_id: u32, name: &str) {
+        let (policy, replacement) = {
+            let topics = self.topics();
+            (topics.config.name_policy, topics.config.name_replacement)
+        };
+        let mut name = topic::sanitize_name(name, policy, replacement);
+        if name.is_empty() {
+            return;
+        }
+        let mut names = self.device_names.write().unwrap_or_else(|e| e.into_inner());
+        if names.iter().any(|(id, known)| *id != device_id && *known == name) {
+            warn!("Device {} shares the name '{}' with another device, appending its instance", device_id, name);
+            name = format!("{}_{}", name, device_id);
+        }
+        names.insert(device_id, name);
+    }
+
+    pub fn naming(&self) -> &NamingConfig {
+        &self.config.naming
+    }
+
+    /// `topics.site`, also available to the entity naming templates
+    pub fn site(&self) -> String {
+        self.topics().config.site.clone()
+    }
+
+    /// Configured names of devices and objects
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    /// A device alias made safe for a topic level under `topics.name_policy`
+    fn sanitize_alias(&self, alias: &str) -> String {
+        let topics = self.topics();
+        topic::sanitize_name(alias, topics.config.name_policy, topics.config.name_replacement)
+    }
+
+    /// Name substituted for `{device_name}`: the device's alias, else its
+    /// object name, `bacnet_<id>` until that is read
+    fn device_name(&self, device_id: u32) -> String {
+        if let Some(alias) = self.aliases.device(device_id) {
+            return self.sanitize_alias(&alias);
+        }
+        let names = self.device_names.read().unwrap_or_else(|e| e.into_inner());
+        names.get(&device_id).cloned().unwrap_or_else(|| format!("bacnet_{}", device_id))
+    }
+
+    fn device_by_name(&self, name: &str) -> Option<u32> {
+        if let Some(device_id) = self.aliases.find_device(|alias| self.sanitize_alias(alias) == name) {
+            return Some(device_id);
+        }
+        let names = self.device_names.read().unwrap_or_else(|e| e.into_inner());
+        names
+            .iter()
+            .find(|(id, known)| *known == name && self.aliases.device(**id).is_none())
+            .map(|(id, _)| *id)
+            .or_else(|| name.strip_prefix("bacnet_")?.parse().ok())
+    }
+
+    /// Name substituted for `{object_name}`, the object's alias or reference
+    fn object_name(&self, device_id: u32, object: ObjectRef) -> String {
+        self.aliases.object(device_id, object).unwrap_or_else(|| object.to_string())
+    }
+
+    /// State topic of a device's point, rendered from `topics.state`
+    pub fn device_state_topic(&self, device_id: u32, object: ObjectRef) -> String {
+        let name = self.device_name(device_id);
+        self.topics().state.render(device_id, &name, object, &self.object_name(device_id, object))
+    }
+
+    /// Availability of a gateway-level entity, or with a device also that
+    /// device's availability
+    pub fn availability(&self, device_id: Option<u32>) -> (Vec<HaAvailability>, Option<&'static str>) {
+        let mut topics = vec![HaAvailability { topic: gateway_status_topic(&self.config) }];
+        if let Some(device_id) = device_id {
+            topics.push(HaAvailability { topic: self.device_availability_topic(device_id) });
+            topics.push(HaAvailability { topic: self.device_reachability_topic(device_id) });
+        }
+        let mode = (topics.len() > 1).then_some("all");
+        (topics, mode)
+    }
+
+    /// Topic that is offline while a device leaves its polls unanswered
+    pub fn device_reachability_topic(&self, device_id: u32) -> String {
+        format!("{}/{}/availability", self.config.base_topic, device_id)
+    }
+
+    /// Availability topic of a device's entities, offline while polling is suspended
+    pub fn device_availability_topic(&self, device_id: u32) -> String {
+        format!("{}/sensor/bacnet_{}/availability", self.config.discovery_prefix, device_id)
+    }
+
+    /// Topic carrying the status flags, units and name of a device's polled point
+    pub fn device_status_topic(&self, device_id: u32) -> String {
+        format!("{}/sensor/bacnet_{}/status", self.config.discovery_prefix, device_id)
+    }
+
+    /// Configured discovery overrides of a point
+    pub fn entity_override(&self, device_id: u32, object: ObjectRef) -> Option<&EntityOverride> {
+        self.config.entities.iter().find(|e| e.device == device_id && e.object == object)
+    }
+
+    /// Publishes the result of a status refresh as JSON
+    pub async fn publish_point_status(&self, device_id: u32, object: ObjectRef, bundle: &PropertyBundle) {
+        let topic = self.device_status_topic(device_id);
+        let status = serde_json::json!({
+            "object": object.to_string(),
+            "object_name": bundle.object_name,
+            "present_value": bundle.present_value.as_ref().map(|v| v.to_json()),
+            "status_flags": bundle.status_flags,
+            "units": bundle.units,
+        });
+        if let Err(e) = self.publish_buffered(&topic, status.to_string()).await {
+            error!("Failed to publish status {}: {}", topic, e);
+        }
+    }
+
+    /// Topic of the alarm state of one point, e.g. `<base>/alarms/1001/AI:3`
+    pub fn alarm_topic(&self, device_id: u32, object: ObjectRef) -> String {
+        format!("{}/alarms/{}/{}", self.config.base_topic, device_id, object)
+    }
+
+    /// Publishes an alarm state change, or with `renotification` > 0 a repeat
+    /// of an unacknowledged one, as JSON
+    pub async fn publish_alarm(&self, device_id: u32, object: ObjectRef, active: bool, label: &str, renotification: u32) {
+        let topic = self.alarm_topic(device_id, object);
+        let mut payload = serde_json::json!({ "active": active, "label": label });
+        if renotification > 0 {
+            payload["renotification"] = renotification.into();
+        }
+        if let Err(e) = self.send(&topic, qos(&self.config.publish.alarms), self.config.publish.alarms.retain, payload.to_string()).await {
+            error!("Failed to publish alarm {}: {}", topic, e);
+        }
+    }
+
+    /// Topic of a supervisory heartbeat's health, e.g. `<base>/heartbeat/1001/BV:5`
+    pub fn heartbeat_topic(&self, device_id: u32, object: ObjectRef) -> String {
+        format!("{}/heartbeat/{}/{}", self.config.base_topic, device_id, object)
+    }
+
+    /// Publishes whether a heartbeat's writes succeed, with the last error once failed
+    pub async fn publish_heartbeat(&self, device_id: u32, object: ObjectRef, failures: u32, error: Option<&str>) {
+        let topic = self.heartbeat_topic(device_id, object);
+        let mut payload = serde_json::json!({
+            "status": if error.is_some() { "failed" } else { "ok" },
+            "consecutive_failures": failures,
+        });
+        if let Some(error) = error {
+            payload["error"] = error.into();
+        }
+        if let Err(e) = self.publish_buffered(&topic, payload.to_string()).await {
+            error!("Failed to publish heartbeat {}: {}", topic, e);
+        }
+    }
+
+    /// Topic discovery progress events are published to
+    pub fn discovery_progress_topic(&self) -> String {
+        format!("{}/discovery/progress", self.config.base_topic)
+    }
+
+    /// Topic on-demand requests are sent to, e.g. `<base>/rpc/read`
+    pub fn rpc_topic(&self, method: &str) -> String {
+        format!("{}/rpc/{}", self.config.base_topic, method)
+    }
+
+    pub fn rpc_response_topic(&self, correlation_id: &str) -> String {
+        format!("{}/rpc/response/{}", self.config.base_topic, topic::sanitize(correlation_id))
+    }
+
+    /// Topic write audit entries are published to
+    pub fn audit_topic(&self) -> String {
+        format!("{}/audit", self.config.base_topic)
+    }
+
+    /// Topic filter matching every command topic `topics.command` can produce
+    pub fn command_filter(&self) -> String {
+        self.topics().command.filter()
+    }
+
+    /// Topic the outcome of a command received on `command_topic` goes to
+    pub fn command_result_topic(&self, command_topic: &str) -> String {
+        format!("{}/result", command_topic)
+    }
+
+    /// Publishes the outcome of a command, never retained
+    pub async fn publish_command_result(&self, command_topic: &str, result: &crate::command::CommandResult) {
+        if let Ok(json) = serde_json::to_string(result) {
+            self.publish(&self.command_result_topic(command_topic), &json, false).await;
+        }
+    }
+
+    /// Command topic writing the present-value of a device's object
+    pub fn command_topic(&self, device_id: u32, object: ObjectRef) -> String {
+        let name = self.device_name(device_id);
+        self.topics().command.render(device_id, &name, object, &self.object_name(device_id, object))
+    }
+
+    /// Device and object addressed by a command topic
+    pub fn parse_command_topic(&self, topic: &str) -> Option<(u32, ObjectRef)> {
+        self.topics().command.capture(topic, |name| self.device_by_name(name))
+    }
+
+    /// Topic of one value of a grouped entity, e.g. `<base>/climate/1001/AV:1/temperature`
+    pub fn group_topic(&self, component: &str, device_id: u32, key: ObjectRef, leaf: &str) -> String {
+        format!("{}/{}/{}/{}/{}", self.config.base_topic, component, device_id, key, leaf)
+    }
+
+    pub fn group_command_topic(&self, component: &str, device_id: u32, key: ObjectRef, leaf: &str) -> String {
+        format!("{}/set", self.group_topic(component, device_id, key, leaf))
+    }
+
+    /// Topic filter matching every grouped entity command topic
+    pub fn group_command_filter(&self) -> String {
+        format!("{}/+/+/+/+/set", self.config.base_topic)
+    }
+
+    /// Component, device, key object and value addressed by a grouped entity command topic
+    pub fn parse_group_command_topic(&self, topic: &str) -> Option<(String, u32, ObjectRef, String)> {
+        let rest = topic.strip_prefix(&self.config.base_topic)?.strip_prefix('/')?;
+        let levels: Vec<&str> = rest.split('/').collect();
+        let [component, device, key, leaf, "set"] = levels.as_slice() else {
+            return None;
+        };
+        Some((component.to_string(), device.parse().ok()?, key.parse().ok()?, leaf.to_string()))
+    }
+
+    /// Membership heartbeat topic of a cluster node, `+` for all of them
+    pub fn cluster_topic(&self, node_id: &str) -> String {
+        format!("{}/cluster/members/{}", self.config.base_topic, node_id)
+    }
+
+    pub fn parse_cluster_topic(&self, topic: &str) -> Option<String> {
+        let node_id = topic.strip_prefix(&self.cluster_topic(""))?;
+        (!node_id.is_empty() && !node_id.contains('/')).then(|| node_id.to_string())
+    }
+
+    /// State topic of a gateway-wide roll-up sensor, e.g. `<base>/rollup/active_alarms`
+    pub fn rollup_topic(&self, name: &str) -> String {
+        format!("{}/rollup/{}", self.config.base_topic, name)
+    }
+
+    /// State topic of a device's "any point in fault" binary sensor
+    pub fn device_fault_topic(&self, device_id: u32) -> String {
+        format!("{}/{}/fault", self.config.base_topic, device_id)
+    }
+
+    pub async fn publish_rollup(&self, name: &str, value: usize) {
+        self.publish_state(&self.rollup_topic(name), &value.to_string()).await;
+    }
+
+    pub async fn publish_device_fault(&self, device_id: u32, fault: bool) {
+        self.publish_state(&self.device_fault_topic(device_id), if fault { "ON" } else { "OFF" }).await;
+    }
+
+    /// Publishes the "any point in fault" binary sensor of a device
+    pub async fn publish_fault_discovery(&self, device_id: u32, name: String, device: HaDevice) {
+        let unique_id = format!("bacnet_{}_fault", device_id);
+        let (availability, availability_mode) = self.availability(Some(device_id));
+        let payload = HaDiscoveryPayload {
+            name,
+            state_topic: self.device_fault_topic(device_id),
+            command_topic: None,
+            json_attributes_topic: None,
+            availability,
+            availability_mode,
+            unique_id: unique_id.clone(),
+            unit_of_measurement: None,
+            device_class: Some("problem".to_string()),
+            entity_category: Some("diagnostic".to_string()),
+            options: None,
+            value_template: None,
+            device,
+            ..Default::default()
+        };
+        self.publish_discovery("binary_sensor", &unique_id, Some(device_id), &payload).await;
+    }
+
+    /// Gateway control topic, e.g. `<base>/control/suspend`
+    pub fn control_topic(&self, command: &str) -> String {
+        format!("{}/control/{}", self.config.base_topic, command)
+    }
+
+    /// Marks a device's entities available or unavailable in Home Assistant
+    pub async fn publish_availability(&self, device_id: u32, online: bool) {
+        self.publish_online(&self.device_availability_topic(device_id), online).await;
+    }
+
+    /// Publishes whether a device answers its polls
+    pub async fn publish_reachability(&self, device_id: u32, online: bool) {
+        self.publish_online(&self.device_reachability_topic(device_id), online).await;
+    }
+
+    async fn publish_online(&self, topic: &str, online: bool) {
+        let payload = if online { "online" } else { "offline" };
+        if let Err(e) = self.send(topic, qos(&self.config.publish.availability), self.config.publish.availability.retain, payload).await {
+            error!("Failed to publish availability {}: {}", topic, e);
+        }
+    }
+
+    /// Publishes the suspended gateway/groups/devices as JSON
+    pub async fn publish_suspensions(&self, suspensions: &Suspensions) {
+        let topic = format!("{}/suspensions", self.config.base_topic);
+        if let Ok(json) = serde_json::to_string(suspensions) {
+            if let Err(e) = self.publish_buffered(&topic, json).await {
+                error!("Failed to publish suspensions {}: {}", topic, e);
+            }
+        }
+    }
+
+    /// Publishes the gateway itself as a Home Assistant device linking to the web UI
+    pub async fn publish_gateway(&self, bacnet: &BacnetConfig, translator: &Translator, configuration_url: Option<String>) {
+        let unique_id = gateway_identifier(bacnet.device_id);
+        let payload = HaDiscoveryPayload {
+            name: translator.text(Text::GatewayStatus),
+            // The status itself stays available to show "offline"
+            state_topic: gateway_status_topic(&self.config),
+            command_topic: None,
+            json_attributes_topic: None,
+            availability: Vec::new(),
+            availability_mode: None,
+            unique_id: unique_id.clone(),
+            unit_of_measurement: None,
+            device_class: None,
+            entity_category: Some("diagnostic".to_string()),
+            options: None,
+            value_template: None,
+            device: HaDevice {
+                identifiers: vec![unique_id.clone()],
+                name: translator.format(Text::GatewayName, bacnet.device_id),
+                manufacturer: bacnet.vendor_name.clone(),
+                model: bacnet.model_name.clone(),
+                sw_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                configuration_url,
+                via_device: None,
+            },
+            ..Default::default()
+        };
+
+        self.publish_discovery("sensor", &unique_id, None, &payload).await;
+
+        // Site-wide roll-ups for overview dashboards
+        for (name, text) in [("active_alarms", Text::ActiveAlarms), ("devices_offline", Text::DevicesOffline)] {
+            let rollup_id = format!("{}_{}", unique_id, name);
+            let (availability, availability_mode) = self.availability(None);
+            let rollup = HaDiscoveryPayload {
+                name: translator.text(text),
+                state_topic: self.rollup_topic(name),
+                command_topic: None,
+                json_attributes_topic: None,
+                availability,
+                availability_mode,
+                unique_id: rollup_id.clone(),
+                unit_of_measurement: None,
+                device_class: None,
+                options: None,
+                value_template: None,
+                device: payload.device.clone(),
+                ..Default::default()
+            };
+            self.publish_discovery("sensor", &rollup_id, None, &rollup).await;
+        }
+    }
+
+    /// Publishes a Home Assistant Auto-Discovery payload, remembering it so
+    /// it can be republished and a device's entities retracted
+    pub async fn publish_discovery(&self, component: &str, unique_id: &str, device_id: Option<u32>, payload: &impl Serialize) {
+        if self.config.mode != MqttMode::HomeAssistant {
+            return;
+        }
         let topic = format!("{}/{}/{}/config", self.config.discovery_prefix, component, unique_id);
-        
         if let Ok(json) = serde_json::to_string(payload) {
-            if let Err(e) = self.client.publish(topic, QoS::AtLeastOnce, true, json).await {
+            {
+                let mut discovered = self.discovered.lock().unwrap_or_else(|e| e.into_inner());
+                discovered.entry(device_id).or_default().insert(topic.clone(), json.clone());
+            }
+            if let Err(e) = self.send(&topic, qos(&self.config.publish.discovery), self.config.publish.discovery.retain, json).await {
                 error!("Failed to publish discovery: {}", e);
             } else {
                 info!("Published discovery for {}", unique_id);
@@ -74,9 +1146,128 @@ impl MqttService {
         }
     }
 
+    /// Publishes every remembered discovery config again, returning how many
+    pub async fn republish_discovery(&self) -> usize {
+        let configs: Vec<(String, String)> = {
+            let discovered = self.discovered.lock().unwrap_or_else(|e| e.into_inner());
+            discovered.values().flatten().map(|(topic, json)| (topic.clone(), json.clone())).collect()
+        };
+        for (topic, json) in &configs {
+            if let Err(e) = self.send(topic, qos(&self.config.publish.discovery), self.config.publish.discovery.retain, json.clone()).await {
+                error!("Failed to republish discovery {}: {}", topic, e);
+            }
+        }
+        configs.len()
+    }
+
+    /// Topic Home Assistant publishes `online` on when it starts
+    pub fn birth_topic(&self) -> Option<String> {
+        (self.config.mode == MqttMode::HomeAssistant).then(|| format!("{}/status", self.config.discovery_prefix))
+    }
+
+    /// Removes a device's entities from Home Assistant with empty config payloads
+    pub async fn retract_discovery(&self, device_id: u32) {
+        let topics = {
+            let mut discovered = self.discovered.lock().unwrap_or_else(|e| e.into_inner());
+            discovered.remove(&Some(device_id)).unwrap_or_default().into_keys().collect::<Vec<_>>()
+        };
+        for topic in &topics {
+            if let Err(e) = self.send(topic, qos(&self.config.publish.discovery), self.config.publish.discovery.retain, "").await {
+                error!("Failed to retract discovery {}: {}", topic, e);
+            }
+        }
+        info!("Retracted {} discovery configs of device {}", topics.len(), device_id);
+    }
+
+    /// Publishes the provenance of the last state as JSON attributes
+    pub async fn publish_attributes(&self, state_topic: &str, provenance: &ValueProvenance) {
+        let topic = attributes_topic(state_topic);
+        if let Ok(json) = serde_json::to_string(provenance) {
+            if let Err(e) = self.publish_buffered(&topic, json).await {
+                error!("Failed to publish attributes {}: {}", topic, e);
+            }
+        }
+    }
+
+    /// Template Home Assistant needs to read point states in the configured payload format
+    pub fn value_template(&self) -> Option<String> {
+        match self.config.payload_format {
+            PayloadFormat::Plain => None,
+            PayloadFormat::Json => Some("{{ value_json.value }}".to_string()),
+        }
+    }
+
+    /// Publishes the state of a device's point, bare or as JSON with timestamp,
+    /// quality and units depending on the payload format
+    pub async fn publish_point_state(&self, device_id: u32, object: ObjectRef, value: &str, quality: Quality, units: Option<&str>) {
+        let topic = self.device_state_topic(device_id, object);
+        match self.config.payload_format {
+            PayloadFormat::Plain => self.publish_state(&topic, value).await,
+            PayloadFormat::Json => {
+                let payload = point_payload(value, &utc_timestamp(), quality, units, false);
+                self.publish_state(&topic, &payload.to_string()).await;
+            }
+        }
+    }
+
+    /// Publishes the last state of a point again, keeping the time it was
+    /// read; a stale one only in JSON, plain payloads have no room for the flag
+    pub async fn publish_cached_point(&self, device_id: u32, object: ObjectRef, updated: &str, published: &PublishedState, stale: bool) {
+        let topic = self.device_state_topic(device_id, object);
+        match self.config.payload_format {
+            PayloadFormat::Plain if stale => {}
+            PayloadFormat::Plain => self.publish_state(&topic, &published.state).await,
+            PayloadFormat::Json => {
+                let payload = point_payload(&published.state, updated, published.quality, published.units, stale);
+                self.publish_state(&topic, &payload.to_string()).await;
+            }
+        }
+    }
+
+    /// Topic of the document with all values of a device's poll cycle
+    pub fn snapshot_topic(&self, device_id: u32) -> String {
+        format!("{}/{}/snapshot", self.config.base_topic, device_id)
+    }
+
+    /// Publishes the values read in one poll cycle; `complete` is false if
+    /// the next cycle started before every read was answered
+    pub async fn publish_snapshot(&self, device_id: u32, cycle: u64, complete: bool, values: &BTreeMap<String, serde_json::Value>) {
+        let payload = serde_json::json!({
+            "ts": utc_timestamp(),
+            "cycle": cycle,
+            "complete": complete,
+            "values": values,
+        });
+        self.publish_state(&self.snapshot_topic(device_id), &payload.to_string()).await;
+    }
+
+    /// Publishes a state-class message to every broker, each buffering it
+    /// while unreachable; returns the primary broker's result
+    async fn publish_buffered(&self, topic: &str, payload: String) -> Result<(), ClientError> {
+        let Some(payload) = self.transforms.payload(topic, payload) else {
+            return Ok(());
+        };
+        self.events.emit(FeedEvent::Published { topic: topic.to_string(), payload: payload.clone(), retain: self.config.publish.state.retain });
+        let mut result = Ok(());
+        for broker in self.brokers.iter() {
+            match broker.publish_buffered(topic, &payload, &self.config).await {
+                Err(e) if broker.primary => result = Err(e),
+                Err(e) => warn!("Failed to publish {} to broker {}: {}", topic, broker.name, e),
+                Ok(()) => {}
+            }
+        }
+        result
+    }
+
+    /// Publishes a polled property other than the present-value below the point's state topic
+    pub async fn publish_property(&self, device_id: u32, object: ObjectRef, property: u32, value: &BacnetValue) {
+        let topic = format!("{}/{}", self.device_state_topic(device_id, object), property);
+        self.publish_state(&topic, &value.to_json().to_string()).await;
+    }
+
     /// Publishes a state update
     pub async fn publish_state(&self, topic: &str, value: &str) {
-        if let Err(e) = self.client.publish(topic, QoS::AtLeastOnce, true, value).await {
+        if let Err(e) = self.publish_buffered(topic, value.to_string()).await {
             error!("Failed to publish state {}: {}", topic, e);
         }
     }
@@ -0,0 +1,58 @@
+//! Home Assistant entity names and unique IDs rendered from templates such as
+//! `{device_alias} {object_name}` or `{site}_{device}_{type}{instance}`
+
+use crate::config::NamePolicy;
+use crate::point::ObjectRef;
+use crate::topic;
+
+/// Variables a naming template can use
+const VARIABLES: &[&str] = &["site", "device", "device_alias", "object", "object_name", "type", "instance"];
+
+/// Checks that a template only refers to known variables
+pub fn check(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("unclosed '{{' in naming template '{}'", template))?;
+        let name = &rest[start + 1..start + end];
+        if !VARIABLES.contains(&name) {
+            return Err(format!("unknown variable '{{{}}}' in naming template '{}', expected one of {}", name, template, VARIABLES.join(", ")));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// True if a template refers to `{variable}`
+pub fn uses(template: &str, variable: &str) -> bool {
+    template.contains(&format!("{{{}}}", variable))
+}
+
+/// What the variables of a point resolve to
+pub struct PointNames<'a> {
+    pub site: &'a str,
+    pub device_id: u32,
+    /// The device's alias, else its object name, else its default entity name
+    pub device_alias: &'a str,
+    pub object: ObjectRef,
+    /// The object's alias, else its object name, else the object reference
+    pub object_name: &'a str,
+}
+
+impl PointNames<'_> {
+    pub fn render(&self, template: &str) -> String {
+        let object_type = self.object.type_abbreviation().map_or_else(|| self.object.object_type.to_string(), str::to_string);
+        template
+            .replace("{site}", self.site)
+            .replace("{device_alias}", self.device_alias)
+            .replace("{device}", &self.device_id.to_string())
+            .replace("{object_name}", self.object_name)
+            .replace("{object}", &self.object.to_string())
+            .replace("{type}", &object_type)
+            .replace("{instance}", &self.object.instance.to_string())
+    }
+
+    /// A unique ID, limited to what can be a level of the discovery topic
+    pub fn render_unique_id(&self, template: &str) -> String {
+        topic::sanitize_name(&self.render(template), NamePolicy::Ascii, '_')
+    }
+}
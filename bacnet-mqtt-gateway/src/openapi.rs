@@ -0,0 +1,706 @@
+//! OpenAPI description of the REST API, served at `/api/openapi.json` with a
+//! Swagger UI at `/api/docs`
+
+use serde_json::{json, Value};
+
+/// Where the document is served
+pub const DOCUMENT_PATH: &str = "/api/openapi.json";
+
+fn reference(schema: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", schema) })
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+fn error(description: &str) -> Value {
+    json!({ "description": description, "content": { "text/plain": { "schema": { "type": "string" } } } })
+}
+
+fn no_content(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn path_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "description": description, "schema": schema })
+}
+
+fn query_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "description": description, "schema": schema })
+}
+
+fn device_id() -> Value {
+    path_parameter("device_id", "Device instance", json!({ "type": "integer", "minimum": 0 }))
+}
+
+fn object() -> Value {
+    path_parameter("object", "Object such as `AI:3`", json!({ "type": "string" }))
+}
+
+fn operation(tag: &str, summary: &str, parameters: Vec<Value>, body: Option<Value>, responses: Value) -> Value {
+    let mut operation = json!({ "tags": [tag], "summary": summary, "responses": responses });
+    if !parameters.is_empty() {
+        operation["parameters"] = Value::Array(parameters);
+    }
+    if let Some(body) = body {
+        operation["requestBody"] = body;
+    }
+    operation
+}
+
+fn schemas() -> Value {
+    let entries = vec![
+        ("ObjectRef", json!({ "type": "string", "description": "Object type abbreviation or number and instance, e.g. `AI:3`", "example": "AI:3" })),
+        ("Device", json!({
+            "type": "object",
+            "required": ["instance", "address", "poll_status"],
+            "properties": {
+                "instance": { "type": "integer" },
+                "address": { "type": "string", "example": "10.20.0.15:47808" },
+                "interface": { "type": "string" },
+                "alias": { "type": "string" },
+                "vendor_id": { "type": "integer" },
+                "last_seen": { "type": "string", "format": "date-time" },
+                "poll_status": { "type": "string", "enum": ["pending", "online", "offline"] }
+            }
+        })),
+        ("DiscoverRequest", json!({
+            "type": "object",
+            "properties": {
+                "low_limit": { "type": "integer" },
+                "high_limit": { "type": "integer" },
+                "subnet": { "type": "string", "description": "IPv4 network sent a directed broadcast", "example": "10.20.0.0/24" },
+                "window_ms": { "type": "integer", "default": 3000, "maximum": 60000 }
+            }
+        })),
+        ("DiscoveredDevice", json!({
+            "type": "object",
+            "required": ["instance", "address", "vendor_id"],
+            "properties": {
+                "instance": { "type": "integer" },
+                "address": { "type": "string" },
+                "vendor_id": { "type": "integer" }
+            }
+        })),
+        ("Object", json!({
+            "type": "object",
+            "required": ["object"],
+            "properties": {
+                "object": reference("ObjectRef"),
+                "name": { "type": "string" },
+                "value_type": { "type": "string", "description": "Set if the object is polled" },
+                "value": { "description": "Latest polled present-value" },
+                "updated": { "type": "string", "format": "date-time" },
+                "stale": { "type": "boolean", "description": "Not refreshed within `polling.stale_after_secs`" }
+            }
+        })),
+        ("CachedValue", json!({
+            "type": "object",
+            "properties": {
+                "device_id": { "type": "integer" },
+                "object": reference("ObjectRef"),
+                "value_type": { "type": "string" },
+                "value": { "description": "Latest polled present-value" },
+                "updated": { "type": "string", "format": "date-time" },
+                "stale": { "type": "boolean", "description": "Not refreshed within `polling.stale_after_secs`" }
+            }
+        })),
+        ("Point", json!({
+            "type": "object",
+            "required": ["object"],
+            "properties": {
+                "object": reference("ObjectRef"),
+                "properties": { "type": "array", "items": { "type": "integer" }, "default": [85] },
+                "interval_secs": { "type": "integer" }
+            }
+        })),
+        ("Property", json!({
+            "type": "object",
+            "required": ["property"],
+            "properties": {
+                "property": { "type": "integer" },
+                "array_index": { "type": "integer" },
+                "value_type": { "type": "string" },
+                "value": { "description": "Single values as-is, lists as arrays" },
+                "error": { "type": "string" }
+            }
+        })),
+        ("ReadRequest", json!({
+            "type": "object",
+            "required": ["object"],
+            "properties": {
+                "object": reference("ObjectRef"),
+                "property": { "type": "integer", "default": 85 },
+                "array_index": { "type": "integer" },
+                "timeout_ms": { "type": "integer" }
+            }
+        })),
+        ("Write", json!({
+            "type": "object",
+            "required": ["object", "value"],
+            "properties": {
+                "device_id": { "type": "integer", "description": "Only in batches" },
+                "object": reference("ObjectRef"),
+                "property": { "type": "integer", "default": 85 },
+                "array_index": { "type": "integer" },
+                "value": { "description": "Value to write, `null` to relinquish" },
+                "type": { "type": "string", "description": "BACnet type such as `real` or `enumerated`, inferred from the object type if omitted" },
+                "priority": { "type": "integer", "minimum": 1, "maximum": 16 },
+                "timeout_ms": { "type": "integer", "description": "Only for single writes" }
+            }
+        })),
+        ("BatchRequest", json!({
+            "type": "object",
+            "required": ["writes"],
+            "properties": {
+                "atomic": { "type": "boolean", "default": true, "description": "Roll back a device's applied writes when one fails" },
+                "writes": { "type": "array", "items": reference("Write") }
+            }
+        })),
+        ("BatchResponse", json!({
+            "type": "object",
+            "properties": {
+                "results": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "index": { "type": "integer" },
+                            "device_id": { "type": "integer" },
+                            "object": reference("ObjectRef"),
+                            "property": { "type": "integer" },
+                            "status": { "type": "string", "enum": ["written", "failed", "not_attempted", "rolled_back"] },
+                            "error": { "type": "string" }
+                        }
+                    }
+                }
+            }
+        })),
+        ("SavedConfig", json!({
+            "type": "object",
+            "properties": {
+                "restart_required": { "type": "array", "items": { "type": "string" }, "description": "Sections taking effect after a restart" }
+            }
+        })),
+        ("SetupStatus", json!({
+            "type": "object",
+            "properties": {
+                "pending": { "type": "boolean", "description": "The gateway started without a configuration file" },
+                "interfaces": { "type": "array", "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "address": { "type": "string" },
+                        "subnet": { "type": "string", "example": "10.20.0.0/24" }
+                    }
+                } }
+            }
+        })),
+        ("Config", json!({ "type": "object", "description": "The configuration file's settings, passwords and keys redacted" })),
+        ("Simulation", json!({
+            "type": "object",
+            "properties": {
+                "device_id": { "type": "integer" },
+                "object": reference("ObjectRef"),
+                "value": { "type": "string" }
+            }
+        })),
+        ("Subscription", json!({
+            "type": "object",
+            "properties": {
+                "device_id": { "type": "integer" },
+                "object": reference("ObjectRef"),
+                "address": { "type": "string" },
+                "process_id": { "type": "integer" },
+                "confirmed": { "type": "boolean" },
+                "lifetime_secs": { "type": "integer", "description": "0 if indefinite" },
+                "remaining_secs": { "type": "integer", "description": "Unset if indefinite" },
+                "device_remaining_secs": { "type": "integer", "description": "As announced by the last notification" },
+                "notifications": { "type": "integer" },
+                "last_notification": { "type": "string", "format": "date-time" },
+                "present_value": { "description": "Present_Value of the last notification" }
+            }
+        })),
+        ("SubscribeRequest", json!({
+            "type": "object",
+            "required": ["device_id", "object"],
+            "properties": {
+                "device_id": { "type": "integer" },
+                "object": reference("ObjectRef"),
+                "confirmed": { "type": "boolean", "default": false },
+                "lifetime_secs": { "type": "integer", "default": 300, "description": "0 subscribes indefinitely" }
+            }
+        })),
+        ("DeviceStats", json!({
+            "type": "object",
+            "properties": {
+                "requests": { "type": "integer" },
+                "acks": { "type": "integer" },
+                "errors": { "type": "integer", "description": "Answered with an Error, Reject or Abort PDU, or not sent" },
+                "timeouts": { "type": "integer" },
+                "avg_latency_ms": { "type": "number", "description": "Over the last 256 answers" },
+                "p50_latency_ms": { "type": "integer" },
+                "p95_latency_ms": { "type": "integer" },
+                "p99_latency_ms": { "type": "integer" },
+                "last_error": { "type": "string" },
+                "last_error_at": { "type": "string", "format": "date-time" }
+            }
+        })),
+        ("ScannedObject", json!({
+            "type": "object",
+            "properties": {
+                "object": reference("ObjectRef"),
+                "properties": { "type": "object", "description": "Values by property name, e.g. `present_value`" },
+                "errors": { "type": "object", "description": "Errors by property name, e.g. `unknown-property`" },
+                "failure": { "type": "string", "description": "Why none of the properties could be read" }
+            }
+        })),
+        ("ScanReport", json!({
+            "type": "object",
+            "properties": {
+                "device_id": { "type": "integer" },
+                "address": { "type": "string" },
+                "started": { "type": "string", "format": "date-time" },
+                "finished": { "type": "string", "format": "date-time" },
+                "device": reference("ScannedObject"),
+                "objects": { "type": "array", "items": reference("ScannedObject") }
+            }
+        })),
+        ("WhoIsRequest", json!({
+            "type": "object",
+            "properties": {
+                "target": { "type": "string", "description": "IP address with an optional port, broadcast to the configured targets if unset", "example": "10.30.4.12" },
+                "low_limit": { "type": "integer" },
+                "high_limit": { "type": "integer" },
+                "window_ms": { "type": "integer", "default": 3000, "maximum": 60000 }
+            }
+        })),
+        ("WhoIsResult", json!({
+            "type": "object",
+            "properties": {
+                "target": { "type": "string" },
+                "answers": { "type": "array", "items": {
+                    "type": "object",
+                    "properties": {
+                        "instance": { "type": "integer" },
+                        "address": { "type": "string" },
+                        "vendor_id": { "type": "integer" },
+                        "latency_ms": { "type": "integer" }
+                    }
+                } }
+            }
+        })),
+        ("LogLevel", json!({
+            "type": "object",
+            "properties": {
+                "directives": { "type": "string", "example": "info,bacnet_mqtt_gateway::bacnet=trace" },
+                "initial": { "type": "string", "description": "Directives from `RUST_LOG` at startup" },
+                "revert_in_secs": { "type": "integer", "description": "Unset unless the change is temporary" }
+            }
+        })),
+        ("CaptureStatus", json!({
+            "type": "object",
+            "properties": {
+                "recording": { "type": "boolean" },
+                "started": { "type": "string", "format": "date-time", "description": "Unset before the first capture" },
+                "frames": { "type": "integer" },
+                "bytes": { "type": "integer" },
+                "max_bytes": { "type": "integer" },
+                "dropped": { "type": "integer", "description": "Frames left out because the capture was full or not IPv4" }
+            }
+        })),
+        ("HistoryPoint", json!({
+            "type": "object",
+            "properties": {
+                "device_id": { "type": "integer" },
+                "object": reference("ObjectRef"),
+                "samples": { "type": "integer" },
+                "first": { "type": "string", "format": "date-time" },
+                "last": { "type": "string", "format": "date-time" }
+            }
+        })),
+        ("PointHistory", json!({
+            "type": "object",
+            "properties": {
+                "device_id": { "type": "integer" },
+                "object": reference("ObjectRef"),
+                "samples": { "type": "array", "items": {
+                    "type": "object",
+                    "properties": {
+                        "ts": { "type": "string", "format": "date-time" },
+                        "value": { "description": "The published state, numbers as numbers" },
+                        "quality": { "type": "string", "enum": ["good", "uncertain", "bad", "simulated"] }
+                    }
+                } }
+            }
+        })),
+        ("Readiness", json!({
+            "type": "object",
+            "properties": {
+                "ready": { "type": "boolean" },
+                "mqtt_connected": { "type": "boolean" },
+                "datalink_open": { "type": "boolean" },
+                "receiving": { "type": "boolean" }
+            }
+        })),
+    ];
+    Value::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+fn paths() -> Value {
+    let found = |schema| json_response("OK", schema);
+    let not_discovered = error("Device not discovered");
+    let bacnet_failed = error("The device did not answer or answered with an error");
+    let entries = vec![
+        ("/healthz", json!({ "get": operation("health", "Liveness probe", vec![], None, json!({ "200": no_content("Responds `ok`") })) })),
+        ("/readyz", json!({
+            "get": operation("health", "Readiness probe", vec![], None, json!({
+                "200": found(reference("Readiness")),
+                "503": json_response("Not ready", reference("Readiness"))
+            }))
+        })),
+        ("/api/devices", json!({
+            "get": operation("devices", "Discovered devices", vec![], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Device") }))
+            }))
+        })),
+        ("/api/discover", json!({
+            "post": operation("devices", "Broadcast a Who-Is and list the devices answering", vec![], Some(json_body(reference("DiscoverRequest"))), json!({
+                "200": found(json!({ "type": "array", "items": reference("DiscoveredDevice") })),
+                "400": error("Invalid subnet"),
+                "502": error("The Who-Is could not be sent")
+            }))
+        })),
+        ("/api/who-is", json!({
+            "post": operation("devices", "Send one Who-Is, to an address or with an instance range, and list the I-Am answers", vec![], Some(json_body(reference("WhoIsRequest"))), json!({
+                "200": found(reference("WhoIsResult")),
+                "400": error("Invalid target or range"),
+                "502": error("The Who-Is could not be sent")
+            }))
+        })),
+        ("/api/devices/{device_id}/objects", json!({
+            "get": operation("devices", "Objects of the device's Object_List", vec![device_id()], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Object") })),
+                "404": not_discovered,
+                "502": bacnet_failed
+            }))
+        })),
+        ("/api/devices/{device_id}/points", json!({
+            "get": operation("devices", "Polled points with their latest value", vec![device_id()], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Object") })),
+                "404": not_discovered
+            })),
+            "post": operation("devices", "Add a point to the device's configuration", vec![device_id()], Some(json_body(reference("Point"))), json!({
+                "200": found(reference("SavedConfig")),
+                "404": not_discovered,
+                "409": error("The point is already configured"),
+                "422": error("The configuration became invalid")
+            }))
+        })),
+        ("/api/devices/{device_id}/stats", json!({
+            "get": operation("diagnostics", "Outcomes and latency of the confirmed requests sent to a device", vec![device_id()], None, json!({
+                "200": found(reference("DeviceStats")),
+                "404": not_discovered
+            }))
+        })),
+        ("/api/values", json!({
+            "get": operation("polling", "The last value of every polled point", vec![
+                query_parameter("stale", "Only the stale values if true, only the fresh ones if false", json!({ "type": "boolean" }))
+            ], None, json!({ "200": found(json!({ "type": "array", "items": reference("CachedValue") })) }))
+        })),
+        ("/api/devices/{device_id}/scan", json!({
+            "post": operation("devices", "Read every object of a device with a standard set of properties", vec![
+                device_id(),
+                query_parameter("format", "`json` (default) or `csv`", json!({ "type": "string", "enum": ["json", "csv"] }))
+            ], None, json!({
+                "200": found(reference("ScanReport")),
+                "400": error("Unknown format"),
+                "404": not_discovered,
+                "502": error("Object list unreadable")
+            }))
+        })),
+        ("/api/devices/{device_id}/read", json!({
+            "post": operation("properties", "Read one property with ReadProperty", vec![device_id()], Some(json_body(reference("ReadRequest"))), json!({
+                "200": found(reference("Property")),
+                "404": not_discovered,
+                "502": bacnet_failed,
+                "504": error("Timed out")
+            }))
+        })),
+        ("/api/devices/{device_id}/write", json!({
+            "post": operation("properties", "Write one property with WriteProperty", vec![device_id()], Some(json_body(reference("Write"))), json!({
+                "204": no_content("Acknowledged by the device"),
+                "400": error("Invalid value"),
+                "404": not_discovered,
+                "409": error("Not written in dry-run mode"),
+                "502": bacnet_failed,
+                "504": error("Timed out")
+            }))
+        })),
+        ("/api/devices/{device_id}/objects/{object}/properties", json!({
+            "get": operation("properties", "Read a property, or all, required or optional ones", vec![
+                device_id(),
+                object(),
+                query_parameter("property", "Property number or `all` (default), `required` or `optional`", json!({ "type": "string" }))
+            ], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Property") })),
+                "400": error("Invalid object or property"),
+                "404": not_discovered,
+                "502": bacnet_failed
+            }))
+        })),
+        ("/api/devices/{device_id}/objects/{object}/summary", json!({
+            "get": operation("properties", "Object_Name, Description, Present_Value and Units of an object", vec![device_id(), object()], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Property") })),
+                "400": error("Invalid object"),
+                "404": not_discovered,
+                "502": bacnet_failed
+            }))
+        })),
+        ("/api/write-batch", json!({
+            "post": operation("properties", "Write several properties, one WritePropertyMultiple per device", vec![], Some(json_body(reference("BatchRequest"))), json!({
+                "200": found(reference("BatchResponse"))
+            }))
+        })),
+        ("/api/config", json!({
+            "get": operation("config", "Current configuration", vec![], None, json!({ "200": found(reference("Config")) })),
+            "put": operation("config", "Replace the configuration", vec![], Some(json_body(reference("Config"))), json!({
+                "200": found(reference("SavedConfig")),
+                "422": error("Invalid configuration")
+            })),
+            "patch": operation("config", "Change settings with a JSON merge patch", vec![], Some(json_body(json!({ "type": "object" }))), json!({
+                "200": found(reference("SavedConfig")),
+                "422": error("Invalid configuration")
+            }))
+        })),
+        ("/api/setup", json!({
+            "get": operation("setup", "Whether the first-run setup is pending, with the IPv4 interfaces", vec![], None, json!({ "200": found(reference("SetupStatus")) })),
+            "post": operation("setup", "Write the configuration put together by the setup wizard", vec![], Some(json_body(reference("Config"))), json!({
+                "200": found(reference("SavedConfig")),
+                "422": error("Invalid configuration")
+            })),
+            "delete": operation("setup", "Leave the setup, keeping the default configuration", vec![], None, json!({ "204": no_content("Dismissed") }))
+        })),
+        ("/api/setup/mqtt-test", json!({
+            "post": operation("setup", "Connect to the primary MQTT broker of an unsaved configuration", vec![], Some(json_body(reference("Config"))), json!({
+                "204": no_content("Connected"),
+                "502": error("Connection failed")
+            }))
+        })),
+        ("/api/export", json!({
+            "get": operation("export", "Data dictionary of addresses, objects, topics and entities", vec![
+                query_parameter("format", "`json` (default) or `csv`", json!({ "type": "string", "enum": ["json", "csv"] }))
+            ], None, json!({ "200": no_content("The data dictionary"), "400": error("Unknown format") }))
+        })),
+        ("/api/export/config", json!({
+            "get": operation("export", "`devices:` configuration section of every discovered device", vec![
+                query_parameter("format", "`yaml` (default) or `json`", json!({ "type": "string", "enum": ["yaml", "json"] }))
+            ], None, json!({ "200": no_content("The configuration section"), "400": error("Unknown format") }))
+        })),
+        ("/api/simulations", json!({
+            "get": operation("simulations", "Simulated points", vec![], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Simulation") }))
+            }))
+        })),
+        ("/api/simulations/{device_id}/{object}", json!({
+            "post": operation("simulations", "Override a point's published value", vec![device_id(), object()], Some(json_body(json!({
+                "type": "object", "required": ["value"], "properties": { "value": {} }
+            }))), json!({ "200": found(reference("Simulation")), "400": error("Invalid object") })),
+            "delete": operation("simulations", "Release a simulated point", vec![device_id(), object()], None, json!({
+                "204": no_content("Released"),
+                "404": error("Not simulated")
+            }))
+        })),
+        ("/api/subscriptions", json!({
+            "get": operation("cov", "COV subscriptions with their remaining time and notifications", vec![], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("Subscription") }))
+            })),
+            "post": operation("cov", "Subscribe to COV notifications of an object", vec![], Some(json_body(reference("SubscribeRequest"))), json!({
+                "200": found(reference("Subscription")),
+                "404": not_discovered,
+                "502": bacnet_failed,
+                "504": error("Timed out")
+            }))
+        })),
+        ("/api/subscriptions/{device_id}/{object}", json!({
+            "delete": operation("cov", "Cancel a COV subscription", vec![device_id(), object()], None, json!({
+                "204": no_content("Cancelled"),
+                "404": error("No such subscription"),
+                "502": bacnet_failed
+            }))
+        })),
+        ("/api/subscriptions/{device_id}/{object}/renew", json!({
+            "post": operation("cov", "Renew a COV subscription", vec![device_id(), object()], Some(json!({
+                "required": false,
+                "content": { "application/json": { "schema": {
+                    "type": "object", "properties": { "lifetime_secs": { "type": "integer", "description": "The lifetime subscribed with if not set" } }
+                } } }
+            })), json!({
+                "200": found(reference("Subscription")),
+                "404": error("No such subscription"),
+                "502": bacnet_failed
+            }))
+        })),
+        ("/api/suspensions", json!({
+            "get": operation("polling", "Suspended polling scopes", vec![], None, json!({ "200": found(json!({ "type": "object" })) }))
+        })),
+        ("/api/suspensions/{scope}", json!({
+            "post": operation("polling", "Suspend polling", vec![path_parameter("scope", "`gateway`, `group:<name>` or `device:<id>`", json!({ "type": "string" }))], None, json!({
+                "200": found(json!({ "type": "object" })),
+                "400": error("Invalid scope"),
+                "404": error("Unknown group or device")
+            })),
+            "delete": operation("polling", "Resume polling", vec![path_parameter("scope", "`gateway`, `group:<name>` or `device:<id>`", json!({ "type": "string" }))], None, json!({
+                "200": found(json!({ "type": "object" })),
+                "400": error("Invalid scope"),
+                "404": error("Unknown group or device")
+            }))
+        })),
+        ("/api/bacnet/stats", json!({
+            "get": operation("diagnostics", "Counts of confirmed requests and their outcomes", vec![], None, json!({ "200": found(json!({ "type": "object" })) }))
+        })),
+        ("/api/log-level", json!({
+            "get": operation("diagnostics", "Tracing filter directives in effect", vec![], None, json!({ "200": found(reference("LogLevel")) })),
+            "put": operation("diagnostics", "Change the tracing filter directives", vec![], Some(json_body(json!({
+                "type": "object",
+                "required": ["directives"],
+                "properties": {
+                    "directives": { "type": "string", "example": "info,bacnet_mqtt_gateway::bacnet=trace" },
+                    "revert_after_secs": { "type": "integer", "description": "Restore the startup directives after this long" }
+                }
+            }))), json!({ "200": found(reference("LogLevel")), "400": error("Invalid directives") })),
+            "delete": operation("diagnostics", "Restore the startup directives", vec![], None, json!({ "200": found(reference("LogLevel")) }))
+        })),
+        ("/api/capture", json!({
+            "get": operation("diagnostics", "State of the packet capture", vec![], None, json!({ "200": found(reference("CaptureStatus")) }))
+        })),
+        ("/api/capture/start", json!({
+            "post": operation("diagnostics", "Start recording BACnet/IP frames, discarding the previous capture", vec![], Some(json!({
+                "required": false,
+                "content": { "application/json": { "schema": {
+                    "type": "object", "properties": { "max_bytes": { "type": "integer", "default": 16777216 } }
+                } } }
+            })), json!({ "200": found(reference("CaptureStatus")) }))
+        })),
+        ("/api/capture/stop", json!({
+            "post": operation("diagnostics", "Stop recording", vec![], None, json!({ "200": found(reference("CaptureStatus")) }))
+        })),
+        ("/api/capture/download", json!({
+            "get": operation("diagnostics", "The current or last capture as a pcap file", vec![], None, json!({
+                "200": {
+                    "description": "The capture",
+                    "content": { "application/vnd.tcpdump.pcap": { "schema": { "type": "string", "format": "binary" } } }
+                },
+                "404": error("No capture has been started")
+            }))
+        })),
+        ("/api/history", json!({
+            "get": operation("history", "Points with stored history", vec![], None, json!({
+                "200": found(json!({ "type": "array", "items": reference("HistoryPoint") })),
+                "404": error("The historian is not enabled")
+            }))
+        })),
+        ("/api/history/export", json!({
+            "get": operation("history", "Stored values of points as a CSV or Parquet file", vec![
+                json!({
+                    "name": "point", "in": "query", "required": true,
+                    "description": "Comma separated `<device>/<object>` pairs, e.g. `1200/AI:3,1200/AV:1`",
+                    "schema": { "type": "string" }
+                }),
+                query_parameter("from", "Earliest timestamp, RFC 3339 in UTC", json!({ "type": "string", "format": "date-time" })),
+                query_parameter("to", "Latest timestamp, RFC 3339 in UTC", json!({ "type": "string", "format": "date-time" })),
+                query_parameter("limit", "Most values exported per point", json!({ "type": "integer", "default": 10000 })),
+                query_parameter("format", "File format", json!({ "type": "string", "enum": ["csv", "parquet"], "default": "csv" }))
+            ], None, json!({
+                "200": {
+                    "description": "The values, one row per value",
+                    "content": {
+                        "text/csv": { "schema": { "type": "string" } },
+                        "application/vnd.apache.parquet": { "schema": { "type": "string", "format": "binary" } }
+                    }
+                },
+                "400": error("Invalid point or format"),
+                "404": error("The historian is not enabled")
+            }))
+        })),
+        ("/api/history/{device_id}/{object}", json!({
+            "get": operation("history", "Stored values of a point, oldest first", vec![
+                device_id(),
+                object(),
+                query_parameter("from", "Earliest timestamp, RFC 3339 in UTC", json!({ "type": "string", "format": "date-time" })),
+                query_parameter("to", "Latest timestamp, RFC 3339 in UTC", json!({ "type": "string", "format": "date-time" })),
+                query_parameter("limit", "Most values returned", json!({ "type": "integer", "default": 10000 }))
+            ], None, json!({
+                "200": found(reference("PointHistory")),
+                "400": error("Invalid object"),
+                "404": error("The historian is not enabled")
+            }))
+        })),
+        ("/api/discovery/progress", json!({
+            "get": operation("diagnostics", "Progress of the current discovery", vec![], None, json!({ "200": found(json!({ "type": "object" })) }))
+        })),
+        ("/api/cluster", json!({
+            "get": operation("diagnostics", "Cluster members", vec![], None, json!({
+                "200": found(json!({ "type": "object" })),
+                "404": error("Clustering is not configured")
+            }))
+        })),
+        ("/api/events/ws", json!({
+            "get": operation("diagnostics", "WebSocket stream of gateway events as JSON messages", vec![], None, json!({
+                "101": no_content("Switching to the WebSocket protocol")
+            }))
+        })),
+        ("/api/alarms", json!({
+            "get": operation("alarms", "Alarm sources and their state", vec![], None, json!({
+                "200": found(json!({ "type": "array", "items": { "type": "object" } }))
+            }))
+        })),
+        ("/api/alarms/{device_id}/{object}/shelve", json!({
+            "post": operation("alarms", "Shelve an alarm source", vec![device_id(), object()], Some(json_body(json!({
+                "type": "object", "properties": { "hours": { "type": "number", "description": "Shelving period, indefinitely if not set" } }
+            }))), json!({ "204": no_content("Shelved"), "400": error("Invalid object or period") })),
+            "delete": operation("alarms", "Unshelve an alarm source", vec![device_id(), object()], None, json!({
+                "204": no_content("Unshelved"),
+                "404": error("Not shelved")
+            }))
+        })),
+        ("/api/alarms/{device_id}/{object}/ack", json!({
+            "post": operation("alarms", "Acknowledge an active alarm", vec![device_id(), object()], None, json!({
+                "204": no_content("Acknowledged"),
+                "404": error("No unacknowledged alarm")
+            }))
+        })),
+    ];
+    Value::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+/// The OpenAPI 3.0 document; with authentication configured every path but
+/// the health probes needs the bearer token or a session
+pub fn document(authenticated: bool) -> Value {
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "BACnet-MQTT Gateway",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Devices, properties, configuration and diagnostics of the gateway"
+        },
+        "paths": paths(),
+        "components": { "schemas": schemas() }
+    });
+    if authenticated {
+        document["components"]["securitySchemes"] = json!({
+            "bearer": { "type": "http", "scheme": "bearer" },
+            "session": { "type": "apiKey", "in": "cookie", "name": crate::auth::SESSION_COOKIE }
+        });
+        document["security"] = json!([{ "bearer": [] }, { "session": [] }]);
+        for probe in ["/healthz", "/readyz"] {
+            document["paths"][probe]["get"]["security"] = json!([]);
+        }
+    }
+    document
+}
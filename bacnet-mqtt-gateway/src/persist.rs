@@ -0,0 +1,115 @@
+//! The discovered devices with their object lists and point metadata, saved
+//! periodically so a restart does not rediscover and re-read a whole site
+
+use crate::bacnet::BacnetEngine;
+use crate::point::{ObjectRef, PointMetadata};
+use crate::registry::{DeviceInfo, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedDevice {
+    address: SocketAddr,
+    /// Vendor and last I-Am, absent for configured devices never heard from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    info: Option<DeviceInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    object_list: Vec<ObjectRef>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<ObjectRef, PointMetadata>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedRegistry {
+    #[serde(default)]
+    devices: BTreeMap<u32, SavedDevice>,
+}
+
+/// Running state the saved registry is taken from and restored into
+pub struct Persisted {
+    pub bacnet: Arc<BacnetEngine>,
+    pub devices: Arc<RwLock<HashMap<u32, SocketAddr>>>,
+    pub registry: Arc<Registry>,
+    pub metadata: Arc<RwLock<HashMap<(u32, ObjectRef), PointMetadata>>>,
+}
+
+impl Persisted {
+    /// Seeds the running state from the file; configured device addresses win
+    /// over saved ones
+    pub async fn restore(&self, path: &Path) {
+        let saved: SavedRegistry = match std::fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(saved) => saved,
+                Err(e) => {
+                    warn!("Ignoring unreadable device registry {}: {}", path.display(), e);
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+        let mut devices = self.devices.write().await;
+        let mut metadata = self.metadata.write().await;
+        for (device_id, device) in &saved.devices {
+            devices.entry(*device_id).or_insert(device.address);
+            if let Some(info) = &device.info {
+                self.registry.restore(*device_id, info.clone());
+            }
+            if !device.object_list.is_empty() {
+                self.bacnet.restore_object_list(*device_id, device.object_list.clone());
+            }
+            for (object, point) in &device.metadata {
+                metadata.insert((*device_id, *object), point.clone());
+            }
+        }
+        info!("Restored {} devices from {}", saved.devices.len(), path.display());
+    }
+
+    async fn collect(&self) -> SavedRegistry {
+        let devices = self.devices.read().await.clone();
+        let metadata = self.metadata.read().await;
+        let mut object_lists = self.bacnet.known_object_lists();
+        let mut saved = SavedRegistry::default();
+        for (device_id, address) in devices {
+            let device = SavedDevice {
+                address,
+                info: self.registry.device(device_id),
+                object_list: object_lists.remove(&device_id).unwrap_or_default(),
+                metadata: metadata
+                    .iter()
+                    .filter(|((id, _), _)| *id == device_id)
+                    .map(|((_, object), point)| (*object, point.clone()))
+                    .collect(),
+            };
+            saved.devices.insert(device_id, device);
+        }
+        saved
+    }
+}
+
+/// Writes through a temporary file, so a crash mid-save keeps the previous file
+fn save(path: &Path, saved: &SavedRegistry) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string_pretty(saved)?;
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, json)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Saves the registry to `path` every `interval`
+pub async fn run(path: PathBuf, interval: Duration, persisted: Persisted) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticks.tick().await;
+        let saved = persisted.collect().await;
+        if let Err(e) = save(&path, &saved) {
+            warn!("Failed to save the device registry to {}: {}", path.display(), e);
+        }
+    }
+}
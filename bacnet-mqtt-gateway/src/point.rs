@@ -0,0 +1,290 @@
+use crate::bacnet::BacnetEngine;
+use crate::codec::{self, BacnetValue, PropertyReference, PropertyResult};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tracing::debug;
+
+const PROP_ACTIVE_TEXT: u32 = 4;
+const PROP_DESCRIPTION: u32 = 28;
+const PROP_INACTIVE_TEXT: u32 = 46;
+const PROP_MAX_PRES_VALUE: u32 = 65;
+const PROP_MIN_PRES_VALUE: u32 = 69;
+const PROP_OBJECT_NAME: u32 = 77;
+const PROP_PRESENT_VALUE: u32 = 85;
+const PROP_RESOLUTION: u32 = 106;
+const PROP_STATE_TEXT: u32 = 110;
+const PROP_STATUS_FLAGS: u32 = 111;
+const PROP_UNITS: u32 = 117;
+
+/// Properties read together by `BacnetEngine::read_property_bundle`
+pub const BUNDLE_PROPERTIES: [u32; 4] = [PROP_PRESENT_VALUE, PROP_STATUS_FLAGS, PROP_UNITS, PROP_OBJECT_NAME];
+
+/// Properties the web UI's object browser shows for each object
+pub const BROWSE_PROPERTIES: [u32; 4] = [PROP_OBJECT_NAME, PROP_DESCRIPTION, PROP_PRESENT_VALUE, PROP_UNITS];
+
+/// Object type abbreviations used in config, topics and the REST API
+const OBJECT_TYPES: &[(u16, &str)] = &[
+    (0, "AI"),
+    (1, "AO"),
+    (2, "AV"),
+    (3, "BI"),
+    (4, "BO"),
+    (5, "BV"),
+    (8, "DEV"),
+    (13, "MSI"),
+    (14, "MSO"),
+    (19, "MSV"),
+];
+
+/// A BACnet object reference such as `AI:3`, or `<type number>:<instance>` for
+/// object types without an abbreviation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ObjectRef {
+    pub object_type: u16,
+    pub instance: u32,
+}
+
+impl ObjectRef {
+    pub fn new(object_type: u16, instance: u32) -> Self {
+        Self { object_type, instance }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self.object_type, 3..=5)
+    }
+
+    pub fn is_multistate(&self) -> bool {
+        matches!(self.object_type, 13 | 14 | 19)
+    }
+
+    /// Analog, binary and multi-state objects, whose present-value can be published as a point
+    pub fn is_point(&self) -> bool {
+        matches!(self.object_type, 0..=5) || self.is_multistate()
+    }
+
+    /// Short type name ("AI", "BV", ...) if the object type has one
+    pub fn type_abbreviation(&self) -> Option<&'static str> {
+        OBJECT_TYPES
+            .iter()
+            .find(|(t, _)| *t == self.object_type)
+            .map(|(_, name)| *name)
+    }
+}
+
+impl fmt::Display for ObjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.type_abbreviation() {
+            Some(name) => write!(f, "{}:{}", name, self.instance),
+            None => write!(f, "{}:{}", self.object_type, self.instance),
+        }
+    }
+}
+
+impl FromStr for ObjectRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, instance) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid object reference '{}', expected e.g. AI:3", s))?;
+        let object_type = match OBJECT_TYPES.iter().find(|(_, name)| name.eq_ignore_ascii_case(kind)) {
+            Some((t, _)) => *t,
+            None => kind
+                .parse::<u16>()
+                .map_err(|_| format!("unknown object type '{}' in '{}'", kind, s))?,
+        };
+        let instance = instance
+            .parse::<u32>()
+            .map_err(|_| format!("invalid object instance '{}' in '{}'", instance, s))?;
+        Ok(Self::new(object_type, instance))
+    }
+}
+
+impl TryFrom<String> for ObjectRef {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ObjectRef> for String {
+    fn from(value: ObjectRef) -> Self {
+        value.to_string()
+    }
+}
+
+/// The Status_Flags bit string of an object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatusFlags {
+    pub in_alarm: bool,
+    pub fault: bool,
+    pub overridden: bool,
+    pub out_of_service: bool,
+}
+
+impl StatusFlags {
+    fn from_bits(bits: &[bool]) -> Self {
+        let bit = |n: usize| bits.get(n).copied().unwrap_or(false);
+        Self { in_alarm: bit(0), fault: bit(1), overridden: bit(2), out_of_service: bit(3) }
+    }
+}
+
+/// Present-value, status and identity of one object as read in a single
+/// request; properties the object lacks (e.g. units of a binary) stay `None`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PropertyBundle {
+    pub present_value: Option<BacnetValue>,
+    pub status_flags: Option<StatusFlags>,
+    pub units: Option<u32>,
+    pub object_name: Option<String>,
+}
+
+impl PropertyBundle {
+    pub fn from_results(results: &[PropertyResult]) -> Self {
+        let mut bundle = Self::default();
+        for result in results {
+            let Ok(values) = &result.value else {
+                continue;
+            };
+            match (result.property, values.as_slice()) {
+                (PROP_PRESENT_VALUE, [value]) => bundle.present_value = Some(value.clone()),
+                (PROP_STATUS_FLAGS, [BacnetValue::BitString(bits)]) => bundle.status_flags = Some(StatusFlags::from_bits(bits)),
+                (PROP_UNITS, [BacnetValue::Enumerated(units)]) => bundle.units = Some(*units),
+                (PROP_OBJECT_NAME, [BacnetValue::CharacterString(name)]) => bundle.object_name = Some(name.clone()),
+                _ => {}
+            }
+        }
+        bundle
+    }
+}
+
+/// Descriptive properties of a point, read once when its device is discovered
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PointMetadata {
+    /// Object_Name, read along with the units of analog objects
+    pub object_name: Option<String>,
+    /// Engineering units of analog objects
+    pub units: Option<u32>,
+    /// Names of multi-state values, `state_texts[0]` is state 1
+    pub state_texts: Vec<String>,
+    /// Names of the states of binary objects ("Running"/"Stopped")
+    pub active_text: Option<String>,
+    pub inactive_text: Option<String>,
+    /// Range and resolution of analog outputs and values, bounding what can be written
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub resolution: Option<f64>,
+}
+
+impl PointMetadata {
+    /// Name of a multi-state or binary value, if the device provides state texts
+    pub fn state_text(&self, value: &BacnetValue) -> Option<&str> {
+        match value {
+            BacnetValue::Unsigned(state) if *state >= 1 => self.state_texts.get(*state as usize - 1).map(String::as_str),
+            BacnetValue::Enumerated(0) => self.inactive_text.as_deref(),
+            BacnetValue::Enumerated(1) => self.active_text.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Value of a state name, the inverse of `state_text`
+    pub fn state_value(&self, text: &str) -> Option<u32> {
+        if self.active_text.as_deref() == Some(text) {
+            return Some(1);
+        }
+        if self.inactive_text.as_deref() == Some(text) {
+            return Some(0);
+        }
+        self.state_texts.iter().position(|t| t == text).map(|index| index as u32 + 1)
+    }
+
+    /// Every state name in value order, empty unless all of them are known
+    pub fn options(&self) -> Vec<String> {
+        match (&self.inactive_text, &self.active_text) {
+            (Some(inactive), Some(active)) => vec![inactive.clone(), active.clone()],
+            _ => self.state_texts.clone(),
+        }
+    }
+}
+
+async fn read_value(engine: &BacnetEngine, addr: SocketAddr, object: ObjectRef, property: u32, array_index: Option<u32>) -> Option<Vec<BacnetValue>> {
+    let reference = PropertyReference { object, property, array_index };
+    match engine.read_property_value(addr, &reference).await {
+        Ok(raw) => codec::decode_application_values(&raw).ok(),
+        Err(e) => {
+            debug!("Could not read {} property {} from {}: {}", object, property, addr, e);
+            None
+        }
+    }
+}
+
+/// Object_Name of an object, `None` if it cannot be read
+pub async fn read_object_name(engine: &BacnetEngine, addr: SocketAddr, object: ObjectRef) -> Option<String> {
+    match read_value(engine, addr, object, PROP_OBJECT_NAME, None).await?.into_iter().next()? {
+        BacnetValue::CharacterString(name) => Some(name),
+        _ => None,
+    }
+}
+
+fn strings(values: Vec<BacnetValue>) -> Vec<String> {
+    values
+        .into_iter()
+        .map(|v| match v {
+            BacnetValue::CharacterString(s) => s,
+            other => other.to_string(),
+        })
+        .collect()
+}
+
+/// Reads the properties describing how a point's values are presented
+pub async fn read_metadata(engine: &BacnetEngine, addr: SocketAddr, object: ObjectRef) -> PointMetadata {
+    let mut metadata = PointMetadata::default();
+    if object.is_multistate() {
+        metadata.state_texts = match read_value(engine, addr, object, PROP_STATE_TEXT, None).await {
+            Some(values) => strings(values),
+            // Long arrays may not fit one APDU, fall back to reading them element by element
+            None => {
+                let mut texts = Vec::new();
+                if let Some([BacnetValue::Unsigned(count)]) = read_value(engine, addr, object, PROP_STATE_TEXT, Some(0)).await.as_deref() {
+                    for index in 1..=*count {
+                        match read_value(engine, addr, object, PROP_STATE_TEXT, Some(index)).await {
+                            Some(values) => texts.extend(strings(values)),
+                            None => break,
+                        }
+                    }
+                }
+                texts
+            }
+        };
+    } else if object.is_binary() {
+        let text = |values: Option<Vec<BacnetValue>>| match values.as_deref() {
+            Some([BacnetValue::CharacterString(s)]) if !s.is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        metadata.active_text = text(read_value(engine, addr, object, PROP_ACTIVE_TEXT, None).await);
+        metadata.inactive_text = text(read_value(engine, addr, object, PROP_INACTIVE_TEXT, None).await);
+    } else {
+        match engine.read_property_bundle(addr, object).await {
+            Ok(bundle) => {
+                metadata.units = bundle.units;
+                metadata.object_name = bundle.object_name;
+            }
+            Err(e) => debug!("Could not read the property bundle of {} from {}: {}", object, addr, e),
+        }
+        if matches!(object.object_type, 1 | 2) {
+            let number = |values: Option<Vec<BacnetValue>>| match values.as_deref() {
+                Some([BacnetValue::Real(v)]) => Some(f64::from(*v)),
+                Some([BacnetValue::Double(v)]) => Some(*v),
+                _ => None,
+            };
+            metadata.min_value = number(read_value(engine, addr, object, PROP_MIN_PRES_VALUE, None).await);
+            metadata.max_value = number(read_value(engine, addr, object, PROP_MAX_PRES_VALUE, None).await);
+            metadata.resolution = number(read_value(engine, addr, object, PROP_RESOLUTION, None).await);
+        }
+    }
+    metadata
+}
@@ -0,0 +1,160 @@
+//! Points read on each device and when they are next due
+
+use crate::codec::PropertyReference;
+use crate::config::DeviceConfig;
+use crate::point::ObjectRef;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+struct PolledPoint {
+    object: ObjectRef,
+    properties: Vec<u32>,
+    interval: Duration,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+struct Points {
+    default_object: ObjectRef,
+    default_interval: Duration,
+    devices: HashMap<u32, Vec<PolledPoint>>,
+    /// Instances of every configured device, with points or without
+    configured: HashSet<u32>,
+}
+
+impl Points {
+    fn new(devices: &[DeviceConfig], default_object: ObjectRef, default_interval_secs: u64) -> Self {
+        let default_interval = Duration::from_secs(default_interval_secs.max(1));
+        let configured = devices.iter().map(|device| device.instance).collect();
+        let devices = devices
+            .iter()
+            .filter(|device| !device.points.is_empty())
+            .map(|device| {
+                let device_interval = device.interval_secs.map_or(default_interval, |secs| Duration::from_secs(secs.max(1)));
+                let points = device
+                    .points
+                    .iter()
+                    .map(|point| PolledPoint {
+                        object: point.object,
+                        properties: point.properties.clone(),
+                        interval: point.interval_secs.map_or(device_interval, |secs| Duration::from_secs(secs.max(1))),
+                    })
+                    .collect();
+                (device.instance, points)
+            })
+            .collect();
+        Self { default_object, default_interval, devices, configured }
+    }
+
+    fn of_device(&self, device_id: u32) -> Vec<PolledPoint> {
+        self.devices.get(&device_id).cloned().unwrap_or_else(|| {
+            vec![PolledPoint { object: self.default_object, properties: vec![85], interval: self.default_interval }]
+        })
+    }
+
+    fn interval(&self, device_id: u32, object: ObjectRef) -> Duration {
+        self.of_device(device_id)
+            .iter()
+            .find(|point| point.object == object)
+            .map_or(self.default_interval, |point| point.interval)
+    }
+}
+
+/// The configured points of each device with their intervals; devices
+/// without configuration have the gateway's poll object read at the
+/// default interval
+pub struct PollPlan {
+    points: RwLock<Points>,
+    /// Time each point of each device is next due
+    schedule: Mutex<HashMap<(u32, ObjectRef), Instant>>,
+}
+
+impl PollPlan {
+    pub fn new(devices: &[DeviceConfig], default_object: ObjectRef, default_interval_secs: u64) -> Self {
+        let points = Points::new(devices, default_object, default_interval_secs);
+        Self { points: RwLock::new(points), schedule: Mutex::new(HashMap::new()) }
+    }
+
+    /// Replaces the polled points; points whose interval changed are polled on the next tick
+    pub fn reload(&self, devices: &[DeviceConfig], default_object: ObjectRef, default_interval_secs: u64) {
+        let new = Points::new(devices, default_object, default_interval_secs);
+        let mut points = self.points.write().unwrap_or_else(|e| e.into_inner());
+        let mut schedule = self.schedule.lock().unwrap_or_else(|e| e.into_inner());
+        schedule.retain(|(device_id, object), _| points.interval(*device_id, *object) == new.interval(*device_id, *object));
+        *points = new;
+    }
+
+    fn points(&self, device_id: u32) -> Vec<PolledPoint> {
+        self.points.read().unwrap_or_else(|e| e.into_inner()).of_device(device_id)
+    }
+
+    /// Objects whose present-value is polled on a device, published as its points
+    pub fn objects(&self, device_id: u32) -> Vec<ObjectRef> {
+        self.points(device_id)
+            .into_iter()
+            .filter(|point| point.properties.contains(&85))
+            .map(|point| point.object)
+            .collect()
+    }
+
+    /// True if the property of the object is polled on the device
+    pub fn polls(&self, device_id: u32, object: ObjectRef, property: u32) -> bool {
+        self.points(device_id).iter().any(|point| point.object == object && point.properties.contains(&property))
+    }
+
+    /// Interval the present-value of the object is polled at
+    pub fn interval(&self, device_id: u32, object: ObjectRef) -> Duration {
+        self.points.read().unwrap_or_else(|e| e.into_inner()).interval(device_id, object)
+    }
+
+    /// True if the device is listed in the configuration
+    pub fn is_configured(&self, device_id: u32) -> bool {
+        self.points.read().unwrap_or_else(|e| e.into_inner()).configured.contains(&device_id)
+    }
+
+    /// Period of the poll loop, dividing every configured interval
+    pub fn tick(&self) -> Duration {
+        let points = self.points.read().unwrap_or_else(|e| e.into_inner());
+        let secs = points
+            .devices
+            .values()
+            .flatten()
+            .map(|point| point.interval.as_secs())
+            .fold(points.default_interval.as_secs(), gcd);
+        Duration::from_secs(secs.max(1))
+    }
+
+    /// Reads due on a device at `now`, with the present-value of `extra`
+    /// objects at the default interval, and schedules their next poll
+    pub fn due(&self, device_id: u32, extra: &[ObjectRef], now: Instant) -> Vec<PropertyReference> {
+        let (mut points, default_interval) = {
+            let points = self.points.read().unwrap_or_else(|e| e.into_inner());
+            (points.of_device(device_id), points.default_interval)
+        };
+        for object in extra {
+            if !points.iter().any(|point| point.object == *object) {
+                points.push(PolledPoint { object: *object, properties: vec![85], interval: default_interval });
+            }
+        }
+        let mut schedule = self.schedule.lock().unwrap_or_else(|e| e.into_inner());
+        let mut reads = Vec::new();
+        for point in points {
+            let next = schedule.entry((device_id, point.object)).or_insert(now);
+            if *next > now {
+                continue;
+            }
+            *next = now + point.interval;
+            reads.extend(
+                point
+                    .properties
+                    .iter()
+                    .map(|property| PropertyReference { object: point.object, property: *property, array_index: None }),
+            );
+        }
+        reads
+    }
+}
@@ -0,0 +1,153 @@
+use crate::mqtt::MqttService;
+use crate::point::ObjectRef;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// A Who-Is went out
+    DiscoveryStarted,
+    /// A device answered for the first time
+    DeviceFound,
+    /// The metadata of a device's object was read
+    ObjectRead,
+    /// The last outstanding metadata read finished
+    Complete,
+}
+
+/// Discovery progress as published to MQTT and the web UI
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressSnapshot {
+    pub enumerating: bool,
+    pub devices_found: usize,
+    pub objects_read: u64,
+    pub objects_pending: usize,
+    /// Extrapolated from the read rate since enumeration started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_remaining_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ProgressMessage<'a> {
+    event: ProgressEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    object: Option<ObjectRef>,
+    #[serde(flatten)]
+    progress: &'a ProgressSnapshot,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    devices: BTreeSet<u32>,
+    objects_read: u64,
+    objects_pending: usize,
+    /// Start of the current enumeration and the reads done before it
+    burst: Option<(Instant, u64)>,
+}
+
+impl ProgressState {
+    fn snapshot(&self) -> ProgressSnapshot {
+        let estimated_remaining_secs = self.burst.and_then(|(started, read_before)| {
+            let read = self.objects_read - read_before;
+            (read > 0).then(|| {
+                let per_object = started.elapsed().as_secs_f64() / read as f64;
+                (per_object * self.objects_pending as f64).ceil() as u64
+            })
+        });
+        ProgressSnapshot {
+            enumerating: self.objects_pending > 0,
+            devices_found: self.devices.len(),
+            objects_read: self.objects_read,
+            objects_pending: self.objects_pending,
+            estimated_remaining_secs,
+        }
+    }
+}
+
+/// Counts devices found and object metadata read during discovery, so the
+/// first discovery of a large site reports how far along it is
+pub struct DiscoveryProgress {
+    mqtt: MqttService,
+    state: Mutex<ProgressState>,
+}
+
+impl DiscoveryProgress {
+    pub fn new(mqtt: MqttService) -> Self {
+        Self { mqtt, state: Mutex::new(ProgressState::default()) }
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).snapshot()
+    }
+
+    async fn publish(&self, event: ProgressEvent, device_id: Option<u32>, object: Option<ObjectRef>, progress: ProgressSnapshot) {
+        let message = ProgressMessage { event, device_id, object, progress: &progress };
+        if let Ok(json) = serde_json::to_string(&message) {
+            self.mqtt.publish(&self.mqtt.discovery_progress_topic(), &json, false).await;
+        }
+    }
+
+    pub async fn discovery_started(&self) {
+        let progress = self.snapshot();
+        self.publish(ProgressEvent::DiscoveryStarted, None, None, progress).await;
+    }
+
+    /// Records an I-Am, publishing an event the first time a device answers
+    pub async fn device_found(&self, device_id: u32) {
+        let progress = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if !state.devices.insert(device_id) {
+                return;
+            }
+            state.snapshot()
+        };
+        self.publish(ProgressEvent::DeviceFound, Some(device_id), None, progress).await;
+    }
+
+    /// Records a metadata read about to be issued
+    pub fn object_queued(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.objects_pending == 0 {
+            state.burst = Some((Instant::now(), state.objects_read));
+        }
+        state.objects_pending += 1;
+    }
+
+    fn dequeue(&self, read: bool) -> ProgressSnapshot {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.objects_pending = state.objects_pending.saturating_sub(1);
+        if read {
+            state.objects_read += 1;
+        }
+        if state.objects_pending == 0 {
+            state.burst = None;
+        }
+        state.snapshot()
+    }
+
+    async fn check_complete(&self, progress: ProgressSnapshot) {
+        if !progress.enumerating {
+            info!("Discovery enumeration complete: {} devices, {} objects read", progress.devices_found, progress.objects_read);
+            self.publish(ProgressEvent::Complete, None, None, progress).await;
+        }
+    }
+
+    /// Records a finished metadata read, successful or not
+    pub async fn object_read(&self, device_id: u32, object: ObjectRef) {
+        let progress = self.dequeue(true);
+        self.publish(ProgressEvent::ObjectRead, Some(device_id), Some(object), progress.clone()).await;
+        self.check_complete(progress).await;
+    }
+
+    /// Records a queued metadata read that was dropped, e.g. by the device filters
+    pub async fn object_skipped(&self) {
+        let progress = self.dequeue(false);
+        self.check_complete(progress).await;
+    }
+}
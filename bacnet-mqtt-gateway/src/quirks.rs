@@ -0,0 +1,45 @@
+//! Vendor quirk profiles: workarounds for devices whose BACnet stack
+//! misbehaves, matched on the vendor and model a device identifies with
+
+use crate::config::QuirkProfile;
+use crate::filter::glob_matches;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// The configured profiles and the one each identified device got
+pub struct Quirks {
+    profiles: Vec<Arc<QuirkProfile>>,
+    devices: RwLock<HashMap<u32, Arc<QuirkProfile>>>,
+}
+
+impl Quirks {
+    pub fn new(profiles: &[QuirkProfile]) -> Self {
+        Self { profiles: profiles.iter().cloned().map(Arc::new).collect(), devices: RwLock::new(HashMap::new()) }
+    }
+
+    /// True if a profile of the vendor is limited to some models, whose
+    /// Model_Name then has to be read
+    pub fn needs_model(&self, vendor_id: u32) -> bool {
+        self.profiles.iter().any(|profile| profile.vendor_id == vendor_id && profile.model.is_some())
+    }
+
+    /// Assigns the device the profile of its vendor, preferring one naming its
+    /// model, and returns it if the device had another or none before
+    pub fn identify(&self, device_id: u32, vendor_id: u32, model: Option<&str>) -> Option<Arc<QuirkProfile>> {
+        let vendor = || self.profiles.iter().filter(|profile| profile.vendor_id == vendor_id);
+        let profile = vendor()
+            .find(|profile| profile.model.as_deref().is_some_and(|pattern| model.is_some_and(|model| glob_matches(pattern, model))))
+            .or_else(|| vendor().find(|profile| profile.model.is_none()))
+            .cloned();
+        let mut devices = self.devices.write().unwrap_or_else(|e| e.into_inner());
+        let previous = match &profile {
+            Some(profile) => devices.insert(device_id, profile.clone()),
+            None => devices.remove(&device_id),
+        };
+        profile.filter(|profile| previous.is_none_or(|previous| previous.name != profile.name))
+    }
+
+    pub fn of(&self, device_id: u32) -> Option<Arc<QuirkProfile>> {
+        self.devices.read().unwrap_or_else(|e| e.into_inner()).get(&device_id).cloned()
+    }
+}
@@ -0,0 +1,123 @@
+//! What the gateway learned about each discovered device: its vendor, when
+//! it was last heard from and the latest present-value of its points, stale
+//! once it is older than allowed
+
+use crate::codec::BacnetValue;
+use crate::mqtt::{utc_timestamp, Quality};
+use crate::point::ObjectRef;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    /// Vendor identifier of the device's last I-Am
+    pub vendor_id: u32,
+    /// When the device last announced itself or answered a poll
+    pub last_seen: String,
+}
+
+/// A state as it was last published
+#[derive(Debug, Clone)]
+pub struct PublishedState {
+    pub state: String,
+    pub quality: Quality,
+    pub units: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatestValue {
+    pub value_type: &'static str,
+    pub value: serde_json::Value,
+    pub updated: String,
+    /// Not refreshed within its maximum age
+    pub stale: bool,
+    #[serde(skip)]
+    received: Instant,
+    #[serde(skip)]
+    pub published: Option<PublishedState>,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    devices: RwLock<HashMap<u32, DeviceInfo>>,
+    values: RwLock<HashMap<u32, BTreeMap<ObjectRef, LatestValue>>>,
+}
+
+impl Registry {
+    /// Records an I-Am of the device
+    pub fn announced(&self, device_id: u32, vendor_id: u32) {
+        let mut devices = self.devices.write().unwrap_or_else(|e| e.into_inner());
+        devices.insert(device_id, DeviceInfo { vendor_id, last_seen: utc_timestamp() });
+    }
+
+    /// Records that the device answered a request
+    pub fn heard(&self, device_id: u32) {
+        let mut devices = self.devices.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(device) = devices.get_mut(&device_id) {
+            device.last_seen = utc_timestamp();
+        }
+    }
+
+    /// Records a polled present-value, keeping the state last published
+    pub fn record_value(&self, device_id: u32, object: ObjectRef, value: &BacnetValue) {
+        self.heard(device_id);
+        let mut values = self.values.write().unwrap_or_else(|e| e.into_inner());
+        let points = values.entry(device_id).or_default();
+        let published = points.get(&object).and_then(|latest| latest.published.clone());
+        let latest = LatestValue {
+            value_type: value.type_name(),
+            value: value.to_json(),
+            updated: utc_timestamp(),
+            stale: false,
+            received: Instant::now(),
+            published,
+        };
+        points.insert(object, latest);
+    }
+
+    /// Records the state published for the latest value
+    pub fn record_published(&self, device_id: u32, object: ObjectRef, published: PublishedState) {
+        let mut values = self.values.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(latest) = values.get_mut(&device_id).and_then(|points| points.get_mut(&object)) {
+            latest.published = Some(published);
+        }
+    }
+
+    /// Marks the values older than their maximum age stale, returning the
+    /// ones that just became stale
+    pub fn mark_stale(&self, max_age: impl Fn(u32, ObjectRef) -> Duration) -> Vec<(u32, ObjectRef, LatestValue)> {
+        let mut values = self.values.write().unwrap_or_else(|e| e.into_inner());
+        let mut newly_stale = Vec::new();
+        for (device_id, points) in values.iter_mut() {
+            for (object, latest) in points.iter_mut() {
+                if !latest.stale && latest.received.elapsed() > max_age(*device_id, *object) {
+                    latest.stale = true;
+                    newly_stale.push((*device_id, *object, latest.clone()));
+                }
+            }
+        }
+        newly_stale
+    }
+
+    /// Puts back what a saved registry knew of the device
+    pub fn restore(&self, device_id: u32, info: DeviceInfo) {
+        self.devices.write().unwrap_or_else(|e| e.into_inner()).insert(device_id, info);
+    }
+
+    pub fn device(&self, device_id: u32) -> Option<DeviceInfo> {
+        self.devices.read().unwrap_or_else(|e| e.into_inner()).get(&device_id).cloned()
+    }
+
+    /// Latest present-value of each polled point of the device
+    pub fn values(&self, device_id: u32) -> BTreeMap<ObjectRef, LatestValue> {
+        self.values.read().unwrap_or_else(|e| e.into_inner()).get(&device_id).cloned().unwrap_or_default()
+    }
+
+    /// Latest present-value of every polled point, by device
+    pub fn all_values(&self) -> BTreeMap<u32, BTreeMap<ObjectRef, LatestValue>> {
+        let values = self.values.read().unwrap_or_else(|e| e.into_inner());
+        values.iter().map(|(device_id, points)| (*device_id, points.clone())).collect()
+    }
+}
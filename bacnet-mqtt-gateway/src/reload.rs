@@ -0,0 +1,221 @@
+//! Applies changes to the configuration file while running, on SIGHUP or
+//! when the file's modification time changes
+
+use crate::bacnet::BacnetEngine;
+use crate::config::GatewayConfig;
+use crate::filter::DeviceFilter;
+use crate::mqtt::MqttService;
+use crate::poll::PollPlan;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Notify, RwLock};
+use tracing::{error, info, warn};
+
+/// Interval the configuration file's modification time is checked at
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings that take effect without a restart, as (section, key) with the
+/// whole section when the key is empty
+const RELOADABLE: &[(&str, &str)] = &[
+    ("devices", ""),
+    ("device_filters", ""),
+    ("polling", "interval_secs"),
+    ("bacnet", "poll_object"),
+    ("mqtt", "topics"),
+];
+
+/// Running parts the reloadable settings are applied to
+pub struct Reloadable {
+    pub bacnet: Arc<BacnetEngine>,
+    pub mqtt: MqttService,
+    pub devices: Arc<RwLock<HashMap<u32, SocketAddr>>>,
+    pub poll_plan: Arc<PollPlan>,
+    pub filter: Arc<DeviceFilter>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// SIGHUP notifications, never arriving where signals are unavailable
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        let signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .map_err(|e| warn!("Cannot handle SIGHUP, reloading on file changes only: {}", e))
+            .ok();
+        Self {
+            #[cfg(unix)]
+            signal,
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await;
+    }
+}
+
+fn same(a: &impl Serialize, b: &impl Serialize) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Top-level sections that changed in ways only a restart applies
+pub fn restart_required(current: &GatewayConfig, new: &GatewayConfig) -> Vec<String> {
+    let strip = |config: &GatewayConfig| {
+        let mut value = serde_json::to_value(config).unwrap_or_default();
+        for (section, key) in RELOADABLE {
+            match (value.as_object_mut(), key.is_empty()) {
+                (Some(sections), true) => {
+                    sections.remove(*section);
+                }
+                (Some(sections), false) => {
+                    if let Some(section) = sections.get_mut(*section).and_then(|s| s.as_object_mut()) {
+                        section.remove(*key);
+                    }
+                }
+                (None, _) => {}
+            }
+        }
+        value
+    };
+    let (current, new) = (strip(current), strip(new));
+    let (Some(current), Some(new)) = (current.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    new.iter().filter(|(section, value)| current.get(*section) != Some(*value)).map(|(section, _)| section.clone()).collect()
+}
+
+/// Has every known device announce itself again, republishing its discovery
+async fn rediscover(targets: &Reloadable) {
+    let devices = targets.devices.read().await.clone();
+    if let Err(e) = targets.bacnet.rediscover(&devices) {
+        error!("Failed to send Who-Is: {}", e);
+    }
+}
+
+/// Applies what changed, putting back the running settings of sections that failed to apply
+async fn apply(current: &GatewayConfig, new: &mut GatewayConfig, targets: &Reloadable) {
+    let devices = targets.devices.read().await.clone();
+
+    if !same(&new.devices, &current.devices)
+        || new.polling.interval_secs != current.polling.interval_secs
+        || new.bacnet.poll_object != current.bacnet.poll_object
+    {
+        let before: HashMap<u32, _> = devices.keys().map(|id| (*id, targets.poll_plan.objects(*id))).collect();
+        targets.poll_plan.reload(&new.devices, new.bacnet.poll_object, new.polling.interval_secs);
+        info!("Reloaded the polled points of {} configured devices", new.devices.len());
+        // Renamed devices and objects are published again under their new names
+        if targets.mqtt.aliases().reload(&new.devices) {
+            info!("Reloaded the device and object aliases, rediscovering devices");
+            rediscover(targets).await;
+        }
+        for device in &new.devices {
+            let Some(addr) = device.address.filter(|addr| devices.get(&device.instance) != Some(addr)) else {
+                continue;
+            };
+            targets.devices.write().await.insert(device.instance, addr);
+            if let Err(e) = targets.bacnet.who_is(Some(device.instance), Some(device.instance), Some(addr)) {
+                warn!("Failed to ask device {} at {} for its I-Am: {}", device.instance, addr, e);
+            }
+        }
+        // Their I-Am republishes the discovery of the points now polled
+        for (device_id, objects) in before {
+            if targets.poll_plan.objects(device_id) == objects {
+                continue;
+            }
+            let addr = devices.get(&device_id).copied();
+            if let Err(e) = targets.bacnet.who_is(Some(device_id), Some(device_id), addr) {
+                warn!("Failed to ask device {} for its I-Am: {}", device_id, e);
+            }
+        }
+    }
+
+    if !same(&new.device_filters, &current.device_filters) {
+        match targets.filter.reload(&new.device_filters) {
+            Ok(()) => {
+                info!("Reloaded the device filters");
+                // Devices excluded now leave the registry and Home Assistant
+                for (device_id, addr) in &devices {
+                    if targets.filter.excludes(*device_id, *addr) {
+                        info!("Removing device {}, now excluded by the device filters", device_id);
+                        targets.devices.write().await.remove(device_id);
+                        targets.mqtt.retract_discovery(*device_id).await;
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Keeping the running device filters: {}", e);
+                new.device_filters = current.device_filters.clone();
+            }
+        }
+    }
+
+    if !same(&new.mqtt.topics, &current.mqtt.topics) {
+        match targets.mqtt.reload_topics(&new.mqtt.topics).await {
+            // Every device announcing itself again republishes its discovery on the new topics
+            Ok(true) => {
+                info!("Reloaded the topic templates, rediscovering devices");
+                rediscover(targets).await;
+            }
+            Ok(false) => info!("Reloaded the topic settings"),
+            Err(e) => {
+                error!("Keeping the running topic settings: {}", e);
+                new.mqtt.topics = current.mqtt.topics.clone();
+            }
+        }
+    }
+
+    for section in restart_required(current, new) {
+        warn!("Changes to '{}' take effect after a restart", section);
+    }
+}
+
+/// Reloads the configuration file whenever it changes, SIGHUP arrives or
+/// `requested` is notified after the file was saved; an invalid file is
+/// reported and the running configuration kept
+pub async fn run(path: PathBuf, mut current: GatewayConfig, targets: Reloadable, requested: Arc<Notify>) {
+    let mut last_modified = modified(&path);
+    let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+    let mut hangup = Hangup::new();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let now = modified(&path);
+                if now == last_modified {
+                    continue;
+                }
+                last_modified = now;
+                info!("Configuration file {} changed, reloading", path.display());
+            }
+            _ = hangup.recv() => info!("Received SIGHUP, reloading {}", path.display()),
+            _ = requested.notified() => {
+                // Saved through the web API, no need to notice the change again
+                last_modified = modified(&path);
+                info!("Configuration saved through the web API, reloading");
+            }
+        }
+        let mut new = match GatewayConfig::load_from_file(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Keeping the running configuration, {} is invalid: {}", path.display(), e);
+                continue;
+            }
+        };
+        apply(&current, &mut new, &targets).await;
+        current = new;
+    }
+}
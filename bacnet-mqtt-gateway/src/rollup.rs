@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Reachability {
+    /// Polls in a row the device left unanswered
+    failures: u32,
+    /// Last published availability
+    online: Option<bool>,
+    offline_since: Option<Instant>,
+    /// Discovery configs withdrawn after the device stayed offline too long
+    retracted: bool,
+}
+
+/// Whether a device answers its polls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PollStatus {
+    /// Not polled yet
+    Pending,
+    Online,
+    Offline,
+}
+
+/// Per-device state aggregated into the roll-up entities
+pub struct Rollups {
+    /// Whether any polled point of the device reports a fault
+    faults: Mutex<BTreeMap<u32, bool>>,
+    reachability: Mutex<BTreeMap<u32, Reachability>>,
+    offline_after_failures: u32,
+}
+
+impl Rollups {
+    pub fn new(offline_after_failures: u32) -> Self {
+        Self {
+            faults: Mutex::new(BTreeMap::new()),
+            reachability: Mutex::new(BTreeMap::new()),
+            offline_after_failures: offline_after_failures.max(1),
+        }
+    }
+
+    /// Records the fault state of a device, true if it changed or was unknown
+    pub fn set_fault(&self, device_id: u32, fault: bool) -> bool {
+        let mut faults = self.faults.lock().unwrap_or_else(|e| e.into_inner());
+        faults.insert(device_id, fault) != Some(fault)
+    }
+
+    /// Whether a polled point of the device reported a fault at the last status refresh
+    pub fn has_fault(&self, device_id: u32) -> bool {
+        let faults = self.faults.lock().unwrap_or_else(|e| e.into_inner());
+        faults.get(&device_id).copied().unwrap_or(false)
+    }
+
+    /// Records whether a device answered a poll; when that takes it online or
+    /// offline, returns its availability and the number of offline devices
+    pub fn record_poll(&self, device_id: u32, answered: bool) -> Option<(bool, usize)> {
+        let mut devices = self.reachability.lock().unwrap_or_else(|e| e.into_inner());
+        let device = devices.entry(device_id).or_default();
+        device.failures = if answered { 0 } else { device.failures.saturating_add(1) };
+        let online = device.failures < self.offline_after_failures;
+        if device.online == Some(online) {
+            return None;
+        }
+        device.online = Some(online);
+        device.offline_since = (!online).then(Instant::now);
+        let offline = devices.values().filter(|d| d.online == Some(false)).count();
+        Some((online, offline))
+    }
+
+    pub fn poll_status(&self, device_id: u32) -> PollStatus {
+        let devices = self.reachability.lock().unwrap_or_else(|e| e.into_inner());
+        match devices.get(&device_id).and_then(|d| d.online) {
+            None => PollStatus::Pending,
+            Some(true) => PollStatus::Online,
+            Some(false) => PollStatus::Offline,
+        }
+    }
+
+    /// Devices offline for longer than `ttl` whose discovery has not been
+    /// retracted yet, marked as retracted
+    pub fn offline_beyond(&self, ttl: Duration) -> Vec<u32> {
+        let mut devices = self.reachability.lock().unwrap_or_else(|e| e.into_inner());
+        devices
+            .iter_mut()
+            .filter(|(_, d)| !d.retracted && d.offline_since.is_some_and(|since| since.elapsed() > ttl))
+            .map(|(device_id, d)| {
+                d.retracted = true;
+                *device_id
+            })
+            .collect()
+    }
+
+    /// Clears the retraction of a device that answers again, true if its
+    /// discovery had been retracted
+    pub fn restore(&self, device_id: u32) -> bool {
+        let mut devices = self.reachability.lock().unwrap_or_else(|e| e.into_inner());
+        devices.get_mut(&device_id).is_some_and(|d| std::mem::take(&mut d.retracted))
+    }
+}
@@ -0,0 +1,96 @@
+use crate::bacnet::BacnetEngine;
+use crate::cluster::Cluster;
+use crate::codec::{self, PropertyReference};
+use crate::mqtt::MqttService;
+use crate::point::ObjectRef;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+fn default_property() -> u32 {
+    85
+}
+
+/// Payload of `<base>/rpc/read`
+#[derive(Debug, Deserialize)]
+struct ReadRequest {
+    device: u32,
+    object: ObjectRef,
+    #[serde(default = "default_property")]
+    property: u32,
+    #[serde(default)]
+    array_index: Option<u32>,
+    correlation_id: String,
+}
+
+async fn read(
+    engine: &BacnetEngine,
+    devices: &RwLock<HashMap<u32, SocketAddr>>,
+    request: &ReadRequest,
+) -> Result<serde_json::Value, String> {
+    let addr = devices
+        .read()
+        .await
+        .get(&request.device)
+        .copied()
+        .ok_or_else(|| format!("device {} has not been discovered", request.device))?;
+    let reference = PropertyReference { object: request.object, property: request.property, array_index: request.array_index };
+    let raw = engine.read_property_value(addr, &reference).await.map_err(|e| e.to_string())?;
+    let values = codec::decode_application_values(&raw).map_err(|e| e.to_string())?;
+    Ok(match values.as_slice() {
+        [value] => value.to_json(),
+        values => values.iter().map(|v| v.to_json()).collect(),
+    })
+}
+
+/// Answers `<base>/rpc/read` requests with an immediate ReadProperty, publishing
+/// the value or the error to `<base>/rpc/response/<correlation_id>`
+pub async fn run(
+    engine: Arc<BacnetEngine>,
+    mqtt: MqttService,
+    devices: Arc<RwLock<HashMap<u32, SocketAddr>>>,
+    cluster: Arc<Cluster>,
+) {
+    let request_topic = mqtt.rpc_topic("read");
+    let mut inbound = mqtt.incoming();
+    mqtt.subscribe(&request_topic).await;
+    loop {
+        let msg = match inbound.recv().await {
+            Ok(msg) if msg.topic == request_topic => msg,
+            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+        let request: ReadRequest = match serde_json::from_slice(&msg.payload) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Ignoring RPC read request: {}", e);
+                continue;
+            }
+        };
+        if !cluster.owns(request.device) {
+            continue;
+        }
+        let (engine, mqtt, devices) = (engine.clone(), mqtt.clone(), devices.clone());
+        // Reads wait for the device, keep taking requests meanwhile
+        tokio::spawn(async move {
+            let mut response = serde_json::json!({
+                "correlation_id": request.correlation_id,
+                "device": request.device,
+                "object": request.object,
+                "property": request.property,
+            });
+            match read(&engine, &devices, &request).await {
+                Ok(value) => response["value"] = value,
+                Err(e) => {
+                    debug!("RPC read of {} {} failed: {}", request.device, request.object, e);
+                    response["error"] = e.into();
+                }
+            }
+            let topic = mqtt.rpc_response_topic(&request.correlation_id);
+            mqtt.publish(&topic, &response.to_string(), false).await;
+        });
+    }
+}
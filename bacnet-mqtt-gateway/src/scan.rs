@@ -0,0 +1,129 @@
+//! Full scan of a controller: every object of its object list with a
+//! standard set of properties, as a report for commissioning handoff
+
+use crate::bacnet::{BacnetEngine, BacnetError};
+use crate::codec::{self, BacnetValue, PropertyResult};
+use crate::export::csv_field;
+use crate::mqtt::utc_timestamp;
+use crate::point::ObjectRef;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use tracing::info;
+
+/// Properties read from the device object
+const DEVICE_PROPERTIES: [(u32, &str); 7] = [
+    (77, "object_name"),
+    (28, "description"),
+    (121, "vendor_name"),
+    (70, "model_name"),
+    (44, "firmware_revision"),
+    (12, "application_software_version"),
+    (58, "location"),
+];
+
+/// Properties read from every other object, those it lacks reported as errors
+const OBJECT_PROPERTIES: [(u32, &str); 8] = [
+    (77, "object_name"),
+    (28, "description"),
+    (85, "present_value"),
+    (117, "units"),
+    (111, "status_flags"),
+    (36, "event_state"),
+    (103, "reliability"),
+    (81, "out_of_service"),
+];
+
+#[derive(Debug, Serialize)]
+pub struct ObjectReport {
+    pub object: ObjectRef,
+    pub properties: BTreeMap<&'static str, serde_json::Value>,
+    /// Properties the device answered with an error, e.g. `unknown-property`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<&'static str, String>,
+    /// Why none of the object's properties could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScanReport {
+    pub device_id: u32,
+    pub address: SocketAddr,
+    pub started: String,
+    pub finished: String,
+    pub device: ObjectReport,
+    pub objects: Vec<ObjectReport>,
+}
+
+fn to_json(values: &[BacnetValue]) -> serde_json::Value {
+    match values {
+        [value] => value.to_json(),
+        values => serde_json::Value::Array(values.iter().map(BacnetValue::to_json).collect()),
+    }
+}
+
+async fn read_object(bacnet: &BacnetEngine, addr: SocketAddr, object: ObjectRef, properties: &[(u32, &'static str)]) -> ObjectReport {
+    let mut report = ObjectReport { object, properties: BTreeMap::new(), errors: BTreeMap::new(), failure: None };
+    let numbers: Vec<u32> = properties.iter().map(|(property, _)| *property).collect();
+    let results = match bacnet.read_properties(addr, object, &numbers).await {
+        Ok(results) => results,
+        Err(e) => {
+            report.failure = Some(e.to_string());
+            return report;
+        }
+    };
+    for PropertyResult { property, value, .. } in results {
+        let Some(&(_, name)) = properties.iter().find(|(number, _)| *number == property) else {
+            continue;
+        };
+        match value {
+            Ok(values) => {
+                report.properties.insert(name, to_json(&values));
+            }
+            Err(e) => {
+                report.errors.insert(name, codec::error_code_name(e.code));
+            }
+        }
+    }
+    report
+}
+
+/// Reads the object list and then each object in turn; fails only if the
+/// object list cannot be read
+pub async fn scan(bacnet: &BacnetEngine, addr: SocketAddr, device_id: u32) -> Result<ScanReport, BacnetError> {
+    let started = utc_timestamp();
+    let device_object = ObjectRef::new(8, device_id);
+    let objects = bacnet.read_object_list(addr, device_id).await?;
+    info!("Scanning {} objects of device {}", objects.len(), device_id);
+    let device = read_object(bacnet, addr, device_object, &DEVICE_PROPERTIES).await;
+    let mut reports = Vec::with_capacity(objects.len());
+    for object in objects.into_iter().filter(|object| *object != device_object) {
+        reports.push(read_object(bacnet, addr, object, &OBJECT_PROPERTIES).await);
+    }
+    Ok(ScanReport { device_id, address: addr, started, finished: utc_timestamp(), device, objects: reports })
+}
+
+/// One row per object with the scanned properties as columns
+pub fn to_csv(report: &ScanReport) -> String {
+    let names: Vec<&str> = OBJECT_PROPERTIES.iter().map(|(_, name)| *name).collect();
+    let mut csv = format!("object,{},errors\r\n", names.join(","));
+    for object in &report.objects {
+        let mut row = vec![csv_field(&object.object.to_string())];
+        for name in &names {
+            row.push(match object.properties.get(name) {
+                Some(serde_json::Value::String(text)) => csv_field(text),
+                Some(value) => csv_field(&value.to_string()),
+                None => String::new(),
+            });
+        }
+        let errors: Vec<String> = match &object.failure {
+            Some(failure) => vec![failure.clone()],
+            None => object.errors.iter().map(|(name, error)| format!("{}: {}", name, error)).collect(),
+        };
+        row.push(csv_field(&errors.join("; ")));
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
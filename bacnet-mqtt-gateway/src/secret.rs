@@ -0,0 +1,41 @@
+//! Credentials given in the configuration as `${ENV_VAR}` references or read
+//! from files, e.g. Docker secrets or a Vault agent's mounted files
+
+use std::fs;
+use std::path::Path;
+
+/// Expands `${NAME}` references to environment variables, `$${` giving a literal `${`
+pub fn expand(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(tail) = rest.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("${") {
+            let end = tail.find('}').ok_or_else(|| format!("unclosed '${{' in '{}'", value))?;
+            let name = &tail[..end];
+            let variable = std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+            expanded.push_str(&variable);
+            rest = &tail[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// A secret read from its file if one is configured, else the inline value
+/// with its references expanded
+pub fn resolve(value: Option<&str>, file: Option<&Path>) -> Result<Option<String>, String> {
+    if let Some(path) = file {
+        let secret = fs::read_to_string(path).map_err(|e| format!("cannot read secret file {}: {}", path.display(), e))?;
+        // Files written with `echo` or an editor end with a newline
+        return Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    value.map(expand).transpose()
+}
@@ -0,0 +1,472 @@
+use crate::codec::{self, BacnetValue, PropertyError, PropertyReference, PropertyResult, WriteSpec};
+use crate::config::{BacnetConfig, VirtualObjectConfig};
+use crate::point::ObjectRef;
+use crate::units;
+use bacnet_rs::{app::Apdu, object::Device, service::ConfirmedServiceChoice};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use tracing::{info, warn};
+
+const OBJECT_TYPE_ANALOG_VALUE: u16 = 2;
+const OBJECT_TYPE_BINARY_VALUE: u16 = 5;
+const OBJECT_TYPE_DEVICE: u16 = 8;
+const PROP_PRESENT_VALUE: u32 = 85;
+/// Device instance meaning "whichever device receives this request"
+const WILDCARD_INSTANCE: u32 = 4194303;
+
+/// Property identifiers of the gateway Device object, flagged whether they are required
+const DEVICE_PROPERTIES: &[(u32, bool)] = &[
+    (75, true),   // object-identifier
+    (77, true),   // object-name
+    (79, true),   // object-type
+    (112, true),  // system-status
+    (121, true),  // vendor-name
+    (120, true),  // vendor-identifier
+    (70, true),   // model-name
+    (44, true),   // firmware-revision
+    (12, true),   // application-software-version
+    (98, true),   // protocol-version
+    (139, true),  // protocol-revision
+    (97, true),   // protocol-services-supported
+    (96, true),   // protocol-object-types-supported
+    (76, true),   // object-list
+    (62, true),   // max-apdu-length-accepted
+    (107, true),  // segmentation-supported
+    (11, true),   // apdu-timeout
+    (73, true),   // number-of-apdu-retries
+    (30, true),   // device-address-binding
+    (155, true),  // database-revision
+    (371, true),  // property-list
+    (28, false),  // description
+];
+
+/// Property identifiers of virtual Analog/Binary Value objects, flagged whether they are required
+const VIRTUAL_PROPERTIES: &[(u32, bool)] = &[
+    (75, true),   // object-identifier
+    (77, true),   // object-name
+    (79, true),   // object-type
+    (85, true),   // present-value
+    (111, true),  // status-flags
+    (36, true),   // event-state
+    (81, true),   // out-of-service
+    (117, true),  // units (analog only)
+    (371, true),  // property-list
+    (28, false),  // description
+];
+
+/// Services the gateway executes as a server, as protocol-services-supported bit positions
+const SERVICES_SUPPORTED: &[usize] = &[
+    12, // readProperty
+    14, // readPropertyMultiple
+    15, // writeProperty
+    26, // i-Am
+    34, // who-Is
+];
+
+/// Object types the gateway hosts, as protocol-object-types-supported bit positions
+const OBJECT_TYPES_SUPPORTED: &[usize] = &[
+    OBJECT_TYPE_ANALOG_VALUE as usize,
+    OBJECT_TYPE_BINARY_VALUE as usize,
+    OBJECT_TYPE_DEVICE as usize,
+];
+
+enum PropertyValue {
+    Single(BacnetValue),
+    Array(Vec<BacnetValue>),
+    List(Vec<BacnetValue>),
+}
+
+fn bit_string(len: usize, set: &[usize]) -> BacnetValue {
+    BacnetValue::BitString((0..len).map(|bit| set.contains(&bit)).collect())
+}
+
+/// An Analog or Binary Value hosted by the gateway and mirrored from an MQTT topic
+struct VirtualObject {
+    name: String,
+    topic: String,
+    command_topic: String,
+    writable: bool,
+    units: u32,
+    present_value: BacnetValue,
+}
+
+impl VirtualObject {
+    fn new(config: &VirtualObjectConfig) -> Self {
+        let present_value = if config.object.object_type == OBJECT_TYPE_BINARY_VALUE {
+            BacnetValue::Enumerated(0)
+        } else {
+            BacnetValue::Real(0.0)
+        };
+        Self {
+            name: config.name.clone(),
+            topic: config.topic.clone(),
+            command_topic: config.command_topic.clone().unwrap_or_else(|| config.topic.clone()),
+            writable: config.writable,
+            units: config.units.unwrap_or(units::NO_UNITS),
+            present_value,
+        }
+    }
+
+    fn property_value(&self, object: ObjectRef, property: u32) -> Option<PropertyValue> {
+        use PropertyValue::*;
+        let value = match property {
+            75 => Single(BacnetValue::ObjectId(object)),
+            77 => Single(BacnetValue::CharacterString(self.name.clone())),
+            79 => Single(BacnetValue::Enumerated(object.object_type as u32)),
+            85 => Single(self.present_value.clone()),
+            111 => Single(BacnetValue::BitString(vec![false; 4])),
+            36 => Single(BacnetValue::Enumerated(0)), // normal
+            81 => Single(BacnetValue::Boolean(false)),
+            117 if object.object_type == OBJECT_TYPE_ANALOG_VALUE => Single(BacnetValue::Enumerated(self.units)),
+            371 => Array(
+                VIRTUAL_PROPERTIES
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .filter(|id| !matches!(*id, 75 | 77 | 79 | 371))
+                    .filter(|id| *id != 117 || object.object_type == OBJECT_TYPE_ANALOG_VALUE)
+                    .map(BacnetValue::Unsigned)
+                    .collect(),
+            ),
+            28 => Single(BacnetValue::CharacterString(format!("Mirrors MQTT topic {}", self.topic))),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Converts an MQTT payload into a present-value of this object's type
+    fn parse_payload(&self, object: ObjectRef, payload: &str) -> Option<BacnetValue> {
+        let payload = payload.trim();
+        if object.object_type == OBJECT_TYPE_BINARY_VALUE {
+            match payload.to_ascii_lowercase().as_str() {
+                "1" | "on" | "true" | "active" => Some(BacnetValue::Enumerated(1)),
+                "0" | "off" | "false" | "inactive" => Some(BacnetValue::Enumerated(0)),
+                _ => None,
+            }
+        } else {
+            payload.parse::<f32>().ok().map(BacnetValue::Real)
+        }
+    }
+}
+
+/// Description of a virtual object for listings and exports
+#[derive(Debug, Clone)]
+pub struct VirtualObjectInfo {
+    pub object: ObjectRef,
+    pub name: String,
+    pub topic: String,
+    /// Topic BACnet writes are forwarded to, for writable objects
+    pub command_topic: Option<String>,
+    pub units: Option<u32>,
+}
+
+/// A BACnet write to a virtual object, to be forwarded to MQTT
+#[derive(Debug, Clone)]
+pub struct VirtualWrite {
+    pub object: ObjectRef,
+    pub topic: String,
+    pub payload: String,
+}
+
+/// The objects hosted by the gateway itself, answering ReadProperty,
+/// ReadPropertyMultiple and WriteProperty requests from BACnet workstations
+pub struct LocalDevice {
+    identifier: ObjectRef,
+    object_name: String,
+    vendor_name: String,
+    model_name: String,
+    vendor_identifier: u32,
+    max_apdu_length_accepted: u32,
+    segmentation_supported: u32,
+    apdu_timeout_ms: u32,
+    apdu_retries: u32,
+    virtual_objects: RwLock<BTreeMap<ObjectRef, VirtualObject>>,
+}
+
+impl LocalDevice {
+    pub fn new(config: &BacnetConfig, device: &Device, object_name: &str) -> Self {
+        Self {
+            identifier: ObjectRef::new(OBJECT_TYPE_DEVICE, config.device_id),
+            object_name: object_name.to_string(),
+            vendor_name: config.vendor_name.clone(),
+            model_name: config.model_name.clone(),
+            vendor_identifier: device.vendor_identifier as u32,
+            max_apdu_length_accepted: device.max_apdu_length_accepted as u32,
+            segmentation_supported: device.segmentation_supported as u32,
+            apdu_timeout_ms: config.apdu_timeout_ms.min(u32::MAX as u64) as u32,
+            apdu_retries: config.apdu_retries,
+            virtual_objects: RwLock::new(
+                config
+                    .virtual_objects
+                    .iter()
+                    .filter(|vo| {
+                        let supported = matches!(vo.object.object_type, OBJECT_TYPE_ANALOG_VALUE | OBJECT_TYPE_BINARY_VALUE);
+                        if !supported {
+                            warn!("Virtual object {} must be an Analog Value or Binary Value, ignoring it", vo.object);
+                        }
+                        supported
+                    })
+                    .map(|vo| (vo.object, VirtualObject::new(vo)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn object_list(&self) -> Vec<ObjectRef> {
+        let mut objects = vec![self.identifier];
+        objects.extend(self.virtual_objects.read().unwrap_or_else(|e| e.into_inner()).keys().copied());
+        objects
+    }
+
+    /// Instance number of the gateway's Device object
+    pub fn instance(&self) -> u32 {
+        self.identifier.instance
+    }
+
+    /// The virtual objects with the topics they are mirrored from and written to
+    pub fn virtual_object_infos(&self) -> Vec<VirtualObjectInfo> {
+        let objects = self.virtual_objects.read().unwrap_or_else(|e| e.into_inner());
+        objects
+            .iter()
+            .map(|(object, vo)| VirtualObjectInfo {
+                object: *object,
+                name: vo.name.clone(),
+                topic: vo.topic.clone(),
+                command_topic: vo.writable.then(|| vo.command_topic.clone()),
+                units: (object.object_type != OBJECT_TYPE_BINARY_VALUE).then_some(vo.units),
+            })
+            .collect()
+    }
+
+    /// MQTT topics feeding virtual objects
+    pub fn virtual_topics(&self) -> Vec<String> {
+        let objects = self.virtual_objects.read().unwrap_or_else(|e| e.into_inner());
+        let mut topics: Vec<String> = objects.values().map(|vo| vo.topic.clone()).collect();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// Updates the present-value of every virtual object fed by `topic`
+    pub fn update_from_mqtt(&self, topic: &str, payload: &str) {
+        let mut objects = self.virtual_objects.write().unwrap_or_else(|e| e.into_inner());
+        for (object, vo) in objects.iter_mut().filter(|(_, vo)| vo.topic == topic) {
+            match vo.parse_payload(*object, payload) {
+                Some(value) => vo.present_value = value,
+                None => warn!("Ignoring payload '{}' on {} for virtual object {}", payload, topic, object),
+            }
+        }
+    }
+
+    /// Maps the wildcard device instance onto the gateway's own device
+    fn resolve(&self, object: ObjectRef) -> ObjectRef {
+        if object.object_type == OBJECT_TYPE_DEVICE && object.instance == WILDCARD_INSTANCE {
+            self.identifier
+        } else {
+            object
+        }
+    }
+
+    fn property_ids(&self, object: ObjectRef, selector: u32) -> Result<Vec<u32>, PropertyError> {
+        let properties = if object == self.identifier {
+            DEVICE_PROPERTIES
+        } else if self.virtual_objects.read().unwrap_or_else(|e| e.into_inner()).contains_key(&object) {
+            VIRTUAL_PROPERTIES
+        } else {
+            return Err(PropertyError::UNKNOWN_OBJECT);
+        };
+        Ok(properties
+            .iter()
+            .filter(|(id, _)| *id != 117 || object.object_type == OBJECT_TYPE_ANALOG_VALUE)
+            .filter(|(_, required)| match selector {
+                codec::PROP_REQUIRED => *required,
+                codec::PROP_OPTIONAL => !*required,
+                _ => true,
+            })
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    fn device_property(&self, property: u32) -> Option<PropertyValue> {
+        use PropertyValue::*;
+        let value = match property {
+            75 => Single(BacnetValue::ObjectId(self.identifier)),
+            77 => Single(BacnetValue::CharacterString(self.object_name.clone())),
+            79 => Single(BacnetValue::Enumerated(OBJECT_TYPE_DEVICE as u32)),
+            112 => Single(BacnetValue::Enumerated(0)), // operational
+            121 => Single(BacnetValue::CharacterString(self.vendor_name.clone())),
+            120 => Single(BacnetValue::Unsigned(self.vendor_identifier)),
+            70 => Single(BacnetValue::CharacterString(self.model_name.clone())),
+            44 | 12 => Single(BacnetValue::CharacterString(env!("CARGO_PKG_VERSION").to_string())),
+            98 => Single(BacnetValue::Unsigned(1)),
+            139 => Single(BacnetValue::Unsigned(14)),
+            97 => Single(bit_string(41, SERVICES_SUPPORTED)),
+            96 => Single(bit_string(64, OBJECT_TYPES_SUPPORTED)),
+            76 => Array(self.object_list().into_iter().map(BacnetValue::ObjectId).collect()),
+            62 => Single(BacnetValue::Unsigned(self.max_apdu_length_accepted)),
+            107 => Single(BacnetValue::Enumerated(self.segmentation_supported)),
+            11 => Single(BacnetValue::Unsigned(self.apdu_timeout_ms)),
+            73 => Single(BacnetValue::Unsigned(self.apdu_retries)),
+            30 => List(Vec::new()),
+            155 => Single(BacnetValue::Unsigned(1)),
+            371 => Array(
+                DEVICE_PROPERTIES
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .filter(|id| !matches!(*id, 75 | 77 | 79 | 371))
+                    .map(BacnetValue::Unsigned)
+                    .collect(),
+            ),
+            28 => Single(BacnetValue::CharacterString("BACnet-MQTT bridge gateway".to_string())),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Reads one property of a hosted object
+    pub fn read_property(&self, reference: &PropertyReference) -> Result<Vec<BacnetValue>, PropertyError> {
+        let object = self.resolve(reference.object);
+        let value = if object == self.identifier {
+            self.device_property(reference.property)
+        } else {
+            let objects = self.virtual_objects.read().unwrap_or_else(|e| e.into_inner());
+            let vo = objects.get(&object).ok_or(PropertyError::UNKNOWN_OBJECT)?;
+            vo.property_value(object, reference.property)
+        }
+        .ok_or(PropertyError::UNKNOWN_PROPERTY)?;
+        match (value, reference.array_index) {
+            (PropertyValue::Single(v), None) => Ok(vec![v]),
+            (PropertyValue::List(items), None) | (PropertyValue::Array(items), None) => Ok(items),
+            (PropertyValue::Single(_), Some(_)) | (PropertyValue::List(_), Some(_)) => {
+                Err(PropertyError::PROPERTY_IS_NOT_AN_ARRAY)
+            }
+            (PropertyValue::Array(items), Some(0)) => Ok(vec![BacnetValue::Unsigned(items.len() as u32)]),
+            (PropertyValue::Array(items), Some(index)) => items
+                .get(index as usize - 1)
+                .cloned()
+                .map(|v| vec![v])
+                .ok_or(PropertyError::INVALID_ARRAY_INDEX),
+        }
+    }
+
+    /// Answers a ReadProperty request, returning the complete reply APDU
+    pub fn handle_read_property(&self, invoke_id: u8, service_data: &[u8]) -> Vec<u8> {
+        let service_choice = ConfirmedServiceChoice::ReadProperty as u8;
+        let reference = match codec::decode_read_property_request(service_data) {
+            Ok(reference) => reference,
+            Err(_) => return codec::encode_reject_apdu(invoke_id, codec::REJECT_INVALID_TAG),
+        };
+        match self.read_property(&reference) {
+            Ok(values) => {
+                let reference = PropertyReference { object: self.resolve(reference.object), ..reference };
+                complex_ack(invoke_id, service_choice, codec::encode_read_property_ack(&reference, &values))
+            }
+            Err(error) => codec::encode_error_apdu(invoke_id, service_choice, error),
+        }
+    }
+
+    /// Answers a WriteProperty request against a writable virtual object's
+    /// present-value, returning the reply APDU and the write to forward to MQTT
+    pub fn handle_write_property(&self, invoke_id: u8, service_data: &[u8]) -> (Vec<u8>, Option<VirtualWrite>) {
+        let service_choice = ConfirmedServiceChoice::WriteProperty as u8;
+        let write = match codec::decode_write_property_request(service_data) {
+            Ok(write) => write,
+            Err(_) => return (codec::encode_reject_apdu(invoke_id, codec::REJECT_INVALID_TAG), None),
+        };
+        match self.write_virtual(&write) {
+            Ok(forward) => (codec::encode_simple_ack_apdu(invoke_id, service_choice), Some(forward)),
+            Err(error) => (codec::encode_error_apdu(invoke_id, service_choice, error), None),
+        }
+    }
+
+    fn write_virtual(&self, write: &WriteSpec) -> Result<VirtualWrite, PropertyError> {
+        let object = write.reference.object;
+        let mut objects = self.virtual_objects.write().unwrap_or_else(|e| e.into_inner());
+        let vo = match objects.get_mut(&object) {
+            Some(vo) => vo,
+            // The Device object's properties are read-only
+            None if self.resolve(object) == self.identifier => return Err(PropertyError::WRITE_ACCESS_DENIED),
+            None => return Err(PropertyError::UNKNOWN_OBJECT),
+        };
+        if write.reference.property != PROP_PRESENT_VALUE {
+            return match vo.property_value(object, write.reference.property) {
+                Some(_) => Err(PropertyError::WRITE_ACCESS_DENIED),
+                None => Err(PropertyError::UNKNOWN_PROPERTY),
+            };
+        }
+        if !vo.writable {
+            return Err(PropertyError::WRITE_ACCESS_DENIED);
+        }
+
+        let (value, _) = codec::decode_application(&write.value).map_err(|_| PropertyError::INVALID_DATA_TYPE)?;
+        let value = match (object.object_type, value) {
+            (OBJECT_TYPE_ANALOG_VALUE, BacnetValue::Real(v)) => BacnetValue::Real(v),
+            (OBJECT_TYPE_ANALOG_VALUE, BacnetValue::Unsigned(v)) => BacnetValue::Real(v as f32),
+            (OBJECT_TYPE_ANALOG_VALUE, BacnetValue::Signed(v)) => BacnetValue::Real(v as f32),
+            (OBJECT_TYPE_BINARY_VALUE, BacnetValue::Enumerated(v)) if v <= 1 => BacnetValue::Enumerated(v),
+            _ => return Err(PropertyError::INVALID_DATA_TYPE),
+        };
+
+        info!("BACnet write of {} to virtual object {}", codec::state_text(object, &value), object);
+        vo.present_value = value.clone();
+        Ok(VirtualWrite {
+            object,
+            topic: vo.command_topic.clone(),
+            payload: codec::state_text(object, &value),
+        })
+    }
+
+    /// Answers a ReadPropertyMultiple request, expanding ALL/REQUIRED/OPTIONAL
+    pub fn handle_read_property_multiple(&self, invoke_id: u8, service_data: &[u8]) -> Vec<u8> {
+        let service_choice = ConfirmedServiceChoice::ReadPropertyMultiple as u8;
+        let specs = match codec::decode_read_property_multiple_request(service_data) {
+            Ok(specs) => specs,
+            Err(_) => return codec::encode_reject_apdu(invoke_id, codec::REJECT_INVALID_TAG),
+        };
+
+        let mut results = Vec::new();
+        for (object, properties) in specs {
+            let object = self.resolve(object);
+            let mut object_results = Vec::new();
+            for (property, array_index) in properties {
+                if matches!(property, codec::PROP_ALL | codec::PROP_REQUIRED | codec::PROP_OPTIONAL) {
+                    match self.property_ids(object, property) {
+                        Ok(ids) => {
+                            for id in ids {
+                                let reference = PropertyReference { object, property: id, array_index: None };
+                                object_results.push(PropertyResult {
+                                    property: id,
+                                    array_index: None,
+                                    value: self.read_property(&reference),
+                                });
+                            }
+                        }
+                        Err(error) => object_results.push(PropertyResult { property, array_index, value: Err(error) }),
+                    }
+                } else {
+                    let reference = PropertyReference { object, property, array_index };
+                    object_results.push(PropertyResult {
+                        property,
+                        array_index,
+                        value: self.read_property(&reference),
+                    });
+                }
+            }
+            results.push((object, object_results));
+        }
+
+        complex_ack(invoke_id, service_choice, codec::encode_read_property_multiple_ack(&results))
+    }
+}
+
+fn complex_ack(invoke_id: u8, service_choice: u8, service_data: Vec<u8>) -> Vec<u8> {
+    Apdu::ComplexAck {
+        invoke_id,
+        service_choice,
+        service_data,
+        segmented: false,
+        more_follows: false,
+        sequence_number: None,
+        proposed_window_size: None,
+    }
+    .encode()
+}
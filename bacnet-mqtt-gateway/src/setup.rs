@@ -0,0 +1,53 @@
+//! First-run setup: the network interfaces the web UI's setup wizard offers,
+//! and whether the wizard still has to run
+
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// An IPv4 interface the gateway can bind BACnet/IP to
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub address: IpAddr,
+    /// Network of the interface, e.g. `10.20.0.0/24`, for a directed Who-Is
+    pub subnet: String,
+}
+
+/// IPv4 interfaces other than loopback; BACnet/IP does not run over IPv6
+pub fn interfaces() -> Result<Vec<NetworkInterface>, String> {
+    let interfaces = if_addrs::get_if_addrs().map_err(|e| format!("cannot list network interfaces: {}", e))?;
+    Ok(interfaces
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .filter_map(|interface| match interface.addr {
+            if_addrs::IfAddr::V4(addr) => {
+                let network = Ipv4Addr::from(u32::from(addr.ip) & u32::from(addr.netmask));
+                let prefix = u32::from(addr.netmask).count_ones();
+                Some(NetworkInterface { name: interface.name, address: IpAddr::V4(addr.ip), subnet: format!("{}/{}", network, prefix) })
+            }
+            if_addrs::IfAddr::V6(_) => None,
+        })
+        .collect())
+}
+
+/// Whether the gateway started without a configuration file and nobody has
+/// finished or dismissed the setup wizard since
+#[derive(Debug)]
+pub struct Setup {
+    pending: AtomicBool,
+}
+
+impl Setup {
+    pub fn new(first_run: bool) -> Self {
+        Self { pending: AtomicBool::new(first_run) }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self) {
+        self.pending.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::mqtt::MqttService;
+use crate::point::ObjectRef;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Values of one device's poll cycle collected so far
+#[derive(Default)]
+struct Cycle {
+    cycle: u64,
+    /// Reads the poll issued, unknown until the poll loop has sent them all
+    expected: Option<usize>,
+    answered: usize,
+    values: BTreeMap<String, serde_json::Value>,
+}
+
+impl Cycle {
+    fn is_complete(&self) -> bool {
+        self.expected.is_some_and(|expected| self.answered >= expected)
+    }
+}
+
+/// Coalesces the values read in one poll cycle into a single document per
+/// device on `<base>/<device>/snapshot`, published once every read of the
+/// cycle is answered or failed, or when the next cycle starts
+pub struct SnapshotBatcher {
+    mqtt: MqttService,
+    cycles: Mutex<HashMap<u32, Cycle>>,
+}
+
+impl SnapshotBatcher {
+    pub fn new(mqtt: MqttService) -> Self {
+        Self { mqtt, cycles: Mutex::new(HashMap::new()) }
+    }
+
+    /// Applies `update` to a device's current cycle, returning the cycles
+    /// ready to publish: a superseded one and the updated one once complete
+    fn update(&self, device_id: u32, cycle: u64, update: impl FnOnce(&mut Cycle)) -> Vec<Cycle> {
+        let mut cycles = self.cycles.lock().unwrap_or_else(|e| e.into_inner());
+        let mut ready = Vec::new();
+        let current = cycles.entry(device_id).or_default();
+        if current.cycle != cycle {
+            let previous = std::mem::replace(current, Cycle { cycle, ..Default::default() });
+            if !previous.values.is_empty() {
+                ready.push(previous);
+            }
+        }
+        update(current);
+        if current.is_complete() {
+            ready.extend(cycles.remove(&device_id));
+        }
+        ready
+    }
+
+    async fn publish(&self, device_id: u32, ready: Vec<Cycle>) {
+        for cycle in ready {
+            let complete = cycle.is_complete();
+            self.mqtt.publish_snapshot(device_id, cycle.cycle, complete, &cycle.values).await;
+        }
+    }
+
+    /// Records how many reads a device's poll cycle issued
+    pub async fn expect(&self, device_id: u32, cycle: u64, reads: usize) {
+        let ready = self.update(device_id, cycle, |current| current.expected = Some(reads));
+        self.publish(device_id, ready).await;
+    }
+
+    /// Records the answer to one read of a poll cycle, `None` if it failed
+    pub async fn record(&self, device_id: u32, cycle: u64, answer: Option<(ObjectRef, serde_json::Value)>) {
+        let ready = self.update(device_id, cycle, |current| {
+            current.answered += 1;
+            if let Some((object, value)) = answer {
+                current.values.insert(object.to_string(), value);
+            }
+        });
+        self.publish(device_id, ready).await;
+    }
+}
@@ -0,0 +1,378 @@
+//! Sparkplug B edge node output: the gateway is the edge node (NBIRTH/NDEATH)
+//! and every discovered BACnet device one of its devices (DBIRTH/DDATA/DDEATH),
+//! with the protobuf payloads encoded by hand like the BACnet codec
+
+use crate::codec::BacnetValue;
+use crate::config::SparkplugConfig;
+use crate::mqtt::MqttService;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+const NAMESPACE: &str = "spBv1.0";
+const BD_SEQ_METRIC: &str = "bdSeq";
+const REBIRTH_METRIC: &str = "Node Control/Rebirth";
+
+// Sparkplug B metric datatypes
+const DATATYPE_INT32: u32 = 3;
+const DATATYPE_UINT32: u32 = 7;
+const DATATYPE_UINT64: u32 = 8;
+const DATATYPE_FLOAT: u32 = 9;
+const DATATYPE_DOUBLE: u32 = 10;
+const DATATYPE_BOOLEAN: u32 = 11;
+const DATATYPE_STRING: u32 = 12;
+
+// Protobuf wire types
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LEN: u32 = 2;
+const WIRE_FIXED32: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+enum MetricValue {
+    /// Sent as `int_value`, signed types two's complement
+    Int(u32),
+    Long(u64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Metric {
+    name: String,
+    datatype: u32,
+    value: MetricValue,
+}
+
+impl Metric {
+    fn from_bacnet(name: String, value: &BacnetValue) -> Self {
+        let (datatype, value) = match value {
+            BacnetValue::Boolean(v) => (DATATYPE_BOOLEAN, MetricValue::Boolean(*v)),
+            BacnetValue::Unsigned(v) | BacnetValue::Enumerated(v) => (DATATYPE_UINT32, MetricValue::Int(*v)),
+            BacnetValue::Signed(v) => (DATATYPE_INT32, MetricValue::Int(*v as u32)),
+            BacnetValue::Real(v) => (DATATYPE_FLOAT, MetricValue::Float(*v)),
+            BacnetValue::Double(v) => (DATATYPE_DOUBLE, MetricValue::Double(*v)),
+            other => (DATATYPE_STRING, MetricValue::String(other.to_string())),
+        };
+        Self { name, datatype, value }
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_key(out: &mut Vec<u8>, field: u32, wire_type: u32) {
+    put_varint(out, u64::from(field << 3 | wire_type));
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_key(out, field, WIRE_LEN);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn encode_metric(metric: &Metric, timestamp: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_bytes(&mut out, 1, metric.name.as_bytes());
+    put_key(&mut out, 3, WIRE_VARINT);
+    put_varint(&mut out, timestamp);
+    put_key(&mut out, 4, WIRE_VARINT);
+    put_varint(&mut out, u64::from(metric.datatype));
+    match &metric.value {
+        MetricValue::Int(v) => {
+            put_key(&mut out, 10, WIRE_VARINT);
+            put_varint(&mut out, u64::from(*v));
+        }
+        MetricValue::Long(v) => {
+            put_key(&mut out, 11, WIRE_VARINT);
+            put_varint(&mut out, *v);
+        }
+        MetricValue::Float(v) => {
+            put_key(&mut out, 12, WIRE_FIXED32);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        MetricValue::Double(v) => {
+            put_key(&mut out, 13, WIRE_FIXED64);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        MetricValue::Boolean(v) => {
+            put_key(&mut out, 14, WIRE_VARINT);
+            put_varint(&mut out, u64::from(*v));
+        }
+        MetricValue::String(v) => put_bytes(&mut out, 15, v.as_bytes()),
+    }
+    out
+}
+
+/// Encodes a Sparkplug B payload; NDEATH carries no sequence number
+fn encode_payload<'a>(metrics: impl IntoIterator<Item = &'a Metric>, seq: Option<u64>) -> Vec<u8> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let mut out = Vec::new();
+    put_key(&mut out, 1, WIRE_VARINT);
+    put_varint(&mut out, timestamp);
+    for metric in metrics {
+        put_bytes(&mut out, 2, &encode_metric(metric, timestamp));
+    }
+    if let Some(seq) = seq {
+        put_key(&mut out, 3, WIRE_VARINT);
+        put_varint(&mut out, seq);
+    }
+    out
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Visits the fields of a protobuf message as (field, wire type, varint value, bytes)
+fn for_each_field(data: &[u8], mut visit: impl FnMut(u32, u32, u64, &[u8])) -> Option<()> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let (field, wire_type) = ((key >> 3) as u32, (key & 7) as u32);
+        match wire_type {
+            WIRE_VARINT => {
+                let value = read_varint(data, &mut pos)?;
+                visit(field, wire_type, value, &[]);
+            }
+            WIRE_LEN => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let bytes = data.get(pos..pos.checked_add(len)?)?;
+                pos += len;
+                visit(field, wire_type, 0, bytes);
+            }
+            WIRE_FIXED32 => pos += 4,
+            WIRE_FIXED64 => pos += 8,
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+/// True if an NCMD payload sets `Node Control/Rebirth` to true
+pub fn is_rebirth_request(payload: &[u8]) -> bool {
+    let mut rebirth = false;
+    for_each_field(payload, |field, _, _, metric| {
+        if field != 2 {
+            return;
+        }
+        let (mut name, mut value) = (None, false);
+        for_each_field(metric, |field, wire_type, varint, bytes| match (field, wire_type) {
+            (1, WIRE_LEN) => name = Some(bytes.to_vec()),
+            (14, WIRE_VARINT) => value = varint != 0,
+            _ => {}
+        });
+        rebirth |= value && name.as_deref() == Some(REBIRTH_METRIC.as_bytes());
+    });
+    rebirth
+}
+
+/// Birth/death sequence number, fixed for the process since the MQTT client
+/// reconnects with the will it was created with
+pub fn birth_death_sequence() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() % 256).unwrap_or(0)
+}
+
+fn bd_seq_metric(bd_seq: u64) -> Metric {
+    Metric { name: BD_SEQ_METRIC.to_string(), datatype: DATATYPE_UINT64, value: MetricValue::Long(bd_seq) }
+}
+
+fn topic(config: &SparkplugConfig, message_type: &str, device_id: Option<u32>) -> String {
+    match device_id {
+        Some(device_id) => format!("{}/{}/{}/{}/{}", NAMESPACE, config.group_id, message_type, config.edge_node_id, device_id),
+        None => format!("{}/{}/{}/{}", NAMESPACE, config.group_id, message_type, config.edge_node_id),
+    }
+}
+
+/// Topic and payload of the NDEATH the broker publishes as the gateway's will
+pub fn node_death(config: &SparkplugConfig, bd_seq: u64) -> (String, Vec<u8>) {
+    (topic(config, "NDEATH", None), encode_payload([&bd_seq_metric(bd_seq)], None))
+}
+
+#[derive(Default)]
+struct DeviceMetrics {
+    born: bool,
+    metrics: BTreeMap<String, Metric>,
+}
+
+#[derive(Default)]
+struct NodeState {
+    /// Sequence number of the next message, 0-255
+    seq: u64,
+    devices: BTreeMap<u32, DeviceMetrics>,
+}
+
+impl NodeState {
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.seq;
+        self.seq = (seq + 1) % 256;
+        seq
+    }
+
+    /// Records a metric, returning the message announcing it with its sequence
+    /// number: a DBIRTH with every metric while the device is unborn or the
+    /// metric is new, since DDATA may only carry metrics declared in the birth
+    fn record(&mut self, device_id: u32, metric: Metric) -> Option<(&'static str, Vec<u8>)> {
+        let device = self.devices.entry(device_id).or_default();
+        let known = device.metrics.get(&metric.name);
+        // Report by exception: unchanged values are not republished
+        if device.born && known == Some(&metric) {
+            return None;
+        }
+        let birth = !device.born || known.is_none();
+        device.born = true;
+        device.metrics.insert(metric.name.clone(), metric.clone());
+        let metrics: Vec<Metric> = if birth { device.metrics.values().cloned().collect() } else { vec![metric] };
+        let seq = self.next_seq();
+        let message_type = if birth { "DBIRTH" } else { "DDATA" };
+        Some((message_type, encode_payload(&metrics, Some(seq))))
+    }
+}
+
+/// The gateway as a Sparkplug B edge node
+pub struct SparkplugNode {
+    config: SparkplugConfig,
+    mqtt: MqttService,
+    bd_seq: u64,
+    /// Held while publishing so sequence numbers reach the broker in order
+    state: Mutex<NodeState>,
+}
+
+impl SparkplugNode {
+    pub fn new(config: SparkplugConfig, mqtt: MqttService) -> Self {
+        let bd_seq = mqtt.bd_seq();
+        Self { config, mqtt, bd_seq, state: Mutex::new(NodeState::default()) }
+    }
+
+    /// NCMD topic the host application sends rebirth requests to
+    pub fn command_topic(&self) -> String {
+        topic(&self.config, "NCMD", None)
+    }
+
+    async fn publish(&self, message_type: &str, device_id: Option<u32>, payload: Vec<u8>) {
+        let topic = topic(&self.config, message_type, device_id);
+        debug!("Publishing Sparkplug {} ({} bytes)", topic, payload.len());
+        self.mqtt.publish_bytes(&topic, payload).await;
+    }
+
+    /// Publishes NBIRTH and a DBIRTH for every device with known metrics,
+    /// restarting the sequence numbers
+    pub async fn rebirth(&self) {
+        let mut state = self.state.lock().await;
+        state.seq = 0;
+        let node_metrics = [
+            bd_seq_metric(self.bd_seq),
+            Metric { name: REBIRTH_METRIC.to_string(), datatype: DATATYPE_BOOLEAN, value: MetricValue::Boolean(false) },
+        ];
+        let seq = state.next_seq();
+        self.publish("NBIRTH", None, encode_payload(&node_metrics, Some(seq))).await;
+        let device_ids: Vec<u32> = state.devices.keys().copied().collect();
+        for device_id in device_ids {
+            let seq = state.next_seq();
+            let Some(device) = state.devices.get_mut(&device_id) else {
+                continue;
+            };
+            device.born = true;
+            let payload = encode_payload(device.metrics.values(), Some(seq));
+            self.publish("DBIRTH", Some(device_id), payload).await;
+        }
+        info!("Published Sparkplug birth certificates for {} devices", state.devices.len());
+    }
+
+    /// Publishes a changed metric of a device as DDATA, or a DBIRTH with all
+    /// of its metrics if the device has not been born yet or the metric is new
+    pub async fn update(&self, device_id: u32, name: String, value: &BacnetValue) {
+        let mut state = self.state.lock().await;
+        if let Some((message_type, payload)) = state.record(device_id, Metric::from_bacnet(name, value)) {
+            self.publish(message_type, Some(device_id), payload).await;
+        }
+    }
+
+    /// Publishes DDEATH for a device that stopped answering; its next value births it again
+    pub async fn device_offline(&self, device_id: u32) {
+        let mut state = self.state.lock().await;
+        if !state.devices.get(&device_id).is_some_and(|device| device.born) {
+            return;
+        }
+        let seq = state.next_seq();
+        if let Some(device) = state.devices.get_mut(&device_id) {
+            device.born = false;
+        }
+        self.publish("DDEATH", Some(device_id), encode_payload([], Some(seq))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names of the metrics of an encoded payload
+    fn metric_names(payload: &[u8]) -> Vec<String> {
+        let mut names = Vec::new();
+        for_each_field(payload, |field, _, _, metric| {
+            if field == 2 {
+                for_each_field(metric, |field, wire_type, _, bytes| {
+                    if (field, wire_type) == (1, WIRE_LEN) {
+                        names.push(String::from_utf8_lossy(bytes).into_owned());
+                    }
+                });
+            }
+        })
+        .unwrap();
+        names
+    }
+
+    #[test]
+    fn every_data_metric_is_declared_in_the_preceding_birth() {
+        let mut state = NodeState::default();
+        let updates = [
+            ("AI:1", BacnetValue::Real(21.5)),
+            ("AI:1", BacnetValue::Real(21.5)),
+            ("AI:1", BacnetValue::Real(22.0)),
+            ("BO:2", BacnetValue::Enumerated(1)),
+            ("AI:1", BacnetValue::Real(22.5)),
+            ("BO:2", BacnetValue::Enumerated(0)),
+        ];
+        let mut messages = Vec::new();
+        for (name, value) in &updates {
+            messages.extend(state.record(7, Metric::from_bacnet(name.to_string(), value)));
+        }
+        let types: Vec<&str> = messages.iter().map(|(message_type, _)| *message_type).collect();
+        assert_eq!(types, ["DBIRTH", "DDATA", "DBIRTH", "DDATA", "DDATA"]);
+
+        let mut declared = Vec::new();
+        for (message_type, payload) in &messages {
+            let names = metric_names(payload);
+            if *message_type == "DBIRTH" {
+                declared = names;
+            } else {
+                assert!(names.iter().all(|name| declared.contains(name)), "{:?} not in {:?}", names, declared);
+            }
+        }
+        assert_eq!(declared, ["AI:1", "BO:2"]);
+    }
+
+    #[test]
+    fn rebirth_request_is_recognised() {
+        let rebirth = Metric { name: REBIRTH_METRIC.to_string(), datatype: DATATYPE_BOOLEAN, value: MetricValue::Boolean(true) };
+        assert!(is_rebirth_request(&encode_payload([&rebirth], Some(0))));
+        assert!(!is_rebirth_request(&encode_payload([&bd_seq_metric(3)], Some(0))));
+    }
+}
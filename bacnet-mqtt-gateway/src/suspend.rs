@@ -0,0 +1,168 @@
+use crate::config::PollingConfig;
+use crate::mqtt::MqttService;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::RwLock;
+use tracing::{info, warn};
+
+/// What a suspend/resume command applies to: `gateway`, `group:<name>` or `device:<id>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    Gateway,
+    Group(String),
+    Device(u32),
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Gateway => write!(f, "gateway"),
+            Scope::Group(name) => write!(f, "group:{}", name),
+            Scope::Device(id) => write!(f, "device:{}", id),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("gateway") {
+            return Ok(Scope::Gateway);
+        }
+        match s.split_once(':') {
+            Some(("group", name)) if !name.is_empty() => Ok(Scope::Group(name.to_string())),
+            Some(("device", id)) => id
+                .parse()
+                .map(Scope::Device)
+                .map_err(|_| format!("invalid device instance '{}'", id)),
+            _ => Err(format!("invalid scope '{}', expected gateway, group:<name> or device:<id>", s)),
+        }
+    }
+}
+
+/// Suspended scopes, persisted so a restart does not resume polling by surprise
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Suspensions {
+    #[serde(default)]
+    pub gateway: bool,
+    #[serde(default)]
+    pub groups: BTreeSet<String>,
+    #[serde(default)]
+    pub devices: BTreeSet<u32>,
+}
+
+/// Tracks which devices must not be polled, e.g. during controller firmware upgrades
+pub struct SuspensionManager {
+    state: RwLock<Suspensions>,
+    groups: HashMap<String, Vec<u32>>,
+    path: PathBuf,
+}
+
+impl SuspensionManager {
+    /// Restores the suspensions saved in the state file, if any
+    pub fn load(config: &PollingConfig) -> Self {
+        let state = match std::fs::read_to_string(&config.state_file) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable suspension state {}: {}", config.state_file.display(), e);
+                Suspensions::default()
+            }),
+            Err(_) => Suspensions::default(),
+        };
+        if state.gateway || !state.groups.is_empty() || !state.devices.is_empty() {
+            info!("Restored poll suspensions: {:?}", state);
+        }
+        Self {
+            state: RwLock::new(state),
+            groups: config.groups.clone(),
+            path: config.state_file.clone(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Suspensions {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn is_suspended(&self, device_id: u32) -> bool {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        state.gateway
+            || state.devices.contains(&device_id)
+            || state
+                .groups
+                .iter()
+                .any(|group| self.groups.get(group).is_some_and(|members| members.contains(&device_id)))
+    }
+
+    /// Suspends or resumes polling for a scope and publishes the resulting
+    /// availability of every affected discovered device
+    pub async fn set(
+        &self,
+        scope: &Scope,
+        suspended: bool,
+        mqtt: &MqttService,
+        devices: &HashMap<u32, SocketAddr>,
+    ) -> Result<Suspensions, String> {
+        let snapshot = {
+            let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+            match scope {
+                Scope::Gateway => state.gateway = suspended,
+                Scope::Group(name) => {
+                    if !self.groups.contains_key(name) {
+                        return Err(format!("unknown device group '{}'", name));
+                    }
+                    if suspended {
+                        state.groups.insert(name.clone());
+                    } else {
+                        state.groups.remove(name);
+                    }
+                }
+                Scope::Device(id) => {
+                    if suspended {
+                        state.devices.insert(*id);
+                    } else {
+                        state.devices.remove(id);
+                    }
+                }
+            }
+            state.clone()
+        };
+        info!("Polling {} for {}", if suspended { "suspended" } else { "resumed" }, scope);
+
+        match serde_json::to_string_pretty(&snapshot) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    warn!("Failed to persist suspension state to {}: {}", self.path.display(), e);
+                }
+            }
+            Err(e) => warn!("Failed to serialize suspension state: {}", e),
+        }
+
+        for device_id in devices.keys() {
+            mqtt.publish_availability(*device_id, !self.is_suspended(*device_id)).await;
+        }
+        mqtt.publish_suspensions(&snapshot).await;
+        Ok(snapshot)
+    }
+
+    /// Suspends and resumes scopes until the suspensions match `desired`
+    pub async fn apply(&self, desired: &Suspensions, mqtt: &MqttService, devices: &HashMap<u32, SocketAddr>) -> Result<Suspensions, String> {
+        let current = self.snapshot();
+        let mut changes = Vec::new();
+        if desired.gateway != current.gateway {
+            changes.push((Scope::Gateway, desired.gateway));
+        }
+        changes.extend(desired.groups.difference(&current.groups).map(|name| (Scope::Group(name.clone()), true)));
+        changes.extend(current.groups.difference(&desired.groups).map(|name| (Scope::Group(name.clone()), false)));
+        changes.extend(desired.devices.difference(&current.devices).map(|id| (Scope::Device(*id), true)));
+        changes.extend(current.devices.difference(&desired.devices).map(|id| (Scope::Device(*id), false)));
+        for (scope, suspended) in &changes {
+            self.set(scope, *suspended, mqtt, devices).await?;
+        }
+        Ok(self.snapshot())
+    }
+}
@@ -0,0 +1,304 @@
+//! User-defined state and command topic patterns such as
+//! `{base}/{site}/{device_name}/{object_type}{instance}/state`
+
+use crate::config::NamePolicy;
+use crate::point::ObjectRef;
+
+/// Placeholders resolved per device and object; `{base}`, `{prefix}` and
+/// `{site}` are substituted once when the template is parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    DeviceId,
+    DeviceName,
+    Object,
+    ObjectName,
+    ObjectType,
+    Instance,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "device_id" => Some(Self::DeviceId),
+            "device_name" => Some(Self::DeviceName),
+            "object" => Some(Self::Object),
+            "object_name" => Some(Self::ObjectName),
+            "object_type" => Some(Self::ObjectType),
+            "instance" => Some(Self::Instance),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+/// Replaces the characters MQTT reserves for levels and wildcards
+pub fn sanitize(value: &str) -> String {
+    value.replace(['/', '+', '#'], "_")
+}
+
+/// Makes a BACnet name usable as one topic level under a naming policy,
+/// collapsing runs of replacements and trimming them from the ends
+pub fn sanitize_name(name: &str, policy: NamePolicy, replacement: char) -> String {
+    let keep = |c: char| match policy {
+        NamePolicy::Minimal => !matches!(c, '/' | '+' | '#') && !c.is_control(),
+        NamePolicy::Ascii => c.is_ascii_graphic() && !matches!(c, '/' | '+' | '#'),
+        NamePolicy::Slug => c.is_ascii_alphanumeric() || matches!(c, '-' | '_'),
+    };
+    let mut sanitized = String::new();
+    for c in name.trim().chars() {
+        let c = if policy == NamePolicy::Slug { c.to_ascii_lowercase() } else { c };
+        if keep(c) {
+            sanitized.push(c);
+        } else if !sanitized.is_empty() && !sanitized.ends_with(replacement) {
+            sanitized.push(replacement);
+        }
+    }
+    sanitized.trim_end_matches(replacement).to_string()
+}
+
+/// True if a topic matches a subscription filter with `+` and `#` wildcards
+pub fn matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for pattern in filter.split('/') {
+        match (pattern, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (pattern, Some(level)) if pattern == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// A parsed topic template, split into levels
+#[derive(Debug, Clone)]
+pub struct TopicTemplate {
+    levels: Vec<Vec<Part>>,
+}
+
+impl TopicTemplate {
+    /// Parses a template, substituting the gateway-wide variables
+    pub fn parse(template: &str, base: &str, prefix: &str, site: &str) -> Result<Self, String> {
+        let template = template.replace("{base}", base).replace("{prefix}", prefix).replace("{site}", site);
+        let levels = template
+            .split('/')
+            .map(|level| {
+                let mut parts = Vec::new();
+                let mut rest = level;
+                while let Some(start) = rest.find('{') {
+                    if start > 0 {
+                        parts.push(Part::Literal(rest[..start].to_string()));
+                    }
+                    let end = rest[start..]
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed '{{' in topic template '{}'", template))?;
+                    let name = &rest[start + 1..start + end];
+                    let variable = Variable::from_name(name)
+                        .ok_or_else(|| format!("unknown variable '{{{}}}' in topic template '{}'", name, template))?;
+                    parts.push(Part::Variable(variable));
+                    rest = &rest[start + end + 1..];
+                }
+                if !rest.is_empty() {
+                    parts.push(Part::Literal(rest.to_string()));
+                }
+                Ok(parts)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if template.contains(['+', '#']) {
+            return Err(format!("topic template '{}' contains a wildcard", template));
+        }
+        Ok(Self { levels })
+    }
+
+    fn uses(&self, variable: Variable) -> bool {
+        self.levels.iter().flatten().any(|part| *part == Part::Variable(variable))
+    }
+
+    pub fn uses_device_name(&self) -> bool {
+        self.uses(Variable::DeviceName)
+    }
+
+    /// Checks that topics built from the template can be traced back to the
+    /// device and object, as command topics must
+    pub fn check_reversible(&self) -> Result<(), String> {
+        if !self.uses(Variable::DeviceId) && !self.uses(Variable::DeviceName) {
+            return Err("command topic template needs {device_id} or {device_name}".to_string());
+        }
+        if !self.uses(Variable::Object) && !(self.uses(Variable::ObjectType) && self.uses(Variable::Instance)) {
+            return Err("command topic template needs {object} or {object_type} and {instance}".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, device_id: u32, device_name: &str, object: ObjectRef, object_name: &str) -> String {
+        let levels: Vec<String> = self
+            .levels
+            .iter()
+            .map(|parts| {
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Literal(text) => text.clone(),
+                        Part::Variable(Variable::DeviceId) => device_id.to_string(),
+                        Part::Variable(Variable::DeviceName) => sanitize(device_name),
+                        Part::Variable(Variable::Object) => object.to_string(),
+                        Part::Variable(Variable::ObjectName) => sanitize(object_name),
+                        Part::Variable(Variable::ObjectType) => object
+                            .type_abbreviation()
+                            .map_or_else(|| object.object_type.to_string(), str::to_string),
+                        Part::Variable(Variable::Instance) => object.instance.to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+        levels.join("/")
+    }
+
+    /// Subscription filter matching every topic the template can produce
+    pub fn filter(&self) -> String {
+        let levels: Vec<String> = self
+            .levels
+            .iter()
+            .map(|parts| match parts.as_slice() {
+                [Part::Literal(text)] => text.clone(),
+                [] => String::new(),
+                _ => "+".to_string(),
+            })
+            .collect();
+        levels.join("/")
+    }
+
+    /// Device and object a topic was rendered for, resolving device names
+    /// through `device_by_name`
+    pub fn capture(&self, topic: &str, device_by_name: impl Fn(&str) -> Option<u32>) -> Option<(u32, ObjectRef)> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        if levels.len() != self.levels.len() {
+            return None;
+        }
+        let valid = |variable: Variable, value: &str| match variable {
+            Variable::DeviceId | Variable::Instance => value.parse::<u32>().is_ok(),
+            Variable::DeviceName => device_by_name(value).is_some(),
+            Variable::Object => value.parse::<ObjectRef>().is_ok(),
+            // Only identifies the object together with `{object}` or `{object_type}{instance}`
+            Variable::ObjectName => !value.is_empty(),
+            Variable::ObjectType => format!("{}:0", value).parse::<ObjectRef>().is_ok(),
+        };
+        let mut captured = Vec::new();
+        for (parts, level) in self.levels.iter().zip(levels) {
+            if !capture_level(parts, level, &valid, &mut captured) {
+                return None;
+            }
+        }
+
+        let value = |variable: Variable| captured.iter().find(|(v, _)| *v == variable).map(|(_, value)| *value);
+        let device_id = match value(Variable::DeviceId) {
+            Some(id) => id.parse().ok()?,
+            None => device_by_name(value(Variable::DeviceName)?)?,
+        };
+        let object = match value(Variable::Object) {
+            Some(object) => object.parse().ok()?,
+            None => format!("{}:{}", value(Variable::ObjectType)?, value(Variable::Instance)?).parse().ok()?,
+        };
+        Some((device_id, object))
+    }
+}
+
+/// Matches one topic level against its template parts, trying every split
+/// of adjacent variables such as `{object_type}{instance}`
+fn capture_level<'a>(
+    parts: &[Part],
+    text: &'a str,
+    valid: &impl Fn(Variable, &str) -> bool,
+    captured: &mut Vec<(Variable, &'a str)>,
+) -> bool {
+    match parts.split_first() {
+        None => text.is_empty(),
+        Some((Part::Literal(literal), rest)) => text
+            .strip_prefix(literal.as_str())
+            .is_some_and(|tail| capture_level(rest, tail, valid, captured)),
+        Some((Part::Variable(variable), rest)) => {
+            for end in (1..=text.len()).filter(|end| text.is_char_boundary(*end)) {
+                let (value, tail) = text.split_at(end);
+                if !valid(*variable, value) {
+                    continue;
+                }
+                captured.push((*variable, value));
+                if capture_level(rest, tail, valid, captured) {
+                    return true;
+                }
+                captured.pop();
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(template: &str) -> TopicTemplate {
+        TopicTemplate::parse(template, "bacnet", "homeassistant", "plant").unwrap()
+    }
+
+    fn device_by_name(name: &str) -> Option<u32> {
+        match name {
+            "ahu-1" => Some(1200),
+            "Boiler_North" => Some(1300),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn rendered_topics_are_captured_back() {
+        let objects = [ObjectRef::new(0, 1), ObjectRef::new(2, 12), ObjectRef::new(5, 3), ObjectRef::new(19, 105)];
+        for pattern in [
+            "{base}/{device_id}/{object}/set",
+            "{base}/{site}/{device_name}/{object_type}{instance}/set",
+            "{base}/{device_name}/{object_name}/{object_type}-{instance}/set",
+            "{prefix}/dev{device_id}/{object_type}{instance}",
+        ] {
+            let template = template(pattern);
+            template.check_reversible().unwrap();
+            for (device_id, device_name) in [(1200, "ahu-1"), (1300, "Boiler/North")] {
+                for object in objects {
+                    let topic = template.render(device_id, device_name, object, "Supply/Temp");
+                    assert_eq!(template.capture(&topic, device_by_name), Some((device_id, object)), "{}", topic);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn foreign_topics_are_not_captured() {
+        let template = template("{base}/{site}/{device_name}/{object_type}{instance}/set");
+        assert_eq!(template.capture("bacnet/plant/ahu-1/AV12/set", device_by_name), Some((1200, ObjectRef::new(2, 12))));
+        assert_eq!(template.capture("bacnet/plant/ahu-9/AV12/set", device_by_name), None, "unknown device");
+        assert_eq!(template.capture("bacnet/plant/ahu-1/XY12/set", device_by_name), None, "unknown object type");
+        assert_eq!(template.capture("bacnet/plant/ahu-1/AV/set", device_by_name), None, "missing instance");
+        assert_eq!(template.capture("bacnet/plant/ahu-1/AV12/state", device_by_name), None, "other literal");
+        assert_eq!(template.capture("bacnet/plant/ahu-1/AV12/set/extra", device_by_name), None, "extra level");
+    }
+
+    #[test]
+    fn filter_matches_every_rendered_topic() {
+        let template = template("{base}/{site}/{device_name}/{object_type}{instance}/set");
+        let filter = template.filter();
+        assert_eq!(filter, "bacnet/plant/+/+/set");
+        assert!(matches(&filter, &template.render(1200, "ahu-1", ObjectRef::new(2, 12), "Setpoint")));
+        assert!(!matches(&filter, "bacnet/plant/ahu-1/AV12/state"));
+    }
+
+    #[test]
+    fn templates_without_device_or_object_are_not_reversible() {
+        assert!(template("{base}/{object}/set").check_reversible().is_err());
+        assert!(template("{base}/{device_id}/{object_type}/set").check_reversible().is_err());
+        assert!(TopicTemplate::parse("{base}/{unknown}", "bacnet", "homeassistant", "plant").is_err());
+        assert!(TopicTemplate::parse("{base}/+/{object}", "bacnet", "homeassistant", "plant").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! User scripts in Rhai transforming point values and state payloads before
+//! they are published, e.g. differential pressure to flow:
+//!
+//! ```text
+//! value * 0.8 * sqrt(points["AI:7"])
+//! ```
+
+use crate::config::TransformConfig;
+use crate::point::ObjectRef;
+use crate::topic;
+use rhai::{AST, Dynamic, Engine, Scope};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Operations a script may take per run, stopping runaway loops
+const MAX_OPERATIONS: u64 = 100_000;
+
+enum Target {
+    /// Values of matching points; `None` matches every device or object
+    Point { device: Option<u32>, object: Option<ObjectRef> },
+    /// State payloads on topics matching an MQTT filter
+    Topic(String),
+}
+
+struct Transform {
+    target: Target,
+    script: AST,
+    source: String,
+}
+
+/// The configured transforms, compiled once at startup
+pub struct Transforms {
+    engine: Engine,
+    transforms: Vec<Transform>,
+    /// Latest value of every point, seen by point scripts as `points`
+    latest: Mutex<HashMap<u32, BTreeMap<String, serde_json::Value>>>,
+}
+
+impl Transforms {
+    pub fn new(configs: &[TransformConfig]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let mut transforms = Vec::new();
+        for config in configs {
+            let (code, source) = match (&config.script, &config.script_file) {
+                (Some(code), None) => (code.clone(), "inline script".to_string()),
+                (None, Some(path)) => (std::fs::read_to_string(path)?, path.display().to_string()),
+                _ => return Err("a transform needs either script or script_file".into()),
+            };
+            let target = match (&config.topic, config.device, config.object) {
+                (Some(filter), None, None) => Target::Topic(filter.clone()),
+                (None, device, object) => Target::Point { device, object },
+                _ => return Err(format!("transform {} applies to a topic or to points, not both", source).into()),
+            };
+            let script = engine.compile(&code).map_err(|e| format!("transform {}: {}", source, e))?;
+            transforms.push(Transform { target, script, source });
+        }
+        Ok(Self { engine, transforms, latest: Mutex::new(HashMap::new()) })
+    }
+
+    /// Runs a script, `None` if it returned `()` to drop the message
+    fn run(&self, transform: &Transform, mut scope: Scope) -> Result<Option<serde_json::Value>, String> {
+        let result: Dynamic = self.engine.eval_ast_with_scope(&mut scope, &transform.script).map_err(|e| e.to_string())?;
+        if result.is_unit() {
+            return Ok(None);
+        }
+        rhai::serde::from_dynamic(&result).map(Some).map_err(|e| e.to_string())
+    }
+
+    /// Transforms a point's state text; `None` drops it. Scripts see `value`,
+    /// `device`, `object` and `points`, the latest values of the device's points
+    pub fn point(&self, device_id: u32, object: ObjectRef, value: &str) -> Option<String> {
+        let json = crate::mqtt::state_json(value);
+        let points = {
+            let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+            let points = latest.entry(device_id).or_default();
+            points.insert(object.to_string(), json.clone());
+            points.clone()
+        };
+        let matching = self.transforms.iter().filter(|transform| match transform.target {
+            Target::Point { device, object: o } => device.is_none_or(|d| d == device_id) && o.is_none_or(|o| o == object),
+            Target::Topic(_) => false,
+        });
+        let mut current = json;
+        let mut transformed = false;
+        for transform in matching {
+            let mut scope = Scope::new();
+            scope.push("device", i64::from(device_id));
+            scope.push("object", object.to_string());
+            scope.push_dynamic("value", rhai::serde::to_dynamic(&current).unwrap_or_default());
+            scope.push_dynamic("points", rhai::serde::to_dynamic(&points).unwrap_or_default());
+            match self.run(transform, scope) {
+                Ok(Some(value)) => current = value,
+                Ok(None) => return None,
+                Err(e) => warn!("Transform {} of {} {} failed: {}", transform.source, device_id, object, e),
+            }
+            transformed = true;
+        }
+        Some(match current {
+            _ if !transformed => value.to_string(),
+            serde_json::Value::String(text) => text,
+            other => other.to_string(),
+        })
+    }
+
+    /// Transforms a state payload by topic; `None` drops it. Scripts see
+    /// `topic` and `payload`, parsed if it is JSON
+    pub fn payload(&self, topic_name: &str, payload: String) -> Option<String> {
+        let mut payload = payload;
+        for transform in &self.transforms {
+            let Target::Topic(filter) = &transform.target else {
+                continue;
+            };
+            if !topic::matches(filter, topic_name) {
+                continue;
+            }
+            let json = serde_json::from_str(&payload).unwrap_or_else(|_| serde_json::Value::String(payload.clone()));
+            let mut scope = Scope::new();
+            scope.push("topic", topic_name.to_string());
+            scope.push_dynamic("payload", rhai::serde::to_dynamic(&json).unwrap_or_default());
+            match self.run(transform, scope) {
+                Ok(Some(serde_json::Value::String(text))) => payload = text,
+                Ok(Some(other)) => payload = other.to_string(),
+                Ok(None) => return None,
+                Err(e) => warn!("Transform {} of {} failed: {}", transform.source, topic_name, e),
+            }
+        }
+        Some(payload)
+    }
+}
@@ -0,0 +1,110 @@
+//! Translation of the BACnet engineering-units enumeration into Home Assistant
+//! `unit_of_measurement` and `device_class` values.
+
+/// BACnet engineering units, Home Assistant unit and the device class the unit
+/// implies, if Home Assistant accepts the unit for that class
+const UNITS: &[(u32, &str, Option<&str>)] = &[
+    // Electrical
+    (2, "mA", Some("current")),
+    (3, "A", Some("current")),
+    (4, "Ω", None),
+    (5, "V", Some("voltage")),
+    (6, "kV", Some("voltage")),
+    (124, "mV", Some("voltage")),
+    (8, "VA", Some("apparent_power")),
+    (9, "kVA", Some("apparent_power")),
+    (11, "var", Some("reactive_power")),
+    (15, "", Some("power_factor")),
+    (27, "Hz", Some("frequency")),
+    (129, "kHz", Some("frequency")),
+    (130, "MHz", Some("frequency")),
+    // Energy and power
+    (16, "J", None),
+    (17, "kJ", None),
+    (126, "MJ", Some("energy")),
+    (18, "Wh", Some("energy")),
+    (19, "kWh", Some("energy")),
+    (146, "MWh", Some("energy")),
+    (132, "mW", Some("power")),
+    (47, "W", Some("power")),
+    (48, "kW", Some("power")),
+    (49, "MW", Some("power")),
+    (50, "BTU/h", None),
+    // Humidity and light
+    (29, "%", Some("humidity")),
+    (37, "lx", Some("illuminance")),
+    // Distance and mass
+    (30, "mm", Some("distance")),
+    (118, "cm", Some("distance")),
+    (31, "m", Some("distance")),
+    (32, "in", Some("distance")),
+    (33, "ft", Some("distance")),
+    (39, "kg", Some("weight")),
+    (40, "lb", Some("weight")),
+    // Pressure
+    (53, "Pa", Some("pressure")),
+    (54, "kPa", Some("pressure")),
+    (55, "bar", Some("pressure")),
+    (56, "psi", Some("pressure")),
+    (58, "inH₂O", None),
+    (59, "mmHg", Some("pressure")),
+    (61, "inHg", Some("pressure")),
+    (133, "hPa", Some("pressure")),
+    (134, "mbar", Some("pressure")),
+    // Temperature
+    (62, "°C", Some("temperature")),
+    (63, "K", Some("temperature")),
+    (64, "°F", Some("temperature")),
+    (120, "°F", None), // delta-degrees-fahrenheit
+    (121, "K", None),  // delta-degrees-kelvin
+    // Time
+    (70, "d", Some("duration")),
+    (71, "h", Some("duration")),
+    (72, "min", Some("duration")),
+    (73, "s", Some("duration")),
+    // Velocity
+    (74, "m/s", Some("speed")),
+    (75, "km/h", Some("speed")),
+    (76, "ft/s", Some("speed")),
+    (77, "ft/min", None),
+    (78, "mph", Some("speed")),
+    // Volume and flow
+    (79, "ft³", Some("volume")),
+    (80, "m³", Some("volume")),
+    (82, "L", Some("volume")),
+    (83, "gal", Some("volume")),
+    (84, "ft³/min", Some("volume_flow_rate")),
+    (135, "m³/h", Some("volume_flow_rate")),
+    (87, "L/s", Some("volume_flow_rate")),
+    (88, "L/min", Some("volume_flow_rate")),
+    (136, "L/h", Some("volume_flow_rate")),
+    (89, "gal/min", Some("volume_flow_rate")),
+    // Other
+    (90, "°", None),
+    (96, "ppm", None),
+    (97, "ppb", None),
+    (98, "%", None),
+    (104, "rpm", None),
+];
+
+/// Engineering units "no-units"
+pub const NO_UNITS: u32 = 95;
+
+/// Home Assistant presentation of a BACnet engineering unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HaUnit {
+    pub unit_of_measurement: Option<&'static str>,
+    pub device_class: Option<&'static str>,
+}
+
+/// Maps a BACnet engineering-units value, `None` for no-units and units without
+/// a Home Assistant equivalent
+pub fn ha_unit(units: u32) -> Option<HaUnit> {
+    UNITS
+        .iter()
+        .find(|(id, _, _)| *id == units)
+        .map(|(_, unit, device_class)| HaUnit {
+            unit_of_measurement: (!unit.is_empty()).then_some(*unit),
+            device_class: *device_class,
+        })
+}
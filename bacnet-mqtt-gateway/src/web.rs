@@ -0,0 +1,1513 @@
+use crate::alarm::{AlarmManager, AlarmSummary};
+use crate::assets;
+use crate::audit::AuditLog;
+use crate::auth::{self, Auth};
+use crate::bacnet::{ApduStats, BacnetEngine, BacnetError, BacnetHealth, IAmHeard};
+use crate::batch::{self, BatchRequest, BatchWrite, WriteResult, WriteStatus};
+use crate::capture::{self, CaptureStatus};
+use crate::cluster::{Cluster, ClusterStatus};
+use crate::codec::{self, PropertyReference, PropertyResult};
+use crate::commstats::DeviceCommStats;
+use crate::config::{DeviceConfig, GatewayConfig, PointConfig, MAX_INSTANCE};
+use crate::cov::SubscriptionEntry;
+use crate::deadband::PublishFilters;
+use crate::events::{Events, Record};
+use crate::export;
+use crate::filter::Subnet;
+use crate::historian::{self, Historian, PointHistory, StoredPoint};
+use crate::locale::Translator;
+use crate::logging::{LogFilter, LogLevel};
+use crate::mqtt::{self, MqttService, Quality, ValueProvenance, ValueSource};
+use crate::openapi;
+use crate::point::{self, ObjectRef, PointMetadata};
+use crate::progress::{DiscoveryProgress, ProgressSnapshot};
+use crate::registry::{LatestValue, Registry};
+use crate::reload;
+use crate::rollup::{PollStatus, Rollups};
+use crate::scan;
+use crate::setup::{self, NetworkInterface, Setup};
+use crate::suspend::{Scope, SuspensionManager, Suspensions};
+use crate::units;
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{delete, get, post, put},
+    Form, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify, RwLock};
+use tracing::info;
+use utoipa_swagger_ui::SwaggerUi;
+
+/// Shared handles the web UI and REST API operate on
+#[derive(Clone)]
+pub struct AppState {
+    pub bacnet: Arc<BacnetEngine>,
+    pub mqtt: MqttService,
+    pub devices: Arc<RwLock<HashMap<u32, SocketAddr>>>,
+    pub simulations: Arc<RwLock<HashMap<(u32, ObjectRef), String>>>,
+    /// Deadband state, reset when a simulation is released
+    pub publish_filters: Arc<PublishFilters>,
+    pub suspensions: Arc<SuspensionManager>,
+    pub alarms: Arc<AlarmManager>,
+    pub audit: Arc<AuditLog>,
+    pub cluster: Arc<Cluster>,
+    pub progress: Arc<DiscoveryProgress>,
+    pub metadata: Arc<RwLock<HashMap<(u32, ObjectRef), PointMetadata>>>,
+    pub registry: Arc<Registry>,
+    pub rollups: Arc<Rollups>,
+    pub events: Events,
+    /// Tracing filter the log level API changes
+    pub log_filter: Arc<LogFilter>,
+    pub poll_object: ObjectRef,
+    pub translator: Translator,
+    /// Configuration file the editor and the config API read and save
+    pub config_path: PathBuf,
+    /// Has the saved configuration file applied right away
+    pub reload: Arc<Notify>,
+    /// Login required for everything but the health probes, if configured
+    pub auth: Option<Arc<Auth>>,
+    /// Has `/` send the browser to the setup wizard on first run
+    pub setup: Arc<Setup>,
+    /// Stored values of published points, if the historian is enabled
+    pub historian: Option<Arc<Historian>>,
+}
+
+pub fn router(state: AppState) -> Router {
+    let document = openapi::document(state.auth.is_some());
+    Router::new()
+        .route("/", get(serve_ui))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route("/devices/:device_id", get(serve_ui))
+        .route("/setup", get(serve_ui))
+        .route("/assets/*path", get(serve_asset))
+        .route("/api/devices", get(list_devices))
+        .route("/api/discover", post(discover))
+        .route("/api/who-is", post(send_who_is))
+        .route("/api/devices/:device_id/objects", get(list_objects))
+        .route("/api/devices/:device_id/points", get(list_points).post(add_point))
+        .route("/api/devices/:device_id/stats", get(device_stats))
+        .route("/api/values", get(list_values))
+        .route("/api/devices/:device_id/scan", post(scan_device))
+        .route("/api/devices/:device_id/read", post(read_property))
+        .route("/api/devices/:device_id/write", post(write_property))
+        .route("/api/devices/:device_id/objects/:object/properties", get(read_properties))
+        .route("/api/devices/:device_id/objects/:object/summary", get(read_summary))
+        .route("/api/setup", get(setup_status).post(finish_setup).delete(dismiss_setup))
+        .route("/api/setup/mqtt-test", post(test_mqtt))
+        .route("/api/config", get(read_config).put(save_config).patch(patch_config))
+        .route("/api/export", get(export_registry))
+        .route("/api/export/config", get(export_config_skeleton))
+        .route("/api/write-batch", post(write_batch))
+        .route("/api/simulations", get(list_simulations))
+        .route(
+            "/api/simulations/:device_id/:object",
+            post(start_simulation).delete(stop_simulation),
+        )
+        .route("/api/subscriptions", get(list_subscriptions).post(subscribe))
+        .route("/api/subscriptions/:device_id/:object", delete(unsubscribe))
+        .route("/api/subscriptions/:device_id/:object/renew", post(renew_subscription))
+        .route("/api/suspensions", get(list_suspensions))
+        .route(
+            "/api/suspensions/:scope",
+            post(suspend_polling).delete(resume_polling),
+        )
+        .route("/api/bacnet/stats", get(bacnet_stats))
+        .route("/api/log-level", get(log_level).put(set_log_level).delete(reset_log_level))
+        .route("/api/capture", get(capture_status))
+        .route("/api/capture/start", post(start_capture))
+        .route("/api/capture/stop", post(stop_capture))
+        .route("/api/capture/download", get(download_capture))
+        .route("/api/history", get(history_points))
+        .route("/api/history/export", get(export_history))
+        .route("/api/history/:device_id/:object", get(point_history))
+        .route("/api/events/ws", get(event_stream))
+        .route("/api/cluster", get(cluster_status))
+        .route("/api/discovery/progress", get(discovery_progress))
+        .route("/api/alarms", get(alarm_summary))
+        .route(
+            "/api/alarms/:device_id/:object/shelve",
+            post(shelve_alarm).delete(unshelve_alarm),
+        )
+        .route("/api/alarms/:device_id/:object/ack", post(acknowledge_alarm))
+        .merge(SwaggerUi::new("/api/docs").external_url_unchecked(openapi::DOCUMENT_PATH, document))
+        .layer(middleware::from_fn_with_state(state.clone(), require_login))
+        .with_state(state)
+}
+
+/// Turns away requests without the bearer token or a session: browsers to the
+/// login page, API clients with a 401
+async fn require_login(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(auth) = &state.auth else {
+        return next.run(request).await;
+    };
+    if matches!(request.uri().path(), "/healthz" | "/readyz" | "/login") || auth.authorized(request.headers()) {
+        return next.run(request).await;
+    }
+    let browser = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if request.method() == Method::GET && browser && auth.has_account() {
+        return Redirect::to("/login").into_response();
+    }
+    (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "authentication required").into_response()
+}
+
+async fn login_page() -> Html<&'static str> {
+    Html(r#"<html><body><h1>BACnet-MQTT Gateway</h1>
+<form method="post" action="/login">
+  Username <input name="username" autocomplete="username">
+  Password <input name="password" type="password" autocomplete="current-password">
+  <button type="submit">Log in</button>
+</form>
+</body></html>"#)
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+}
+
+/// Starts a session cookie for valid credentials
+async fn login(State(state): State<AppState>, Form(form): Form<LoginForm>) -> Response {
+    let Some(auth) = &state.auth else {
+        return Redirect::to("/").into_response();
+    };
+    match auth.login(&form.username, &form.password) {
+        Ok(Some(id)) => {
+            info!("Web UI login of {}", form.username);
+            ([(header::SET_COOKIE, auth.cookie(&id))], Redirect::to("/")).into_response()
+        }
+        Ok(None) => {
+            tracing::warn!("Failed web UI login as {}", form.username);
+            (StatusCode::UNAUTHORIZED, "wrong username or password").into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("cannot start a session: {}", e)).into_response(),
+    }
+}
+
+async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(auth) = &state.auth {
+        auth.logout(&headers);
+    }
+    let expired = format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0", auth::SESSION_COOKIE);
+    ([(header::SET_COOKIE, expired)], Redirect::to("/login")).into_response()
+}
+
+/// The single-page UI, which shows the gateway or, under `/devices/<id>`, a device
+async fn serve_ui() -> Response {
+    assets::serve("index.html")
+}
+
+async fn serve_asset(Path(path): Path<String>) -> Response {
+    assets::serve(&path)
+}
+
+/// Liveness probe: the process serves HTTP
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    mqtt_connected: bool,
+    #[serde(flatten)]
+    bacnet: BacnetHealth,
+}
+
+/// Readiness probe: 503 while the broker is unreachable, the BACnet socket is
+/// closed or the receive loop is stalled
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let mqtt_connected = state.mqtt.is_connected();
+    let bacnet = state.bacnet.health();
+    let ready = mqtt_connected && bacnet.datalink_open && bacnet.receiving;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(Readiness { ready, mqtt_connected, bacnet }))
+}
+
+#[derive(Serialize)]
+struct DeviceEntry {
+    instance: u32,
+    address: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interface: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendor_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<String>,
+    poll_status: PollStatus,
+}
+
+/// Every discovered device, by instance
+async fn list_devices(State(state): State<AppState>) -> Json<Vec<DeviceEntry>> {
+    let mut devices: Vec<(u32, SocketAddr)> = state.devices.read().await.iter().map(|(id, addr)| (*id, *addr)).collect();
+    devices.sort_unstable();
+    let entries = devices
+        .into_iter()
+        .map(|(instance, address)| {
+            let info = state.registry.device(instance);
+            DeviceEntry {
+                instance,
+                address,
+                interface: state.bacnet.interface_of(address),
+                alias: state.mqtt.aliases().device(instance),
+                vendor_id: info.as_ref().map(|info| info.vendor_id),
+                last_seen: info.map(|info| info.last_seen),
+                poll_status: state.rollups.poll_status(instance),
+            }
+        })
+        .collect();
+    Json(entries)
+}
+
+#[derive(Serialize)]
+struct ObjectEntry {
+    object: ObjectRef,
+    /// The object's alias, else its Object_Name if read during discovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// Latest polled present-value
+    #[serde(flatten)]
+    latest: Option<LatestValue>,
+}
+
+fn object_name(state: &AppState, metadata: &HashMap<(u32, ObjectRef), PointMetadata>, device_id: u32, object: ObjectRef) -> Option<String> {
+    let object_name = || metadata.get(&(device_id, object)).and_then(|m| m.object_name.clone());
+    state.mqtt.aliases().object(device_id, object).or_else(object_name)
+}
+
+#[derive(Deserialize)]
+struct ValuesQuery {
+    /// Only the stale values if true, only the fresh ones if false
+    stale: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct CachedValue {
+    device_id: u32,
+    object: ObjectRef,
+    #[serde(flatten)]
+    latest: LatestValue,
+}
+
+/// The last value of every polled point with its staleness
+async fn list_values(State(state): State<AppState>, Query(query): Query<ValuesQuery>) -> Json<Vec<CachedValue>> {
+    let values = state
+        .registry
+        .all_values()
+        .into_iter()
+        .flat_map(|(device_id, points)| points.into_iter().map(move |(object, latest)| CachedValue { device_id, object, latest }))
+        .filter(|value| query.stale.is_none_or(|stale| value.latest.stale == stale))
+        .collect();
+    Json(values)
+}
+
+/// Outcomes and latency of the confirmed requests sent to a device
+async fn device_stats(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+) -> Result<Json<DeviceCommStats>, (StatusCode, String)> {
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    Ok(Json(state.bacnet.device_stats(addr)))
+}
+
+#[derive(Deserialize)]
+struct ScanQuery {
+    /// `json` (default) or `csv`
+    format: Option<String>,
+}
+
+/// Every object of a device with a standard set of properties, for commissioning handoff
+async fn scan_device(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+    Query(query): Query<ScanQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let csv = match query.format.as_deref() {
+        Some("csv") => true,
+        Some("json") | None => false,
+        Some(other) => return Err((StatusCode::BAD_REQUEST, format!("unknown report format '{}'", other))),
+    };
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    let report = scan::scan(&state.bacnet, addr, device_id)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("reading the object list of device {} failed: {}", device_id, e)))?;
+    if !csv {
+        return Ok(Json(report).into_response());
+    }
+    let disposition = format!("attachment; filename=\"device-{}-scan.csv\"", device_id);
+    Ok((
+        [(header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        scan::to_csv(&report),
+    )
+        .into_response())
+}
+
+/// Polled points of a device with their latest value, without asking the device
+async fn list_points(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+) -> Result<Json<Vec<ObjectEntry>>, (StatusCode, String)> {
+    if !state.devices.read().await.contains_key(&device_id) {
+        return Err((StatusCode::NOT_FOUND, format!("device {} has not been discovered", device_id)));
+    }
+    let metadata = state.metadata.read().await;
+    let entries = state
+        .registry
+        .values(device_id)
+        .into_iter()
+        .map(|(object, latest)| ObjectEntry {
+            object,
+            name: object_name(&state, &metadata, device_id, object),
+            latest: Some(latest),
+        })
+        .collect();
+    Ok(Json(entries))
+}
+
+/// Adds a point to the device's configuration, polled and published once the
+/// configuration is applied
+async fn add_point(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+    Json(point): Json<PointConfig>,
+) -> Result<Json<SavedConfig>, (StatusCode, String)> {
+    if !state.devices.read().await.contains_key(&device_id) {
+        return Err((StatusCode::NOT_FOUND, format!("device {} has not been discovered", device_id)));
+    }
+    let current = load_config(&state.config_path)?;
+    let mut config = current.clone();
+    let poll_object = config.bacnet.poll_object;
+    let index = config.devices.iter().position(|device| device.instance == device_id).unwrap_or_else(|| {
+        config.devices.push(DeviceConfig {
+            instance: device_id,
+            address: None,
+            points: Vec::new(),
+            interval_secs: None,
+            alias: None,
+            object_aliases: BTreeMap::new(),
+        });
+        config.devices.len() - 1
+    });
+    let points = &mut config.devices[index].points;
+    if points.iter().any(|configured| configured.object == point.object) {
+        return Err((StatusCode::CONFLICT, format!("{} of device {} is already configured", point.object, device_id)));
+    }
+    // Without points the poll object is polled, which it should stay
+    if points.is_empty() && point.object != poll_object {
+        points.push(PointConfig { object: poll_object, properties: vec![85], interval_secs: None });
+    }
+    points.push(point);
+    store_config(&state, &current, config)
+}
+
+/// Objects listed in a device's Object_List, with the latest value of those polled
+async fn list_objects(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+) -> Result<Json<Vec<ObjectEntry>>, (StatusCode, String)> {
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    let objects = state
+        .bacnet
+        .read_object_list(addr, device_id)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("reading the object list of device {} failed: {}", device_id, e)))?;
+    let metadata = state.metadata.read().await;
+    let mut latest = state.registry.values(device_id);
+    let entries = objects
+        .into_iter()
+        .map(|object| ObjectEntry {
+            object,
+            name: object_name(&state, &metadata, device_id, object),
+            latest: latest.remove(&object),
+        })
+        .collect();
+    Ok(Json(entries))
+}
+
+/// Live feed of gateway activity, one JSON message per event
+async fn event_stream(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    let events = state.events.subscribe();
+    ws.on_upgrade(move |socket| stream_events(socket, events))
+}
+
+async fn stream_events(mut socket: WebSocket, mut events: broadcast::Receiver<Record>) {
+    loop {
+        tokio::select! {
+            received = events.recv() => {
+                let text = match received {
+                    Ok(record) => match serde_json::to_string(&record) {
+                        Ok(text) => text,
+                        Err(_) => continue,
+                    },
+                    // A slow client misses events rather than holding up the gateway
+                    Err(broadcast::error::RecvError::Lagged(missed)) => serde_json::json!({ "kind": "lagged", "missed": missed }).to_string(),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            // Clients only listen, anything but a close is ignored
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+fn load_config(path: &std::path::Path) -> Result<GatewayConfig, (StatusCode, String)> {
+    GatewayConfig::load_from_file(path)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("cannot read {}: {}", path.display(), e)))
+}
+
+/// The configuration file's settings with passwords and keys redacted
+async fn read_config(State(state): State<AppState>) -> Result<Json<GatewayConfig>, (StatusCode, String)> {
+    Ok(Json(load_config(&state.config_path)?.redacted()))
+}
+
+#[derive(Serialize)]
+struct SavedConfig {
+    /// Sections whose changes take effect after a restart
+    restart_required: Vec<String>,
+}
+
+/// Validates, saves and applies a configuration replacing `current`.
+/// Passwords and keys left redacted keep their saved values
+fn store_config(state: &AppState, current: &GatewayConfig, mut config: GatewayConfig) -> Result<Json<SavedConfig>, (StatusCode, String)> {
+    config.restore_secrets(current);
+    config.validate().map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    config.save_to_file(&state.config_path).map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("cannot write {}: {}", state.config_path.display(), e))
+    })?;
+    info!("Saved the configuration edited through the web API to {}", state.config_path.display());
+    state.reload.notify_one();
+    Ok(Json(SavedConfig { restart_required: reload::restart_required(current, &config) }))
+}
+
+/// Replaces the whole configuration
+async fn save_config(
+    State(state): State<AppState>,
+    Json(config): Json<GatewayConfig>,
+) -> Result<Json<SavedConfig>, (StatusCode, String)> {
+    let current = load_config(&state.config_path)?;
+    store_config(&state, &current, config)
+}
+
+/// Applies a JSON merge patch (RFC 7386): objects are merged key by key,
+/// `null` removes a key and anything else replaces the value
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Changes some settings, e.g. `{"polling": {"interval_secs": 30}}`, given
+/// as a JSON merge patch of the configuration
+async fn patch_config(
+    State(state): State<AppState>,
+    Json(patch): Json<serde_json::Value>,
+) -> Result<Json<SavedConfig>, (StatusCode, String)> {
+    let current = load_config(&state.config_path)?;
+    let mut value = serde_json::to_value(&current).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    merge_patch(&mut value, patch);
+    let config = serde_json::from_value(value).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("invalid configuration: {}", e)))?;
+    store_config(&state, &current, config)
+}
+
+/// Longest wait for a broker's CONNACK in a connection test
+const MQTT_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct SetupStatus {
+    /// The gateway started without a configuration file
+    pending: bool,
+    interfaces: Vec<NetworkInterface>,
+}
+
+/// What the setup wizard starts from
+async fn setup_status(State(state): State<AppState>) -> Result<Json<SetupStatus>, (StatusCode, String)> {
+    let interfaces = setup::interfaces().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(SetupStatus { pending: state.setup.pending(), interfaces }))
+}
+
+/// Writes the configuration the setup wizard put together
+async fn finish_setup(
+    State(state): State<AppState>,
+    Json(config): Json<GatewayConfig>,
+) -> Result<Json<SavedConfig>, (StatusCode, String)> {
+    let current = load_config(&state.config_path)?;
+    let saved = store_config(&state, &current, config)?;
+    state.setup.finish();
+    info!("Setup finished");
+    Ok(saved)
+}
+
+/// Keeps the defaults written on first run, leaving the wizard
+async fn dismiss_setup(State(state): State<AppState>) -> StatusCode {
+    state.setup.finish();
+    StatusCode::NO_CONTENT
+}
+
+/// Connects to the primary MQTT broker of a configuration that is not saved yet
+async fn test_mqtt(
+    State(state): State<AppState>,
+    Json(mut config): Json<GatewayConfig>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    config.restore_secrets(&load_config(&state.config_path)?);
+    mqtt::test_connection(&config.mqtt, MQTT_TEST_TIMEOUT)
+        .await
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("cannot connect to the MQTT broker: {}", e)))
+}
+
+#[derive(Deserialize)]
+struct PropertyQuery {
+    /// Property number or `all`, `required` or `optional`
+    property: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PropertyEntry {
+    property: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    array_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_type: Option<&'static str>,
+    /// Single values as-is, lists (arrays, multiple values) as JSON arrays
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Reads a property of a device's object, or all/required/optional ones to
+/// dump everything a vendor object exposes
+async fn read_properties(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+    Query(query): Query<PropertyQuery>,
+) -> Result<Json<Vec<PropertyEntry>>, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let property = match query.property.as_deref() {
+        Some(property) => codec::parse_property_identifier(property).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => codec::PROP_ALL,
+    };
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    let results = state
+        .bacnet
+        .read_object_properties(addr, object, property)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("reading {} of device {} failed: {}", object, device_id, e)))?;
+
+    Ok(Json(results.into_iter().map(property_entry).collect()))
+}
+
+/// Object_Name, Description, Present_Value and Units of an object for the object browser
+async fn read_summary(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+) -> Result<Json<Vec<PropertyEntry>>, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    let results = state
+        .bacnet
+        .read_properties(addr, object, &point::BROWSE_PROPERTIES)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("reading {} of device {} failed: {}", object, device_id, e)))?;
+    Ok(Json(results.into_iter().map(property_entry).collect()))
+}
+
+fn property_entry(result: PropertyResult) -> PropertyEntry {
+    match result.value {
+        Ok(values) => PropertyEntry {
+            property: result.property,
+            array_index: result.array_index,
+            value_type: match values.as_slice() {
+                [value] => Some(value.type_name()),
+                _ => None,
+            },
+            value: Some(match values.as_slice() {
+                [value] => value.to_json(),
+                values => serde_json::Value::Array(values.iter().map(codec::BacnetValue::to_json).collect()),
+            }),
+            error: None,
+        },
+        Err(e) => PropertyEntry {
+            property: result.property,
+            array_index: result.array_index,
+            value_type: None,
+            value: None,
+            error: Some(format!("error class {} code {}", e.class, e.code)),
+        },
+    }
+}
+
+fn present_value() -> u32 {
+    85
+}
+
+/// Waits for a BACnet request at most `timeout_ms`, as long as the device's
+/// retry policy allows if unset
+async fn within<T>(timeout_ms: Option<u64>, request: impl std::future::Future<Output = Result<T, BacnetError>>) -> Result<T, BacnetError> {
+    match timeout_ms {
+        Some(ms) => tokio::time::timeout(Duration::from_millis(ms), request).await.unwrap_or(Err(BacnetError::Timeout)),
+        None => request.await,
+    }
+}
+
+fn bacnet_failure(what: String, e: BacnetError) -> (StatusCode, String) {
+    let status = match e {
+        BacnetError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        BacnetError::DryRun => StatusCode::CONFLICT,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    (status, format!("{} failed: {}", what, e))
+}
+
+#[derive(Deserialize)]
+struct ReadRequest {
+    object: ObjectRef,
+    #[serde(default = "present_value")]
+    property: u32,
+    #[serde(default)]
+    array_index: Option<u32>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+/// Reads one property with ReadProperty and returns it decoded, an Error
+/// PDU as the entry's error
+async fn read_property(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+    Json(req): Json<ReadRequest>,
+) -> Result<Json<PropertyEntry>, (StatusCode, String)> {
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    let reference = PropertyReference { object: req.object, property: req.property, array_index: req.array_index };
+    let value = match within(req.timeout_ms, state.bacnet.read_property_value(addr, &reference)).await {
+        Ok(raw) => Ok(codec::decode_property_value(&raw)),
+        Err(BacnetError::Error { class, code, .. }) => Err(codec::PropertyError { class, code }),
+        Err(e) => return Err(bacnet_failure(format!("reading {} property {} of device {}", req.object, req.property, device_id), e)),
+    };
+    Ok(Json(property_entry(PropertyResult { property: req.property, array_index: req.array_index, value })))
+}
+
+#[derive(Deserialize)]
+struct WriteRequest {
+    object: ObjectRef,
+    #[serde(default = "present_value")]
+    property: u32,
+    #[serde(default)]
+    array_index: Option<u32>,
+    value: serde_json::Value,
+    /// Explicit BACnet type ("real", "enumerated", ...), inferred from the object type if omitted
+    #[serde(default, rename = "type")]
+    value_type: Option<String>,
+    #[serde(default)]
+    priority: Option<u8>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+/// Writes one property with WriteProperty, answering once the device acknowledged it
+async fn write_property(
+    State(state): State<AppState>,
+    Path(device_id): Path<u32>,
+    Json(req): Json<WriteRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let addr = state.devices.read().await.get(&device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", device_id),
+    ))?;
+    let write = BatchWrite {
+        device_id,
+        object: req.object,
+        property: req.property,
+        array_index: req.array_index,
+        value: req.value,
+        value_type: req.value_type,
+        priority: req.priority,
+    };
+    let spec = batch::encode_write(&write).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let before = state.audit.before(&state.bacnet, Some(addr), &write).await;
+    let result = within(req.timeout_ms, state.bacnet.write_property(addr, &spec)).await;
+    let (status, error) = match &result {
+        Ok(()) => (WriteStatus::Written, None),
+        Err(e) => (WriteStatus::Failed, Some(e.to_string())),
+    };
+    state.audit.record("api", &write, before, status, error.as_deref()).await;
+    result
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(|e| bacnet_failure(format!("writing {} property {} of device {}", req.object, req.property, device_id), e))
+}
+
+/// Longest a discovery request may listen for I-Am answers
+const MAX_DISCOVER_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct DiscoverRequest {
+    #[serde(default)]
+    low_limit: Option<u32>,
+    #[serde(default)]
+    high_limit: Option<u32>,
+    /// Network such as `10.20.0.0/24`, sent a directed broadcast instead of the configured targets
+    #[serde(default)]
+    subnet: Option<String>,
+    #[serde(default = "discover_window_ms")]
+    window_ms: u64,
+}
+
+fn discover_window_ms() -> u64 {
+    3000
+}
+
+#[derive(Serialize)]
+struct DiscoveredDevice {
+    instance: u32,
+    address: SocketAddr,
+    vendor_id: u32,
+}
+
+/// Broadcasts a Who-Is and lists the devices answering within the window,
+/// including those the gateway already knows
+async fn discover(
+    State(state): State<AppState>,
+    Json(req): Json<DiscoverRequest>,
+) -> Result<Json<Vec<DiscoveredDevice>>, (StatusCode, String)> {
+    check_instance_range(req.low_limit, req.high_limit)?;
+    let subnet = req.subnet.as_deref().map(Subnet::parse).transpose().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let target = match subnet {
+        Some(subnet) => Some(subnet.broadcast().ok_or((StatusCode::BAD_REQUEST, "subnet must be an IPv4 network".to_string()))?),
+        None => None,
+    };
+    let range = req.low_limit.unwrap_or(0)..=req.high_limit.unwrap_or(u32::MAX);
+    // Subscribed before sending so no early answer is missed
+    let answers = state.bacnet.i_ams();
+    let sent = match target {
+        Some(ip) => state.bacnet.who_is(req.low_limit, req.high_limit, Some(SocketAddr::new(ip, state.bacnet.port()))),
+        None => state.bacnet.discover_range(req.low_limit, req.high_limit),
+    };
+    sent.map_err(|e| (StatusCode::BAD_GATEWAY, format!("sending Who-Is failed: {}", e)))?;
+
+    let found: Vec<DiscoveredDevice> = collect_i_ams(answers, req.window_ms, |heard| {
+        range.contains(&heard.device_id) && subnet.is_none_or(|subnet| subnet.contains(heard.address.ip()))
+    })
+    .await
+    .into_iter()
+    .map(|(heard, _)| DiscoveredDevice { instance: heard.device_id, address: heard.address, vendor_id: heard.vendor_id })
+    .collect();
+    info!("Discovery request found {} devices", found.len());
+    Ok(Json(found))
+}
+
+/// Who-Is limits are given together and within the device instance range
+fn check_instance_range(low_limit: Option<u32>, high_limit: Option<u32>) -> Result<(), (StatusCode, String)> {
+    if low_limit.is_some() != high_limit.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "low_limit and high_limit go together".to_string()));
+    }
+    if let Some(limit) = low_limit.into_iter().chain(high_limit).find(|limit| *limit > MAX_INSTANCE) {
+        return Err((StatusCode::BAD_REQUEST, format!("limit {} exceeds {}", limit, MAX_INSTANCE)));
+    }
+    Ok(())
+}
+
+/// The first I-Am of each device `keep` accepts within the window, by
+/// instance, with the time it took to arrive
+async fn collect_i_ams(
+    mut answers: broadcast::Receiver<IAmHeard>,
+    window_ms: u64,
+    keep: impl Fn(&IAmHeard) -> bool,
+) -> Vec<(IAmHeard, Duration)> {
+    let mut found = BTreeMap::new();
+    let started = tokio::time::Instant::now();
+    let deadline = started + Duration::from_millis(window_ms).min(MAX_DISCOVER_WINDOW);
+    loop {
+        match tokio::time::timeout_at(deadline, answers.recv()).await {
+            Ok(Ok(heard)) => {
+                if keep(&heard) {
+                    found.entry(heard.device_id).or_insert((heard, started.elapsed()));
+                }
+            }
+            Ok(Err(broadcast::error::RecvError::Lagged(missed))) => info!("Who-Is request missed {} I-Am answers", missed),
+            Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => break,
+        }
+    }
+    found.into_values().collect()
+}
+
+#[derive(Deserialize)]
+struct WhoIsRequest {
+    /// IP address, with the port if not the gateway's, e.g. `10.30.4.12` or `10.30.4.12:47809`;
+    /// the configured broadcast targets if unset
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    low_limit: Option<u32>,
+    #[serde(default)]
+    high_limit: Option<u32>,
+    #[serde(default = "discover_window_ms")]
+    window_ms: u64,
+}
+
+#[derive(Serialize)]
+struct WhoIsAnswer {
+    instance: u32,
+    address: SocketAddr,
+    vendor_id: u32,
+    /// Time from sending the Who-Is to receiving the I-Am
+    latency_ms: u64,
+}
+
+#[derive(Serialize)]
+struct WhoIsResult {
+    /// Where the Who-Is went, unset for the configured broadcast targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<SocketAddr>,
+    answers: Vec<WhoIsAnswer>,
+}
+
+/// Sends one Who-Is, to a single address or with an instance range, and lists
+/// every I-Am in range heard within the window, wherever it came from
+async fn send_who_is(
+    State(state): State<AppState>,
+    Json(req): Json<WhoIsRequest>,
+) -> Result<Json<WhoIsResult>, (StatusCode, String)> {
+    check_instance_range(req.low_limit, req.high_limit)?;
+    let target = req
+        .target
+        .as_deref()
+        .map(|target| {
+            target
+                .parse::<SocketAddr>()
+                .or_else(|_| target.parse::<std::net::IpAddr>().map(|ip| SocketAddr::new(ip, state.bacnet.port())))
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("invalid target '{}', expected an IP address", target)))
+        })
+        .transpose()?;
+    let range = req.low_limit.unwrap_or(0)..=req.high_limit.unwrap_or(u32::MAX);
+    let answers = state.bacnet.i_ams();
+    let sent = match target {
+        Some(target) => state.bacnet.who_is(req.low_limit, req.high_limit, Some(target)),
+        None => state.bacnet.discover_range(req.low_limit, req.high_limit),
+    };
+    sent.map_err(|e| (StatusCode::BAD_GATEWAY, format!("sending Who-Is failed: {}", e)))?;
+    let answers: Vec<WhoIsAnswer> = collect_i_ams(answers, req.window_ms, |heard| range.contains(&heard.device_id))
+        .await
+        .into_iter()
+        .map(|(heard, latency)| WhoIsAnswer {
+            instance: heard.device_id,
+            address: heard.address,
+            vendor_id: heard.vendor_id,
+            latency_ms: latency.as_millis() as u64,
+        })
+        .collect();
+    match target {
+        Some(target) => info!("Who-Is to {} was answered by {} devices", target, answers.len()),
+        None => info!("Who-Is was answered by {} devices", answers.len()),
+    }
+    Ok(Json(WhoIsResult { target, answers }))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// `json` (default) or `csv`
+    format: Option<String>,
+}
+
+/// Cross-reference of BACnet addresses, objects, MQTT topics and Home Assistant
+/// entities, generated from the live registry for handover documentation
+async fn export_registry(State(state): State<AppState>, Query(query): Query<ExportQuery>) -> Response {
+    let entries = export::build(
+        &state.devices.read().await,
+        &state.metadata.read().await,
+        &state.simulations.read().await,
+        state.poll_object,
+        &state.mqtt,
+        &state.translator,
+        &state.bacnet.local_device(),
+    );
+    match query.format.as_deref() {
+        Some("csv") => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"bacnet-data-dictionary.csv\""),
+            ],
+            export::to_csv(&entries),
+        )
+            .into_response(),
+        Some("json") | None => Json(entries).into_response(),
+        Some(other) => (StatusCode::BAD_REQUEST, format!("unknown export format '{}'", other)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SkeletonQuery {
+    /// `yaml` (default) or `json`
+    format: Option<String>,
+}
+
+/// `devices:` section for the configuration file with every discovered device
+/// and its points, read from the devices' object lists
+async fn export_config_skeleton(State(state): State<AppState>, Query(query): Query<SkeletonQuery>) -> Response {
+    let devices = state.devices.read().await.clone();
+    let skeleton = export::skeleton(&state.bacnet, &devices).await;
+    match query.format.as_deref() {
+        Some("yaml") | None => match serde_yaml::to_string(&skeleton) {
+            Ok(yaml) => (
+                [
+                    (header::CONTENT_TYPE, "application/yaml"),
+                    (header::CONTENT_DISPOSITION, "attachment; filename=\"devices.yaml\""),
+                ],
+                yaml,
+            )
+                .into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("cannot write YAML: {}", e)).into_response(),
+        },
+        Some("json") => Json(skeleton).into_response(),
+        Some(other) => (StatusCode::BAD_REQUEST, format!("unknown export format '{}'", other)).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    results: Vec<WriteResult>,
+}
+
+/// Executes a list of writes, grouped into one WritePropertyMultiple per device
+async fn write_batch(State(state): State<AppState>, Json(req): Json<BatchRequest>) -> Json<BatchResponse> {
+    let devices = state.devices.read().await.clone();
+    let writes = req.writes.clone();
+    let mut before = Vec::with_capacity(writes.len());
+    for write in &writes {
+        before.push(state.audit.before(&state.bacnet, devices.get(&write.device_id).copied(), write).await);
+    }
+    let results = batch::execute(&state.bacnet, &devices, req).await;
+    for ((write, before), result) in writes.iter().zip(before).zip(&results) {
+        state.audit.record("api", write, before, result.status, result.error.as_deref()).await;
+    }
+    Json(BatchResponse { results })
+}
+
+#[derive(Deserialize)]
+struct SimulationRequest {
+    value: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct SimulationEntry {
+    device_id: u32,
+    object: ObjectRef,
+    value: String,
+}
+
+async fn list_simulations(State(state): State<AppState>) -> Json<Vec<SimulationEntry>> {
+    let entries = state
+        .simulations
+        .read()
+        .await
+        .iter()
+        .map(|((device_id, object), value)| SimulationEntry {
+            device_id: *device_id,
+            object: *object,
+            value: value.clone(),
+        })
+        .collect();
+    Json(entries)
+}
+
+/// Overrides a point's published value until released, without touching the device
+async fn start_simulation(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+    Json(req): Json<SimulationRequest>,
+) -> Result<Json<SimulationEntry>, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let req_value = req.value.clone();
+    let value = match req.value {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    };
+
+    state.simulations.write().await.insert((device_id, object), value.clone());
+    info!("Simulating device {} {} = {}", device_id, object, value);
+
+    let units = state
+        .metadata
+        .read()
+        .await
+        .get(&(device_id, object))
+        .and_then(|metadata| metadata.units)
+        .and_then(units::ha_unit)
+        .and_then(|unit| unit.unit_of_measurement);
+    state.mqtt.publish_point_state(device_id, object, &value, Quality::Simulated, units).await;
+    let state_topic = state.mqtt.device_state_topic(device_id, object);
+    let provenance = ValueProvenance {
+        source: ValueSource::Simulation,
+        latency_ms: None,
+        invoke_id: None,
+        poll_cycle: None,
+        simulated: true,
+        value_type: None,
+        value: Some(req_value),
+        label: None,
+        maintenance: None,
+    };
+    state.mqtt.publish_attributes(&state_topic, &provenance).await;
+
+    Ok(Json(SimulationEntry { device_id, object, value }))
+}
+
+/// Releases a simulated point, the next poll publishes the real value again
+async fn stop_simulation(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    match state.simulations.write().await.remove(&(device_id, object)) {
+        Some(_) => {
+            // The real value may equal the one recorded before the simulation
+            state.publish_filters.forget(device_id, object);
+            info!("Released simulation of device {} {}", device_id, object);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("device {} {} is not simulated", device_id, object),
+        )),
+    }
+}
+
+/// Default lifetime of subscriptions made through the API
+fn default_cov_lifetime_secs() -> u32 {
+    300
+}
+
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    device_id: u32,
+    object: ObjectRef,
+    #[serde(default)]
+    confirmed: bool,
+    /// 0 subscribes indefinitely, which not every device accepts
+    #[serde(default = "default_cov_lifetime_secs")]
+    lifetime_secs: u32,
+}
+
+#[derive(Deserialize, Default)]
+struct RenewRequest {
+    /// The lifetime subscribed with if not set
+    #[serde(default)]
+    lifetime_secs: Option<u32>,
+}
+
+/// COV subscriptions made through the API with their remaining time and notifications
+async fn list_subscriptions(State(state): State<AppState>) -> Json<Vec<SubscriptionEntry>> {
+    Json(state.bacnet.cov_subscriptions())
+}
+
+fn subscription_entry(state: &AppState, device_id: u32, object: ObjectRef) -> Result<Json<SubscriptionEntry>, (StatusCode, String)> {
+    state
+        .bacnet
+        .cov_subscriptions()
+        .into_iter()
+        .find(|entry| entry.device_id == device_id && entry.object == object)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("no COV subscription to device {} {}", device_id, object)))
+}
+
+/// Subscribes to COV notifications of an object
+async fn subscribe(
+    State(state): State<AppState>,
+    Json(req): Json<SubscribeRequest>,
+) -> Result<Json<SubscriptionEntry>, (StatusCode, String)> {
+    let addr = state.devices.read().await.get(&req.device_id).copied().ok_or((
+        StatusCode::NOT_FOUND,
+        format!("device {} has not been discovered", req.device_id),
+    ))?;
+    state
+        .bacnet
+        .subscribe_cov(addr, req.device_id, req.object, req.confirmed, req.lifetime_secs)
+        .await
+        .map_err(|e| bacnet_failure(format!("subscribing to COV of device {} {}", req.device_id, req.object), e))?;
+    subscription_entry(&state, req.device_id, req.object)
+}
+
+/// Repeats a subscription, restarting its lifetime
+async fn renew_subscription(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+    req: Option<Json<RenewRequest>>,
+) -> Result<Json<SubscriptionEntry>, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let subscription = state
+        .bacnet
+        .cov_subscription(device_id, object)
+        .ok_or((StatusCode::NOT_FOUND, format!("no COV subscription to device {} {}", device_id, object)))?;
+    let lifetime_secs = req.unwrap_or_default().0.lifetime_secs.unwrap_or(subscription.lifetime_secs);
+    state
+        .bacnet
+        .subscribe_cov(subscription.address, device_id, object, subscription.confirmed, lifetime_secs)
+        .await
+        .map_err(|e| bacnet_failure(format!("renewing the COV subscription to device {} {}", device_id, object), e))?;
+    subscription_entry(&state, device_id, object)
+}
+
+/// Cancels a subscription on the device
+async fn unsubscribe(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let subscription = state
+        .bacnet
+        .cov_subscription(device_id, object)
+        .ok_or((StatusCode::NOT_FOUND, format!("no COV subscription to device {} {}", device_id, object)))?;
+    state
+        .bacnet
+        .cancel_cov(device_id, object, subscription)
+        .await
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(|e| bacnet_failure(format!("cancelling the COV subscription to device {} {}", device_id, object), e))
+}
+
+#[derive(Deserialize)]
+struct LogLevelRequest {
+    directives: String,
+    /// Restores the startup directives after this long if set
+    #[serde(default)]
+    revert_after_secs: Option<u64>,
+}
+
+async fn log_level(State(state): State<AppState>) -> Json<LogLevel> {
+    Json(state.log_filter.level())
+}
+
+/// Changes the tracing filter directives, e.g. `info,bacnet_mqtt_gateway::bacnet=trace`
+async fn set_log_level(
+    State(state): State<AppState>,
+    Json(req): Json<LogLevelRequest>,
+) -> Result<Json<LogLevel>, (StatusCode, String)> {
+    state
+        .log_filter
+        .set(&req.directives, req.revert_after_secs.map(Duration::from_secs))
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+/// Restores the directives the gateway started with
+async fn reset_log_level(State(state): State<AppState>) -> Result<Json<LogLevel>, (StatusCode, String)> {
+    state.log_filter.reset().map(Json).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+#[derive(Deserialize, Default)]
+struct CaptureRequest {
+    /// Size the capture stops growing at
+    #[serde(default)]
+    max_bytes: Option<usize>,
+}
+
+async fn capture_status(State(state): State<AppState>) -> Json<CaptureStatus> {
+    Json(state.bacnet.capture().status())
+}
+
+/// Starts recording the BACnet/IP frames, discarding the previous capture
+async fn start_capture(State(state): State<AppState>, req: Option<Json<CaptureRequest>>) -> Json<CaptureStatus> {
+    let max_bytes = req.unwrap_or_default().0.max_bytes.unwrap_or(capture::DEFAULT_MAX_BYTES);
+    Json(state.bacnet.capture().start(max_bytes))
+}
+
+async fn stop_capture(State(state): State<AppState>) -> Json<CaptureStatus> {
+    Json(state.bacnet.capture().stop())
+}
+
+/// The current or last capture as a pcap file
+async fn download_capture(State(state): State<AppState>) -> Result<Response, (StatusCode, String)> {
+    let pcap = state
+        .bacnet
+        .capture()
+        .pcap()
+        .ok_or((StatusCode::NOT_FOUND, "no capture has been started".to_string()))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.tcpdump.pcap"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"bacnet-capture.pcap\""),
+        ],
+        pcap,
+    )
+        .into_response())
+}
+
+fn historian(state: &AppState) -> Result<&Arc<Historian>, (StatusCode, String)> {
+    state
+        .historian
+        .as_ref()
+        .ok_or((StatusCode::NOT_FOUND, "the historian is not enabled".to_string()))
+}
+
+/// Runs a historian query on the blocking thread pool, off the async workers
+async fn query_history<T: Send + 'static>(
+    state: &AppState,
+    query: impl FnOnce(&Historian) -> Result<T, rusqlite::Error> + Send + 'static,
+) -> Result<T, (StatusCode, String)> {
+    let historian = historian(state)?.clone();
+    match tokio::task::spawn_blocking(move || query(&historian)).await {
+        Ok(result) => result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("querying the history failed: {}", e))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("querying the history failed: {}", e))),
+    }
+}
+
+/// Points with stored history and the time range it covers
+async fn history_points(State(state): State<AppState>) -> Result<Json<Vec<StoredPoint>>, (StatusCode, String)> {
+    let points = query_history(&state, |historian| historian.points()).await?;
+    Ok(Json(points))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Stored values of a point between `from` and `to`, oldest first
+async fn point_history(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<PointHistory>, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let limit = query.limit.unwrap_or(historian::DEFAULT_LIMIT);
+    let samples =
+        query_history(&state, move |historian| historian.query(device_id, object, query.from.as_deref(), query.to.as_deref(), limit)).await?;
+    Ok(Json(PointHistory { device_id, object, samples }))
+}
+
+#[derive(Deserialize)]
+struct HistoryExportQuery {
+    /// Comma separated `<device>/<object>` pairs, e.g. `1200/AI:3,1200/AV:1`
+    point: String,
+    from: Option<String>,
+    to: Option<String>,
+    /// Most values per point
+    limit: Option<u32>,
+    format: Option<String>,
+}
+
+/// Stored values of the points between `from` and `to` as a CSV or Parquet file
+async fn export_history(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let parquet = match query.format.as_deref() {
+        Some("parquet") => true,
+        Some("csv") | None => false,
+        Some(other) => return Err((StatusCode::BAD_REQUEST, format!("unknown export format '{}'", other))),
+    };
+    let historian = historian(&state)?.clone();
+    let mut points = Vec::new();
+    for point in query.point.split(',').map(str::trim).filter(|point| !point.is_empty()) {
+        let parsed = point
+            .split_once('/')
+            .and_then(|(device_id, object)| Some((device_id.parse::<u32>().ok()?, object.parse::<ObjectRef>().ok()?)))
+            .ok_or((StatusCode::BAD_REQUEST, format!("invalid point '{}', expected <device>/<object>", point)))?;
+        points.push(parsed);
+    }
+    if points.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "no point to export".to_string()));
+    }
+    let limit = query.limit.unwrap_or(historian::DEFAULT_LIMIT);
+    // SQLite and the Parquet writer block, keep them off the async workers
+    let written = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, String> {
+        let mut history = Vec::new();
+        for (device_id, object) in points {
+            let samples = historian
+                .query(device_id, object, query.from.as_deref(), query.to.as_deref(), limit)
+                .map_err(|e| format!("querying the history failed: {}", e))?;
+            history.push(PointHistory { device_id, object, samples });
+        }
+        if parquet {
+            historian::to_parquet(&history).map_err(|e| format!("writing the Parquet file failed: {}", e))
+        } else {
+            Ok(historian::to_csv(&history).into_bytes())
+        }
+    })
+    .await;
+    let file = match written {
+        Ok(result) => result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("the export failed: {}", e))),
+    };
+    if !parquet {
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"history.csv\""),
+            ],
+            file,
+        )
+            .into_response());
+    }
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.apache.parquet"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"history.parquet\""),
+        ],
+        file,
+    )
+        .into_response())
+}
+
+async fn list_suspensions(State(state): State<AppState>) -> Json<Suspensions> {
+    Json(state.suspensions.snapshot())
+}
+
+/// Suspends polling for `gateway`, `group:<name>` or `device:<id>`
+async fn suspend_polling(
+    State(state): State<AppState>,
+    Path(scope): Path<String>,
+) -> Result<Json<Suspensions>, (StatusCode, String)> {
+    set_suspended(state, scope, true).await
+}
+
+async fn resume_polling(
+    State(state): State<AppState>,
+    Path(scope): Path<String>,
+) -> Result<Json<Suspensions>, (StatusCode, String)> {
+    set_suspended(state, scope, false).await
+}
+
+async fn set_suspended(
+    state: AppState,
+    scope: String,
+    suspended: bool,
+) -> Result<Json<Suspensions>, (StatusCode, String)> {
+    let scope: Scope = scope.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let devices = state.devices.read().await.clone();
+    state
+        .suspensions
+        .set(&scope, suspended, &state.mqtt, &devices)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::NOT_FOUND, e))
+}
+
+/// Counts of confirmed requests and of the Error, Reject, Abort and timeout outcomes
+async fn bacnet_stats(State(state): State<AppState>) -> Json<ApduStats> {
+    Json(state.bacnet.stats())
+}
+
+async fn discovery_progress(State(state): State<AppState>) -> Json<ProgressSnapshot> {
+    Json(state.progress.snapshot())
+}
+
+/// Cluster members as seen by this instance, 404 when clustering is off
+async fn cluster_status(State(state): State<AppState>) -> Result<Json<ClusterStatus>, (StatusCode, String)> {
+    state
+        .cluster
+        .status()
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "clustering is not configured".to_string()))
+}
+
+async fn alarm_summary(State(state): State<AppState>) -> Json<Vec<AlarmSummary>> {
+    Json(state.alarms.summary())
+}
+
+#[derive(Deserialize)]
+struct ShelveRequest {
+    /// Shelving period, indefinitely if not set
+    hours: Option<f64>,
+}
+
+/// Shelves an alarm source so its changes are not published
+async fn shelve_alarm(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+    Json(req): Json<ShelveRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let period = match req.hours {
+        Some(hours) => Some(
+            Duration::try_from_secs_f64(hours * 3600.0)
+                .ok()
+                .filter(|p| !p.is_zero())
+                .ok_or((StatusCode::BAD_REQUEST, format!("invalid shelving period {} hours", hours)))?,
+        ),
+        None => None,
+    };
+    state.alarms.shelve(device_id, object, period);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unshelve_alarm(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if state.alarms.unshelve(device_id, object) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("alarms of device {} {} are not shelved", device_id, object)))
+    }
+}
+
+/// Acknowledges an active alarm, stopping its re-notification and escalation
+async fn acknowledge_alarm(
+    State(state): State<AppState>,
+    Path((device_id, object)): Path<(u32, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let object: ObjectRef = object.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if state.alarms.acknowledge(device_id, object) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("device {} {} has no unacknowledged alarm", device_id, object)))
+    }
+}
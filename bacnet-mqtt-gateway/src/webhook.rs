@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Host, port and path of an `http://host[:port]/path` URL
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported URL '{}', only http:// is supported", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("invalid port in '{}'", url))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in '{}'", url));
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// POSTs a JSON document and fails unless the server answers with a 2xx status
+pub async fn post_json(url: &str, body: &serde_json::Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+    post(url, "application/json", &[], &body.to_string()).await
+}
+
+/// POSTs a body with extra request headers and fails unless the server
+/// answers with a 2xx status
+pub async fn post(url: &str, content_type: &str, headers: &[(&str, &str)], body: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (host, port, path) = parse_url(url)?;
+    let extra: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        content_type,
+        extra,
+        body.len(),
+        body
+    );
+
+    let exchange = async {
+        let mut stream = TcpStream::connect((host.as_str(), port)).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("POST to {} timed out", url))??;
+
+    let status_line = String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_string();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("{} answered '{}'", url, status_line).into()),
+    }
+}
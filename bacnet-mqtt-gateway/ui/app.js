@@ -0,0 +1,434 @@
+// Single-page UI of the gateway: `/` shows the gateway, `/devices/<id>` one device
+
+const $ = id => document.getElementById(id);
+const show = v => v === undefined || v === null ? '' : typeof v === 'object' ? JSON.stringify(v) : String(v);
+
+// Gateway view
+
+let config;
+const fields = {
+  broker_host: ['mqtt', 'broker_host'], broker_port: ['mqtt', 'broker_port', Number],
+  username: ['mqtt', 'username'], password: ['mqtt', 'password'], base_topic: ['mqtt', 'base_topic'],
+  device_id: ['bacnet', 'device_id', Number], bind_addr: ['bacnet', 'bind_addr'],
+  poll_object: ['bacnet', 'poll_object'], interval_secs: ['polling', 'interval_secs', Number],
+};
+
+function addDevice(d) {
+  const row = $('devices').insertRow();
+  row.device = d;
+  row.innerHTML = '<td><input type="number" size="8"></td><td><input size="20"></td><td><input size="16"></td>'
+    + '<td></td><td><button type="button">Remove</button></td>';
+  const [instance, address, alias] = row.querySelectorAll('input');
+  instance.value = d.instance ?? '';
+  address.value = d.address ?? '';
+  alias.value = d.alias ?? '';
+  row.cells[3].textContent = (d.points || []).length || 'poll object';
+  row.querySelector('button').onclick = () => row.remove();
+}
+
+async function loadConfig() {
+  const res = await fetch('/api/config');
+  if (!res.ok) { $('config_result').textContent = await res.text(); return; }
+  config = await res.json();
+  for (const [id, [section, key]] of Object.entries(fields)) $(id).value = config[section][key] ?? '';
+  $('devices').innerHTML = '';
+  config.devices.forEach(addDevice);
+}
+
+async function saveConfig(e) {
+  e.preventDefault();
+  for (const [id, [section, key, convert]] of Object.entries(fields)) {
+    const value = $(id).value;
+    config[section][key] = value === '' ? null : convert ? convert(value) : value;
+  }
+  config.devices = Array.from($('devices').rows).map(row => {
+    const [instance, address, alias] = row.querySelectorAll('input');
+    const d = Object.assign({}, row.device, { instance: Number(instance.value) });
+    for (const [key, input] of [['address', address], ['alias', alias]]) {
+      if (input.value) d[key] = input.value; else delete d[key];
+    }
+    return d;
+  });
+  const res = await fetch('/api/config', { method: 'PUT', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify(config) });
+  if (!res.ok) { $('config_result').textContent = await res.text(); return; }
+  const saved = await res.json();
+  $('config_result').textContent = saved.restart_required.length
+    ? 'Saved. Restart the gateway to apply changes to: ' + saved.restart_required.join(', ')
+    : 'Saved and applied.';
+  loadConfig();
+}
+
+async function refreshProgress() {
+  const res = await fetch('/api/discovery/progress');
+  if (!res.ok) return;
+  const p = await res.json();
+  let text = `Discovery: ${p.devices_found} devices found, ${p.objects_read} objects read`;
+  if (p.enumerating) {
+    text += `, ${p.objects_pending} remaining`;
+    if (p.estimated_remaining_secs !== undefined) text += ` (about ${p.estimated_remaining_secs} s)`;
+  }
+  $('progress').textContent = text;
+}
+
+const devicesAt = {}, pointCells = {};
+
+function addPoint(instance, p) {
+  const row = $('dashboard').insertRow();
+  for (const text of [p.object, p.name ?? '', JSON.stringify(p.value ?? null), p.updated ?? '']) row.insertCell().textContent = text;
+  if (p.stale) row.className = 'stale';
+  pointCells[instance + '|' + p.object] = row.cells;
+}
+
+async function loadDashboard() {
+  const res = await fetch('/api/devices');
+  if (!res.ok) return;
+  const list = await res.json();
+  const points = await Promise.all(list.map(d => fetch(`/api/devices/${d.instance}/points`).then(r => r.ok ? r.json() : [])));
+  const dashboard = $('dashboard');
+  dashboard.innerHTML = '';
+  list.forEach((d, i) => {
+    devicesAt[d.address] = d.instance;
+    const header = dashboard.insertRow().insertCell();
+    header.colSpan = 4;
+    const link = document.createElement('a');
+    link.href = '/devices/' + d.instance;
+    link.textContent = d.alias ? `${d.instance} (${d.alias})` : String(d.instance);
+    header.append(link, ` at ${d.address}: ${d.poll_status}` + (d.last_seen ? `, last seen ${d.last_seen}` : ''));
+    points[i].forEach(p => addPoint(d.instance, p));
+  });
+}
+
+function followEvents() {
+  const activity = $('activity');
+  const feed = new WebSocket((location.protocol === 'https:' ? 'wss://' : 'ws://') + location.host + '/api/events/ws');
+  feed.onmessage = m => {
+    activity.textContent = [m.data, ...activity.textContent.split('\n').slice(0, 199)].join('\n');
+    const e = JSON.parse(m.data);
+    if (e.kind === 'i_am' || e.kind === 'device_moved') { loadDashboard(); return; }
+    if (e.kind !== 'property_read' || e.property !== 85) return;
+    const instance = devicesAt[e.address];
+    if (instance === undefined) return;
+    const cells = pointCells[instance + '|' + e.object];
+    if (!cells) { loadDashboard(); return; }
+    cells[2].textContent = JSON.stringify(e.value);
+    cells[3].textContent = e.ts;
+  };
+}
+
+async function whoIs(e) {
+  e.preventDefault();
+  const number = id => $(id).value === '' ? undefined : Number($(id).value);
+  const body = { target: $('whoIsTarget').value || undefined, low_limit: number('whoIsLow'), high_limit: number('whoIsHigh') };
+  $('whoIsResult').textContent = 'Waiting for I-Am answers...';
+  const res = await fetch('/api/who-is', { method: 'POST', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify(body) });
+  if (!res.ok) { $('whoIsResult').textContent = await res.text(); return; }
+  const result = await res.json();
+  $('whoIsResult').textContent = result.answers.length
+    ? result.answers.map(a => `${a.instance} at ${a.address} (vendor ${a.vendor_id}) after ${a.latency_ms} ms`).join('\n')
+    : 'No answer';
+}
+
+async function simulate(e, method) {
+  e.preventDefault();
+  const url = `/api/simulations/${$('device').value}/${$('object').value}`;
+  const opts = { method, headers: { 'Content-Type': 'application/json' } };
+  if (method === 'POST') opts.body = JSON.stringify({ value: $('value').value });
+  const res = await fetch(url, opts);
+  $('result').textContent = await res.text();
+}
+
+async function loadSubscriptions() {
+  const res = await fetch('/api/subscriptions');
+  if (!res.ok) return;
+  const rows = $('subscriptions');
+  rows.innerHTML = '';
+  for (const sub of await res.json()) {
+    const row = rows.insertRow();
+    const remaining = sub.remaining_secs === undefined ? 'indefinite' : sub.remaining_secs + ' s';
+    const lifetime = sub.lifetime_secs ? sub.lifetime_secs + ' s' : 'indefinite';
+    for (const text of [sub.device_id, sub.object, lifetime, remaining, sub.notifications, show(sub.present_value)]) {
+      row.insertCell().textContent = text;
+    }
+    const url = `/api/subscriptions/${sub.device_id}/${sub.object}`;
+    const renew = document.createElement('button');
+    renew.textContent = 'Renew';
+    renew.onclick = () => changeSubscription(url + '/renew', 'POST');
+    const cancel = document.createElement('button');
+    cancel.textContent = 'Cancel';
+    cancel.onclick = () => changeSubscription(url, 'DELETE');
+    row.insertCell().append(renew, ' ', cancel);
+  }
+}
+
+async function changeSubscription(url, method) {
+  const res = await fetch(url, { method });
+  $('covResult').textContent = res.ok ? '' : await res.text();
+  loadSubscriptions();
+}
+
+async function subscribe(e) {
+  e.preventDefault();
+  const body = {
+    device_id: Number($('covDevice').value), object: $('covObject').value,
+    lifetime_secs: Number($('covLifetime').value), confirmed: $('covConfirmed').checked,
+  };
+  const res = await fetch('/api/subscriptions', { method: 'POST', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify(body) });
+  $('covResult').textContent = res.ok ? '' : await res.text();
+  loadSubscriptions();
+}
+
+async function logLevel(method, body) {
+  const opts = { method, headers: { 'Content-Type': 'application/json' } };
+  if (body) opts.body = JSON.stringify(body);
+  const res = await fetch('/api/log-level', opts);
+  if (!res.ok) { $('logStatus').textContent = await res.text(); return; }
+  const level = await res.json();
+  $('logDirectives').value = level.directives;
+  $('logStatus').textContent = level.revert_in_secs === undefined ? `Started with ${level.initial || 'the default'}`
+    : `Reverts to ${level.initial || 'the default'} in ${level.revert_in_secs} s`;
+}
+
+function setLogLevel(e) {
+  e.preventDefault();
+  const minutes = $('logMinutes').value;
+  logLevel('PUT', { directives: $('logDirectives').value, revert_after_secs: minutes ? Number(minutes) * 60 : undefined });
+}
+
+async function capture(action) {
+  const res = await fetch('/api/capture' + action, { method: action ? 'POST' : 'GET' });
+  if (!res.ok) { $('captureStatus').textContent = await res.text(); return; }
+  const c = await res.json();
+  $('captureStatus').textContent = c.started === undefined ? 'No capture yet'
+    : `${c.recording ? 'Recording' : 'Stopped'}: ${c.frames} frames, ${c.bytes} bytes` + (c.dropped ? `, ${c.dropped} dropped` : '');
+}
+
+function exportHistory(e) {
+  e.preventDefault();
+  const query = new URLSearchParams({ point: $('historyPoints').value, format: $('historyFormat').value });
+  for (const bound of ['From', 'To']) {
+    const value = $('history' + bound).value;
+    if (value) query.set(bound.toLowerCase(), new Date(value).toISOString());
+  }
+  location.href = '/api/history/export?' + query;
+}
+
+function showGateway() {
+  $('gatewayView').hidden = false;
+  $('config').onsubmit = saveConfig;
+  $('addDeviceButton').onclick = () => addDevice({});
+  $('whoIs').onsubmit = whoIs;
+  $('simulation').onsubmit = e => simulate(e, 'POST');
+  $('releaseButton').onclick = e => simulate(e, 'DELETE');
+  $('subscribe').onsubmit = subscribe;
+  $('logLevel').onsubmit = setLogLevel;
+  $('logReset').onclick = () => logLevel('DELETE');
+  $('captureStart').onclick = () => capture('/start');
+  $('captureStop').onclick = () => capture('/stop');
+  $('historyExport').onsubmit = exportHistory;
+  loadConfig();
+  refreshProgress();
+  setInterval(refreshProgress, 2000);
+  loadDashboard();
+  setInterval(loadDashboard, 30000);
+  loadSubscriptions();
+  setInterval(loadSubscriptions, 5000);
+  logLevel('GET');
+  capture('');
+  setInterval(() => capture(''), 5000);
+  followEvents();
+}
+
+// Device view
+
+async function inspect(e, deviceId) {
+  e.preventDefault();
+  const res = await fetch(`/api/devices/${deviceId}/objects/${$('inspectObject').value}/properties?property=${$('inspectProperty').value}`);
+  $('inspectResult').textContent = res.ok ? JSON.stringify(await res.json(), null, 2) : await res.text();
+}
+
+async function browse(deviceId) {
+  const status = $('browseStatus');
+  status.textContent = 'Reading the object list...';
+  const res = await fetch(`/api/devices/${deviceId}/objects`);
+  if (!res.ok) { status.textContent = await res.text(); return; }
+  const entries = await res.json();
+  status.textContent = entries.length + ' objects';
+  const rows = $('objects').tBodies[0];
+  rows.replaceChildren();
+  for (const entry of entries) {
+    const row = rows.insertRow();
+    row.insertCell().textContent = entry.object;
+    const cells = [row.insertCell(), row.insertCell(), row.insertCell(), row.insertCell()];
+    cells[0].textContent = entry.name || '';
+    cells[2].textContent = show(entry.value);
+    const read = document.createElement('button');
+    read.textContent = 'Read';
+    read.onclick = () => details(deviceId, entry.object, cells);
+    const publish = document.createElement('button');
+    publish.textContent = entry.value_type === undefined ? 'Publish to MQTT' : 'Published';
+    publish.disabled = entry.value_type !== undefined;
+    publish.onclick = () => publishPoint(deviceId, entry.object, publish);
+    row.insertCell().append(read, ' ', publish);
+  }
+}
+
+async function details(deviceId, object, cells) {
+  const res = await fetch(`/api/devices/${deviceId}/objects/${object}/summary`);
+  if (!res.ok) { cells[0].textContent = await res.text(); return; }
+  const values = {};
+  for (const entry of await res.json()) values[entry.property] = entry.error || entry.value;
+  [77, 28, 85, 117].forEach((property, i) => cells[i].textContent = show(values[property]));
+}
+
+async function publishPoint(deviceId, object, button) {
+  const res = await fetch(`/api/devices/${deviceId}/points`, {
+    method: 'POST', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify({ object }),
+  });
+  if (res.ok) { button.textContent = 'Published'; button.disabled = true; } else { button.title = await res.text(); }
+}
+
+async function scanReport(deviceId, format) {
+  const status = $('scanStatus');
+  status.textContent = 'Scanning, this takes a while on large controllers...';
+  const res = await fetch(`/api/devices/${deviceId}/scan?format=${format}`, { method: 'POST' });
+  if (!res.ok) { status.textContent = await res.text(); return; }
+  const link = document.createElement('a');
+  link.href = URL.createObjectURL(await res.blob());
+  link.download = `device-${deviceId}-scan.${format}`;
+  link.click();
+  URL.revokeObjectURL(link.href);
+  status.textContent = '';
+}
+
+async function showDevice(deviceId) {
+  $('deviceView').hidden = false;
+  const res = await fetch('/api/devices');
+  const device = res.ok ? (await res.json()).find(d => d.instance === deviceId) : undefined;
+  if (!device) {
+    $('deviceTitle').textContent = `Device ${deviceId} has not been discovered`;
+    return;
+  }
+  const title = device.alias ? `${device.alias} (BACnet Device ${deviceId})` : `BACnet Device ${deviceId}`;
+  $('deviceTitle').textContent = title;
+  document.title = title;
+  $('deviceAddress').textContent = 'Address: ' + device.address;
+  $('inspector').onsubmit = e => inspect(e, deviceId);
+  $('browseButton').onclick = () => browse(deviceId);
+  $('scanJson').onclick = () => scanReport(deviceId, 'json');
+  $('scanCsv').onclick = () => scanReport(deviceId, 'csv');
+}
+
+// Setup view
+
+let draft, interfaces = [];
+const setupFields = {
+  setupBrokerHost: ['mqtt', 'broker_host'], setupBrokerPort: ['mqtt', 'broker_port', Number],
+  setupUsername: ['mqtt', 'username'], setupPassword: ['mqtt', 'password'], setupBaseTopic: ['mqtt', 'base_topic'],
+  setupDeviceId: ['bacnet', 'device_id', Number],
+};
+
+// The draft configuration with the wizard's fields applied
+function setupConfig() {
+  for (const [id, [section, key, convert]] of Object.entries(setupFields)) {
+    const value = $(id).value;
+    draft[section][key] = value === '' ? null : convert ? convert(value) : value;
+  }
+  const address = $('setupInterface').value || '0.0.0.0';
+  draft.bacnet.bind_addr = `${address}:${$('setupPort').value}`;
+  return draft;
+}
+
+async function testMqtt() {
+  $('setupMqttResult').textContent = 'Connecting...';
+  const res = await fetch('/api/setup/mqtt-test', { method: 'POST', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify(setupConfig()) });
+  $('setupMqttResult').textContent = res.ok ? 'Connected' : await res.text();
+}
+
+async function scan() {
+  const status = $('setupScanResult');
+  status.textContent = 'Waiting for I-Am answers...';
+  const iface = interfaces.find(i => i.address === $('setupInterface').value);
+  const body = iface ? { subnet: iface.subnet } : {};
+  const res = await fetch('/api/discover', { method: 'POST', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify(body) });
+  if (!res.ok) { status.textContent = await res.text(); return; }
+  const found = await res.json();
+  status.textContent = found.length + ' devices answered';
+  const rows = $('setupDevices');
+  rows.innerHTML = '';
+  for (const d of found) {
+    const row = rows.insertRow();
+    row.device = d;
+    const pick = document.createElement('input');
+    pick.type = 'checkbox';
+    pick.checked = true;
+    row.insertCell().append(pick);
+    for (const text of [d.instance, d.address, d.vendor_id]) row.insertCell().textContent = text;
+    const load = document.createElement('button');
+    load.type = 'button';
+    load.textContent = 'Choose points';
+    const points = row.insertCell();
+    points.append(load);
+    load.onclick = () => choosePoints(d.instance, points);
+  }
+}
+
+async function choosePoints(instance, cell) {
+  cell.textContent = 'Reading the object list...';
+  const res = await fetch(`/api/devices/${instance}/objects`);
+  if (!res.ok) { cell.textContent = await res.text(); return; }
+  cell.textContent = '';
+  for (const entry of await res.json()) {
+    const label = document.createElement('label');
+    const pick = document.createElement('input');
+    pick.type = 'checkbox';
+    pick.value = entry.object;
+    label.append(pick, ' ' + entry.object + (entry.name ? ` (${entry.name})` : ''));
+    cell.append(label, document.createElement('br'));
+  }
+}
+
+async function finishSetup(e) {
+  e.preventDefault();
+  const config = setupConfig();
+  config.devices = Array.from($('setupDevices').rows).filter(row => row.cells[0].firstChild.checked).map(row => ({
+    instance: row.device.instance,
+    address: row.device.address,
+    points: Array.from(row.cells[4].querySelectorAll('input:checked')).map(pick => ({ object: pick.value })),
+  }));
+  const res = await fetch('/api/setup', { method: 'POST', headers: { 'Content-Type': 'application/json' }, body: JSON.stringify(config) });
+  if (!res.ok) { $('setupResult').textContent = await res.text(); return; }
+  const saved = await res.json();
+  $('setupResult').textContent = saved.restart_required.length
+    ? 'Saved. Restart the gateway to apply changes to: ' + saved.restart_required.join(', ')
+    : 'Saved and applied.';
+  $('setupResult').append(document.createElement('br'), Object.assign(document.createElement('a'), { href: '/', textContent: 'Go to the gateway' }));
+}
+
+async function showSetup() {
+  $('setupView').hidden = false;
+  const [status, current] = await Promise.all([fetch('/api/setup'), fetch('/api/config')]);
+  if (!status.ok || !current.ok) { $('setupResult').textContent = await (status.ok ? current : status).text(); return; }
+  interfaces = (await status.json()).interfaces;
+  draft = await current.json();
+  for (const i of interfaces) $('setupInterface').add(new Option(`${i.name} (${i.address}, ${i.subnet})`, i.address));
+  for (const [id, [section, key]] of Object.entries(setupFields)) $(id).value = draft[section][key] ?? '';
+  const [address, port] = [draft.bacnet.bind_addr.replace(/:\d+$/, ''), draft.bacnet.bind_addr.match(/:(\d+)$/)[1]];
+  if (interfaces.some(i => i.address === address)) $('setupInterface').value = address;
+  $('setupPort').value = port;
+  $('setupMqttTest').onclick = testMqtt;
+  $('setupScan').onclick = scan;
+  $('setup').onsubmit = finishSetup;
+  $('setupSkip').onclick = async () => { await fetch('/api/setup', { method: 'DELETE' }); location.href = '/'; };
+}
+
+// The gateway page sends the browser to the wizard until the setup is done
+async function start() {
+  const route = location.pathname.match(/^\/devices\/(\d+)$/);
+  if (route) { showDevice(Number(route[1])); return; }
+  if (location.pathname === '/setup') { showSetup(); return; }
+  const res = await fetch('/api/setup');
+  if (res.ok && (await res.json()).pending) { location.href = '/setup'; return; }
+  showGateway();
+}
+
+start();